@@ -133,6 +133,7 @@ impl<'tcx> ArgAbiExt<'tcx> for ArgAbi<'tcx, Ty<'tcx>> {
                     apply_arg_attrs_to_abi_param(AbiParam::new(pointer_ty(tcx)), extra_attrs),
                 ]
             }
+            PassMode::Spilled { .. } => unreachable!("spilled arguments are only used on BPF"),
         }
     }
 
@@ -165,6 +166,7 @@ fn get_abi_return(&self, tcx: TyCtxt<'tcx>) -> (Option<AbiParam>, Vec<AbiParam>)
             PassMode::Indirect { attrs: _, extra_attrs: Some(_), on_stack: _ } => {
                 unreachable!("unsized return value")
             }
+            PassMode::Spilled { .. } => unreachable!("spilled return value"),
         }
     }
 }
@@ -241,6 +243,7 @@ pub(super) fn adjust_arg_for_abi<'tcx>(
             (ptr, None) => smallvec![ptr.get_addr(fx)],
             (ptr, Some(meta)) => smallvec![ptr.get_addr(fx), meta],
         },
+        PassMode::Spilled { .. } => unreachable!("spilled arguments are only used on BPF"),
     }
 }
 
@@ -297,5 +300,6 @@ pub(super) fn cvalue_for_param<'tcx>(
                 arg_abi.layout,
             ))
         }
+        PassMode::Spilled { .. } => unreachable!("spilled arguments are only used on BPF"),
     }
 }
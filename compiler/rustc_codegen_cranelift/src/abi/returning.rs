@@ -47,6 +47,7 @@ pub(crate) fn can_return_to_ssa_var<'tcx>(
         PassMode::Ignore | PassMode::Direct(_) | PassMode::Pair(_, _) => true,
         // FIXME Make it possible to return Cast and Indirect to an ssa var.
         PassMode::Cast(_) | PassMode::Indirect { .. } => false,
+        PassMode::Spilled { .. } => unreachable!("spilled return value"),
     }
 }
 
@@ -82,6 +83,7 @@ pub(super) fn codegen_return_param<'tcx>(
         PassMode::Indirect { attrs: _, extra_attrs: Some(_), on_stack: _ } => {
             unreachable!("unsized return value")
         }
+        PassMode::Spilled { .. } => unreachable!("spilled return value"),
     };
 
     #[cfg(not(debug_assertions))]
@@ -118,6 +120,7 @@ pub(super) fn codegen_with_call_return_arg<'tcx, T>(
         PassMode::Indirect { attrs: _, extra_attrs: Some(_), on_stack: _ } => {
             unreachable!("unsized return value")
         }
+        PassMode::Spilled { .. } => unreachable!("spilled return value"),
         PassMode::Direct(_) | PassMode::Pair(_, _) | PassMode::Cast(_) => None,
     };
 
@@ -158,6 +161,7 @@ pub(super) fn codegen_with_call_return_arg<'tcx, T>(
         PassMode::Indirect { attrs: _, extra_attrs: Some(_), on_stack: _ } => {
             unreachable!("unsized return value")
         }
+        PassMode::Spilled { .. } => unreachable!("spilled return value"),
     }
 
     (call_inst, meta)
@@ -172,6 +176,7 @@ pub(crate) fn codegen_return(fx: &mut FunctionCx<'_, '_, '_>) {
         PassMode::Indirect { attrs: _, extra_attrs: Some(_), on_stack: _ } => {
             unreachable!("unsized return value")
         }
+        PassMode::Spilled { .. } => unreachable!("spilled return value"),
         PassMode::Direct(_) => {
             let place = fx.get_local_place(RETURN_PLACE);
             let ret_val = place.to_cvalue(fx).load_scalar(fx);
@@ -285,6 +285,10 @@ impl<'tcx> HasTargetSpec for FunctionCx<'_, '_, 'tcx> {
     fn target_spec(&self) -> &Target {
         &self.tcx.sess.target
     }
+
+    fn target_feature_flags(&self) -> &str {
+        &self.tcx.sess.opts.cg.target_feature
+    }
 }
 
 impl<'tcx> FunctionCx<'_, '_, 'tcx> {
@@ -409,4 +413,8 @@ impl<'tcx> HasTargetSpec for RevealAllLayoutCx<'tcx> {
     fn target_spec(&self) -> &Target {
         &self.0.sess.target
     }
+
+    fn target_feature_flags(&self) -> &str {
+        &self.0.sess.opts.cg.target_feature
+    }
 }
@@ -288,6 +288,7 @@ fn store_fn_arg(
                 let next_arg = next();
                 self.store(bx, next_arg, dst);
             }
+            PassMode::Spilled { .. } => bug!("spilled arguments are read from their block"),
         }
     }
 }
@@ -340,6 +341,7 @@ fn llvm_type(&self, cx: &CodegenCx<'ll, 'tcx>) -> &'ll Type {
                 llargument_tys.push(cx.type_ptr_to(self.ret.memory_ty(cx)));
                 cx.type_void()
             }
+            PassMode::Spilled { .. } => bug!("return values can't be spilled"),
         };
 
         for arg in &self.args {
@@ -349,7 +351,7 @@ fn llvm_type(&self, cx: &CodegenCx<'ll, 'tcx>) -> &'ll Type {
             }
 
             let llarg_ty = match arg.mode {
-                PassMode::Ignore => continue,
+                PassMode::Ignore | PassMode::Spilled { .. } => continue,
                 PassMode::Direct(_) => arg.layout.immediate_llvm_type(cx),
                 PassMode::Pair(..) => {
                     llargument_tys.push(arg.layout.scalar_pair_element_llvm_type(cx, 0, true));
@@ -371,6 +373,10 @@ fn llvm_type(&self, cx: &CodegenCx<'ll, 'tcx>) -> &'ll Type {
             llargument_tys.push(llarg_ty);
         }
 
+        if self.spill_block().is_some() {
+            llargument_tys.push(cx.type_i8p());
+        }
+
         if self.c_variadic {
             cx.type_variadic_func(&llargument_tys, llreturn_ty)
         } else {
@@ -445,7 +451,7 @@ fn apply_attrs_llfn(&self, cx: &CodegenCx<'ll, 'tcx>, llfn: &'ll Value) {
                 apply(&ArgAttributes::new());
             }
             match arg.mode {
-                PassMode::Ignore => {}
+                PassMode::Ignore | PassMode::Spilled { .. } => {}
                 PassMode::Indirect { ref attrs, extra_attrs: None, on_stack: true } => {
                     let i = apply(attrs);
                     unsafe {
@@ -520,7 +526,7 @@ fn apply_attrs_callsite(&self, bx: &mut Builder<'a, 'll, 'tcx>, callsite: &'ll V
                 apply(&ArgAttributes::new());
             }
             match arg.mode {
-                PassMode::Ignore => {}
+                PassMode::Ignore | PassMode::Spilled { .. } => {}
                 PassMode::Indirect { ref attrs, extra_attrs: None, on_stack: true } => {
                     let i = apply(attrs);
                     unsafe {
@@ -82,6 +82,10 @@ impl HasTargetSpec for Builder<'_, '_, 'tcx> {
     fn target_spec(&self) -> &Target {
         &self.cx.target_spec()
     }
+
+    fn target_feature_flags(&self) -> &str {
+        self.cx.target_feature_flags()
+    }
 }
 
 impl abi::LayoutOf for Builder<'_, '_, 'tcx> {
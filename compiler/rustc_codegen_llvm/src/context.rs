@@ -771,6 +771,10 @@ impl HasTargetSpec for CodegenCx<'ll, 'tcx> {
     fn target_spec(&self) -> &Target {
         &self.tcx.sess.target
     }
+
+    fn target_feature_flags(&self) -> &str {
+        &self.tcx.sess.opts.cg.target_feature
+    }
 }
 
 impl ty::layout::HasTyCtxt<'tcx> for CodegenCx<'ll, 'tcx> {
@@ -20,7 +20,7 @@
 use rustc_middle::ty::{self, Instance, Ty, TypeFoldable};
 use rustc_span::source_map::Span;
 use rustc_span::{sym, Symbol};
-use rustc_target::abi::call::{ArgAbi, FnAbi, PassMode};
+use rustc_target::abi::call::{ArgAbi, CastTarget, FnAbi, PassMode, Reg, Uniform};
 use rustc_target::abi::{self, LayoutOf};
 use rustc_target::spec::abi::Abi;
 
@@ -239,6 +239,8 @@ fn codegen_return_terminator(&mut self, mut bx: Bx) {
                 return;
             }
 
+            PassMode::Spilled { .. } => bug!("return values can't be spilled"),
+
             PassMode::Direct(_) | PassMode::Pair(..) => {
                 let op = self.codegen_consume(&mut bx, mir::Place::return_place().as_ref());
                 if let Ref(llval, _, align) = op.val {
@@ -683,6 +685,13 @@ fn codegen_call_terminator(
             (args, None)
         };
 
+        // Arguments that don't fit in registers are stored together, the
+        // callee gets the address of their block after the other arguments.
+        let spill_block = fn_abi.spill_block().map(|(size, align)| {
+            let block = Uniform { unit: Reg::i64(), total: size };
+            bx.alloca(bx.cast_backend_type(&CastTarget::from(block)), align)
+        });
+
         'make_args: for (i, arg) in first_args.iter().enumerate() {
             let mut op = self.codegen_operand(&mut bx, arg);
 
@@ -746,7 +755,7 @@ fn codegen_call_terminator(
                 _ => {}
             }
 
-            self.codegen_argument(&mut bx, op, &mut llargs, &fn_abi.args[i]);
+            self.codegen_argument(&mut bx, op, &mut llargs, &fn_abi.args[i], spill_block);
         }
         if let Some(tup) = untuple {
             self.codegen_arguments_untupled(
@@ -754,6 +763,7 @@ fn codegen_call_terminator(
                 tup,
                 &mut llargs,
                 &fn_abi.args[first_args.len()..],
+                spill_block,
             )
         }
 
@@ -773,7 +783,10 @@ fn codegen_call_terminator(
             );
 
             let last_arg = fn_abi.args.last().unwrap();
-            self.codegen_argument(&mut bx, location, &mut llargs, last_arg);
+            self.codegen_argument(&mut bx, location, &mut llargs, last_arg, spill_block);
+        }
+        if let Some(block) = spill_block {
+            llargs.push(bx.pointercast(block, bx.type_i8p()));
         }
 
         let fn_ptr = match (llfn, instance) {
@@ -1023,6 +1036,7 @@ fn codegen_argument(
         op: OperandRef<'tcx, Bx::Value>,
         llargs: &mut Vec<Bx::Value>,
         arg: &ArgAbi<'tcx, Ty<'tcx>>,
+        spill_block: Option<Bx::Value>,
     ) {
         // Fill padding with undef value, where applicable.
         if let Some(ty) = arg.pad {
@@ -1033,6 +1047,15 @@ fn codegen_argument(
             return;
         }
 
+        // Spilled arguments are stored in the block the caller allocated
+        // for them, its address is passed after all other arguments.
+        if let PassMode::Spilled { offset } = arg.mode {
+            let block = spill_block.expect("codegen_argument: no block for spilled arguments");
+            let dst = PlaceRef::spilled_arg(bx, block, offset, arg.layout);
+            op.val.store(bx, dst);
+            return;
+        }
+
         if let PassMode::Pair(..) = arg.mode {
             match op.val {
                 Pair(a, b) => {
@@ -1117,6 +1140,7 @@ fn codegen_arguments_untupled(
         operand: &mir::Operand<'tcx>,
         llargs: &mut Vec<Bx::Value>,
         args: &[ArgAbi<'tcx, Ty<'tcx>>],
+        spill_block: Option<Bx::Value>,
     ) {
         let tuple = self.codegen_operand(bx, operand);
 
@@ -1126,7 +1150,7 @@ fn codegen_arguments_untupled(
             for i in 0..tuple.layout.fields.count() {
                 let field_ptr = tuple_ptr.project_field(bx, i);
                 let field = bx.load_operand(field_ptr);
-                self.codegen_argument(bx, field, llargs, &args[i]);
+                self.codegen_argument(bx, field, llargs, &args[i], spill_block);
             }
         } else if let Ref(_, Some(_), _) = tuple.val {
             bug!("closure arguments must be sized")
@@ -1134,7 +1158,7 @@ fn codegen_arguments_untupled(
             // If the tuple is immediate, the elements are as well.
             for i in 0..tuple.layout.fields.count() {
                 let op = tuple.extract_field(bx, i);
-                self.codegen_argument(bx, op, llargs, &args[i]);
+                self.codegen_argument(bx, op, llargs, &args[i], spill_block);
             }
         }
     }
@@ -1,5 +1,6 @@
 use crate::base;
 use crate::traits::*;
+use crate::MemFlags;
 use rustc_errors::ErrorReported;
 use rustc_middle::mir;
 use rustc_middle::mir::interpret::ErrorHandled;
@@ -357,6 +358,7 @@ fn arg_local_refs<'a, 'tcx, Bx: BuilderMethods<'a, 'tcx>>(
     let mir = fx.mir;
     let mut idx = 0;
     let mut llarg_idx = fx.fn_abi.ret.is_indirect() as usize;
+    let mut spill_block = None;
 
     let args = mir
         .args_iter()
@@ -384,6 +386,12 @@ fn arg_local_refs<'a, 'tcx, Bx: BuilderMethods<'a, 'tcx>>(
                         llarg_idx += 1;
                     }
                     let pr_field = place.project_field(bx, i);
+                    if let PassMode::Spilled { offset } = arg.mode {
+                        let block = spilled_args(bx, &mut spill_block, &mut llarg_idx);
+                        let src = PlaceRef::spilled_arg(bx, block, offset, arg.layout);
+                        bx.load_operand(src).val.store(bx, pr_field);
+                        continue;
+                    }
                     bx.store_fn_arg(arg, &mut llarg_idx, pr_field);
                 }
 
@@ -405,6 +413,11 @@ fn arg_local_refs<'a, 'tcx, Bx: BuilderMethods<'a, 'tcx>>(
                 llarg_idx += 1;
             }
 
+            if let PassMode::Spilled { offset } = arg.mode {
+                let block = spilled_args(bx, &mut spill_block, &mut llarg_idx);
+                return LocalRef::Place(PlaceRef::spilled_arg(bx, block, offset, arg.layout));
+            }
+
             if !memory_locals.contains(local) {
                 // We don't have to cast or keep the argument in the alloca.
                 // FIXME(eddyb): We should figure out how to use llvm.dbg.value instead
@@ -440,7 +453,30 @@ fn arg_local_refs<'a, 'tcx, Bx: BuilderMethods<'a, 'tcx>>(
                 // FIXME: lifetimes
                 let llarg = bx.get_param(llarg_idx);
                 llarg_idx += 1;
-                LocalRef::Place(PlaceRef::new_sized(llarg, arg.layout))
+                let align = match arg.mode {
+                    PassMode::Indirect { ref attrs, .. } => attrs.pointee_align,
+                    _ => None,
+                };
+                match align {
+                    // Unless the ABI promises less alignment than the type
+                    // has. Accesses through the argument would assume too
+                    // much then, so it's copied to a local that is aligned
+                    // like the type.
+                    Some(align) if align < arg.layout.align.abi => {
+                        let tmp = PlaceRef::alloca(bx, arg.layout);
+                        base::memcpy_ty(
+                            bx,
+                            tmp.llval,
+                            tmp.align,
+                            llarg,
+                            align,
+                            arg.layout,
+                            MemFlags::empty(),
+                        );
+                        LocalRef::Place(tmp)
+                    }
+                    _ => LocalRef::Place(PlaceRef::new_sized(llarg, arg.layout)),
+                }
             } else if arg.is_unsized_indirect() {
                 // As the storage for the indirect argument lives during
                 // the whole function call, we just copy the fat pointer.
@@ -469,20 +505,40 @@ fn arg_local_refs<'a, 'tcx, Bx: BuilderMethods<'a, 'tcx>>(
         );
 
         let arg = fx.fn_abi.args.last().unwrap();
-        match arg.mode {
-            PassMode::Direct(_) => (),
+        fx.caller_location = Some(match arg.mode {
+            PassMode::Direct(_) => OperandRef {
+                val: OperandValue::Immediate(bx.get_param(llarg_idx)),
+                layout: arg.layout,
+            },
+            PassMode::Spilled { offset } => {
+                let block = spilled_args(bx, &mut spill_block, &mut llarg_idx);
+                let place = PlaceRef::spilled_arg(bx, block, offset, arg.layout);
+                bx.load_operand(place)
+            }
             _ => bug!("caller location must be PassMode::Direct, found {:?}", arg.mode),
-        }
-
-        fx.caller_location = Some(OperandRef {
-            val: OperandValue::Immediate(bx.get_param(llarg_idx)),
-            layout: arg.layout,
         });
     }
 
     args
 }
 
+/// Returns the address of the block of `PassMode::Spilled` arguments. Only
+/// trailing arguments are spilled, so it's the parameter after the ones
+/// passed so far when the first spilled argument is reached.
+fn spilled_args<'a, 'tcx, Bx: BuilderMethods<'a, 'tcx>>(
+    bx: &mut Bx,
+    block: &mut Option<Bx::Value>,
+    llarg_idx: &mut usize,
+) -> Bx::Value {
+    if let Some(block) = *block {
+        return block;
+    }
+    let param = bx.get_param(*llarg_idx);
+    *llarg_idx += 1;
+    *block = Some(param);
+    param
+}
+
 mod analyze;
 mod block;
 pub mod constant;
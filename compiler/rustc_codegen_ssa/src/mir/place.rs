@@ -10,7 +10,7 @@
 use rustc_middle::mir::tcx::PlaceTy;
 use rustc_middle::ty::layout::{HasTyCtxt, TyAndLayout};
 use rustc_middle::ty::{self, Ty};
-use rustc_target::abi::{Abi, Align, FieldsShape, Int, TagEncoding};
+use rustc_target::abi::{Abi, Align, FieldsShape, Int, Size, TagEncoding};
 use rustc_target::abi::{LayoutOf, VariantIdx, Variants};
 
 #[derive(Copy, Clone, Debug)]
@@ -67,6 +67,20 @@ pub fn alloca_unsized_indirect<Bx: BuilderMethods<'a, 'tcx, Value = V>>(
         Self::alloca(bx, ptr_layout)
     }
 
+    /// Returns the place of an argument passed as `PassMode::Spilled`, at
+    /// `offset` in the block of spilled arguments that `block` points to.
+    pub fn spilled_arg<Bx: BuilderMethods<'a, 'tcx, Value = V>>(
+        bx: &mut Bx,
+        block: V,
+        offset: Size,
+        layout: TyAndLayout<'tcx>,
+    ) -> Self {
+        let byte_ptr = bx.pointercast(block, bx.cx().type_i8p());
+        let llval = bx.inbounds_gep(byte_ptr, &[bx.cx().const_usize(offset.bytes())]);
+        let llval = bx.pointercast(llval, bx.cx().type_ptr_to(bx.cx().backend_type(layout)));
+        Self::new_sized(llval, layout)
+    }
+
     pub fn len<Cx: ConstMethods<'tcx, Value = V>>(&self, cx: &Cx) -> V {
         if let FieldsShape::Array { count, .. } = self.layout.fields {
             if self.layout.is_unsized() {
@@ -1,28 +1,142 @@
-use crate::abi::call::{ArgAbi, FnAbi};
+use crate::abi::call::{ArgAbi, FnAbi, PassMode, Reg, RegKind};
+use crate::abi::{Abi, FieldsShape, Size, Variants};
+use crate::spec::HasTargetSpec;
 
-fn classify_ret_ty<Ty>(ret: &mut ArgAbi<'_, Ty>) {
+#[cfg(test)]
+mod tests;
+
+/// Target feature selecting the calling convention of the SBFv2 virtual
+/// machine.
+const SBF_V2_FEATURE: &str = "solana-v2";
+
+/// Number of registers SBFv2 passes arguments in, `r1` to `r5`.
+const SBF_V2_ARG_REGS: usize = 5;
+
+/// Target feature of the Solana flavour of the BPF backend, which can return
+/// 128-bit values in the `r0`/`r1` register pair. Upstream LLVM can't, so
+/// without it such values are returned through memory.
+const REG_PAIR_FEATURE: &str = "solana";
+
+/// Whether `feature` is enabled by the target spec or by `-C target-feature`.
+/// Like for LLVM, the last `+feature` or `-feature` wins.
+fn has_feature<C: HasTargetSpec>(cx: &C, feature: &str) -> bool {
+    let spec = cx.target_spec().features.split(',');
+    spec.chain(cx.target_feature_flags().split(','))
+        .filter(|f| f.get(1..) == Some(feature))
+        .last()
+        .map_or(false, |f| f.starts_with('+'))
+}
+
+/// Passes `arg` by reference, with the pointee aligned like the type itself.
+///
+/// The generic indirect mode leaves the alignment to the caller's copy, so
+/// for types like `#[repr(align(16))]` structs it's promised in the
+/// argument's attributes too. Callers then allocate their copy at the
+/// type's alignment and callees can rely on it.
+fn make_indirect<Ty>(arg: &mut ArgAbi<'_, Ty>) {
+    arg.make_indirect();
+    if let PassMode::Indirect { ref mut attrs, extra_attrs: _, on_stack: _ } = arg.mode {
+        attrs.pointee_align = Some(arg.layout.align.abi);
+    }
+}
+
+fn classify_ret_ty<Ty>(ret: &mut ArgAbi<'_, Ty>, reg_pairs: bool) {
     if ret.layout.is_aggregate() || ret.layout.size.bits() > 64 {
-        if ret.layout.size.bits() != 128 {
-            ret.make_indirect();
+        // Only 128-bit integers go in the register pair, aggregates of the
+        // same size are still returned through memory.
+        let scalar = matches!(ret.layout.abi, Abi::Scalar(_));
+        if !scalar || ret.layout.size.bits() != 128 || !reg_pairs {
+            make_indirect(ret);
         }
     } else {
         ret.extend_integer_width_to(64);
     }
 }
 
+/// Whether `arg` is a struct or tuple made of two scalars, like `(u32, u32)`.
+/// Enums and unions don't qualify even when their layout is a scalar pair.
+fn is_scalar_aggregate<Ty>(arg: &ArgAbi<'_, Ty>) -> bool {
+    matches!(arg.layout.abi, Abi::ScalarPair(..))
+        && matches!(arg.layout.fields, FieldsShape::Arbitrary { .. })
+        && matches!(arg.layout.variants, Variants::Single { .. })
+}
+
 fn classify_arg_ty<Ty>(arg: &mut ArgAbi<'_, Ty>) {
+    // Like clang, pass aggregates of scalars that fit in a register as an
+    // integer of the same size rather than spilling them to the stack.
+    // Newtypes of a scalar have a scalar layout and are passed as one anyway.
+    if is_scalar_aggregate(arg) && arg.layout.size.bits() <= 64 {
+        arg.cast_to(Reg { kind: RegKind::Integer, size: arg.layout.size });
+        return;
+    }
     if arg.layout.is_aggregate() || arg.layout.size.bits() > 64 {
         if arg.layout.size.bits() != 128 {
-            arg.make_indirect();
+            make_indirect(arg);
         }
     } else {
         arg.extend_integer_width_to(64);
     }
 }
 
-pub fn compute_abi_info<Ty>(fty: &mut FnAbi<'_,Ty>) {
+/// Returns how many registers `classify_arg_ty` has `arg` passed in.
+fn arg_regs<Ty>(arg: &ArgAbi<'_, Ty>) -> usize {
+    if arg.layout.size.bits() == 128 { 2 } else { 1 }
+}
+
+/// Keeps arguments in registers for as long as they fit. When they don't
+/// all fit, the rest are spilled to a tuple in the caller's memory and its
+/// address is passed in `r5`.
+fn compute_abi_info_v2<Ty>(fty: &mut FnAbi<'_, Ty>, reg_pairs: bool) {
+    let mut regs = 0;
+    if !fty.ret.is_ignore() {
+        classify_ret_ty(&mut fty.ret, reg_pairs);
+        // The return slot takes up `r1`.
+        if fty.ret.is_indirect() {
+            regs += 1;
+        }
+    }
+
+    for arg in &mut fty.args {
+        if !arg.is_ignore() {
+            classify_arg_ty(arg);
+        }
+    }
+    let needed: usize = fty.args.iter().filter(|arg| !arg.is_ignore()).map(arg_regs).sum();
+    if regs + needed <= SBF_V2_ARG_REGS {
+        return;
+    }
+
+    // Arguments have to stay in order, so once one doesn't fit in the
+    // registers left next to the tuple's address, everything after it is
+    // spilled too.
+    let mut spilled = false;
+    let mut offset = Size::ZERO;
+    for arg in &mut fty.args {
+        if arg.is_ignore() {
+            continue;
+        }
+        spilled |= regs + arg_regs(arg) > SBF_V2_ARG_REGS - 1;
+        if spilled {
+            offset = offset.align_to(arg.layout.align.abi);
+            arg.mode = PassMode::Spilled { offset };
+            offset += arg.layout.size;
+        } else {
+            regs += arg_regs(arg);
+        }
+    }
+}
+
+pub fn compute_abi_info<C, Ty>(cx: &C, fty: &mut FnAbi<'_, Ty>)
+where
+    C: HasTargetSpec,
+{
+    let reg_pairs = has_feature(cx, REG_PAIR_FEATURE);
+    if has_feature(cx, SBF_V2_FEATURE) {
+        return compute_abi_info_v2(fty, reg_pairs);
+    }
+
     if !fty.ret.is_ignore() {
-        classify_ret_ty(&mut fty.ret);
+        classify_ret_ty(&mut fty.ret, reg_pairs);
     }
 
     for arg in &mut fty.args {
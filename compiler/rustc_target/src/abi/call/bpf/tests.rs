@@ -0,0 +1,180 @@
+use super::*;
+use crate::abi::call::{ArgAttributes, Conv};
+use crate::abi::{AbiAndPrefAlign, Align, FieldsShape, Integer, Layout, Primitive, Scalar};
+use crate::abi::{Abi, Size, TargetDataLayout, TyAndLayout, VariantIdx, Variants};
+use rustc_index::vec::Idx;
+use crate::spec::{Target, TargetTriple};
+
+fn bpf_target(features: &str) -> Target {
+    let triple = TargetTriple::from_triple("bpfel-unknown-unknown");
+    let mut target = Target::search(&triple).unwrap();
+    target.options.features = features.to_string();
+    target
+}
+
+/// A target along with the `-C target-feature` flags of the session.
+struct SessionTarget {
+    target: Target,
+    flags: &'static str,
+}
+
+impl HasTargetSpec for SessionTarget {
+    fn target_spec(&self) -> &Target {
+        &self.target
+    }
+
+    fn target_feature_flags(&self) -> &str {
+        self.flags
+    }
+}
+
+fn u64_layout(dl: &TargetDataLayout) -> Layout {
+    let scalar =
+        Scalar { value: Primitive::Int(Integer::I64, false), valid_range: 0..=u64::MAX as u128 };
+    Layout::scalar(dl, scalar)
+}
+
+fn u128_layout(dl: &TargetDataLayout) -> Layout {
+    let scalar = Scalar { value: Primitive::Int(Integer::I128, false), valid_range: 0..=u128::MAX };
+    Layout::scalar(dl, scalar)
+}
+
+/// Builds the `FnAbi` of an `extern "C" fn(u64, ...) -> ()` with `args`
+/// arguments before any target specific adjustments.
+fn fn_abi<'a>(dl: &TargetDataLayout, layout: &'a Layout, args: usize) -> FnAbi<'a, ()> {
+    let arg = || ArgAbi::new(dl, TyAndLayout { ty: (), layout }, |_, _, _| ArgAttributes::new());
+    let mut ret = arg();
+    ret.mode = PassMode::Ignore;
+    FnAbi {
+        args: (0..args).map(|_| arg()).collect(),
+        ret,
+        c_variadic: false,
+        fixed_count: args,
+        conv: Conv::C,
+        can_unwind: false,
+    }
+}
+
+#[test]
+fn six_scalar_args_v1() {
+    let target = bpf_target("+solana");
+    let dl = TargetDataLayout::parse(&target).unwrap();
+    let layout = u64_layout(&dl);
+    let mut abi = fn_abi(&dl, &layout, 6);
+    compute_abi_info(&target, &mut abi);
+    assert!(abi.args.iter().all(|arg| matches!(arg.mode, PassMode::Direct(_))));
+}
+
+#[test]
+fn six_scalar_args_v2() {
+    let target = bpf_target("+solana,+solana-v2");
+    let dl = TargetDataLayout::parse(&target).unwrap();
+    let layout = u64_layout(&dl);
+    let mut abi = fn_abi(&dl, &layout, 6);
+    compute_abi_info(&target, &mut abi);
+    // `r5` holds the address of the last two.
+    assert!(abi.args[..4].iter().all(|arg| matches!(arg.mode, PassMode::Direct(_))));
+    assert_eq!(abi.args[4].mode, PassMode::Spilled { offset: Size::ZERO });
+    assert_eq!(abi.args[5].mode, PassMode::Spilled { offset: Size::from_bytes(8) });
+    assert_eq!(abi.spill_block(), Some((Size::from_bytes(16), Align::from_bytes(8).unwrap())));
+
+    let mut abi = fn_abi(&dl, &layout, 5);
+    compute_abi_info(&target, &mut abi);
+    assert!(abi.args.iter().all(|arg| matches!(arg.mode, PassMode::Direct(_))));
+    assert_eq!(abi.spill_block(), None);
+}
+
+#[test]
+fn v2_from_target_feature_flags() {
+    let target = bpf_target("+solana");
+    let dl = TargetDataLayout::parse(&target).unwrap();
+    let layout = u64_layout(&dl);
+
+    let session = SessionTarget { target, flags: "+solana-v2" };
+    let mut abi = fn_abi(&dl, &layout, 6);
+    compute_abi_info(&session, &mut abi);
+    assert!(matches!(abi.args[5].mode, PassMode::Spilled { .. }));
+
+    // The last flag wins.
+    let session = SessionTarget { target: bpf_target("+solana,+solana-v2"), flags: "-solana-v2" };
+    let mut abi = fn_abi(&dl, &layout, 6);
+    compute_abi_info(&session, &mut abi);
+    assert!(abi.args.iter().all(|arg| matches!(arg.mode, PassMode::Direct(_))));
+}
+
+#[test]
+fn u128_return() {
+    let target = bpf_target("+solana");
+    let dl = TargetDataLayout::parse(&target).unwrap();
+    let layout = u128_layout(&dl);
+    let mut abi = fn_abi(&dl, &layout, 0);
+    abi.ret.mode = PassMode::Direct(ArgAttributes::new());
+    compute_abi_info(&target, &mut abi);
+    assert!(matches!(abi.ret.mode, PassMode::Direct(_)));
+
+    // Upstream LLVM has no register pairs, the value goes through memory.
+    let target = bpf_target("");
+    abi.ret.mode = PassMode::Direct(ArgAttributes::new());
+    compute_abi_info(&target, &mut abi);
+    assert!(matches!(abi.ret.mode, PassMode::Indirect { on_stack: false, .. }));
+}
+
+/// Layout of a struct with a single field of the given size and alignment.
+fn aggregate_layout(size: u64, align: u64) -> Layout {
+    Layout {
+        variants: Variants::Single { index: VariantIdx::new(0) },
+        fields: FieldsShape::Arbitrary { offsets: vec![Size::ZERO], memory_index: vec![0] },
+        abi: Abi::Aggregate { sized: true },
+        largest_niche: None,
+        align: AbiAndPrefAlign::new(Align::from_bytes(align).unwrap()),
+        size: Size::from_bytes(size),
+    }
+}
+
+#[test]
+fn u128_sized_struct_return() {
+    let target = bpf_target("+solana");
+    let dl = TargetDataLayout::parse(&target).unwrap();
+    // `struct S([u64; 2])`, as big as a `u128` but not a scalar.
+    let layout = aggregate_layout(16, 8);
+    let mut abi = fn_abi(&dl, &layout, 0);
+    abi.ret.mode = PassMode::Direct(ArgAttributes::new());
+    compute_abi_info(&target, &mut abi);
+    assert!(matches!(abi.ret.mode, PassMode::Indirect { on_stack: false, .. }));
+}
+
+#[test]
+fn over_aligned_indirect_arg() {
+    let target = bpf_target("+solana");
+    let dl = TargetDataLayout::parse(&target).unwrap();
+    // `#[repr(C, align(16))] struct S([u64; 4])`
+    let layout = aggregate_layout(32, 16);
+    let mut abi = fn_abi(&dl, &layout, 1);
+    compute_abi_info(&target, &mut abi);
+    match abi.args[0].mode {
+        PassMode::Indirect { ref attrs, extra_attrs: None, on_stack: false } => {
+            assert_eq!(attrs.pointee_align, Some(Align::from_bytes(16).unwrap()));
+            assert_eq!(attrs.pointee_size, Size::from_bytes(32));
+        }
+        ref mode => panic!("expected an indirect argument, got {:?}", mode),
+    }
+}
+
+#[test]
+fn ignored_zst_args_take_no_register() {
+    let target = bpf_target("+solana,+solana-v2");
+    let dl = TargetDataLayout::parse(&target).unwrap();
+    // `struct Marker(PhantomData<u64>)`, which `fn_abi_of` already ignores.
+    let marker = aggregate_layout(0, 1);
+    let mut marker = ArgAbi::new(&dl, TyAndLayout { ty: (), layout: &marker }, |_, _, _| {
+        ArgAttributes::new()
+    });
+    marker.mode = PassMode::Ignore;
+
+    let layout = u64_layout(&dl);
+    let mut abi = fn_abi(&dl, &layout, 5);
+    abi.args.insert(0, marker);
+    compute_abi_info(&target, &mut abi);
+    assert!(abi.args[0].is_ignore());
+    assert!(abi.args[1..].iter().all(|arg| matches!(arg.mode, PassMode::Direct(_))));
+}
@@ -49,6 +49,11 @@ pub enum PassMode {
     /// stack offset in accordance to the ABI rather than passed using a
     /// pointer. This corresponds to the `byval` LLVM argument attribute.
     Indirect { attrs: ArgAttributes, extra_attrs: Option<ArgAttributes>, on_stack: bool },
+    /// Pass the argument in a block of memory shared with the other spilled
+    /// arguments, at `offset` from its start. The address of the block is
+    /// passed after all other arguments, so only the trailing arguments of a
+    /// function can be spilled.
+    Spilled { offset: Size },
 }
 
 // Hack to disable non_upper_case_globals only for the bitflags! and not for the rest
@@ -599,6 +604,20 @@ pub struct FnAbi<'a, Ty> {
 }
 
 impl<'a, Ty> FnAbi<'a, Ty> {
+    /// Size and alignment of the block holding the `PassMode::Spilled`
+    /// arguments, if there are any.
+    pub fn spill_block(&self) -> Option<(Size, Align)> {
+        let mut block: Option<(Size, Align)> = None;
+        for arg in &self.args {
+            if let PassMode::Spilled { offset } = arg.mode {
+                let (size, align) = block.unwrap_or((Size::ZERO, arg.layout.align.abi));
+                let end = offset + arg.layout.size;
+                block = Some((size.max(end), align.max(arg.layout.align.abi)));
+            }
+        }
+        block.map(|(size, align)| (size.align_to(align), align))
+    }
+
     pub fn adjust_for_cabi<C>(&mut self, cx: &C, abi: spec::abi::Abi) -> Result<(), String>
     where
         Ty: TyAndLayoutMethods<'a, C> + Copy,
@@ -633,7 +652,7 @@ pub fn adjust_for_cabi<C>(&mut self, cx: &C, abi: spec::abi::Abi) -> Result<(),
             "amdgpu" => amdgpu::compute_abi_info(cx, self),
             "arm" => arm::compute_abi_info(cx, self),
             "avr" => avr::compute_abi_info(self),
-            "bpf" => bpf::compute_abi_info(self),
+            "bpf" => bpf::compute_abi_info(cx, self),
             "mips" => mips::compute_abi_info(cx, self),
             "mips64" => mips64::compute_abi_info(cx, self),
             "powerpc" => powerpc::compute_abi_info(self),
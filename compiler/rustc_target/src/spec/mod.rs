@@ -840,6 +840,12 @@ pub struct Target {
 
 pub trait HasTargetSpec {
     fn target_spec(&self) -> &Target;
+
+    /// The `-C target-feature` flags, applied on top of the `features` of the
+    /// target spec.
+    fn target_feature_flags(&self) -> &str {
+        ""
+    }
 }
 
 impl HasTargetSpec for Target {
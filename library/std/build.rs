@@ -3,6 +3,10 @@
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     let target = env::var("TARGET").expect("TARGET was not set");
+    if target.contains("bpf") {
+        println!("cargo:rerun-if-env-changed=RUST_SBF_HEAP_SIZE");
+        println!("cargo:rerun-if-env-changed=RUST_BPF_STACK_SIZE");
+    }
     if target.contains("freebsd") {
         if env::var("RUST_STD_FREEBSD_12_ABI").is_ok() {
             println!("cargo:rustc-cfg=freebsd12");
@@ -7,6 +7,13 @@
 
 use crate::alloc::{GlobalAlloc, Layout, System};
 
+/// Size of the heap the loader sets up for the program, which it reads from
+/// this section. Configured with `target.<triple>.sbf-heap-size` when building
+/// std, rustbuild checks that the value is one the loader accepts.
+#[used]
+#[link_section = ".sbf.heap_size"]
+static HEAP_SIZE: u64 = super::parse_size(option_env!("RUST_SBF_HEAP_SIZE"), 32 * 1024);
+
 #[stable(feature = "alloc_system_type", since = "1.28.0")]
 unsafe impl GlobalAlloc for System {
     #[inline]
@@ -48,6 +48,22 @@
     fn sol_log_(message: *const u8, length: u64);
 }
 
+/// Parses a size in bytes that rustbuild passed in through the environment
+/// and has already validated, falling back to `default` if there is none.
+const fn parse_size(size: Option<&str>, default: u64) -> u64 {
+    let size = match size {
+        Some(size) => size.as_bytes(),
+        None => return default,
+    };
+    let mut value = 0;
+    let mut i = 0;
+    while i < size.len() {
+        value = value * 10 + (size[i] - b'0') as u64;
+        i += 1;
+    }
+    value
+}
+
 pub fn sol_log(message: &str) {
     unsafe {
         sol_log_(message.as_ptr(), message.len() as u64);
@@ -3,6 +3,11 @@
 use crate::sys::{unsupported, Void};
 use crate::time::Duration;
 
+/// Size of the stack frames the loader gives the program, set with
+/// `--bpf-stack-size` or `rust.bpf-stack-size` when building std.
+pub const DEFAULT_MIN_STACK_SIZE: usize =
+    super::parse_size(option_env!("RUST_BPF_STACK_SIZE"), 4096) as usize;
+
 pub struct Thread(Void);
 
 impl Thread {
@@ -23,5 +23,5 @@ pub fn min_stack() -> usize {
 
 #[cfg(target_arch = "bpf")]
 pub fn min_stack() -> usize {
-    0
+    crate::sys::thread::DEFAULT_MIN_STACK_SIZE
 }
@@ -0,0 +1,204 @@
+//! Implementation of `x.py audit-sysroot`.
+//!
+//! Programs for BPF targets are loaded by a runtime that neither resolves
+//! shared libraries nor exports anything beyond its own syscalls, so every
+//! shared object meant for it has to be fully self-contained. This module
+//! runs `llvm-readobj` over the shared objects in a target's sysroot (or over
+//! the paths given on the command line) and reports any dynamic dependency or
+//! undefined dynamic symbol the loader would not be able to satisfy.
+//!
+//! For targets configured with `no-float` the artifacts are also disassembled
+//! with `llvm-objdump`. BPF has no floating point instructions, so floating
+//! point shows up as calls to the soft float routines of compiler-builtins.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+use build_helper::{output, t};
+
+use crate::util::exe;
+use crate::{Build, Compiler};
+
+/// Undefined symbols the loader resolves on its own, in addition to its
+/// `sol_*` syscalls.
+const ALLOWED_SYMBOLS: &[&str] = &["abort"];
+
+/// Operations of the compiler-builtins soft float routines, which are named
+/// `__<op><types><arity>`, e.g. `__adddf3` or `__fixunssfdi`.
+const SOFT_FLOAT_OPS: &[&str] = &[
+    "add", "sub", "mul", "div", "neg", "pow", "eq", "ne", "lt", "le", "gt", "ge", "unord", "cmp",
+    "fix", "float", "extend", "trunc",
+];
+
+pub fn audit_sysroot(build: &Build, paths: &[PathBuf]) {
+    if build.config.dry_run {
+        return;
+    }
+
+    // Pairs of artifacts and whether they must be free of floating point.
+    let artifacts = if paths.is_empty() {
+        let mut artifacts = Vec::new();
+        for target in &build.targets {
+            if !target.contains("bpf") {
+                build.info(&format!("Skipping {}, only BPF sysroots are audited", target));
+                continue;
+            }
+            let compiler = Compiler { stage: build.config.stage, host: build.build };
+            let libdir =
+                build.sysroot_for(compiler).join("lib/rustlib").join(&*target.triple).join("lib");
+            let no_float = build.no_float(*target);
+            artifacts.extend(shared_objects(&libdir).into_iter().map(|so| (so, no_float)));
+        }
+        artifacts
+    } else {
+        // There's no telling which target a given path was built for, so
+        // apply the policy if any of the requested targets has it.
+        let no_float = build.targets.iter().any(|&target| build.no_float(target));
+        paths.iter().map(|path| (path.clone(), no_float)).collect()
+    };
+
+    let readobj = build.llvm_bin(build.config.build).join(exe("llvm-readobj", build.config.build));
+    let objdump = build.llvm_bin(build.config.build).join(exe("llvm-objdump", build.config.build));
+    let mut failed = false;
+    for (artifact, no_float) in &artifacts {
+        build.verbose(&format!("auditing {}", artifact.display()));
+        let out =
+            output(Command::new(&readobj).arg("--dynamic").arg("--dyn-symbols").arg(artifact));
+        let mut problems = audit_readobj_output(&out);
+        if *no_float {
+            let out = output(Command::new(&objdump).arg("-d").arg("-r").arg(artifact));
+            problems.extend(audit_float_ops(&out));
+        }
+        if !problems.is_empty() {
+            failed = true;
+            println!("{}:", artifact.display());
+            for problem in problems {
+                println!("    {}", problem);
+            }
+        }
+    }
+
+    if failed {
+        println!("\nfound artifacts the loader would reject, see above");
+        process::exit(1);
+    }
+    build.info(&format!(
+        "Audited {} artifacts, no unexpected dynamic dependencies or floating point",
+        artifacts.len()
+    ));
+}
+
+/// Returns all shared objects directly inside `dir`, which may not exist yet.
+fn shared_objects(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut objects = entries
+        .map(|entry| t!(entry).path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "so"))
+        .collect::<Vec<_>>();
+    objects.sort();
+    objects
+}
+
+/// Checks the output of `llvm-readobj --dynamic --dyn-symbols`,
+/// returning a description of everything the loader would reject.
+fn audit_readobj_output(output: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut symbol = None;
+    for line in output.lines().map(|line| line.trim()) {
+        if let Some(pos) = line.find("Shared library: [") {
+            let library = &line[pos + "Shared library: [".len()..];
+            let library = library.trim_end_matches(']');
+            problems.push(format!("dynamic dependency on `{}`", library));
+        } else if let Some(name) = line.strip_prefix("Name:") {
+            // Symbol names are followed by their string table offset, `foo (12)`.
+            let name = name.trim();
+            let name = name.rsplitn(2, ' ').last().unwrap_or(name);
+            symbol = if name.starts_with('(') { None } else { Some(name.to_string()) };
+        } else if line.starts_with("Section: Undefined") {
+            if let Some(name) = symbol.take() {
+                if !name.starts_with("sol_") && !ALLOWED_SYMBOLS.contains(&&*name) {
+                    problems.push(format!("undefined dynamic symbol `{}`", name));
+                }
+            }
+        }
+    }
+    problems
+}
+
+/// Checks the output of `llvm-objdump -d -r`, returning a description of
+/// every call to a soft float routine along with where it happens.
+pub(crate) fn audit_float_ops(output: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut function = "";
+    for line in output.lines() {
+        let line = line.trim();
+        // Functions start with `0000000000000000 <entrypoint>:`.
+        if let Some(name) = line.strip_suffix(">:") {
+            if let Some(pos) = name.find(" <") {
+                function = &name[pos + 2..];
+            }
+            continue;
+        }
+        // Relocations of calls look like `0000000000000010:  R_BPF_64_32  __adddf3`.
+        let mut parts = line.split_whitespace();
+        let (offset, kind, symbol) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(offset), Some(kind), Some(symbol)) if kind.starts_with("R_BPF_") => {
+                (offset.trim_end_matches(':'), kind, symbol)
+            }
+            _ => continue,
+        };
+        if is_soft_float(symbol) {
+            let offset = u64::from_str_radix(offset, 16).unwrap_or(0);
+            problems.push(format!(
+                "floating point operation `{}` in `{}` at offset {:#x} ({})",
+                symbol, function, offset, kind
+            ));
+        }
+    }
+    problems
+}
+
+/// Returns the symbols listed in the contents of `src/etc/bpf-host-symbols.txt`.
+pub(crate) fn parse_host_symbols(denylist: &str) -> Vec<&str> {
+    denylist
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+}
+
+/// Returns the symbols in the output of `llvm-nm` that are on `denylist`,
+/// sorted and without duplicates.
+pub(crate) fn host_symbols(nm_output: &str, denylist: &[&str]) -> Vec<String> {
+    let mut found = nm_output
+        .lines()
+        // Lines are `<value> <type> <name>`, the value is blank if undefined.
+        .filter_map(|line| line.split_whitespace().last())
+        .filter(|symbol| {
+            let name = symbol.split('@').next().unwrap();
+            denylist.contains(&name)
+        })
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    found.sort();
+    found.dedup();
+    found
+}
+
+fn is_soft_float(symbol: &str) -> bool {
+    let name = match symbol.strip_prefix("__") {
+        Some(name) => name.trim_end_matches(|c: char| c.is_ascii_digit()),
+        None => return false,
+    };
+    SOFT_FLOAT_OPS.iter().any(|op| match name.strip_prefix(op) {
+        Some(types) => ["sf", "df", "tf"].iter().any(|ty| types.contains(ty)),
+        None => false,
+    })
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,134 @@
+use super::*;
+
+const CLEAN: &str = "\
+File: clean.so
+Format: elf64-bpf
+Arch: bpfel
+AddressSize: 64bit
+LoadName: <Not found>
+DynamicSection [ (4 entries)
+  Tag                Type                 Name/Value
+  0x0000000000000006 SYMTAB               0x1a8
+  0x000000000000000B SYMENT               24 (bytes)
+  0x0000000000000005 STRTAB               0x1f0
+  0x0000000000000000 NULL                 0x0
+]
+DynamicSymbols [
+  Symbol {
+    Name:  (0)
+    Value: 0x0
+    Size: 0
+    Binding: Local (0x0)
+    Type: None (0x0)
+    Other: 0
+    Section: Undefined (0x0)
+  }
+  Symbol {
+    Name: entrypoint (1)
+    Value: 0x120
+    Size: 64
+    Binding: Global (0x1)
+    Type: Function (0x2)
+    Other: 0
+    Section: .text (0x1)
+  }
+  Symbol {
+    Name: sol_log_ (12)
+    Value: 0x0
+    Size: 0
+    Binding: Global (0x1)
+    Type: None (0x0)
+    Other: 0
+    Section: Undefined (0x0)
+  }
+  Symbol {
+    Name: abort (21)
+    Value: 0x0
+    Size: 0
+    Binding: Global (0x1)
+    Type: None (0x0)
+    Other: 0
+    Section: Undefined (0x0)
+  }
+]
+";
+
+#[test]
+fn clean_artifact_passes() {
+    assert!(audit_readobj_output(CLEAN).is_empty());
+}
+
+#[test]
+fn unexpected_dynamic_dependency() {
+    let output = CLEAN
+        .replace(
+            "  0x0000000000000000 NULL",
+            "  0x0000000000000001 NEEDED               Shared library: [libc.so.6]\n  \
+             0x0000000000000000 NULL",
+        )
+        .replace("Name: abort (21)", "Name: malloc (21)");
+    assert_eq!(
+        audit_readobj_output(&output),
+        vec![
+            "dynamic dependency on `libc.so.6`".to_string(),
+            "undefined dynamic symbol `malloc`".to_string(),
+        ]
+    );
+}
+
+const INTEGER_ONLY: &str = "
+program.so:\tfile format elf64-bpf
+
+Disassembly of section .text:
+
+0000000000000120 <entrypoint>:
+      36:\tb7 01 00 00 2a 00 00 00\tr1 = 42
+      37:\t85 10 00 00 ff ff ff ff\tcall -1
+\t\t0000000000000128:  R_BPF_64_32\tsol_log_
+      38:\t85 10 00 00 ff ff ff ff\tcall -1
+\t\t0000000000000130:  R_BPF_64_32\t__udivti3
+      39:\t95 00 00 00 00 00 00 00\texit
+";
+
+#[test]
+fn integer_only_passes() {
+    assert!(audit_float_ops(INTEGER_ONLY).is_empty());
+}
+
+#[test]
+fn float_op_is_flagged() {
+    let output = INTEGER_ONLY.replace("sol_log_", "__adddf3");
+    assert_eq!(
+        audit_float_ops(&output),
+        vec!["floating point operation `__adddf3` in `entrypoint` at offset 0x128 (R_BPF_64_32)"]
+    );
+    assert!(is_soft_float("__fixunssfdi"));
+    assert!(is_soft_float("__floatditf"));
+    assert!(!is_soft_float("__multi3"));
+    assert!(!is_soft_float("memcpy"));
+}
+
+const NM_CLEAN: &str = "\
+0000000000000120 T entrypoint
+0000000000000000 T memcpy
+                 U sol_log_
+0000000000000200 r .L__unnamed_1
+";
+
+const NM_HOST: &str = "\
+0000000000000120 T entrypoint
+                 U __libc_start_main@GLIBC_2.2.5
+0000000000000340 T malloc
+                 U malloc
+                 U sol_log_
+";
+
+#[test]
+fn host_symbols_denylist() {
+    let denylist = parse_host_symbols(include_str!("../../etc/bpf-host-symbols.txt"));
+    assert!(denylist.contains(&"__libc_start_main"));
+    assert!(!denylist.iter().any(|symbol| symbol.starts_with('#') || symbol.is_empty()));
+
+    assert_eq!(host_symbols(NM_CLEAN, &denylist), Vec::<String>::new());
+    assert_eq!(host_symbols(NM_HOST, &denylist), ["__libc_start_main@GLIBC_2.2.5", "malloc"]);
+}
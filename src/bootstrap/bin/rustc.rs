@@ -16,8 +16,10 @@
 //! never get replaced.
 
 use std::env;
+use std::fs::File;
+use std::io::{self, BufReader};
 use std::path::PathBuf;
-use std::process::{Child, Command};
+use std::process::{Child, Command, Stdio};
 use std::str::FromStr;
 use std::time::Instant;
 
@@ -70,6 +72,17 @@ fn main() {
         }
     }
 
+    // `--time-passes` asks for the passes of the crates built for a target.
+    // What rustc prints for them is split off from its diagnostics into a file
+    // per crate.
+    let time_passes = match (crate_name, env::var_os("RUSTC_TIME_PASSES")) {
+        (Some(crate_name), Some(dir)) if target.is_some() => {
+            cmd.arg("-Ztime-passes").stderr(Stdio::piped());
+            Some(PathBuf::from(dir).join(format!("{}.txt", crate_name)))
+        }
+        _ => None,
+    };
+
     // Print backtrace in case of ICE
     if env::var("RUSTC_BACKTRACE_ON_ICE").is_ok() && env::var("RUST_BACKTRACE").is_err() {
         cmd.env("RUST_BACKTRACE", "1");
@@ -166,6 +179,12 @@ fn main() {
     let (child, status) = {
         let errmsg = format!("\nFailed to run:\n{:?}\n-------------", cmd);
         let mut child = cmd.spawn().expect(&errmsg);
+        if let Some(path) = &time_passes {
+            let stderr = BufReader::new(child.stderr.take().unwrap());
+            let mut passes = File::create(path).expect(&errmsg);
+            bootstrap::util::split_time_passes(stderr, &mut passes, &mut io::stderr())
+                .expect(&errmsg);
+        }
         let status = child.wait().expect(&errmsg);
         (child, status)
     };
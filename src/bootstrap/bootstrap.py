@@ -3,6 +3,7 @@ import argparse
 import contextlib
 import datetime
 import distutils.version
+import functools
 import hashlib
 import os
 import re
@@ -11,8 +12,13 @@ import subprocess
 import sys
 import tarfile
 import tempfile
+import threading
 
-from time import time
+from time import sleep, time
+
+# How often a download is tried before giving up, unless `download-attempts`
+# says otherwise
+DEFAULT_DOWNLOAD_ATTEMPTS = 5
 
 def support_xz():
     try:
@@ -24,7 +30,7 @@ def support_xz():
     except tarfile.CompressionError:
         return False
 
-def get(url, path, verbose=False, do_verify=True):
+def get(url, path, verbose=False, do_verify=True, attempts=DEFAULT_DOWNLOAD_ATTEMPTS):
     suffix = '.sha256'
     sha_url = url + suffix
     with tempfile.NamedTemporaryFile(delete=False) as temp_file:
@@ -34,7 +40,7 @@ def get(url, path, verbose=False, do_verify=True):
 
     try:
         if do_verify:
-            download(sha_path, sha_url, False, verbose)
+            download(sha_path, sha_url, False, verbose, attempts)
             if os.path.exists(path):
                 if verify(path, sha_path, False):
                     if verbose:
@@ -45,7 +51,7 @@ def get(url, path, verbose=False, do_verify=True):
                         print("ignoring already-download file",
                             path, "due to failed verification")
                     os.unlink(path)
-        download(temp_path, url, True, verbose)
+        download(temp_path, url, True, verbose, attempts)
         if do_verify and not verify(temp_path, sha_path, verbose):
             raise RuntimeError("failed verification")
         if verbose:
@@ -56,6 +62,38 @@ def get(url, path, verbose=False, do_verify=True):
         delete_if_present(temp_path, verbose)
 
 
+def get_all(downloads, jobs, verbose=False, fetch=get):
+    """Fetch each `(url, path, do_verify)` in `downloads`, at most `jobs` at a time
+
+    The downloads are independent of each other and each one is verified on
+    its own by `fetch`. Any failure is raised again once every download has
+    finished, so that a single bad mirror doesn't leave the others half done.
+    """
+    pending = list(downloads)
+    failures = []
+    lock = threading.Lock()
+
+    def worker():
+        while True:
+            with lock:
+                if not pending:
+                    return
+                url, path, do_verify = pending.pop(0)
+            try:
+                fetch(url, path, verbose=verbose, do_verify=do_verify)
+            except BaseException as exc:  # pylint: disable=broad-except
+                with lock:
+                    failures.append(exc)
+
+    threads = [threading.Thread(target=worker) for _ in range(min(jobs, len(pending)))]
+    for thread in threads:
+        thread.start()
+    for thread in threads:
+        thread.join()
+    if failures:
+        raise failures[0]
+
+
 def delete_if_present(path, verbose):
     """Remove the given file if present"""
     if os.path.isfile(path):
@@ -64,38 +102,82 @@ def delete_if_present(path, verbose):
         os.unlink(path)
 
 
-def download(path, url, probably_big, verbose):
-    for _ in range(0, 4):
+class DownloadError(RuntimeError):
+    """A failed download, `status` is the HTTP status if the server answered"""
+    def __init__(self, url, status=None):
+        if status is None:
+            msg = "failed to download {}".format(url)
+        else:
+            msg = "failed to download {}: HTTP status {}".format(url, status)
+        super(DownloadError, self).__init__(msg)
+        self.status = status
+
+    def retryable(self):
+        """Network and server errors may go away, anything else won't
+
+        >>> DownloadError("https://example.com").retryable()
+        True
+        >>> DownloadError("https://example.com", 503).retryable()
+        True
+        >>> DownloadError("https://example.com", 404).retryable()
+        False
+        """
+        return self.status is None or self.status >= 500
+
+
+def download(path, url, probably_big, verbose, attempts=DEFAULT_DOWNLOAD_ATTEMPTS,
+             fetch=None, wait=sleep):
+    """Download `url` to `path`, retrying with exponential backoff
+
+    Only network and server errors are retried, a missing file won't show up
+    by asking again. Exits once all `attempts` failed.
+    """
+    if fetch is None:
+        fetch = _download
+    for attempt in range(1, attempts + 1):
         try:
-            _download(path, url, probably_big, verbose, True)
+            fetch(path, url, probably_big, verbose)
             return
-        except RuntimeError:
-            print("\nspurious failure, trying again")
-    _download(path, url, probably_big, verbose, False)
+        except DownloadError as exc:
+            if not exc.retryable() or attempt == attempts:
+                sys.exit("error: {}".format(exc))
+            delay = 2 ** (attempt - 1)
+            print("\nspurious failure ({}), trying again in {}s".format(exc, delay))
+            wait(delay)
 
 
-def _download(path, url, probably_big, verbose, exception):
+def _download(path, url, probably_big, verbose):
     if probably_big or verbose:
         print("downloading {}".format(url))
     # see http://serverfault.com/questions/301128/how-to-download
     if sys.platform == 'win32':
-        run(["PowerShell.exe", "/nologo", "-Command",
-             "[Net.ServicePointManager]::SecurityProtocol = [Net.SecurityProtocolType]::Tls12;",
-             "(New-Object System.Net.WebClient).DownloadFile('{}', '{}')".format(url, path)],
-            verbose=verbose,
-            exception=exception)
+        try:
+            run(["PowerShell.exe", "/nologo", "-Command",
+                 "[Net.ServicePointManager]::SecurityProtocol = [Net.SecurityProtocolType]::Tls12;",
+                 "(New-Object System.Net.WebClient).DownloadFile('{}', '{}')".format(url, path)],
+                verbose=verbose,
+                exception=True)
+        except RuntimeError:
+            raise DownloadError(url)
     else:
         if probably_big or verbose:
             option = "-#"
         else:
             option = "-s"
         require(["curl", "--version"])
-        run(["curl", option,
-             "-y", "30", "-Y", "10",    # timeout if speed is < 10 bytes/sec for > 30 seconds
-             "--connect-timeout", "30",  # timeout if cannot connect within 30 seconds
-             "--retry", "3", "-Sf", "-o", path, url],
-            verbose=verbose,
-            exception=exception)
+        args = ["curl", option,
+                "-y", "30", "-Y", "10",    # timeout if speed is < 10 bytes/sec for > 30 seconds
+                "--connect-timeout", "30",  # timeout if cannot connect within 30 seconds
+                "--retry", "3", "-Sf", "-w", "%{http_code}", "-o", path, url]
+        if verbose:
+            print("running: " + ' '.join(args))
+        sys.stdout.flush()
+        proc = subprocess.Popen(args, stdout=subprocess.PIPE)
+        status, _ = proc.communicate()
+        if proc.returncode != 0:
+            # curl prints `000` if the server never answered
+            status = int(status.strip() or 0)
+            raise DownloadError(url, status or None)
 
 
 def verify(path, sha_path, verbose):
@@ -115,9 +197,10 @@ def verify(path, sha_path, verbose):
 
 
 def unpack(tarball, tarball_suffix, dst, verbose=False, match=None):
-    """Unpack the given tarball file"""
+    """Unpack the given tarball file, returning the paths of the files extracted"""
     print("extracting", tarball)
     fname = os.path.basename(tarball).replace(tarball_suffix, "")
+    files = []
     with contextlib.closing(tarfile.open(tarball)) as tar:
         for member in tar.getnames():
             if "/" not in member:
@@ -135,7 +218,27 @@ def unpack(tarball, tarball_suffix, dst, verbose=False, match=None):
             if os.path.isdir(src_path) and os.path.exists(dst_path):
                 continue
             shutil.move(src_path, dst_path)
+            if not os.path.isdir(dst_path):
+                files.append(dst_path)
     shutil.rmtree(os.path.join(dst, fname))
+    return files
+
+
+def write_sha256s(path, root, files):
+    """Record the sha256 of each of the given files in `path`
+
+    Each line holds a hash and the path of the file relative to `root`, like
+    the output of `sha256sum`. rustbuild checks the extracted stage0 against
+    them before building anything.
+    """
+    with output(path) as sums:
+        for name in sorted(set(files)):
+            sha256 = hashlib.sha256()
+            with open(name, "rb") as source:
+                for chunk in iter(lambda: source.read(64 * 1024), b""):
+                    sha256.update(chunk)
+            rel = os.path.relpath(name, root).replace(os.sep, "/")
+            sums.write("{}  {}\n".format(sha256.hexdigest(), rel))
 
 
 def run(args, verbose=False, exception=False, **kwargs):
@@ -378,6 +481,7 @@ class RustBuild(object):
         self.rust_root = ''
         self.use_locked_deps = ''
         self.use_vendored_sources = ''
+        self.use_offline = ''
         self.verbose = False
         self.git_version = None
         self.nix_deps_dir = None
@@ -390,61 +494,54 @@ class RustBuild(object):
         tarball which has the stage0 compiler used to then bootstrap the Rust
         compiler itself.
 
-        Each downloaded tarball is extracted, after that, the script
-        will move all the content to the right place.
+        All tarballs that are needed are fetched first, up to `download-jobs`
+        of them at a time. Each downloaded tarball is then extracted, after
+        that, the script will move all the content to the right place.
         """
         rustc_channel = self.rustc_channel
         rustfmt_channel = self.rustfmt_channel
+        tarball_suffix = '.tar.xz' if support_xz() else '.tar.gz'
 
+        rustc_components = []
         if self.rustc().startswith(self.bin_root()) and \
                 (not os.path.exists(self.rustc()) or
                  self.program_out_of_date(self.rustc_stamp(), self.date + str(self.rustc_commit))):
             if os.path.exists(self.bin_root()):
                 shutil.rmtree(self.bin_root())
             download_rustc = self.rustc_commit is not None
-            tarball_suffix = '.tar.xz' if support_xz() else '.tar.gz'
             filename = "rust-std-{}-{}{}".format(
                 rustc_channel, self.build, tarball_suffix)
             pattern = "rust-std-{}".format(self.build)
-            self._download_component_helper(filename, pattern, tarball_suffix, download_rustc)
+            rustc_components.append(
+                self._component_download(filename, pattern, download_rustc)
+            )
             filename = "rustc-{}-{}{}".format(rustc_channel, self.build,
                                               tarball_suffix)
-            self._download_component_helper(filename, "rustc", tarball_suffix, download_rustc)
+            rustc_components.append(
+                self._component_download(filename, "rustc", download_rustc)
+            )
             filename = "cargo-{}-{}{}".format(rustc_channel, self.build,
                                               tarball_suffix)
-            self._download_component_helper(filename, "cargo", tarball_suffix)
+            rustc_components.append(self._component_download(filename, "cargo"))
             if self.rustc_commit is not None:
                 filename = "rustc-dev-{}-{}{}".format(rustc_channel, self.build, tarball_suffix)
-                self._download_component_helper(
-                    filename, "rustc-dev", tarball_suffix, download_rustc
+                rustc_components.append(
+                    self._component_download(filename, "rustc-dev", download_rustc)
                 )
 
-            self.fix_bin_or_dylib("{}/bin/rustc".format(self.bin_root()))
-            self.fix_bin_or_dylib("{}/bin/rustdoc".format(self.bin_root()))
-            self.fix_bin_or_dylib("{}/bin/cargo".format(self.bin_root()))
-            lib_dir = "{}/lib".format(self.bin_root())
-            for lib in os.listdir(lib_dir):
-                if lib.endswith(".so"):
-                    self.fix_bin_or_dylib(os.path.join(lib_dir, lib), rpath_libz=True)
-            with output(self.rustc_stamp()) as rust_stamp:
-                rust_stamp.write(self.date + str(self.rustc_commit))
-
+        rustfmt_components = []
         if self.rustfmt() and self.rustfmt().startswith(self.bin_root()) and (
             not os.path.exists(self.rustfmt())
             or self.program_out_of_date(self.rustfmt_stamp(), self.rustfmt_channel)
         ):
             if rustfmt_channel:
-                tarball_suffix = '.tar.xz' if support_xz() else '.tar.gz'
                 [channel, date] = rustfmt_channel.split('-', 1)
                 filename = "rustfmt-{}-{}{}".format(channel, self.build, tarball_suffix)
-                self._download_component_helper(
-                    filename, "rustfmt-preview", tarball_suffix, key=date
+                rustfmt_components.append(
+                    self._component_download(filename, "rustfmt-preview", key=date)
                 )
-                self.fix_bin_or_dylib("{}/bin/rustfmt".format(self.bin_root()))
-                self.fix_bin_or_dylib("{}/bin/cargo-fmt".format(self.bin_root()))
-                with output(self.rustfmt_stamp()) as rustfmt_stamp:
-                    rustfmt_stamp.write(self.rustfmt_channel)
 
+        llvm_components = []
         if self.downloading_llvm():
             # We want the most recent LLVM submodule update to avoid downloading
             # LLVM more often than necessary.
@@ -465,17 +562,55 @@ class RustBuild(object):
                 "{}/src/bootstrap/download-ci-llvm-stamp".format(top_level),
             ]).decode(sys.getdefaultencoding()).strip()
             llvm_assertions = self.get_toml('assertions', 'llvm') == 'true'
+            if self.program_out_of_date(self.llvm_stamp(), llvm_sha + str(llvm_assertions)):
+                llvm_components.append(self._ci_llvm_download(llvm_sha, llvm_assertions))
+
+        components = rustc_components + rustfmt_components + llvm_components
+        get_all(
+            [(url, tarball, do_verify) for (url, tarball, do_verify, _) in components
+             if not os.path.exists(tarball)],
+            self.download_jobs(),
+            verbose=self.verbose,
+            fetch=functools.partial(get, attempts=self.download_attempts()),
+        )
+
+        if rustc_components:
+            files = []
+            for (_, tarball, _, pattern) in rustc_components:
+                files += unpack(tarball, tarball_suffix, self.bin_root(), match=pattern,
+                                verbose=self.verbose)
+            self.fix_bin_or_dylib("{}/bin/rustc".format(self.bin_root()))
+            self.fix_bin_or_dylib("{}/bin/rustdoc".format(self.bin_root()))
+            self.fix_bin_or_dylib("{}/bin/cargo".format(self.bin_root()))
+            lib_dir = "{}/lib".format(self.bin_root())
+            for lib in os.listdir(lib_dir):
+                if lib.endswith(".so"):
+                    self.fix_bin_or_dylib(os.path.join(lib_dir, lib), rpath_libz=True)
+            write_sha256s(self.rustc_sha256s(), self.bin_root(), files)
+            with output(self.rustc_stamp()) as rust_stamp:
+                rust_stamp.write(self.date + str(self.rustc_commit))
+
+        if rustfmt_components:
+            for (_, tarball, _, pattern) in rustfmt_components:
+                unpack(tarball, tarball_suffix, self.bin_root(), match=pattern,
+                       verbose=self.verbose)
+            self.fix_bin_or_dylib("{}/bin/rustfmt".format(self.bin_root()))
+            self.fix_bin_or_dylib("{}/bin/cargo-fmt".format(self.bin_root()))
+            with output(self.rustfmt_stamp()) as rustfmt_stamp:
+                rustfmt_stamp.write(self.rustfmt_channel)
+
+        if llvm_components:
             llvm_root = self.llvm_root()
             llvm_lib = os.path.join(llvm_root, "lib")
-            if self.program_out_of_date(self.llvm_stamp(), llvm_sha + str(llvm_assertions)):
-                self._download_ci_llvm(llvm_sha, llvm_assertions)
-                for binary in ["llvm-config", "FileCheck"]:
-                    self.fix_bin_or_dylib(os.path.join(llvm_root, "bin", binary), rpath_libz=True)
-                for lib in os.listdir(llvm_lib):
-                    if lib.endswith(".so"):
-                        self.fix_bin_or_dylib(os.path.join(llvm_lib, lib), rpath_libz=True)
-                with output(self.llvm_stamp()) as llvm_stamp:
-                    llvm_stamp.write(llvm_sha + str(llvm_assertions))
+            for (_, tarball, _, pattern) in llvm_components:
+                unpack(tarball, '.tar.xz', llvm_root, match=pattern, verbose=self.verbose)
+            for binary in ["llvm-config", "FileCheck"]:
+                self.fix_bin_or_dylib(os.path.join(llvm_root, "bin", binary), rpath_libz=True)
+            for lib in os.listdir(llvm_lib):
+                if lib.endswith(".so"):
+                    self.fix_bin_or_dylib(os.path.join(llvm_lib, lib), rpath_libz=True)
+            with output(self.llvm_stamp()) as llvm_stamp:
+                llvm_stamp.write(llvm_sha + str(llvm_assertions))
 
     def downloading_llvm(self):
         opt = self.get_toml('download-ci-llvm', 'llvm')
@@ -495,9 +630,34 @@ class RustBuild(object):
         return opt == "true" \
             or (opt == "if-available" and self.build in supported_platforms)
 
-    def _download_component_helper(
-        self, filename, pattern, tarball_suffix, download_rustc=False, key=None
-    ):
+    def download_jobs(self):
+        """Return how many tarballs may be downloaded at the same time
+
+        >>> rb = RustBuild()
+        >>> rb.download_jobs()
+        1
+        >>> rb.config_toml = '[build]\\ndownload-jobs = 4\\n'
+        >>> rb.download_jobs()
+        4
+        """
+        jobs = self.get_toml('download-jobs', 'build')
+        return max(1, int(jobs)) if jobs is not None else 1
+
+    def download_attempts(self):
+        """Return how often a download is tried before giving up
+
+        >>> rb = RustBuild()
+        >>> rb.download_attempts() == DEFAULT_DOWNLOAD_ATTEMPTS
+        True
+        >>> rb.config_toml = '[build]\\ndownload-attempts = 2\\n'
+        >>> rb.download_attempts()
+        2
+        """
+        attempts = self.get_toml('download-attempts', 'build')
+        return max(1, int(attempts)) if attempts is not None else DEFAULT_DOWNLOAD_ATTEMPTS
+
+    def _component_download(self, filename, pattern, download_rustc=False, key=None):
+        """Return `(url, tarball, do_verify, pattern)` for a stage0 component"""
         if key is None:
             if download_rustc:
                 key = self.rustc_commit
@@ -513,12 +673,10 @@ class RustBuild(object):
         else:
             url = "{}/dist/{}".format(self._download_url, key)
         tarball = os.path.join(rustc_cache, filename)
-        if not os.path.exists(tarball):
-            do_verify = not download_rustc
-            get("{}/{}".format(url, filename), tarball, verbose=self.verbose, do_verify=do_verify)
-        unpack(tarball, tarball_suffix, self.bin_root(), match=pattern, verbose=self.verbose)
+        return ("{}/{}".format(url, filename), tarball, not download_rustc, pattern)
 
-    def _download_ci_llvm(self, llvm_sha, llvm_assertions):
+    def _ci_llvm_download(self, llvm_sha, llvm_assertions):
+        """Return `(url, tarball, do_verify, pattern)` for the CI-built LLVM"""
         cache_prefix = "llvm-{}-{}".format(llvm_sha, llvm_assertions)
         cache_dst = os.path.join(self.build_dir, "cache")
         rustc_cache = os.path.join(cache_dst, cache_prefix)
@@ -533,14 +691,9 @@ class RustBuild(object):
             print("error: XZ support is required to download LLVM")
             print("help: consider disabling `download-ci-llvm` or using python3")
             exit(1)
-        tarball_suffix = '.tar.xz'
-        filename = "rust-dev-nightly-" + self.build + tarball_suffix
+        filename = "rust-dev-nightly-" + self.build + '.tar.xz'
         tarball = os.path.join(rustc_cache, filename)
-        if not os.path.exists(tarball):
-            get("{}/{}".format(url, filename), tarball, verbose=self.verbose, do_verify=False)
-        unpack(tarball, tarball_suffix, self.llvm_root(),
-                match="rust-dev",
-                verbose=self.verbose)
+        return ("{}/{}".format(url, filename), tarball, False, "rust-dev")
 
     def fix_bin_or_dylib(self, fname, rpath_libz=False):
         """Modifies the interpreter section of 'fname' to fix the dynamic linker,
@@ -668,6 +821,16 @@ class RustBuild(object):
         """
         return os.path.join(self.bin_root(), '.rustc-stamp')
 
+    def rustc_sha256s(self):
+        """Return the path for .rustc-sha256, the hashes of the extracted stage0
+
+        >>> rb = RustBuild()
+        >>> rb.build_dir = "build"
+        >>> rb.rustc_sha256s() == os.path.join("build", "stage0", ".rustc-sha256")
+        True
+        """
+        return os.path.join(self.bin_root(), '.rustc-sha256')
+
     def rustfmt_stamp(self):
         """Return the path for .rustfmt-stamp
 
@@ -897,6 +1060,8 @@ class RustBuild(object):
             args.append("--locked")
         if self.use_vendored_sources:
             args.append("--frozen")
+        if self.use_offline:
+            args.append("--offline")
         run(args, env=env, verbose=self.verbose)
 
     def build_triple(self):
@@ -1072,6 +1237,8 @@ def bootstrap(help_triggered):
     parser.add_argument('--config')
     parser.add_argument('--build')
     parser.add_argument('--clean', action='store_true')
+    parser.add_argument('--locked', action='store_true')
+    parser.add_argument('--offline', action='store_true')
     parser.add_argument('-v', '--verbose', action='count', default=0)
 
     args = [a for a in sys.argv if a != '-h' and a != '--help']
@@ -1112,7 +1279,8 @@ def bootstrap(help_triggered):
 
     build.use_vendored_sources = build.get_toml('vendor', 'build') == 'true'
 
-    build.use_locked_deps = build.get_toml('locked-deps', 'build') == 'true'
+    build.use_locked_deps = args.locked or build.get_toml('locked-deps', 'build') == 'true'
+    build.use_offline = args.offline or build.get_toml('offline', 'build') == 'true'
 
     build.check_vendored_status()
 
@@ -7,6 +7,7 @@ import unittest
 import tempfile
 import hashlib
 import sys
+import threading
 
 from shutil import rmtree
 
@@ -60,6 +61,33 @@ class VerifyTestCase(unittest.TestCase):
         self.assertFalse(bootstrap.verify(self.bad_src, self.sums, False))
 
 
+class WriteSha256sTestCase(unittest.TestCase):
+    """Test Case for write_sha256s"""
+    def setUp(self):
+        self.container = tempfile.mkdtemp()
+        os.makedirs(os.path.join(self.container, "lib", "rustlib"))
+        self.files = [
+            os.path.join(self.container, "bin"),
+            os.path.join(self.container, "lib", "rustlib", "components"),
+        ]
+        for name in self.files:
+            with open(name, "w") as f:
+                f.write(os.path.basename(name))
+        self.sums = os.path.join(self.container, ".rustc-sha256")
+
+    def tearDown(self):
+        rmtree(self.container)
+
+    def test_relative_paths(self):
+        """Each file is listed once, relative to the root"""
+        bootstrap.write_sha256s(self.sums, self.container, self.files + self.files[:1])
+        with open(self.sums) as sums:
+            self.assertEqual(sums.read(), "".join(
+                "{}  {}\n".format(hashlib.sha256(name.encode("utf-8")).hexdigest(), path)
+                for (name, path) in [("bin", "bin"), ("components", "lib/rustlib/components")]
+            ))
+
+
 class ProgramOutOfDate(unittest.TestCase):
     """Test if a program is out of date"""
     def setUp(self):
@@ -94,6 +122,98 @@ class ProgramOutOfDate(unittest.TestCase):
         self.assertFalse(self.build.program_out_of_date(self.rustc_stamp_path, self.key))
 
 
+class GetAllTestCase(unittest.TestCase):
+    """Test Case for get_all"""
+    def setUp(self):
+        self.lock = threading.Lock()
+        self.active = 0
+        self.max_active = 0
+        self.fetched = []
+        self.both_started = threading.Event()
+
+    def fetch(self, url, path, verbose=False, do_verify=True):
+        """Stub fetcher recording how many downloads run at the same time"""
+        with self.lock:
+            self.active += 1
+            self.max_active = max(self.max_active, self.active)
+            if self.active == 2:
+                self.both_started.set()
+        self.both_started.wait(0.5)
+        with self.lock:
+            self.active -= 1
+            self.fetched.append((url, path, do_verify))
+
+    def downloads(self):
+        return [("https://example.com/{}.tar.xz".format(name), name, True)
+                for name in ["rustc", "cargo", "rust-std", "llvm"]]
+
+    def test_concurrent(self):
+        """Independent downloads run concurrently and are all verified"""
+        bootstrap.get_all(self.downloads(), 2, fetch=self.fetch)
+        self.assertEqual(self.max_active, 2)
+        self.assertEqual(sorted(self.fetched), sorted(self.downloads()))
+
+    def test_serial(self):
+        """A single job never overlaps downloads"""
+        bootstrap.get_all(self.downloads(), 1, fetch=self.fetch)
+        self.assertEqual(self.max_active, 1)
+        self.assertEqual(sorted(self.fetched), sorted(self.downloads()))
+
+    def test_failure(self):
+        """A failed download is reported after the others finished"""
+        def fetch(url, path, verbose=False, do_verify=True):
+            if path == "cargo":
+                raise RuntimeError("failed verification")
+            self.fetch(url, path, verbose, do_verify)
+        with self.assertRaises(RuntimeError):
+            bootstrap.get_all(self.downloads(), 2, fetch=fetch)
+        self.assertEqual(len(self.fetched), 3)
+
+
+class DownloadTestCase(unittest.TestCase):
+    """Test Case for download"""
+    def setUp(self):
+        self.waits = []
+        self.calls = 0
+
+    def failing(self, failures, status=None):
+        """Stub fetcher failing `failures` times before succeeding"""
+        def fetch(path, url, probably_big, verbose):
+            self.calls += 1
+            if self.calls <= failures:
+                raise bootstrap.DownloadError(url, status)
+        return fetch
+
+    def download(self, fetch, attempts=5):
+        bootstrap.download("rustc.tar.xz", "https://example.com/rustc.tar.xz", True,
+                           False, attempts, fetch=fetch, wait=self.waits.append)
+
+    def test_retry_network_errors(self):
+        """Network errors are retried with exponential backoff"""
+        self.download(self.failing(3))
+        self.assertEqual(self.calls, 4)
+        self.assertEqual(self.waits, [1, 2, 4])
+
+    def test_retry_server_errors(self):
+        """Server errors are retried"""
+        self.download(self.failing(1, 503))
+        self.assertEqual(self.calls, 2)
+
+    def test_no_retry_not_found(self):
+        """A missing file fails right away"""
+        with self.assertRaises(SystemExit):
+            self.download(self.failing(1, 404))
+        self.assertEqual(self.calls, 1)
+        self.assertEqual(self.waits, [])
+
+    def test_attempts(self):
+        """Downloads give up after the configured number of attempts"""
+        with self.assertRaises(SystemExit):
+            self.download(self.failing(5), attempts=3)
+        self.assertEqual(self.calls, 3)
+        self.assertEqual(self.waits, [1, 2])
+
+
 if __name__ == '__main__':
     SUITE = unittest.TestSuite()
     TEST_LOADER = unittest.TestLoader()
@@ -101,7 +221,9 @@ if __name__ == '__main__':
     SUITE.addTests([
         TEST_LOADER.loadTestsFromTestCase(Stage0DataTestCase),
         TEST_LOADER.loadTestsFromTestCase(VerifyTestCase),
-        TEST_LOADER.loadTestsFromTestCase(ProgramOutOfDate)])
+        TEST_LOADER.loadTestsFromTestCase(ProgramOutOfDate),
+        TEST_LOADER.loadTestsFromTestCase(GetAllTestCase),
+        TEST_LOADER.loadTestsFromTestCase(DownloadTestCase)])
 
     RUNNER = unittest.TextTestRunner(stream=sys.stdout, verbosity=2)
     result = RUNNER.run(SUITE)
@@ -1,6 +1,6 @@
 use std::any::Any;
 use std::cell::{Cell, RefCell};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::env;
 use std::ffi::OsStr;
 use std::fmt::Debug;
@@ -12,11 +12,13 @@
 use std::time::{Duration, Instant};
 
 use build_helper::{output, t};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::Serialize;
 
 use crate::cache::{Cache, Interned, INTERNER};
 use crate::check;
 use crate::compile;
-use crate::config::TargetSelection;
+use crate::config::{StepSummary, TargetSelection};
 use crate::dist;
 use crate::doc;
 use crate::flags::{Color, Subcommand};
@@ -26,7 +28,7 @@
 use crate::test;
 use crate::tool::{self, SourceType};
 use crate::util::{self, add_dylib_path, add_link_lib_path, exe, libdir};
-use crate::{Build, DocTests, GitRepo, Mode};
+use crate::{Build, DocTests, GitRepo, Mode, TargetKind};
 
 pub use crate::Compiler;
 
@@ -37,6 +39,16 @@ pub struct Builder<'a> {
     cache: Cache,
     stack: RefCell<Vec<Box<dyn Any>>>,
     time_spent_on_dependencies: Cell<Duration>,
+    /// Time spent on each step that was run, for `build.step-summary`.
+    step_timings: RefCell<Vec<(String, Duration)>>,
+    step_graph: RefCell<StepGraph>,
+    /// Why the steps that the rule being run asks for are scheduled, for
+    /// `--explain`.
+    run_reason: RefCell<String>,
+    /// Each step that was run and why, for `--explain`.
+    explanations: RefCell<Vec<(String, String)>>,
+    /// `config.exclude_globs`, compiled.
+    exclude_globs: GlobSet,
     pub paths: Vec<PathBuf>,
 }
 
@@ -60,6 +72,11 @@ pub trait Step: 'static + Clone + Debug + PartialEq + Eq + Hash {
     /// If true, then this rule should be skipped if --target was specified, but --host was not
     const ONLY_HOSTS: bool = false;
 
+    /// What this rule runs against, for `x.py test --target-kind`. `None`
+    /// classifies it by the target it is run for, or as `Host` for
+    /// `ONLY_HOSTS` rules.
+    const TARGET_KIND: Option<TargetKind> = None;
+
     /// Primary function to execute this rule. Can call `builder.ensure()`
     /// with other steps to run those.
     fn run(self, builder: &Builder<'_>) -> Self::Output;
@@ -100,11 +117,24 @@ pub fn build_triple(&self) -> TargetSelection {
 struct StepDescription {
     default: bool,
     only_hosts: bool,
+    target_kind: Option<TargetKind>,
     should_run: fn(ShouldRun<'_>) -> ShouldRun<'_>,
     make_run: fn(RunConfig<'_>),
     name: &'static str,
 }
 
+/// A registered step, as `x.py list-steps` prints it.
+#[derive(Serialize, Debug)]
+pub struct StepInfo {
+    /// The type of the step, e.g. `compile::Std`.
+    pub name: String,
+    pub paths: Vec<PathBuf>,
+    /// Directories every test under which the step runs for.
+    pub suites: Vec<PathBuf>,
+    /// Whether the step runs when no path is given.
+    pub default: bool,
+}
+
 /// Collection of paths used to match a task rule.
 #[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
 pub enum PathSet {
@@ -141,6 +171,21 @@ fn has(&self, needle: &Path) -> bool {
         }
     }
 
+    /// Like `has`, but for glob patterns. Just like a needle, a pattern can
+    /// match the whole path or only its last components, so both `src/doc/*`
+    /// and `doc/*` match `src/doc/book`.
+    fn matches(&self, globs: &GlobSet) -> bool {
+        let matches = |path: &Path| {
+            let components = path.components().collect::<Vec<_>>();
+            (0..components.len())
+                .any(|i| globs.is_match(components[i..].iter().collect::<PathBuf>()))
+        };
+        match self {
+            PathSet::Set(set) => set.iter().any(|p| matches(p)),
+            PathSet::Suite(suite) => matches(suite),
+        }
+    }
+
     fn path(&self, builder: &Builder<'_>) -> PathBuf {
         match self {
             PathSet::Set(set) => set.iter().next().unwrap_or(&builder.build.src).to_path_buf(),
@@ -154,6 +199,7 @@ fn from<S: Step>() -> StepDescription {
         StepDescription {
             default: S::DEFAULT,
             only_hosts: S::ONLY_HOSTS,
+            target_kind: S::TARGET_KIND,
             should_run: S::should_run,
             make_run: S::make_run,
             name: std::any::type_name::<S>(),
@@ -161,13 +207,15 @@ fn from<S: Step>() -> StepDescription {
     }
 
     fn maybe_run(&self, builder: &Builder<'_>, pathset: &PathSet) {
-        if builder.config.exclude.iter().any(|e| pathset.has(e)) {
+        if builder.config.exclude.iter().any(|e| pathset.has(e))
+            || pathset.matches(&builder.exclude_globs)
+        {
             eprintln!("Skipping {:?} because it is excluded", pathset);
             return;
-        } else if !builder.config.exclude.is_empty() {
+        } else if !builder.config.exclude.is_empty() || !builder.config.exclude_globs.is_empty() {
             eprintln!(
-                "{:?} not skipped for {:?} -- not in {:?}",
-                pathset, self.name, builder.config.exclude
+                "{:?} not skipped for {:?} -- not in {:?} or {:?}",
+                pathset, self.name, builder.config.exclude, builder.config.exclude_globs
             );
         }
 
@@ -175,11 +223,24 @@ fn maybe_run(&self, builder: &Builder<'_>, pathset: &PathSet) {
         let targets = if self.only_hosts { &builder.hosts } else { &builder.targets };
 
         for target in targets {
+            if let Some(kind) = builder.config.cmd.target_kind() {
+                if self.target_kind(*target) != kind {
+                    continue;
+                }
+            }
             let run = RunConfig { builder, path: pathset.path(builder), target: *target };
             (self.make_run)(run);
         }
     }
 
+    fn target_kind(&self, target: TargetSelection) -> TargetKind {
+        match self.target_kind {
+            Some(kind) => kind,
+            None if self.only_hosts => TargetKind::Host,
+            None => TargetKind::of(target),
+        }
+    }
+
     fn run(v: &[StepDescription], builder: &Builder<'_>, paths: &[PathBuf]) {
         let should_runs =
             v.iter().map(|desc| (desc.should_run)(ShouldRun::new(builder))).collect::<Vec<_>>();
@@ -196,6 +257,7 @@ fn run(v: &[StepDescription], builder: &Builder<'_>, paths: &[PathBuf]) {
         if paths.is_empty() || builder.config.include_default_paths {
             for (desc, should_run) in v.iter().zip(&should_runs) {
                 if desc.default && should_run.is_really_default {
+                    builder.run_reason.replace(format!("default rule of {}", desc.name));
                     for pathset in &should_run.paths {
                         desc.maybe_run(builder, pathset);
                     }
@@ -211,6 +273,7 @@ fn run(v: &[StepDescription], builder: &Builder<'_>, paths: &[PathBuf]) {
             };
 
             let mut attempted_run = false;
+            builder.run_reason.replace(format!("requested path `{}`", path.display()));
             for (desc, should_run) in v.iter().zip(&should_runs) {
                 if let Some(suite) = should_run.is_suite_path(path) {
                     attempted_run = true;
@@ -400,6 +463,7 @@ macro_rules! describe {
                 test::CrateRustdocJsonTypes,
                 test::Linkcheck,
                 test::TierCheck,
+                test::BpfSmoke,
                 test::Cargotest,
                 test::Cargo,
                 test::Rls,
@@ -471,6 +535,7 @@ macro_rules! describe {
                 dist::LlvmTools,
                 dist::RustDev,
                 dist::Extended,
+                dist::Combined,
                 dist::BuildManifest,
                 dist::ReproducibleArtifacts,
             ),
@@ -487,13 +552,13 @@ macro_rules! describe {
                 install::Src,
                 install::Rustc
             ),
-            Kind::Run => describe!(run::ExpandYamlAnchors, run::BuildManifest),
+            Kind::Run => describe!(run::ExpandYamlAnchors, run::BuildManifest, run::RunTool),
         }
     }
 
     pub fn get_help(build: &Build, subcommand: &str) -> Option<String> {
         let kind = match subcommand {
-            "build" => Kind::Build,
+            "build" | "verify-reproducible" => Kind::Build,
             "doc" => Kind::Doc,
             "test" => Kind::Test,
             "bench" => Kind::Bench,
@@ -527,6 +592,53 @@ pub fn get_help(build: &Build, subcommand: &str) -> Option<String> {
         Some(help)
     }
 
+    /// Describes the steps registered for every subcommand that takes paths,
+    /// for `x.py list-steps`.
+    pub fn step_tree(build: &Build) -> BTreeMap<&'static str, Vec<StepInfo>> {
+        let kinds = [
+            ("bench", Kind::Bench),
+            ("build", Kind::Build),
+            ("check", Kind::Check),
+            ("clippy", Kind::Clippy),
+            ("dist", Kind::Dist),
+            ("doc", Kind::Doc),
+            ("fix", Kind::Fix),
+            ("install", Kind::Install),
+            ("run", Kind::Run),
+            ("test", Kind::Test),
+        ];
+        kinds
+            .iter()
+            .map(|&(name, kind)| {
+                (name, Self::describe_steps(build, kind, &Self::get_step_descriptions(kind)))
+            })
+            .collect()
+    }
+
+    fn describe_steps(build: &Build, kind: Kind, descs: &[StepDescription]) -> Vec<StepInfo> {
+        let builder = Self::new_internal(build, kind, vec![]);
+        descs
+            .iter()
+            .map(|desc| {
+                let should_run = (desc.should_run)(ShouldRun::new(&builder));
+                let mut info = StepInfo {
+                    // Drop the name of the crate, every step is in bootstrap.
+                    name: desc.name.splitn(2, "::").last().unwrap().to_string(),
+                    paths: Vec::new(),
+                    suites: Vec::new(),
+                    default: desc.default && should_run.is_really_default,
+                };
+                for pathset in should_run.paths {
+                    match pathset {
+                        PathSet::Set(set) => info.paths.extend(set),
+                        PathSet::Suite(path) => info.suites.push(path),
+                    }
+                }
+                info
+            })
+            .collect()
+    }
+
     fn new_internal(build: &Build, kind: Kind, paths: Vec<PathBuf>) -> Builder<'_> {
         Builder {
             build,
@@ -535,6 +647,11 @@ fn new_internal(build: &Build, kind: Kind, paths: Vec<PathBuf>) -> Builder<'_> {
             cache: Cache::new(),
             stack: RefCell::new(Vec::new()),
             time_spent_on_dependencies: Cell::new(Duration::new(0, 0)),
+            step_timings: RefCell::new(Vec::new()),
+            step_graph: RefCell::new(StepGraph::default()),
+            run_reason: RefCell::new(String::new()),
+            explanations: RefCell::new(Vec::new()),
+            exclude_globs: exclude_globs(&build.config.exclude_globs),
             paths,
         }
     }
@@ -549,11 +666,22 @@ pub fn new(build: &Build) -> Builder<'_> {
             Subcommand::Test { ref paths, .. } => (Kind::Test, &paths[..]),
             Subcommand::Bench { ref paths, .. } => (Kind::Bench, &paths[..]),
             Subcommand::Dist { ref paths } => (Kind::Dist, &paths[..]),
-            Subcommand::Install { ref paths } => (Kind::Install, &paths[..]),
-            Subcommand::Run { ref paths } => (Kind::Run, &paths[..]),
-            Subcommand::Format { .. } | Subcommand::Clean { .. } | Subcommand::Setup { .. } => {
-                panic!()
-            }
+            Subcommand::Install { ref paths, .. } => (Kind::Install, &paths[..]),
+            Subcommand::Run { ref paths, .. } => (Kind::Run, &paths[..]),
+            // Run twice by `manifest::verify_reproducible`.
+            Subcommand::VerifyReproducible { ref paths } => (Kind::Build, &paths[..]),
+            // Only used to `ensure` the steps `x.py profile-llvm` needs.
+            Subcommand::ProfileLlvm => (Kind::Build, &[][..]),
+            Subcommand::Format { .. }
+            | Subcommand::Clean { .. }
+            | Subcommand::Setup { .. }
+            | Subcommand::AuditSysroot { .. }
+            | Subcommand::Doctor
+            | Subcommand::ReproReport { .. }
+            | Subcommand::Disasm { .. }
+            | Subcommand::SizeReport { .. }
+            | Subcommand::ShowConfig { .. }
+            | Subcommand::ListSteps => panic!(),
         };
 
         Self::new_internal(build, kind, paths.to_owned())
@@ -941,7 +1069,7 @@ pub fn cargo(
         }
 
         match mode {
-            Mode::Std | Mode::ToolBootstrap | Mode::ToolStd => {}
+            Mode::Std | Mode::ToolBootstrap | Mode::ToolStd | Mode::ToolBpf => {}
             Mode::Rustc | Mode::Codegen | Mode::ToolRustc => {
                 // Build proc macros both for the host and the target
                 if target != compiler.host && cmd != "check" {
@@ -1126,6 +1254,9 @@ pub fn cargo(
             let target = crate::envify(&target.triple);
             cargo.env(&format!("CARGO_TARGET_{}_LINKER", target), target_linker);
         }
+        for arg in compile::link_args(self, target) {
+            rustflags.arg(&arg);
+        }
         if self.is_fuse_ld_lld(target) {
             rustflags.arg("-Clink-args=-fuse-ld=lld");
         }
@@ -1137,7 +1268,7 @@ pub fn cargo(
         let debuginfo_level = match mode {
             Mode::Rustc | Mode::Codegen => self.config.rust_debuginfo_level_rustc,
             Mode::Std => self.config.rust_debuginfo_level_std,
-            Mode::ToolBootstrap | Mode::ToolStd | Mode::ToolRustc => {
+            Mode::ToolBootstrap | Mode::ToolStd | Mode::ToolRustc | Mode::ToolBpf => {
                 self.config.rust_debuginfo_level_tools
             }
         };
@@ -1196,7 +1327,7 @@ pub fn cargo(
 
         // Enable usage of unstable features
         cargo.env("RUSTC_BOOTSTRAP", "1");
-        self.add_rust_test_threads(&mut cargo);
+        self.add_rust_test_threads(&mut cargo, target);
 
         // Almost all of the crates that we compile as part of the bootstrap may
         // have a build script, including the standard library. To compile a
@@ -1344,7 +1475,7 @@ pub fn cargo(
             let cc = ccacheify(&self.cc(target));
             cargo.env(format!("CC_{}", target.triple), &cc);
 
-            let cflags = self.cflags(target, GitRepo::Rustc).join(" ");
+            let cflags = self.cflags_for_command(target, GitRepo::Rustc, "cc-rs").join(" ");
             cargo.env(format!("CFLAGS_{}", target.triple), &cflags);
 
             if let Some(ar) = self.ar(target) {
@@ -1456,12 +1587,7 @@ pub fn cargo(
             }
         }
 
-        if self.config.locked_deps {
-            cargo.arg("--locked");
-        }
-        if self.config.vendor || self.is_sudo {
-            cargo.arg("--frozen");
-        }
+        cargo.args(self.cargo_lock_flags());
 
         // Try to use a sysroot-relative bindir, in case it was configured absolutely.
         cargo.env("RUSTC_INSTALL_BINDIR", self.config.bindir_relative());
@@ -1511,13 +1637,29 @@ pub fn ensure<S: Step>(&'a self, step: S) -> S::Output {
                 }
                 panic!("{}", out);
             }
+            let node = if self.config.dump_step_graph || self.config.explain {
+                Some(self.step_graph.borrow_mut().add(format!("{:?}", step)))
+            } else {
+                None
+            };
             if let Some(out) = self.cache.get(&step) {
                 self.verbose(&format!("{}c {:?}", "  ".repeat(stack.len()), step));
 
                 return out;
             }
             self.verbose(&format!("{}> {:?}", "  ".repeat(stack.len()), step));
+            if self.config.explain {
+                let graph = self.step_graph.borrow();
+                let reason = match graph.stack.last() {
+                    Some(&parent) => format!("dependency of {}", graph.nodes[parent]),
+                    None => self.run_reason.borrow().clone(),
+                };
+                self.explanations.borrow_mut().push((format!("{:?}", step), reason));
+            }
             stack.push(Box::new(step.clone()));
+            if let Some(node) = node {
+                self.step_graph.borrow_mut().stack.push(node);
+            }
         }
 
         let (out, dur) = {
@@ -1533,20 +1675,135 @@ pub fn ensure<S: Step>(&'a self, step: S) -> S::Output {
         if self.config.print_step_timings && !self.config.dry_run {
             println!("[TIMING] {:?} -- {}.{:03}", step, dur.as_secs(), dur.subsec_millis());
         }
+        if self.config.step_summary.is_some() {
+            self.step_timings.borrow_mut().push((format!("{:?}", step), dur));
+        }
 
         {
             let mut stack = self.stack.borrow_mut();
             let cur_step = stack.pop().expect("step stack empty");
             assert_eq!(cur_step.downcast_ref(), Some(&step));
         }
+        if self.config.dump_step_graph || self.config.explain {
+            self.step_graph.borrow_mut().stack.pop();
+        }
         self.verbose(&format!("{}< {:?}", "  ".repeat(self.stack.borrow().len()), step));
         self.cache.put(step, out.clone());
         out
     }
+
+    /// Prints the hits and misses of the step cache if `build.print-cache-stats`
+    /// is set.
+    pub fn print_cache_stats(&self) {
+        if self.config.print_cache_stats && !self.config.dry_run {
+            println!("{}", self.cache.stats());
+        }
+    }
+
+    /// Prints the time spent on each step that was run, slowest first, as
+    /// configured by `build.step-summary`.
+    pub fn print_step_summary(&self) {
+        let format = match self.config.step_summary {
+            Some(format) if !self.config.dry_run => format,
+            _ => return,
+        };
+        let mut timings = self.step_timings.borrow().clone();
+        timings.sort_by_key(|t| std::cmp::Reverse(t.1));
+
+        println!("\nTime spent on each step:");
+        for (step, dur) in &timings {
+            println!("{}: {}.{:03}s", step, dur.as_secs(), dur.subsec_millis());
+        }
+
+        if format == StepSummary::Json {
+            let json = timings
+                .iter()
+                .map(|(step, dur)| StepTiming { step: step.clone(), secs: dur.as_secs_f64() })
+                .collect::<Vec<_>>();
+            let path = self.out.join("step-timings.json");
+            t!(fs::write(&path, t!(serde_json::to_string_pretty(&json))));
+            self.info(&format!("Wrote the step timings to {}", path.display()));
+        }
+    }
+
+    /// Prints the steps that were requested and everything they depend on in
+    /// DOT format, for `--dump-step-graph`.
+    pub fn print_step_graph(&self) {
+        if self.config.dump_step_graph {
+            print!("{}", self.step_graph.borrow().to_dot());
+        }
+    }
+
+    /// Prints each step that was run in the order it was scheduled in, along
+    /// with why, for `--explain`.
+    pub fn print_explanations(&self) {
+        if self.config.explain {
+            for (step, reason) in self.explanations.borrow().iter() {
+                println!("{} ({})", step, reason);
+            }
+        }
+    }
+}
+
+/// Compiles the `--exclude` glob patterns. `*` doesn't match `/`, so that
+/// `src/*` only matches the directories right inside `src`.
+fn exclude_globs(patterns: &[String]) -> GlobSet {
+    let mut globs = GlobSetBuilder::new();
+    for pattern in patterns {
+        globs.add(t!(GlobBuilder::new(pattern).literal_separator(true).build()));
+    }
+    t!(globs.build())
+}
+
+/// Every step passed to `ensure`, with an edge from each step to the ones it
+/// ensured while running. Steps that were already cached still get an edge,
+/// so the graph holds all dependencies, not just the order things ran in.
+#[derive(Default)]
+struct StepGraph {
+    /// The `Debug` output of each step.
+    nodes: Vec<String>,
+    index: HashMap<String, usize>,
+    edges: BTreeSet<(usize, usize)>,
+    /// The nodes of the steps that are currently running.
+    stack: Vec<usize>,
+}
+
+impl StepGraph {
+    /// Adds `step` as a dependency of the step that is currently running.
+    fn add(&mut self, step: String) -> usize {
+        let nodes = &mut self.nodes;
+        let node = *self.index.entry(step).or_insert_with_key(|step| {
+            nodes.push(step.clone());
+            nodes.len() - 1
+        });
+        if let Some(&parent) = self.stack.last() {
+            self.edges.insert((parent, node));
+        }
+        node
+    }
+
+    fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph steps {\n");
+        for (i, step) in self.nodes.iter().enumerate() {
+            let label = step.replace('\\', "\\\\").replace('"', "\\\"");
+            dot += &format!("    n{} [label=\"{}\"];\n", i, label);
+        }
+        for (from, to) in &self.edges {
+            dot += &format!("    n{} -> n{};\n", from, to);
+        }
+        dot += "}\n";
+        dot
+    }
+}
+
+#[derive(Serialize)]
+struct StepTiming {
+    step: String,
+    secs: f64,
 }
 
 #[cfg(test)]
-mod tests;
+pub(crate) mod tests;
 
 #[derive(Debug, Clone)]
 struct Rustflags(String, TargetSelection);
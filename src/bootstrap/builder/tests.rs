@@ -2,7 +2,7 @@
 use crate::config::{Config, TargetSelection};
 use std::thread;
 
-fn configure(cmd: &str, host: &[&str], target: &[&str]) -> Config {
+pub(crate) fn configure(cmd: &str, host: &[&str], target: &[&str]) -> Config {
     let mut config = Config::parse(&[cmd.to_owned()]);
     // don't save toolstates
     config.save_toolstates = None;
@@ -132,11 +132,244 @@ fn build_cross_compile() {
         );
     }
 
+    #[test]
+    fn sysroot_for() {
+        let build = Build::new(configure("build", &["A"], &["A", "bpfel-unknown-unknown"]));
+        let builder = Builder::new(&build);
+        let a = TargetSelection::from_user("A");
+        let bpf = TargetSelection::from_user("bpfel-unknown-unknown");
+
+        assert_eq!(
+            build.sysroot_for(Compiler { host: a, stage: 0 }),
+            build.out.join("A").join("stage0-sysroot")
+        );
+        for stage in 1..=2 {
+            let compiler = Compiler { host: a, stage };
+            let sysroot = build.out.join("A").join(format!("stage{}", stage));
+            assert_eq!(build.sysroot_for(compiler), sysroot);
+            assert_eq!(builder.sysroot(compiler).to_path_buf(), sysroot);
+            assert_eq!(
+                builder.sysroot_libdir(compiler, a).to_path_buf(),
+                sysroot.join("lib").join("rustlib").join("A").join("lib")
+            );
+            assert_eq!(
+                builder.sysroot_libdir(compiler, bpf).to_path_buf(),
+                sysroot.join("lib").join("rustlib").join("bpfel-unknown-unknown").join("lib")
+            );
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    struct Outer;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    struct Inner;
+
+    impl Step for Outer {
+        type Output = ();
+
+        fn should_run(run: ShouldRun<'_>) -> ShouldRun<'_> {
+            run.never()
+        }
+
+        fn run(self, builder: &Builder<'_>) {
+            builder.ensure(Inner);
+            builder.ensure(Inner);
+        }
+    }
+
+    impl Step for Inner {
+        type Output = ();
+
+        fn should_run(run: ShouldRun<'_>) -> ShouldRun<'_> {
+            run.never()
+        }
+
+        fn run(self, _builder: &Builder<'_>) {}
+    }
+
+    #[test]
+    fn step_timings() {
+        let mut config = configure("build", &["A"], &["A"]);
+        config.step_summary = Some(crate::config::StepSummary::Text);
+        let build = Build::new(config);
+        let builder = Builder::new(&build);
+        builder.ensure(Outer);
+
+        // Steps are recorded once they finish, cached ones aren't recorded again.
+        let timings = builder.step_timings.borrow();
+        assert_eq!(first(timings.clone()), ["Inner", "Outer"]);
+    }
+
+    #[test]
+    fn step_graph() {
+        let mut config = configure("build", &["A"], &["A"]);
+        config.dump_step_graph = true;
+        let build = Build::new(config);
+        let builder = Builder::new(&build);
+        builder.ensure(Outer);
+
+        // `Inner` is ensured twice but only shows up once, the second time is cached.
+        let dot = builder.step_graph.borrow().to_dot();
+        assert_eq!(dot.lines().filter(|l| l.contains("[label=")).count(), 2);
+        assert_eq!(dot.lines().filter(|l| l.contains(" -> ")).count(), 1);
+        assert!(dot.contains("n0 [label=\"Outer\"];\n    n1 [label=\"Inner\"];\n    n0 -> n1;"));
+    }
+
+    #[test]
+    fn cargo_lock_flags() {
+        let lock_flags = |locked: bool, offline: bool| {
+            let mut config = configure("build", &["A"], &["A"]);
+            config.locked_deps = locked;
+            config.offline = offline;
+            let build = Build::new(config);
+            let builder = Builder::new(&build);
+            let compiler = Compiler { host: TargetSelection::from_user("A"), stage: 0 };
+            let cargo = builder.cargo(
+                compiler,
+                Mode::ToolBootstrap,
+                SourceType::InTree,
+                compiler.host,
+                "build",
+            );
+            let cmd = format!("{:?}", Command::from(cargo));
+            (cmd.contains(r#""--locked""#), cmd.contains(r#""--offline""#))
+        };
+        assert_eq!(lock_flags(false, false), (false, false));
+        assert_eq!(lock_flags(true, false), (true, false));
+        assert_eq!(lock_flags(true, true), (true, true));
+
+        let config = Config::parse(&["build".to_owned(), "--locked".to_owned()]);
+        assert!(config.locked_deps && !config.offline);
+    }
+
+    #[test]
+    fn cache_stats() {
+        use crate::cache::CacheStats;
+
+        let build = Build::new(configure("build", &["A"], &["A"]));
+        let builder = Builder::new(&build);
+        let compiler = Compiler { host: TargetSelection::from_user("A"), stage: 0 };
+        assert_eq!(builder.cache.stats(), CacheStats { hits: 0, misses: 0 });
+        let sysroot = builder.sysroot(compiler);
+        assert_eq!(builder.cache.stats(), CacheStats { hits: 0, misses: 1 });
+        assert_eq!(builder.sysroot(compiler), sysroot);
+        assert_eq!(builder.sysroot(compiler), sysroot);
+        assert_eq!(builder.cache.stats(), CacheStats { hits: 2, misses: 1 });
+        assert_eq!(builder.cache.stats().to_string(), "cache: 2 hits, 1 misses");
+    }
+
+    #[test]
+    fn exclude_globs() {
+        let globs = crate::builder::exclude_globs(&["src/doc/*".into(), "*-fulldeps".into()]);
+        let excluded = |path: &str| PathSet::one(path).matches(&globs);
+        assert!(excluded("src/doc/book"));
+        assert!(excluded("src/doc/rustc-dev-guide"));
+        assert!(excluded("src/test/ui-fulldeps"));
+        assert!(PathSet::Suite("src/test/run-make-fulldeps".into()).matches(&globs));
+
+        // `*` doesn't cross directories.
+        assert!(!excluded("src/doc/book/redirects"));
+        assert!(!excluded("src/doc"));
+        assert!(!excluded("src/tools/tidy"));
+        assert!(!excluded("src/test/ui"));
+
+        let globs = crate::builder::exclude_globs(&["doc/**".into()]);
+        assert!(PathSet::one("src/doc/book/redirects").matches(&globs));
+        assert!(!PathSet::one("library/std").matches(&crate::builder::exclude_globs(&[])));
+    }
+
+    #[test]
+    fn verify_reproducible() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static DRIFTING_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+        /// Links `lib<name>.rlib` with `contents` into a fake sysroot.
+        fn link(builder: &Builder<'_>, target: TargetSelection, name: &str, contents: &str) {
+            let dir = builder.out.join(&*target.triple).join("stage1-fixture");
+            t!(fs::create_dir_all(&dir));
+            let lib = dir.join(format!("lib{}.rlib", name));
+            t!(fs::write(&lib, contents));
+            let stamp = dir.join(format!(".lib{}.stamp", name));
+            t!(fs::write(&stamp, format!("t{}\0", lib.display())));
+            builder.record_sysroot_stamp(target, &stamp);
+        }
+
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+        struct Fixture {
+            target: TargetSelection,
+        }
+
+        impl Step for Fixture {
+            type Output = ();
+
+            fn should_run(run: ShouldRun<'_>) -> ShouldRun<'_> {
+                run.path("src/test/fixture")
+            }
+
+            fn make_run(run: RunConfig<'_>) {
+                run.builder.ensure(Fixture { target: run.target });
+            }
+
+            fn run(self, builder: &Builder<'_>) {
+                link(builder, self.target, "fixture", "the same every time");
+            }
+        }
+
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+        struct Drifting {
+            target: TargetSelection,
+        }
+
+        impl Step for Drifting {
+            type Output = ();
+
+            fn should_run(run: ShouldRun<'_>) -> ShouldRun<'_> {
+                run.path("src/test/drifting")
+            }
+
+            fn make_run(run: RunConfig<'_>) {
+                run.builder.ensure(Drifting { target: run.target });
+            }
+
+            fn run(self, builder: &Builder<'_>) {
+                let run = DRIFTING_RUNS.fetch_add(1, Ordering::SeqCst);
+                link(builder, self.target, "drifting", &format!("run {}", run));
+            }
+        }
+
+        let mut config = configure("build", &["A"], &["A"]);
+        config.cmd = Subcommand::VerifyReproducible { paths: Vec::new() };
+        let mut build = Build::new(config);
+        build.config.dry_run = false;
+        let verify = |paths: &[&str]| {
+            let paths = paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+            crate::manifest::build_twice(&build, || {
+                Builder::new(&build).run_step_descriptions(
+                    &[StepDescription::from::<Fixture>(), StepDescription::from::<Drifting>()],
+                    &paths,
+                )
+            })
+        };
+
+        let report = verify(&["src/test/fixture"]);
+        assert!(report.is_reproducible());
+        assert_eq!(report.to_string(), "1 matching artifacts\n");
+
+        let report = verify(&["src/test/fixture", "src/test/drifting"]);
+        assert!(!report.is_reproducible());
+        assert_eq!(
+            report.to_string(),
+            "1 matching artifacts\n1 differing:\n    A: A/stage1-fixture/libdrifting.rlib\n"
+        );
+    }
+
     #[test]
     fn doc_default() {
         let mut config = configure("doc", &["A"], &["A"]);
         config.compiler_docs = true;
-        config.cmd = Subcommand::Doc { paths: Vec::new(), open: false };
+        config.cmd = Subcommand::Doc { paths: Vec::new(), open: false, json: false };
         let build = Build::new(config);
         let mut builder = Builder::new(&build);
         builder.run_step_descriptions(&Builder::get_step_descriptions(Kind::Doc), &[]);
@@ -228,6 +461,52 @@ fn dist_with_targets() {
         assert_eq!(first(builder.cache.all::<dist::Src>()), &[dist::Src]);
     }
 
+    #[test]
+    fn dist_combined() {
+        let bpf = TargetSelection::from_user("bpfel-unknown-unknown");
+        let mut config = configure(&["A"], &["A", &bpf.triple]);
+        config.dist_combined = true;
+        let build = Build::new(config);
+        let mut builder = Builder::new(&build);
+        builder.run_step_descriptions(
+            &Builder::get_step_descriptions(Kind::Dist),
+            &["combined".into()],
+        );
+
+        let a = TargetSelection::from_user("A");
+        assert_eq!(
+            first(builder.cache.all::<dist::Rustc>()),
+            &[dist::Rustc { compiler: Compiler { host: a, stage: 2 } },]
+        );
+        assert_eq!(
+            first(builder.cache.all::<dist::Cargo>()),
+            &[dist::Cargo { compiler: Compiler { host: a, stage: 1 }, target: a },]
+        );
+        let std = builder.cache.all::<dist::Std>();
+        assert_eq!(std.iter().map(|(std, _)| std.target).collect::<Vec<_>>(), &[a, bpf]);
+        // Only what was asked for goes in.
+        assert!(builder.cache.all::<dist::Clippy>().is_empty());
+
+        // The installer holds the host's rustc and the std of the BPF target.
+        let tarball = |component: &str, target: &str| {
+            format!("{}-{}.tar.gz", dist::pkgname(&builder, component), target)
+        };
+        let expected = vec![
+            tarball("rustc", "A"),
+            tarball("cargo", "A"),
+            tarball("rust-std", "A"),
+            tarball("rust-std", &bpf.triple),
+        ];
+        let combined = builder.cache.all::<dist::Combined>();
+        assert_eq!(combined.len(), 1);
+        let inputs = combined[0]
+            .1
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(inputs, expected);
+    }
+
     #[test]
     fn dist_with_hosts() {
         let build = Build::new(configure(&["A", "B"], &["A", "B"]));
@@ -489,6 +768,7 @@ fn test_with_no_doc_stage0() {
             compare_mode: None,
             rustfix_coverage: false,
             pass: None,
+            target_kind: None,
         };
 
         let build = Build::new(config);
@@ -515,6 +795,30 @@ fn test_with_no_doc_stage0() {
         );
     }
 
+    #[test]
+    fn explain() {
+        let mut config = configure(&["A"], &["A"]);
+        config.explain = true;
+        let build = Build::new(config);
+        let builder = Builder::new(&build);
+        builder.run_step_descriptions(
+            &[StepDescription::from::<test::Tidy>()],
+            &["src/tools/tidy".into()],
+        );
+
+        let explanations = builder.explanations.borrow();
+        let reason = |step: &str| {
+            let found = explanations.iter().find(|(s, _)| s.starts_with(step));
+            found
+                .map(|(_, reason)| reason.as_str())
+                .unwrap_or_else(|| panic!("{} didn't run", step))
+        };
+        assert_eq!(reason("Tidy"), "requested path `src/tools/tidy`");
+        assert_eq!(reason("Tidy {"), "dependency of Tidy");
+        assert!(reason("ToolBuild {").starts_with("dependency of Tidy {"), "{:?}", explanations);
+        assert_eq!(explanations[0].0, "Tidy");
+    }
+
     #[test]
     fn test_exclude() {
         let mut config = configure(&["A"], &["A"]);
@@ -529,6 +833,7 @@ fn test_exclude() {
             compare_mode: None,
             rustfix_coverage: false,
             pass: None,
+            target_kind: None,
         };
 
         let build = Build::new(config);
@@ -542,11 +847,83 @@ fn test_exclude() {
         assert!(builder.cache.contains::<test::RustdocUi>());
     }
 
+    #[test]
+    fn test_target_kind() {
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        struct Probe {
+            target: TargetSelection,
+        }
+
+        impl Step for Probe {
+            type Output = ();
+
+            fn should_run(run: ShouldRun<'_>) -> ShouldRun<'_> {
+                run.path("src/test/probe")
+            }
+
+            fn make_run(run: RunConfig<'_>) {
+                run.builder.ensure(Probe { target: run.target });
+            }
+
+            fn run(self, _builder: &Builder<'_>) {}
+        }
+
+        let scheduled = |target_kind| {
+            let mut config = configure(&["A"], &["A", "bpfel-unknown-unknown"]);
+            config.cmd = Subcommand::Test {
+                paths: Vec::new(),
+                test_args: Vec::new(),
+                rustc_args: Vec::new(),
+                fail_fast: true,
+                doc_tests: DocTests::No,
+                bless: false,
+                compare_mode: None,
+                rustfix_coverage: false,
+                pass: None,
+                target_kind,
+            };
+            let build = Build::new(config);
+            let mut builder = Builder::new(&build);
+            builder.run_step_descriptions(
+                &[StepDescription::from::<Probe>(), StepDescription::from::<test::Tidy>()],
+                &["src/test/probe".into(), "src/tools/tidy".into()],
+            );
+            let probes = builder.cache.all::<Probe>();
+            let targets = probes.iter().map(|(probe, _)| probe.target.triple.to_string());
+            (targets.collect::<Vec<_>>(), builder.cache.contains::<test::Tidy>())
+        };
+
+        let bpf = "bpfel-unknown-unknown".to_string();
+        assert_eq!(scheduled(None), (vec!["A".to_string(), bpf.clone()], true));
+        assert_eq!(scheduled(Some(TargetKind::Bpf)), (vec![bpf], false));
+        assert_eq!(scheduled(Some(TargetKind::Host)), (vec!["A".to_string()], true));
+    }
+
+    #[test]
+    fn step_tree() {
+        let build = Build::new(configure(&["A"], &["A"]));
+        let json = serde_json::to_value(Builder::step_tree(&build)).unwrap();
+        let tidy = json["test"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|step| step["name"] == "test::Tidy")
+            .unwrap();
+        assert_eq!(tidy["paths"], serde_json::json!(["src/tools/tidy"]));
+        assert_eq!(tidy["suites"], serde_json::json!([]));
+        assert_eq!(tidy["default"], true);
+
+        let std =
+            json["build"].as_array().unwrap().iter().find(|step| step["name"] == "compile::Std");
+        let paths = std.unwrap()["paths"].as_array().unwrap();
+        assert!(paths.contains(&serde_json::json!("library/std")));
+    }
+
     #[test]
     fn doc_ci() {
         let mut config = configure(&["A"], &["A"]);
         config.compiler_docs = true;
-        config.cmd = Subcommand::Doc { paths: Vec::new(), open: false };
+        config.cmd = Subcommand::Doc { paths: Vec::new(), open: false, json: false };
         let build = Build::new(config);
         let mut builder = Builder::new(&build);
         builder.run_step_descriptions(&Builder::get_step_descriptions(Kind::Doc), &[]);
@@ -584,6 +961,7 @@ fn test_docs() {
             compare_mode: None,
             rustfix_coverage: false,
             pass: None,
+            target_kind: None,
         };
         // Make sure rustfmt binary not being found isn't an error.
         config.channel = "beta".to_string();
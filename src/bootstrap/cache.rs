@@ -11,6 +11,7 @@
 use std::mem;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 use std::sync::Mutex;
 
 use lazy_static::lazy_static;
@@ -231,22 +232,41 @@ pub fn intern_path(&self, s: PathBuf) -> Interned<PathBuf> {
 /// which means that references to the value can safely be returned from the
 /// `get()` method.
 #[derive(Debug)]
-pub struct Cache(
-    RefCell<
+pub struct Cache {
+    steps: RefCell<
         HashMap<
             TypeId,
             Box<dyn Any>, // actually a HashMap<Step, Interned<Step::Output>>
         >,
     >,
-);
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+/// How many of the lookups in a `Cache` found a step that already ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cache: {} hits, {} misses", self.hits, self.misses)
+    }
+}
 
 impl Cache {
     pub fn new() -> Cache {
-        Cache(RefCell::new(HashMap::new()))
+        Cache {
+            steps: RefCell::new(HashMap::new()),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
     }
 
     pub fn put<S: Step>(&self, step: S, value: S::Output) {
-        let mut cache = self.0.borrow_mut();
+        let mut cache = self.steps.borrow_mut();
         let type_id = TypeId::of::<S>();
         let stepcache = cache
             .entry(type_id)
@@ -258,21 +278,31 @@ pub fn put<S: Step>(&self, step: S, value: S::Output) {
     }
 
     pub fn get<S: Step>(&self, step: &S) -> Option<S::Output> {
-        let mut cache = self.0.borrow_mut();
+        let mut cache = self.steps.borrow_mut();
         let type_id = TypeId::of::<S>();
         let stepcache = cache
             .entry(type_id)
             .or_insert_with(|| Box::new(HashMap::<S, S::Output>::new()))
             .downcast_mut::<HashMap<S, S::Output>>()
             .expect("invalid type mapped");
-        stepcache.get(step).cloned()
+        let out = stepcache.get(step).cloned();
+        let counter = if out.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, AtomicOrdering::Relaxed);
+        out
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(AtomicOrdering::Relaxed),
+            misses: self.misses.load(AtomicOrdering::Relaxed),
+        }
     }
 }
 
 #[cfg(test)]
 impl Cache {
     pub fn all<S: Ord + Copy + Step>(&mut self) -> Vec<(S, S::Output)> {
-        let cache = self.0.get_mut();
+        let cache = self.steps.get_mut();
         let type_id = TypeId::of::<S>();
         let mut v = cache
             .remove(&type_id)
@@ -284,6 +314,6 @@ pub fn all<S: Ord + Copy + Step>(&mut self) -> Vec<(S, S::Output)> {
     }
 
     pub fn contains<S: Step>(&self) -> bool {
-        self.0.borrow().contains_key(&TypeId::of::<S>())
+        self.steps.borrow().contains_key(&TypeId::of::<S>())
     }
 }
@@ -22,6 +22,7 @@
 //! everything.
 
 use std::collections::HashSet;
+use std::fmt;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::{env, iter};
@@ -113,6 +114,14 @@ pub fn find(build: &mut Build) {
         };
 
         build.cc.insert(target, compiler.clone());
+        // Only `target.<triple>.min-cc-version` looks at the version, don't
+        // run the compiler for targets without one.
+        let wants_version = config.map_or(false, |c| c.min_cc_version.is_some());
+        if wants_version && !build.config.dry_run && !target.contains("msvc") {
+            if let Some(version) = detect_version(&compiler) {
+                build.cc_version.insert(target, version);
+            }
+        }
         let cflags = build.cflags(target, GitRepo::Rustc);
 
         // If we use llvm-libunwind, we will need a C++ compiler as well for all targets
@@ -159,6 +168,73 @@ pub fn find(build: &mut Build) {
     }
 }
 
+/// Version of a C compiler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CcVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl CcVersion {
+    /// Parses a version like `9.4.0` or `14.0`, where the missing components
+    /// are 0. Anything after the numbers, like the `-1ubuntu1` of
+    /// `14.0.0-1ubuntu1`, is ignored.
+    pub fn parse(s: &str) -> Option<CcVersion> {
+        let end = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+        let mut parts = s[..end].trim_end_matches('.').split('.');
+        let major = parts.next()?.parse().ok()?;
+        // A bare number is more likely to be a date than a version.
+        let minor = parts.next()?.parse().ok()?;
+        let patch = parts.next().map_or(Some(0), |patch| patch.parse().ok())?;
+        Some(CcVersion { major, minor, patch })
+    }
+
+    /// Finds the version in the first line of what `--version` prints. It's
+    /// the first word that is a version, once the parenthesized parts are
+    /// left out:
+    ///
+    /// ```text
+    /// gcc (Ubuntu 9.4.0-1ubuntu1~20.04.1) 9.4.0
+    /// gcc (GCC) 11.2.1 20211203 (Red Hat 11.2.1-7)
+    /// Ubuntu clang version 14.0.0-1ubuntu1
+    /// clang version 13.0.0 (https://github.com/solana-labs/llvm-project.git 33c3629)
+    /// ```
+    fn from_banner(banner: &str) -> Option<CcVersion> {
+        let line = banner.lines().next()?;
+        let mut depth = 0;
+        let mut unparenthesized = String::new();
+        for c in line.chars() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ if depth == 0 => unparenthesized.push(c),
+                _ => {}
+            }
+        }
+        unparenthesized
+            .split_whitespace()
+            .filter(|word| word.starts_with(|c: char| c.is_ascii_digit()))
+            .find_map(CcVersion::parse)
+    }
+}
+
+impl fmt::Display for CcVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Runs `compiler --version`. Fails for the in-tree clang of BPF targets,
+/// which isn't built yet.
+fn detect_version(compiler: &cc::Tool) -> Option<CcVersion> {
+    let output = compiler.to_command().arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    CcVersion::from_banner(&String::from_utf8_lossy(&output.stdout))
+}
+
 fn set_compiler(
     cfg: &mut cc::Build,
     compiler: Language,
@@ -259,3 +335,6 @@ fn clang(self) -> &'static str {
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
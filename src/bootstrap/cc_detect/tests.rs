@@ -0,0 +1,32 @@
+use super::*;
+
+#[test]
+fn parse_version() {
+    let version = |major, minor, patch| Some(CcVersion { major, minor, patch });
+    assert_eq!(CcVersion::parse("9.4.0"), version(9, 4, 0));
+    assert_eq!(CcVersion::parse("14.0"), version(14, 0, 0));
+    assert_eq!(CcVersion::parse("14.0.6-1ubuntu1"), version(14, 0, 6));
+    assert_eq!(CcVersion::parse("20211203"), None);
+    assert_eq!(CcVersion::parse("x.y"), None);
+    assert_eq!(CcVersion::parse("11.2.1").unwrap().to_string(), "11.2.1");
+}
+
+#[test]
+fn parse_banner() {
+    let version = |major, minor, patch| Some(CcVersion { major, minor, patch });
+    let banners = [
+        ("gcc (Ubuntu 9.4.0-1ubuntu1~20.04.1) 9.4.0\nCopyright (C) 2019", version(9, 4, 0)),
+        ("gcc (GCC) 11.2.1 20211203 (Red Hat 11.2.1-7)", version(11, 2, 1)),
+        ("x86_64-linux-gnu-gcc-10 (Debian 10.2.1-6) 10.2.1 20210110", version(10, 2, 1)),
+        ("Ubuntu clang version 14.0.0-1ubuntu1\nTarget: x86_64-pc-linux-gnu", version(14, 0, 0)),
+        ("Apple clang version 15.0.0 (clang-1500.3.9.4)", version(15, 0, 0)),
+        (
+            "clang version 13.0.0 (https://github.com/solana-labs/llvm-project.git 33c3629)",
+            version(13, 0, 0),
+        ),
+        ("cl : Command line error D8003 : missing source filename", None),
+    ];
+    for (banner, expected) in banners.iter() {
+        assert_eq!(CcVersion::from_banner(banner), *expected, "{}", banner);
+    }
+}
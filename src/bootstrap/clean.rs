@@ -4,6 +4,9 @@
 //! artifacts to prepare for a fresh build. Currently doesn't remove the
 //! `build/cache` directory (download cache) or the `build/$target/llvm`
 //! directory unless the `--all` flag is present.
+//!
+//! With `--target` only the outputs of the given targets are removed instead,
+//! see `Build::clean_target`.
 
 use std::fs;
 use std::io::{self, ErrorKind};
@@ -41,7 +44,7 @@ pub fn clean(build: &Build, all: bool) {
     }
 }
 
-fn rm_rf(path: &Path) {
+pub fn rm_rf(path: &Path) {
     match path.symlink_metadata() {
         Err(e) => {
             if e.kind() == ErrorKind::NotFound {
@@ -7,7 +7,7 @@
 //! goes along from the output of the previous stage.
 
 use std::borrow::Cow;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
 use std::io::prelude::*;
@@ -19,16 +19,20 @@
 use build_helper::{output, t, up_to_date};
 use filetime::FileTime;
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
 
 use crate::builder::Cargo;
 use crate::builder::{Builder, Kind, RunConfig, ShouldRun, Step};
 use crate::cache::{Interned, INTERNER};
-use crate::config::TargetSelection;
+use crate::config::{Config, TargetSelection};
 use crate::dist;
 use crate::native;
 use crate::tool::SourceType;
-use crate::util::{exe, is_debug_info, is_dylib, symlink_dir};
-use crate::{Compiler, DependencyType, GitRepo, Mode};
+use crate::util::{exe, is_debug_info, is_dylib, sha256_file, symlink_dir};
+use crate::{Compiler, DependencyType, GitRepo, Mode, Subcommand};
+
+#[cfg(test)]
+mod tests;
 
 #[derive(Debug, PartialOrd, Ord, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Std {
@@ -69,9 +73,7 @@ fn run(self, builder: &Builder<'_>) {
             return;
         }
 
-        if builder.config.keep_stage.contains(&compiler.stage)
-            || builder.config.keep_stage_std.contains(&compiler.stage)
-        {
+        if keep_std(&builder.config, compiler.stage) {
             builder.info("Warning: Using a potentially old libstd. This may not behave well.");
             builder.ensure(StdLink { compiler, target_compiler: compiler, target });
             return;
@@ -264,11 +266,12 @@ pub fn std_cargo(builder: &Builder<'_>, target: TargetSelection, stage: u32, car
         ""
     };
 
-    if builder.no_std(target) == Some(true) {
-        let mut features = "compiler-builtins-mem".to_string();
-        features.push_str(compiler_builtins_c_feature);
+    let mut features = builder.std_features(target);
+    features.push_str(compiler_builtins_c_feature);
 
+    if builder.no_std(target) == Some(true) {
         // for no-std targets we only compile a few no_std crates
+        builder.verbose(&format!("{} is a no-std target, only building alloc", target));
         cargo
             .args(&["-p", "alloc"])
             .arg("--manifest-path")
@@ -276,8 +279,10 @@ pub fn std_cargo(builder: &Builder<'_>, target: TargetSelection, stage: u32, car
             .arg("--features")
             .arg(features);
     } else {
-        let mut features = builder.std_features(target);
-        features.push_str(compiler_builtins_c_feature);
+        for (var, value) in builder.std_env(target) {
+            builder.verbose(&format!("building std for {} with {}={}", target, var, value));
+            cargo.env(var, value);
+        }
 
         cargo
             .arg("--features")
@@ -323,6 +328,143 @@ pub fn std_cargo(builder: &Builder<'_>, target: TargetSelection, stage: u32, car
     if target.contains("riscv") {
         cargo.rustflag("-Cforce-unwind-tables=yes");
     }
+
+    // The heap size is baked into std, see `library/std/src/sys/bpf/alloc.rs`.
+    if target.contains("bpf") {
+        if let Some(size) = builder.config.target_config.get(&target).and_then(|t| t.sbf_heap_size)
+        {
+            if let Err(e) = check_sbf_heap_size(size) {
+                panic!("invalid `target.{}.sbf-heap-size`: {}", target, e);
+            }
+            cargo.env("RUST_SBF_HEAP_SIZE", size.to_string());
+        }
+    }
+
+    // Per target, for when warnings aren't denied everywhere already.
+    if !builder.config.deny_warnings
+        && builder.config.target_config.get(&target).map_or(false, |t| t.deny_warnings)
+    {
+        cargo.rustflag("-Dwarnings");
+    }
+
+    if let Some(dir) = time_passes_dir(builder, target, stage) {
+        builder.create_dir(&dir);
+        cargo.env("RUSTC_TIME_PASSES", &dir);
+    }
+}
+
+/// Returns the directory the rustc shim writes the `-Ztime-passes` output of
+/// each crate of the std of `target` to, with `--time-passes`. The flag is
+/// unstable, so it's left out on the beta and stable channels.
+pub fn time_passes_dir(
+    builder: &Builder<'_>,
+    target: TargetSelection,
+    stage: u32,
+) -> Option<PathBuf> {
+    if !builder.config.time_passes || !builder.unstable_features() {
+        return None;
+    }
+    Some(builder.out.join(&*target.triple).join("time-passes").join(format!("stage{}", stage)))
+}
+
+/// Returns the build id, hex encoded, to link a BPF program built by
+/// `compiler` from `sources` for `target` with, if
+/// `target.<triple>.deterministic-build-id` is set. Otherwise the linker
+/// leaves the id out.
+pub fn sbf_build_id(
+    builder: &Builder<'_>,
+    compiler: Compiler,
+    target: TargetSelection,
+    sources: &[&Path],
+) -> Option<String> {
+    let deterministic =
+        builder.config.target_config.get(&target).map_or(false, |t| t.deterministic_build_id);
+    if !target.contains("bpf") || !deterministic {
+        return None;
+    }
+    let commit = builder.rust_sha().map_or_else(|| builder.rust_version(), |sha| sha.to_string());
+    let mut inputs = sysroot_inputs(builder, compiler, target);
+    inputs.extend(sources.iter().map(|source| source.to_path_buf()));
+    let inputs = inputs.iter().map(|input| &**input).collect::<Vec<_>>();
+    Some(build_id(&commit, &target.triple, &inputs))
+}
+
+/// Returns the files in the sysroot of `compiler` that programs built for
+/// `target` link against, in a stable order.
+fn sysroot_inputs(
+    builder: &Builder<'_>,
+    compiler: Compiler,
+    target: TargetSelection,
+) -> Vec<PathBuf> {
+    if builder.config.dry_run {
+        return Vec::new();
+    }
+    let libdir = builder.sysroot_libdir(compiler, target);
+    let mut inputs = t!(fs::read_dir(&*libdir))
+        .map(|entry| t!(entry).path())
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    inputs.sort();
+    inputs
+}
+
+/// Returns the `linker_flags` of `target` as arguments to rustc, for every
+/// link command of a crate built for it.
+pub fn link_args(builder: &Builder<'_>, target: TargetSelection) -> Vec<String> {
+    builder.linker_flags(target).iter().map(|arg| format!("-Clink-arg={}", arg)).collect()
+}
+
+/// Hashes everything a program is built from into the 20 bytes of a build
+/// id, the size `--build-id=sha1` would give it: the commit the compiler was
+/// built from, the target and the contents of the inputs, both the sysroot
+/// and the program's own sources.
+fn build_id(commit: &str, target: &str, inputs: &[&Path]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}\n{}\n", commit, target));
+    for input in inputs {
+        hasher.update(format!("{}\n", sha256_file(input)));
+    }
+    hex::encode(&hasher.finalize()[..20])
+}
+
+/// Heap size std requests without `target.<triple>.sbf-heap-size`.
+const SBF_DEFAULT_HEAP_SIZE: u64 = 32 * 1024;
+
+/// Returns the heap size std for `target` is built to request.
+pub fn sbf_heap_size(builder: &Builder<'_>, target: TargetSelection) -> u64 {
+    builder
+        .config
+        .target_config
+        .get(&target)
+        .and_then(|t| t.sbf_heap_size)
+        .unwrap_or(SBF_DEFAULT_HEAP_SIZE)
+}
+
+/// Heap sizes the SBF loader is willing to set up for a program.
+const SBF_HEAP_SIZE_RANGE: std::ops::RangeInclusive<u64> = 32 * 1024..=256 * 1024;
+
+fn check_sbf_heap_size(size: u64) -> Result<(), String> {
+    if !SBF_HEAP_SIZE_RANGE.contains(&size) {
+        Err(format!(
+            "{} is outside of the supported range of {} to {} bytes",
+            size,
+            SBF_HEAP_SIZE_RANGE.start(),
+            SBF_HEAP_SIZE_RANGE.end()
+        ))
+    } else if size % 1024 != 0 {
+        Err(format!("{} is not a multiple of 1024", size))
+    } else {
+        Ok(())
+    }
+}
+
+/// Builds the BPF standard library that `Mode::ToolBpf` crates link against
+/// and puts it in the sysroot of `compiler`.
+pub fn bpf_tool_std(builder: &Builder<'_>, compiler: Compiler, target: TargetSelection) {
+    if !target.contains("bpf") {
+        panic!("`Mode::ToolBpf` builds for BPF targets only, not {}", target);
+    }
+    builder.ensure(Std { compiler, target });
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -357,7 +499,13 @@ fn run(self, builder: &Builder<'_>) {
         ));
         let libdir = builder.sysroot_libdir(target_compiler, target);
         let hostdir = builder.sysroot_libdir(target_compiler, compiler.host);
-        add_to_sysroot(builder, &libdir, &hostdir, &libstd_stamp(builder, compiler, target));
+        let stamp = libstd_stamp(builder, compiler, target);
+        add_to_sysroot(builder, &libdir, &hostdir, &stamp);
+        builder.record_sysroot_stamp(target, &stamp);
+
+        if builder.config.verify_rlib_metadata && !builder.config.dry_run {
+            verify_rlib_metadata(&libdir);
+        }
     }
 }
 
@@ -520,9 +668,15 @@ fn run(self, builder: &Builder<'_>) {
             return;
         }
 
+        if reuse_stage1_rustc(builder, compiler, target) {
+            builder.info("Reusing the stage1 compiler, it is up to date");
+            builder.ensure(RustcLink { compiler, target_compiler: compiler, target });
+            return;
+        }
+
         builder.ensure(Std { compiler, target });
 
-        if builder.config.keep_stage.contains(&compiler.stage) {
+        if keep_rustc(&builder.config, compiler.stage) {
             builder.info("Warning: Using a potentially old librustc. This may not behave well.");
             builder.info("Warning: Use `--keep-stage-std` if you want to rebuild the compiler when it changes");
             builder.ensure(RustcLink { compiler, target_compiler: compiler, target });
@@ -716,12 +870,14 @@ fn run(self, builder: &Builder<'_>) {
             "Copying stage{} rustc from stage{} ({} -> {} / {})",
             target_compiler.stage, compiler.stage, &compiler.host, target_compiler.host, target
         ));
+        let stamp = librustc_stamp(builder, compiler, target);
         add_to_sysroot(
             builder,
             &builder.sysroot_libdir(target_compiler, target),
             &builder.sysroot_libdir(target_compiler, compiler.host),
-            &librustc_stamp(builder, compiler, target),
+            &stamp,
         );
+        builder.record_sysroot_stamp(target, &stamp);
     }
 }
 
@@ -763,7 +919,7 @@ fn run(self, builder: &Builder<'_>) {
 
         builder.ensure(Rustc { compiler, target });
 
-        if builder.config.keep_stage.contains(&compiler.stage) {
+        if keep_rustc(&builder.config, compiler.stage) {
             builder.info(
                 "Warning: Using a potentially old codegen backend. \
                 This may not behave well.",
@@ -785,7 +941,7 @@ fn run(self, builder: &Builder<'_>) {
             builder.cargo(compiler, Mode::Codegen, SourceType::Submodule, target, "build");
         cargo
             .arg("--manifest-path")
-            .arg(builder.src.join(format!("compiler/rustc_codegen_{}/Cargo.toml", backend)));
+            .arg(codegen_backend_crate(builder, backend).join("Cargo.toml"));
         rustc_cargo_env(builder, &mut cargo, target);
 
         let tmp_stamp = out_dir.join(".tmp.stamp");
@@ -794,9 +950,10 @@ fn run(self, builder: &Builder<'_>) {
         if builder.config.dry_run {
             return;
         }
+        let crate_name = format!("rustc_codegen_{}", backend);
         let mut files = files.into_iter().filter(|f| {
             let filename = f.file_name().unwrap().to_str().unwrap();
-            is_dylib(filename) && filename.contains("rustc_codegen_")
+            is_dylib(filename) && filename.contains(&crate_name)
         });
         let codegen_backend = match files.next() {
             Some(f) => f,
@@ -821,7 +978,7 @@ fn run(self, builder: &Builder<'_>) {
 /// This will take the codegen artifacts produced by `compiler` and link them
 /// into an appropriate location for `target_compiler` to be a functional
 /// compiler.
-fn copy_codegen_backends_to_sysroot(
+pub fn copy_codegen_backends_to_sysroot(
     builder: &Builder<'_>,
     compiler: Compiler,
     target_compiler: Compiler,
@@ -852,17 +1009,29 @@ fn copy_codegen_backends_to_sysroot(
         let dylib = t!(fs::read_to_string(&stamp));
         let file = Path::new(&dylib);
         let filename = file.file_name().unwrap().to_str().unwrap();
-        // change `librustc_codegen_cranelift-xxxxxx.so` to
-        // `librustc_codegen_cranelift-release.so`
-        let target_filename = {
-            let dash = filename.find('-').unwrap();
-            let dot = filename.find('.').unwrap();
-            format!("{}-{}{}", &filename[..dash], builder.rust_release(), &filename[dot..])
-        };
+        let target_filename = staged_codegen_backend(filename, backend, &builder.rust_release());
         builder.copy(&file, &dst.join(target_filename));
     }
 }
 
+/// The directory of the crate implementing `backend`, either configured
+/// through `rust.codegen-backend-paths` or in `compiler/`.
+pub fn codegen_backend_crate(builder: &Builder<'_>, backend: Interned<String>) -> PathBuf {
+    match builder.config.rust_codegen_backend_paths.get(&backend) {
+        Some(path) => path.clone(),
+        None => builder.src.join(format!("compiler/rustc_codegen_{}", backend)),
+    }
+}
+
+/// Changes `librustc_codegen_cranelift-xxxxxx.so` to
+/// `librustc_codegen_cranelift-release.so`, the name rustc looks for when
+/// loading the backend selected by `-Zcodegen-backend`.
+fn staged_codegen_backend(filename: &str, backend: &str, release: &str) -> String {
+    let prefix = filename.find("rustc_codegen_").unwrap();
+    let dot = filename[prefix..].find('.').unwrap() + prefix;
+    format!("{}rustc_codegen_{}-{}{}", &filename[..prefix], backend, release, &filename[dot..])
+}
+
 /// Cargo's output path for the standard library in a given stage, compiled
 /// by a particular compiler for the specified target.
 pub fn libstd_stamp(builder: &Builder<'_>, compiler: Compiler, target: TargetSelection) -> PathBuf {
@@ -881,7 +1050,7 @@ pub fn librustc_stamp(
 
 /// Cargo's output path for librustc_codegen_llvm in a given stage, compiled by a particular
 /// compiler for the specified target and backend.
-fn codegen_backend_stamp(
+pub fn codegen_backend_stamp(
     builder: &Builder<'_>,
     compiler: Compiler,
     target: TargetSelection,
@@ -899,7 +1068,7 @@ pub fn compiler_file(
     file: &str,
 ) -> PathBuf {
     let mut cmd = Command::new(compiler);
-    cmd.args(builder.cflags(target, GitRepo::Rustc));
+    cmd.args(builder.cflags_for_command(target, GitRepo::Rustc, "print-file-name"));
     cmd.arg(format!("-print-file-name={}", file));
     let out = output(&mut cmd);
     PathBuf::from(out.trim())
@@ -925,11 +1094,7 @@ fn should_run(run: ShouldRun<'_>) -> ShouldRun<'_> {
     /// 1-3.
     fn run(self, builder: &Builder<'_>) -> Interned<PathBuf> {
         let compiler = self.compiler;
-        let sysroot = if compiler.stage == 0 {
-            builder.out.join(&compiler.host.triple).join("stage0-sysroot")
-        } else {
-            builder.out.join(&compiler.host.triple).join(format!("stage{}", compiler.stage))
-        };
+        let sysroot = builder.sysroot_for(compiler);
         let _ = fs::remove_dir_all(&sysroot);
         t!(fs::create_dir_all(&sysroot));
 
@@ -939,9 +1104,10 @@ fn run(self, builder: &Builder<'_>) -> Interned<PathBuf> {
                 builder.config.build, compiler.host,
                 "Cross-compiling is not yet supported with `download-rustc`",
             );
-            // Copy the compiler into the correct sysroot.
+            // Link the compiler into the correct sysroot, nothing else gets
+            // added to it.
             let stage0_dir = builder.config.out.join(&*builder.config.build.triple).join("stage0");
-            builder.cp_r(&stage0_dir, &sysroot);
+            builder.symlink_or_copy_dir(&stage0_dir, &sysroot);
             return INTERNER.intern_path(sysroot);
         }
 
@@ -1092,7 +1258,8 @@ fn run(self, builder: &Builder<'_>) -> Compiler {
         if let Some(lld_install) = lld_install {
             let src_exe = exe("lld", target_compiler.host);
             let dst_exe = exe("rust-lld", target_compiler.host);
-            builder.copy(&lld_install.join("bin").join(&src_exe), &libdir_bin.join(&dst_exe));
+            let src = lld_install.join("bin").join(&src_exe);
+            builder.copy_with_progress(&src, &libdir_bin.join(&dst_exe));
         }
 
         // Similarly, copy `llvm-dwp` into libdir for Split DWARF. Only copy it when the LLVM
@@ -1105,7 +1272,8 @@ fn run(self, builder: &Builder<'_>) -> Compiler {
             if !builder.config.dry_run {
                 let llvm_bin_dir = output(Command::new(llvm_config_bin).arg("--bindir"));
                 let llvm_bin_dir = Path::new(llvm_bin_dir.trim());
-                builder.copy(&llvm_bin_dir.join(&src_exe), &libdir_bin.join(&dst_exe));
+                let src = llvm_bin_dir.join(&src_exe);
+                builder.copy_with_progress(&src, &libdir_bin.join(&dst_exe));
             }
         }
 
@@ -1126,6 +1294,90 @@ fn run(self, builder: &Builder<'_>) -> Compiler {
     }
 }
 
+/// Whether the std of `stage` is reused as is. `--keep-stage` keeps all of a
+/// stage while `--keep-stage-std` only keeps its std, so the compiler of that
+/// stage is still rebuilt against it.
+fn keep_std(config: &Config, stage: u32) -> bool {
+    config.keep_stage.contains(&stage) || config.keep_stage_std.contains(&stage)
+}
+
+/// Whether the compiler of `stage`, along with its codegen backends, is reused
+/// as is. Only `--keep-stage` does that, `--keep-stage-std` never affects it.
+fn keep_rustc(config: &Config, stage: u32) -> bool {
+    config.keep_stage.contains(&stage)
+}
+
+/// Returns whether the stage1 compiler that `Rustc { compiler, target }` builds
+/// can be linked as is. That's the case for `x.py build library --target
+/// bpfel-unknown-unknown`, where only BPF standard libraries are built, once the
+/// compiler has been built and neither its sources, the LLVM it links nor
+/// `config.toml` changed since its stamp was written. Otherwise any change to
+/// `library` would rebuild the host std and with it the whole compiler.
+///
+/// This only ever covers the stage1 compiler. `force_use_stage1` doesn't come
+/// into play: `uplift-stage1` can make it uplift the BPF standard libraries,
+/// but a compiler is only ever built for hosts, so `--stage 2` still builds a
+/// stage2 compiler the usual way, with the reused stage1 one as its build
+/// compiler.
+fn reuse_stage1_rustc(builder: &Builder<'_>, compiler: Compiler, target: TargetSelection) -> bool {
+    if compiler.stage != 0 || builder.is_cross_compiling(target) {
+        return false;
+    }
+    if !matches!(builder.config.cmd, Subcommand::Build { .. })
+        || !builder.targets.iter().all(|t| t.contains("bpf"))
+    {
+        return false;
+    }
+    let stamp = librustc_stamp(builder, compiler, target);
+    let rustc = builder
+        .sysroot_for(Compiler { stage: 1, host: target })
+        .join("bin")
+        .join(exe("rustc", target));
+    let mut inputs = vec![builder.src.join("compiler"), builder.src.join("Cargo.lock")];
+    if builder.is_rust_llvm(target) && !builder.config.llvm_from_ci {
+        inputs.push(builder.llvm_out(target).join("llvm-finished-building"));
+    }
+    if builder.config.config.exists() {
+        inputs.push(builder.config.config.clone());
+    }
+    rustc_is_fresh(&rustc, &stamp, &inputs)
+}
+
+/// Returns whether the compiler at `rustc` exists and its `stamp` is newer
+/// than all of `inputs`, which all have to exist.
+fn rustc_is_fresh(rustc: &Path, stamp: &Path, inputs: &[PathBuf]) -> bool {
+    rustc.exists() && inputs.iter().all(|input| input.exists() && up_to_date(input, stamp))
+}
+
+/// LLVM tools the build can't do without, see `native::LlvmPassTools`.
+const REQUIRED_LLVM_TOOLS: &[&str] = &["llc", "opt"];
+
+/// Splits `(name, path)` pairs of LLVM tools into the paths that exist and the
+/// names of the optional tools that don't. A missing required tool is an error
+/// naming the `llvm_config` the tools were looked up with.
+pub(crate) fn find_llvm_tools(
+    tools: Vec<(&'static str, PathBuf)>,
+    llvm_config: &Path,
+) -> Result<(Vec<PathBuf>, Vec<&'static str>), String> {
+    let mut found = Vec::new();
+    let mut missing = Vec::new();
+    for (tool, path) in tools {
+        if path.exists() {
+            found.push(path);
+        } else if REQUIRED_LLVM_TOOLS.contains(&tool) {
+            return Err(format!(
+                "`{}` is required but was not found at {} (LLVM from {})",
+                tool,
+                path.display(),
+                llvm_config.display()
+            ));
+        } else {
+            missing.push(tool);
+        }
+    }
+    Ok((found, missing))
+}
+
 /// Link some files into a rustc sysroot.
 ///
 /// For a particular stage this will link the file listed in `stamp` into the
@@ -1150,6 +1402,110 @@ pub fn add_to_sysroot(
     }
 }
 
+/// Checks that every rlib in `libdir` was produced by the same compiler.
+///
+/// Mixing rlibs from different compilers in one sysroot leads to the "found
+/// crate compiled by an incompatible version of rustc" errors much later, so
+/// fail early and list the offending files instead.
+fn verify_rlib_metadata(libdir: &Path) {
+    if let Err(outliers) = check_rlib_metadata(libdir) {
+        let mut msg = format!("inconsistent rlib metadata in {}:\n", libdir.display());
+        for (path, version) in outliers {
+            let version = version.as_deref().unwrap_or("<unreadable metadata>");
+            msg.push_str(&format!("  {} ({})\n", path.display(), version));
+        }
+        panic!("{}", msg);
+    }
+}
+
+/// Compares the rustc version recorded in the metadata of each rlib in `dir`.
+///
+/// The version shared by most rlibs is taken as the reference; every rlib
+/// disagreeing with it, or whose version could not be read, is returned.
+fn check_rlib_metadata(dir: &Path) -> Result<(), Vec<(PathBuf, Option<String>)>> {
+    let mut rlibs = t!(fs::read_dir(dir))
+        .map(|entry| t!(entry).path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "rlib"))
+        .map(|path| {
+            let version = rlib_rustc_version(&t!(fs::read(&path)));
+            (path, version)
+        })
+        .collect::<Vec<_>>();
+    rlibs.sort();
+
+    let mut counts = HashMap::new();
+    for (_, version) in rlibs.iter() {
+        if let Some(version) = version {
+            *counts.entry(version.clone()).or_insert(0) += 1;
+        }
+    }
+    let reference = counts.into_iter().max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)));
+    let reference = reference.map(|(version, _)| version);
+
+    let outliers = rlibs
+        .into_iter()
+        .filter(|(_, version)| version.is_none() || *version != reference)
+        .collect::<Vec<_>>();
+    if outliers.is_empty() { Ok(()) } else { Err(outliers) }
+}
+
+/// Extracts the rustc version string from the `lib.rmeta` member of an rlib.
+///
+/// The metadata starts with `rust\0\0\0` and a format version byte, followed
+/// by a 32-bit root position and then the LEB128 length-prefixed version.
+fn rlib_rustc_version(rlib: &[u8]) -> Option<String> {
+    const METADATA_MAGIC: &[u8] = b"rust\0\0\0";
+
+    let rmeta = ar_member(rlib, "lib.rmeta")?;
+    let start = rmeta.windows(METADATA_MAGIC.len()).position(|w| w == METADATA_MAGIC)?;
+    let mut pos = start + METADATA_MAGIC.len() + 1 + 4;
+
+    let mut len = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = *rmeta.get(pos)?;
+        pos += 1;
+        len |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+        if shift >= 64 {
+            return None;
+        }
+    }
+    let version = rmeta.get(pos..pos.checked_add(len)?)?;
+    str::from_utf8(version).ok().map(|s| s.to_string())
+}
+
+/// Returns the contents of the member called `name` in an `ar` archive,
+/// handling both the GNU (`name/`) and BSD (`#1/len`) naming conventions.
+fn ar_member<'a>(archive: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    const HEADER_LEN: usize = 60;
+
+    let mut pos = b"!<arch>\n".len();
+    if !archive.starts_with(b"!<arch>\n") {
+        return None;
+    }
+    while pos + HEADER_LEN <= archive.len() {
+        let header = &archive[pos..pos + HEADER_LEN];
+        let ident = str::from_utf8(&header[..16]).ok()?.trim_end();
+        let size = str::from_utf8(&header[48..58]).ok()?.trim_end().parse::<usize>().ok()?;
+        let data = archive.get(pos + HEADER_LEN..pos + HEADER_LEN + size)?;
+        if let Some(len) = ident.strip_prefix("#1/") {
+            let len = len.parse::<usize>().ok()?;
+            let member = data.get(..len)?;
+            if member.split(|&b| b == 0).next() == Some(name.as_bytes()) {
+                return Some(&data[len..]);
+            }
+        } else if ident.strip_suffix('/') == Some(name) {
+            return Some(data);
+        }
+        pos += HEADER_LEN + size + size % 2;
+    }
+    None
+}
+
 pub fn run_cargo(
     builder: &Builder<'_>,
     cargo: Cargo,
@@ -1275,19 +1631,26 @@ pub fn run_cargo(
     }
 
     deps.extend(additional_target_deps);
-    deps.sort();
-    let mut new_contents = Vec::new();
+    t!(fs::write(stamp, stamp_contents(&mut deps)));
+    deps.into_iter().map(|(d, _)| d).collect()
+}
+
+/// Serializes `deps` for a stamp file, see `Build::read_stamp_file`. They are
+/// sorted first so that identical builds write byte-identical stamps.
+fn stamp_contents(deps: &mut Vec<(PathBuf, DependencyType)>) -> Vec<u8> {
+    deps.sort_by(|(a_path, a_type), (b_path, b_type)| (a_type, a_path).cmp(&(b_type, b_path)));
+    deps.dedup();
+    let mut contents = Vec::new();
     for (dep, dependency_type) in deps.iter() {
-        new_contents.extend(match *dependency_type {
+        contents.extend(match *dependency_type {
             DependencyType::Host => b"h",
             DependencyType::Target => b"t",
             DependencyType::TargetSelfContained => b"s",
         });
-        new_contents.extend(dep.to_str().unwrap().as_bytes());
-        new_contents.extend(b"\0");
+        contents.extend(dep.to_str().unwrap().as_bytes());
+        contents.extend(b"\0");
     }
-    t!(fs::write(&stamp, &new_contents));
-    deps.into_iter().map(|(d, _)| d).collect()
+    contents
 }
 
 pub fn stream_cargo(
@@ -1354,6 +1717,71 @@ pub fn stream_cargo(
     status.success()
 }
 
+/// What `Build::run_captured_json` collected from a cargo command.
+#[derive(Debug, Default, PartialEq)]
+pub struct CargoJsonOutput {
+    /// The artifacts of every `compiler-artifact` message, in order.
+    pub artifacts: Vec<CargoArtifact>,
+    /// The diagnostics rustc emitted, as cargo rendered them.
+    pub diagnostics: Vec<String>,
+    pub success: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CargoArtifact {
+    pub package_id: String,
+    pub crate_types: Vec<String>,
+    pub filenames: Vec<PathBuf>,
+}
+
+impl CargoJsonOutput {
+    /// Records the message on `line` of cargo's stdout, returning `false` if
+    /// it isn't one, like the output of a build script.
+    pub fn add_message(&mut self, line: &str) -> bool {
+        match serde_json::from_str::<CargoMessage<'_>>(line) {
+            Ok(CargoMessage::CompilerArtifact { package_id, filenames, target, .. }) => {
+                self.artifacts.push(CargoArtifact {
+                    package_id: package_id.into_owned(),
+                    crate_types: target.crate_types.into_iter().map(Cow::into_owned).collect(),
+                    filenames: filenames.iter().map(|f| PathBuf::from(&**f)).collect(),
+                });
+                true
+            }
+            Ok(_) => true,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Splits the diagnostics cargo rendered out of the rest of its `stderr`,
+/// each being a `warning:` or `error:` line followed by everything up to the
+/// next empty line. Cargo's own summaries like "`foo` generated 1 warning"
+/// or "could not compile `foo`" aren't rustc's and are left out.
+pub fn rendered_diagnostics(stderr: &str) -> Vec<String> {
+    let mut diagnostics = Vec::new();
+    let mut current: Option<String> = None;
+    for line in stderr.lines() {
+        if let Some(diagnostic) = &mut current {
+            if line.trim().is_empty() {
+                diagnostics.extend(current.take());
+            } else {
+                diagnostic.push('\n');
+                diagnostic.push_str(line);
+            }
+            continue;
+        }
+        let is_diagnostic = (line.starts_with("warning") || line.starts_with("error"))
+            && line.contains(": ")
+            && !line.starts_with("warning: `")
+            && !line.starts_with("error: could not compile");
+        if is_diagnostic {
+            current = Some(line.to_string());
+        }
+    }
+    diagnostics.extend(current);
+    diagnostics
+}
+
 #[derive(Deserialize)]
 pub struct CargoTarget<'a> {
     crate_types: Vec<Cow<'a, str>>,
@@ -0,0 +1,436 @@
+use super::*;
+use crate::builder::tests::configure;
+use crate::util::{skip_test, test_dir};
+use crate::Build;
+use filetime::FileTime;
+
+/// Builds a minimal rlib whose `lib.rmeta` member records `version`.
+fn fake_rlib(version: &str) -> Vec<u8> {
+    let mut rmeta = b"rust\0\0\0\x05\0\0\0\0".to_vec();
+    rmeta.push(version.len() as u8);
+    rmeta.extend_from_slice(version.as_bytes());
+
+    let mut rlib = b"!<arch>\n".to_vec();
+    rlib.extend_from_slice(
+        format!("{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", "lib.rmeta/", 0, 0, 0, 644, rmeta.len())
+            .as_bytes(),
+    );
+    rlib.extend_from_slice(&rmeta);
+    if rmeta.len() % 2 == 1 {
+        rlib.push(b'\n');
+    }
+    rlib
+}
+
+#[test]
+fn rlib_version() {
+    assert_eq!(rlib_rustc_version(&fake_rlib("rustc 1.52.1")).as_deref(), Some("rustc 1.52.1"));
+    assert_eq!(rlib_rustc_version(b"!<arch>\n"), None);
+    assert_eq!(rlib_rustc_version(b"not an archive"), None);
+}
+
+#[test]
+fn consistent_sysroot() {
+    let dir = test_dir();
+    for name in &["libcore-1234.rlib", "liballoc-5678.rlib", "libstd-9abc.rlib"] {
+        t!(fs::write(dir.join(name), fake_rlib("rustc 1.52.1")));
+    }
+    t!(fs::write(dir.join("libstd-9abc.so"), b"not an rlib"));
+    assert_eq!(check_rlib_metadata(&dir), Ok(()));
+}
+
+#[test]
+fn mismatched_rlib() {
+    let dir = test_dir();
+    for name in &["libcore-1234.rlib", "liballoc-5678.rlib", "libstd-9abc.rlib"] {
+        t!(fs::write(dir.join(name), fake_rlib("rustc 1.52.1")));
+    }
+    t!(fs::write(dir.join("libstale-0000.rlib"), fake_rlib("rustc 1.51.0")));
+    t!(fs::write(dir.join("libbroken-1111.rlib"), b"garbage"));
+    assert_eq!(
+        check_rlib_metadata(&dir),
+        Err(vec![
+            (dir.join("libbroken-1111.rlib"), None),
+            (dir.join("libstale-0000.rlib"), Some("rustc 1.51.0".to_string())),
+        ])
+    );
+}
+
+#[test]
+fn sbf_heap_size() {
+    assert_eq!(check_sbf_heap_size(32 * 1024), Ok(()));
+    assert_eq!(check_sbf_heap_size(64 * 1024), Ok(()));
+    assert_eq!(check_sbf_heap_size(256 * 1024), Ok(()));
+    assert!(check_sbf_heap_size(16 * 1024).is_err());
+    assert!(check_sbf_heap_size(512 * 1024).is_err());
+    assert!(check_sbf_heap_size(40_000).is_err());
+}
+
+#[test]
+fn missing_optional_llvm_tool() {
+    let dir = test_dir();
+    t!(fs::write(dir.join("llc"), ""));
+    t!(fs::write(dir.join("opt"), ""));
+    let tools =
+        vec![("llc", dir.join("llc")), ("llvm-mc", dir.join("llvm-mc")), ("opt", dir.join("opt"))];
+    let (found, missing) = find_llvm_tools(tools, Path::new("/opt/llvm/bin/llvm-config")).unwrap();
+    assert_eq!(found, [dir.join("llc"), dir.join("opt")]);
+    assert_eq!(missing, ["llvm-mc"]);
+}
+
+#[test]
+fn missing_required_llvm_tool() {
+    let dir = test_dir();
+    t!(fs::write(dir.join("llvm-mc"), ""));
+    let tools = vec![("llvm-mc", dir.join("llvm-mc")), ("opt", dir.join("opt"))];
+    let err = find_llvm_tools(tools, Path::new("/opt/llvm/bin/llvm-config")).unwrap_err();
+    assert!(err.contains("`opt` is required"), "{}", err);
+    assert!(err.contains("/opt/llvm/bin/llvm-config"), "{}", err);
+}
+
+#[test]
+fn stamp_round_trip() {
+    let mut deps = vec![
+        (PathBuf::from("/build/deps/libstd-1234.rlib"), DependencyType::Target),
+        (PathBuf::from("/build/deps/libserde_derive-5678.so"), DependencyType::Host),
+        (PathBuf::from("/build/self-contained/crt1.o"), DependencyType::TargetSelfContained),
+        (PathBuf::from("/build/deps/libcore-9abc.rlib"), DependencyType::Target),
+    ];
+    let contents = stamp_contents(&mut deps.clone());
+    let parsed = crate::parse_stamp(&contents);
+    deps.sort_by_key(|(path, ty)| (*ty, path.clone()));
+    assert_eq!(parsed, deps);
+    assert_eq!(parsed[0].1, DependencyType::Host);
+}
+
+#[test]
+fn stamp_order_independent() {
+    let deps = vec![
+        (PathBuf::from("/build/deps/libstd-1234.rlib"), DependencyType::Target),
+        (PathBuf::from("/build/deps/libserde_derive-5678.so"), DependencyType::Host),
+        (PathBuf::from("/build/deps/libcore-9abc.rlib"), DependencyType::Target),
+        (PathBuf::from("/build/deps/libstd-1234.rlib"), DependencyType::Target),
+    ];
+    let mut reversed = deps.clone();
+    reversed.reverse();
+    assert_eq!(stamp_contents(&mut deps.clone()), stamp_contents(&mut reversed));
+}
+
+#[test]
+fn keep_stage_matrix() {
+    // (--keep-stage, --keep-stage-std, std kept, compiler kept) for stage 1.
+    let cases = [
+        (vec![], vec![], false, false),
+        (vec![1], vec![], true, true),
+        (vec![], vec![1], true, false),
+        (vec![1], vec![1], true, true),
+        // Keeping other stages doesn't affect stage 1.
+        (vec![0], vec![2], false, false),
+    ];
+    for (keep_stage, keep_stage_std, std, rustc) in cases.iter() {
+        let config = Config {
+            keep_stage: keep_stage.clone(),
+            keep_stage_std: keep_stage_std.clone(),
+            ..Config::default()
+        };
+        assert_eq!(keep_std(&config, 1), *std, "{:?} {:?}", keep_stage, keep_stage_std);
+        assert_eq!(keep_rustc(&config, 1), *rustc, "{:?} {:?}", keep_stage, keep_stage_std);
+    }
+}
+
+/// Captured from `cargo build --message-format=json-render-diagnostics`, the
+/// `profile` and `executable` fields are left out.
+const CARGO_JSON: &str = r#"{"reason":"compiler-artifact","package_id":"cfg-if 1.0.0 (registry+https://github.com/rust-lang/crates.io-index)","manifest_path":"/cargo/registry/src/cfg-if-1.0.0/Cargo.toml","target":{"kind":["lib"],"crate_types":["lib"],"name":"cfg-if","src_path":"/cargo/registry/src/cfg-if-1.0.0/src/lib.rs","edition":"2018","doc":true,"doctest":true,"test":true},"features":[],"filenames":["/build/target/debug/deps/libcfg_if-d2ef2a4b2e4a7e4c.rlib","/build/target/debug/deps/libcfg_if-d2ef2a4b2e4a7e4c.rmeta"],"fresh":true}
+{"reason":"build-script-executed","package_id":"libc 0.2.93 (registry+https://github.com/rust-lang/crates.io-index)","linked_libs":[],"linked_paths":[],"cfgs":["freebsd11","libc_priv_mod_use"],"env":[],"out_dir":"/build/target/debug/build/libc-6e3e4a1d0b4b1a0c/out"}
+Running the build script of a dependency
+{"reason":"compiler-artifact","package_id":"hello 0.1.0 (path+file:///src/hello)","manifest_path":"/src/hello/Cargo.toml","target":{"kind":["cdylib","rlib"],"crate_types":["cdylib","rlib"],"name":"hello","src_path":"/src/hello/src/lib.rs","edition":"2018","doc":true,"doctest":true,"test":true},"features":["default"],"filenames":["/build/target/debug/libhello.so","/build/target/debug/libhello.rlib"],"fresh":false}
+{"reason":"build-finished","success":true}
+"#;
+
+const CARGO_STDERR: &str = "\
+   Compiling hello v0.1.0 (/src/hello)
+warning: unused variable: `x`
+ --> src/lib.rs:1:17
+  |
+1 | fn f() { let x = 1; }
+  |              ^ help: if this is intentional, prefix it with an underscore: `_x`
+  |
+  = note: `#[warn(unused_variables)]` on by default
+
+error[E0425]: cannot find value `y` in this scope
+ --> src/lib.rs:2:10
+  |
+2 | fn g() { y }
+  |          ^ not found in this scope
+
+warning: `hello` (lib) generated 1 warning
+error: could not compile `hello` due to previous error
+";
+
+#[test]
+fn cargo_json_messages() {
+    let mut output = CargoJsonOutput::default();
+    let not_json = CARGO_JSON.lines().filter(|line| !output.add_message(line)).collect::<Vec<_>>();
+    assert_eq!(not_json, ["Running the build script of a dependency"]);
+
+    let artifacts = output.artifacts;
+    assert_eq!(artifacts.len(), 2);
+    assert_eq!(artifacts[0].crate_types, ["lib"]);
+    assert_eq!(artifacts[1].package_id, "hello 0.1.0 (path+file:///src/hello)");
+    assert_eq!(artifacts[1].crate_types, ["cdylib", "rlib"]);
+    assert_eq!(
+        artifacts[1].filenames,
+        [
+            PathBuf::from("/build/target/debug/libhello.so"),
+            "/build/target/debug/libhello.rlib".into()
+        ]
+    );
+}
+
+#[test]
+fn cargo_rendered_diagnostics() {
+    let diagnostics = rendered_diagnostics(CARGO_STDERR);
+    assert_eq!(diagnostics.len(), 2);
+    assert!(diagnostics[0].starts_with("warning: unused variable: `x`\n --> src/lib.rs:1:17\n"));
+    assert!(diagnostics[0].ends_with("= note: `#[warn(unused_variables)]` on by default"));
+    assert!(diagnostics[1].starts_with("error[E0425]: cannot find value `y` in this scope"));
+    assert!(diagnostics[1].ends_with("not found in this scope"));
+    assert_eq!(
+        rendered_diagnostics("   Compiling hello v0.1.0\n    Finished dev\n"),
+        Vec::<String>::new()
+    );
+}
+
+#[test]
+fn staged_codegen_backend_name() {
+    assert_eq!(
+        staged_codegen_backend("librustc_codegen_cranelift-1a2b3c4d.so", "cranelift", "1.52.0"),
+        "librustc_codegen_cranelift-1.52.0.so"
+    );
+    assert_eq!(
+        staged_codegen_backend("rustc_codegen_sbf-1a2b3c4d.dll", "sbf", "1.52.0-dev"),
+        "rustc_codegen_sbf-1.52.0-dev.dll"
+    );
+    assert_eq!(
+        staged_codegen_backend("librustc_codegen_sbf-0f9e8d7c.dylib", "sbf", "1.52.0"),
+        "librustc_codegen_sbf-1.52.0.dylib"
+    );
+}
+
+#[test]
+fn deterministic_build_id() {
+    let dir = test_dir();
+    let fixture = |name: &str, contents: &str| {
+        let path = dir.join(name);
+        t!(fs::write(&path, contents));
+        path
+    };
+    let first = fixture("first.c", "int entrypoint(void) { return 0; }\n");
+    let second = fixture("second.c", "int entrypoint(void) { return 0; }\n");
+    let changed = fixture("changed.c", "int entrypoint(void) { return 1; }\n");
+
+    let libstd = fixture("libstd.rlib", "std\n");
+    let patched = fixture("libstd-patched.rlib", "patched std\n");
+
+    let commit = "d1206f950ffb76c76e1b74a19ae33c2b7d949454";
+    let target = "bpfel-unknown-unknown";
+    let id = build_id(commit, target, &[&libstd, &first]);
+    assert_eq!(id.len(), 40);
+    assert_eq!(build_id(commit, target, &[&libstd, &second]), id);
+    assert_ne!(build_id(commit, target, &[&libstd, &changed]), id);
+    assert_ne!(build_id(commit, target, &[&patched, &first]), id);
+    let other = "4d1ac8a8f5cab2d5d2f2f0a41a4d2d5c4f2c3b1e";
+    assert_ne!(build_id(other, target, &[&libstd, &first]), id);
+    assert_ne!(build_id(commit, "bpfeb-unknown-unknown", &[&libstd, &first]), id);
+
+    // Two builds of the fixture with the id end up carrying it.
+    for (source, output) in &[(&first, "first.so"), (&second, "second.so")] {
+        let linked = Command::new("cc")
+            .arg("-shared")
+            .arg(format!("-Wl,--build-id=0x{}", id))
+            .arg(source)
+            .arg("-o")
+            .arg(dir.join(output))
+            .status();
+        if !linked.map_or(false, |s| s.success()) {
+            return skip_test("`cc` can't link the fixture");
+        }
+        let notes = match Command::new("llvm-readobj").arg("--notes").arg(dir.join(output)).output()
+        {
+            Ok(notes) => String::from_utf8_lossy(&notes.stdout).into_owned(),
+            Err(_) => return skip_test("`llvm-readobj` isn't installed"),
+        };
+        assert!(notes.contains(&format!("Build ID: {}", id)), "{}", notes);
+    }
+}
+
+#[test]
+fn stage1_rustc_freshness() {
+    let dir = test_dir();
+    let inputs =
+        vec![dir.join("compiler"), dir.join("llvm-finished-building"), dir.join("config.toml")];
+    t!(fs::create_dir_all(&inputs[0]));
+    t!(fs::write(inputs[0].join("lib.rs"), ""));
+    t!(fs::write(&inputs[1], ""));
+    t!(fs::write(&inputs[2], ""));
+    let (rustc, stamp) = (dir.join("rustc"), dir.join(".librustc.stamp"));
+    t!(fs::write(&rustc, ""));
+    t!(fs::write(&stamp, ""));
+    let built = FileTime::from_unix_time(1_000_000, 0);
+    let set_mtime = |path: &Path, time| t!(filetime::set_file_mtime(path, time));
+    for path in &[&inputs[0].join("lib.rs"), &inputs[1], &inputs[2]] {
+        set_mtime(path, FileTime::from_unix_time(900_000, 0));
+    }
+    set_mtime(&stamp, built);
+    assert!(rustc_is_fresh(&rustc, &stamp, &inputs));
+
+    // Rebuilding LLVM or editing `config.toml` invalidates the compiler.
+    set_mtime(&inputs[1], FileTime::from_unix_time(1_100_000, 0));
+    assert!(!rustc_is_fresh(&rustc, &stamp, &inputs));
+    set_mtime(&inputs[1], FileTime::from_unix_time(900_000, 0));
+    set_mtime(&inputs[2], FileTime::from_unix_time(1_100_000, 0));
+    assert!(!rustc_is_fresh(&rustc, &stamp, &inputs));
+    set_mtime(&inputs[2], FileTime::from_unix_time(900_000, 0));
+
+    // So does an LLVM that was never built.
+    t!(fs::remove_file(&inputs[1]));
+    assert!(!rustc_is_fresh(&rustc, &stamp, &inputs));
+}
+
+#[test]
+fn stage_codegen_backend() {
+    let mut config = configure("build", &["A"], &["A"]);
+    let sbf = INTERNER.intern_str("sbf");
+    let fixture = config.out.join("rustc_codegen_sbf");
+    config.rust_codegen_backends = vec![INTERNER.intern_str("llvm"), sbf];
+    config.rust_codegen_backend_paths.insert(sbf, fixture.clone());
+    let mut build = Build::new(config);
+    let builder = Builder::new(&build);
+    assert_eq!(codegen_backend_crate(&builder, sbf), fixture);
+    assert_eq!(
+        codegen_backend_crate(&builder, INTERNER.intern_str("cranelift")),
+        build.src.join("compiler/rustc_codegen_cranelift")
+    );
+
+    // The dylib the `CodegenBackend` step recorded in its stamp ends up
+    // under the name rustc looks for.
+    build.config.dry_run = false;
+    let builder = Builder::new(&build);
+    let a = TargetSelection::from_user("A");
+    let (compiler, target_compiler) =
+        (Compiler { host: a, stage: 0 }, Compiler { host: a, stage: 1 });
+    let dylib = fixture.join("target/librustc_codegen_sbf-1a2b3c4d.so");
+    t!(fs::create_dir_all(dylib.parent().unwrap()));
+    t!(fs::write(&dylib, "backend"));
+    let stamp = codegen_backend_stamp(&builder, compiler, a, sbf);
+    t!(fs::create_dir_all(stamp.parent().unwrap()));
+    t!(fs::write(&stamp, dylib.to_str().unwrap()));
+    copy_codegen_backends_to_sysroot(&builder, compiler, target_compiler);
+    let staged = builder
+        .sysroot_codegen_backends(target_compiler)
+        .join(format!("librustc_codegen_sbf-{}.so", build.rust_release()));
+    assert_eq!(t!(fs::read_to_string(staged)), "backend");
+}
+
+/// Returns the value of `var` in the environment the cargo `std_cargo` set
+/// up for `target` runs with. The cargo is a stub printing its environment.
+#[cfg(unix)]
+fn std_cargo_env(config: Config, target: TargetSelection, var: &str) -> Option<String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let cargo = test_dir().join("cargo");
+    t!(fs::write(&cargo, "#!/bin/sh\nenv\n"));
+    t!(fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)));
+    let mut build = Build::new(config);
+    build.initial_cargo = cargo;
+    let builder = Builder::new(&build);
+    let compiler = Compiler { host: TargetSelection::from_user("A"), stage: 1 };
+    let mut cargo = builder.cargo(compiler, Mode::Std, SourceType::InTree, target, "build");
+    std_cargo(&builder, target, compiler.stage, &mut cargo);
+
+    let output = t!(Command::from(cargo).output());
+    assert!(output.status.success());
+    let prefix = format!("{}=", var);
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .find(|line| line.starts_with(&prefix))
+        .map(|line| line[prefix.len()..].to_string())
+}
+
+#[cfg(unix)]
+#[test]
+fn std_deny_warnings() {
+    let bpf = TargetSelection::from_user("bpfel-unknown-unknown");
+    let std_rustflags = |target: TargetSelection| {
+        let mut config = configure("build", &["A"], &["A", &bpf.triple]);
+        config.deny_warnings = false;
+        let mut bpf_config = crate::config::Target::from_triple(&bpf.triple);
+        bpf_config.deny_warnings = true;
+        config.target_config.insert(bpf, bpf_config);
+        std_cargo_env(config, target, "RUSTFLAGS").unwrap_or_default()
+    };
+    assert!(std_rustflags(bpf).split(' ').any(|flag| flag == "-Dwarnings"));
+    let a = TargetSelection::from_user("A");
+    assert!(!std_rustflags(a).split(' ').any(|flag| flag == "-Dwarnings"));
+}
+
+#[cfg(unix)]
+#[test]
+fn cc_rs_cflags_response_file() {
+    let a = TargetSelection::from_user("A");
+    let cflags = |count: usize| {
+        let mut config = configure("build", &["A"], &["A"]);
+        let mut target = crate::config::Target::from_triple(&a.triple);
+        target.cflags = (0..count).map(|i| format!("-DSHIM_{:04}=1", i)).collect();
+        config.target_config.insert(a, target);
+        std_cargo_env(config, a, "CFLAGS_A").unwrap()
+    };
+
+    assert!(cflags(2).ends_with("-DSHIM_0000=1 -DSHIM_0001=1"), "{}", cflags(2));
+    // Too long for a command line, `compiler_builtins` and friends get them
+    // through a response file.
+    let rsp = cflags(crate::util::COMMAND_LINE_LIMIT / 8);
+    assert!(rsp.starts_with('@') && !rsp.contains(' '), "{}", rsp);
+    assert!(t!(fs::read_to_string(&rsp[1..])).contains("\"-DSHIM_0000=1\""));
+}
+
+#[cfg(unix)]
+#[test]
+fn time_passes() {
+    let a = TargetSelection::from_user("A");
+    let env = |time_passes: bool, channel: &str| {
+        let mut config = configure("build", &["A"], &["A"]);
+        config.time_passes = time_passes;
+        config.channel = channel.to_string();
+        std_cargo_env(config, a, "RUSTC_TIME_PASSES").map(PathBuf::from)
+    };
+
+    for channel in &["nightly", "dev"] {
+        let dir = env(true, channel).expect("std is built with RUSTC_TIME_PASSES");
+        assert!(dir.ends_with("A/time-passes/stage1"), "{}", dir.display());
+    }
+    // Stable and beta compilers can't take `-Ztime-passes`.
+    assert_eq!(env(true, "beta"), None);
+    assert_eq!(env(true, "stable"), None);
+    assert_eq!(env(false, "nightly"), None);
+}
+
+#[test]
+fn linker_args() {
+    let bpf = TargetSelection::from_user("bpfel-unknown-unknown");
+    let mut config = configure("build", &["A"], &["A", &bpf.triple]);
+    let mut target = crate::config::Target::from_triple(&bpf.triple);
+    target.linker_args = vec!["--script=bpf.ld".to_string(), "--emit-relocs".to_string()];
+    config.target_config.insert(bpf, target);
+    let build = Build::new(config);
+    let builder = Builder::new(&build);
+
+    assert_eq!(
+        link_args(&builder, bpf),
+        ["-Clink-arg=--script=bpf.ld", "-Clink-arg=--emit-relocs"]
+    );
+    let a = TargetSelection::from_user("A");
+    assert!(build.linker_flags(a).is_empty());
+    assert!(link_args(&builder, a).is_empty());
+}
@@ -20,6 +20,9 @@
 use merge::Merge;
 use serde::Deserialize;
 
+#[cfg(test)]
+mod tests;
+
 macro_rules! check_ci_llvm {
     ($name:expr) => {
         assert!(
@@ -53,8 +56,10 @@ pub struct Config {
     pub compiler_docs: bool,
     pub docs_minification: bool,
     pub docs: bool,
+    pub versioned_docs: bool,
     pub locked_deps: bool,
     pub vendor: bool,
+    pub offline: bool,
     pub target_config: HashMap<TargetSelection, Target>,
     pub full_bootstrap: bool,
     pub extended: bool,
@@ -63,9 +68,11 @@ pub struct Config {
     pub profiler: bool,
     pub ignore_git: bool,
     pub exclude: Vec<PathBuf>,
+    pub exclude_globs: Vec<String>,
     pub include_default_paths: bool,
     pub rustc_error_format: Option<String>,
     pub json_output: bool,
+    pub strict: bool,
     pub test_compare_mode: bool,
     pub llvm_libunwind: Option<LlvmLibunwind>,
     pub color: Color,
@@ -78,9 +85,17 @@ pub struct Config {
     // defaults to `config.toml`
     pub config: PathBuf,
     pub jobs: Option<u32>,
+    pub reserve_cpus: u32,
     pub cmd: Subcommand,
     pub incremental: bool,
     pub dry_run: bool,
+    pub build_manifest: bool,
+    pub dump_step_graph: bool,
+    pub explain: bool,
+    /// Tools to recompile even if they are fresh, from `--rebuild-tool`.
+    pub rebuild_tools: Vec<String>,
+    /// Compile the standard library with `-Ztime-passes`, from `--time-passes`.
+    pub time_passes: bool,
     pub download_rustc: bool,
 
     pub deny_warnings: bool,
@@ -133,12 +148,18 @@ pub struct Config {
     pub rust_optimize_tests: bool,
     pub rust_dist_src: bool,
     pub rust_codegen_backends: Vec<Interned<String>>,
+    /// Crate directories of codegen backends that don't live in
+    /// `compiler/rustc_codegen_<name>`.
+    pub rust_codegen_backend_paths: HashMap<Interned<String>, PathBuf>,
     pub rust_verify_llvm_ir: bool,
     pub rust_thin_lto_import_instr_limit: Option<u32>,
     pub rust_remap_debuginfo: bool,
+    pub rust_remap_debuginfo_prefix: Option<String>,
+    pub rust_remap_debuginfo_llvm_prefix: Option<String>,
     pub rust_new_symbol_mangling: bool,
     pub rust_profile_use: Option<String>,
     pub rust_profile_generate: Option<String>,
+    pub bpf_stack_size: Option<u64>,
 
     pub build: TargetSelection,
     pub hosts: Vec<TargetSelection>,
@@ -152,18 +173,30 @@ pub struct Config {
     pub dist_upload_addr: Option<String>,
     pub dist_gpg_password_file: Option<PathBuf>,
     pub dist_compression_formats: Option<Vec<String>>,
+    pub dist_zstd_level: Option<u32>,
+    pub dist_strip_tools: bool,
+    pub dist_relocatable: bool,
+    pub dist_combined: bool,
+    pub dist_combined_components: Vec<String>,
 
     // libstd features
     pub backtrace: bool, // support for RUST_BACKTRACE
 
     // misc
     pub low_priority: bool,
+    pub preserve_xattrs: bool,
     pub channel: String,
+    /// Vendor suffix appended to release and package versions, e.g. `solana.3`.
+    pub channel_suffix: Option<String>,
     pub description: Option<String>,
     pub verbose_tests: bool,
     pub save_toolstates: Option<PathBuf>,
     pub print_step_timings: bool,
     pub print_step_rusage: bool,
+    pub step_summary: Option<StepSummary>,
+    pub print_cache_stats: bool,
+    pub verify_rlib_metadata: bool,
+    pub hash_stamps: bool,
     pub missing_tools: bool,
 
     // Fallback musl-root for all targets
@@ -175,11 +208,16 @@ pub struct Config {
     pub bindir: PathBuf,
     pub libdir: Option<PathBuf>,
     pub mandir: Option<PathBuf>,
+    pub install_on_conflict: OnConflict,
     pub codegen_tests: bool,
     pub nodejs: Option<PathBuf>,
     pub npm: Option<PathBuf>,
     pub gdb: Option<PathBuf>,
     pub python: Option<PathBuf>,
+    /// GNU tar and `zstd`, found by the sanity checks if `.tar.zst` dist
+    /// tarballs are asked for.
+    pub gnu_tar: Option<PathBuf>,
+    pub zstd: Option<PathBuf>,
     pub cargo_native_static: bool,
     pub configure_args: Vec<String>,
 
@@ -216,6 +254,53 @@ fn from_str(value: &str) -> Result<Self, Self::Err> {
     }
 }
 
+/// What `Build::install` does when the destination file already exists.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnConflict {
+    Overwrite,
+    Backup,
+    Error,
+}
+
+impl Default for OnConflict {
+    fn default() -> Self {
+        Self::Overwrite
+    }
+}
+
+impl FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "overwrite" => Ok(Self::Overwrite),
+            "backup" => Ok(Self::Backup),
+            "error" => Ok(Self::Error),
+            invalid => Err(format!("Invalid value '{}' for install.on-conflict config.", invalid)),
+        }
+    }
+}
+
+/// How `build.step-summary` reports the time spent on each step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepSummary {
+    Text,
+    /// Also writes the timings to `build/step-timings.json`.
+    Json,
+}
+
+impl FromStr for StepSummary {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            invalid => Err(format!("Invalid value '{}' for build.step-summary config.", invalid)),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TargetSelection {
     pub triple: Interned<String>,
@@ -244,6 +329,11 @@ pub fn from_user(selection: &str) -> Self {
         Self { triple, file }
     }
 
+    /// Returns the path of the target specification file, for custom targets.
+    pub fn spec_file(&self) -> Option<PathBuf> {
+        self.file.map(|file| PathBuf::from(&*file))
+    }
+
     pub fn rustc_target_arg(&self) -> &str {
         self.file.as_ref().unwrap_or(&self.triple)
     }
@@ -284,20 +374,33 @@ pub struct Target {
     pub llvm_config: Option<PathBuf>,
     /// Some(path to FileCheck) if one was specified.
     pub llvm_filecheck: Option<PathBuf>,
+    /// Name FileCheck is installed under next to an external `llvm_config`.
+    pub llvm_filecheck_name: Option<String>,
     pub cc: Option<PathBuf>,
     pub cxx: Option<PathBuf>,
     pub ar: Option<PathBuf>,
     pub ranlib: Option<PathBuf>,
     pub linker: Option<PathBuf>,
+    pub linker_args: Vec<String>,
     pub ndk: Option<PathBuf>,
     pub sanitizers: Option<bool>,
     pub profiler: Option<bool>,
     pub crt_static: Option<bool>,
     pub musl_root: Option<PathBuf>,
     pub musl_libdir: Option<PathBuf>,
+    pub probe_musl_libdir: bool,
     pub wasi_root: Option<PathBuf>,
     pub qemu_rootfs: Option<PathBuf>,
     pub no_std: bool,
+    pub sbf_heap_size: Option<u64>,
+    pub deterministic_build_id: bool,
+    pub deny_warnings: bool,
+    pub no_float: bool,
+    pub cflags: Vec<String>,
+    pub min_cc_version: Option<String>,
+    pub llvm_jobs: Option<u32>,
+    pub uplift_stage1: bool,
+    pub std_features: Vec<String>,
 }
 
 impl Target {
@@ -325,12 +428,23 @@ struct TomlConfig {
     target: Option<HashMap<String, TomlTarget>>,
     dist: Option<Dist>,
     profile: Option<String>,
+    build_profiles: Option<HashMap<String, TomlConfig>>,
 }
 
 impl Merge for TomlConfig {
     fn merge(
         &mut self,
-        TomlConfig { build, install, llvm, rust, dist, target, profile: _, changelog_seen: _ }: Self,
+        TomlConfig {
+            build,
+            install,
+            llvm,
+            rust,
+            dist,
+            target,
+            profile: _,
+            changelog_seen: _,
+            build_profiles: _,
+        }: Self,
     ) {
         fn do_merge<T: Merge>(x: &mut Option<T>, y: Option<T>) {
             if let Some(new) = y {
@@ -359,12 +473,17 @@ struct Build {
     target: Option<Vec<String>>,
     // This is ignored, the rust code always gets the build directory from the `BUILD_DIR` env variable
     build_dir: Option<String>,
+    // This is only used by bootstrap.py when downloading the stage0 toolchain and CI LLVM
+    download_jobs: Option<u32>,
+    download_attempts: Option<u32>,
+    reserve_cpus: Option<u32>,
     cargo: Option<String>,
     rustc: Option<String>,
     rustfmt: Option<PathBuf>,
     docs: Option<bool>,
     compiler_docs: Option<bool>,
     docs_minification: Option<bool>,
+    versioned_docs: Option<bool>,
     submodules: Option<bool>,
     fast_submodules: Option<bool>,
     gdb: Option<String>,
@@ -373,6 +492,7 @@ struct Build {
     python: Option<String>,
     locked_deps: Option<bool>,
     vendor: Option<bool>,
+    offline: Option<bool>,
     full_bootstrap: Option<bool>,
     extended: Option<bool>,
     tools: Option<HashSet<String>>,
@@ -381,10 +501,15 @@ struct Build {
     profiler: Option<bool>,
     cargo_native_static: Option<bool>,
     low_priority: Option<bool>,
+    preserve_xattrs: Option<bool>,
     configure_args: Option<Vec<String>>,
     local_rebuild: Option<bool>,
     print_step_timings: Option<bool>,
     print_step_rusage: Option<bool>,
+    step_summary: Option<String>,
+    print_cache_stats: Option<bool>,
+    verify_rlib_metadata: Option<bool>,
+    hash_stamps: Option<bool>,
     check_stage: Option<u32>,
     doc_stage: Option<u32>,
     build_stage: Option<u32>,
@@ -405,6 +530,7 @@ struct Install {
     libdir: Option<String>,
     mandir: Option<String>,
     datadir: Option<String>,
+    on_conflict: Option<String>,
 
     // standard paths, currently unused
     infodir: Option<String>,
@@ -450,6 +576,11 @@ struct Dist {
     src_tarball: Option<bool>,
     missing_tools: Option<bool>,
     compression_formats: Option<Vec<String>>,
+    zstd_level: Option<u32>,
+    strip_tools: Option<bool>,
+    relocatable: Option<bool>,
+    combined: Option<bool>,
+    combined_components: Option<Vec<String>>,
 }
 
 #[derive(Deserialize)]
@@ -483,10 +614,12 @@ struct Rust {
     debuginfo_level_tests: Option<u32>,
     run_dsymutil: Option<bool>,
     backtrace: Option<bool>,
+    bpf_stack_size: Option<u64>,
     incremental: Option<bool>,
     parallel_compiler: Option<bool>,
     default_linker: Option<String>,
     channel: Option<String>,
+    channel_suffix: Option<String>,
     description: Option<String>,
     musl_root: Option<String>,
     rpath: Option<bool>,
@@ -497,6 +630,7 @@ struct Rust {
     dist_src: Option<bool>,
     save_toolstates: Option<String>,
     codegen_backends: Option<Vec<String>>,
+    codegen_backend_paths: Option<HashMap<String, String>>,
     lld: Option<bool>,
     use_lld: Option<bool>,
     llvm_tools: Option<bool>,
@@ -505,6 +639,8 @@ struct Rust {
     verify_llvm_ir: Option<bool>,
     thin_lto_import_instr_limit: Option<u32>,
     remap_debuginfo: Option<bool>,
+    remap_debuginfo_prefix: Option<String>,
+    remap_debuginfo_llvm_prefix: Option<String>,
     jemalloc: Option<bool>,
     test_compare_mode: Option<bool>,
     llvm_libunwind: Option<String>,
@@ -524,17 +660,29 @@ struct TomlTarget {
     ar: Option<String>,
     ranlib: Option<String>,
     linker: Option<String>,
+    linker_args: Option<Vec<String>>,
     llvm_config: Option<String>,
     llvm_filecheck: Option<String>,
+    llvm_filecheck_name: Option<String>,
     android_ndk: Option<String>,
     sanitizers: Option<bool>,
     profiler: Option<bool>,
     crt_static: Option<bool>,
     musl_root: Option<String>,
     musl_libdir: Option<String>,
+    probe_musl_libdir: Option<bool>,
     wasi_root: Option<String>,
     qemu_rootfs: Option<String>,
     no_std: Option<bool>,
+    sbf_heap_size: Option<u64>,
+    deterministic_build_id: Option<bool>,
+    deny_warnings: Option<bool>,
+    no_float: Option<bool>,
+    cflags: Option<Vec<String>>,
+    min_cc_version: Option<String>,
+    llvm_jobs: Option<u32>,
+    uplift_stage1: Option<bool>,
+    std_features: Option<Vec<String>>,
 }
 
 impl Config {
@@ -570,6 +718,8 @@ pub fn default_opts() -> Config {
         config.rust_codegen_backends = vec![INTERNER.intern_str("llvm")];
         config.deny_warnings = true;
         config.missing_tools = false;
+        config.dist_combined_components =
+            vec!["rustc".to_string(), "cargo".to_string(), "rust-std".to_string()];
 
         // set by build.rs
         config.build = TargetSelection::from_user(&env!("BUILD_TRIPLE"));
@@ -585,18 +735,55 @@ pub fn default_opts() -> Config {
     }
 
     pub fn parse(args: &[String]) -> Config {
-        let flags = Flags::parse(&args);
+        #[cfg(test)]
+        let get_toml = |_: &Path| TomlConfig::default();
+        #[cfg(not(test))]
+        let get_toml = |file: &Path| {
+            use std::process;
+
+            let contents = t!(fs::read_to_string(file), "`include` config not found");
+            match toml::from_str(&contents) {
+                Ok(table) => table,
+                Err(err) => {
+                    println!("failed to parse TOML configuration '{}': {}", file.display(), err);
+                    process::exit(2);
+                }
+            }
+        };
+
+        // Tests shouldn't depend on the environment they run in.
+        #[cfg(test)]
+        let vars = Vec::new().into_iter();
+        #[cfg(not(test))]
+        let vars = env::vars();
+
+        Self::parse_inner(Flags::parse(args), get_toml, vars)
+    }
 
+    fn parse_inner(
+        flags: Flags,
+        get_toml: impl Fn(&Path) -> TomlConfig,
+        vars: impl Iterator<Item = (String, String)>,
+    ) -> Config {
         let mut config = Config::default_opts();
         config.exclude = flags.exclude;
+        config.exclude_globs = flags.exclude_globs;
         config.include_default_paths = flags.include_default_paths;
         config.rustc_error_format = flags.rustc_error_format;
         config.json_output = flags.json_output;
+        config.strict = flags.strict;
         config.on_fail = flags.on_fail;
         config.jobs = flags.jobs.map(threads_from_config);
         config.cmd = flags.cmd;
         config.incremental = flags.incremental;
-        config.dry_run = flags.dry_run;
+        // Resolving the step graph means running every step, just without
+        // doing any of the work.
+        config.dry_run = flags.dry_run || flags.dump_step_graph || flags.explain;
+        config.build_manifest = flags.build_manifest;
+        config.dump_step_graph = flags.dump_step_graph;
+        config.explain = flags.explain;
+        config.rebuild_tools = flags.rebuild_tools;
+        config.time_passes = flags.time_passes;
         config.keep_stage = flags.keep_stage;
         config.keep_stage_std = flags.keep_stage_std;
         config.bindir = "bin".into(); // default
@@ -611,23 +798,10 @@ pub fn parse(args: &[String]) -> Config {
             config.out = dir;
         }
 
-        #[cfg(test)]
-        let get_toml = |_| TomlConfig::default();
-        #[cfg(not(test))]
-        let get_toml = |file: &Path| {
-            use std::process;
-
-            let contents = t!(fs::read_to_string(file), "`include` config not found");
-            match toml::from_str(&contents) {
-                Ok(table) => table,
-                Err(err) => {
-                    println!("failed to parse TOML configuration '{}': {}", file.display(), err);
-                    process::exit(2);
-                }
-            }
-        };
-
-        let mut toml = flags.config.as_deref().map(get_toml).unwrap_or_else(TomlConfig::default);
+        let mut toml = flags.config.as_deref().map(&get_toml).unwrap_or_default();
+        if let Some(name) = &flags.build_profile {
+            toml = apply_build_profile(toml, name);
+        }
         if let Some(include) = &toml.profile {
             let mut include_path = config.src.clone();
             include_path.push("src");
@@ -637,6 +811,7 @@ pub fn parse(args: &[String]) -> Config {
             let included_toml = get_toml(&include_path);
             toml.merge(included_toml);
         }
+        toml = apply_env_overrides(toml, vars);
 
         config.changelog_seen = toml.changelog_seen;
         if let Some(cfg) = flags.config {
@@ -667,13 +842,19 @@ pub fn parse(args: &[String]) -> Config {
         config.gdb = build.gdb.map(PathBuf::from);
         config.python = build.python.map(PathBuf::from);
         set(&mut config.low_priority, build.low_priority);
+        set(&mut config.preserve_xattrs, build.preserve_xattrs);
         set(&mut config.compiler_docs, build.compiler_docs);
         set(&mut config.docs_minification, build.docs_minification);
+        set(&mut config.versioned_docs, build.versioned_docs);
         set(&mut config.docs, build.docs);
         set(&mut config.submodules, build.submodules);
         set(&mut config.fast_submodules, build.fast_submodules);
         set(&mut config.locked_deps, build.locked_deps);
         set(&mut config.vendor, build.vendor);
+        set(&mut config.offline, build.offline);
+        // The flags can only make cargo stricter than `config.toml` does.
+        config.locked_deps |= flags.locked;
+        config.offline |= flags.offline;
         set(&mut config.full_bootstrap, build.full_bootstrap);
         set(&mut config.extended, build.extended);
         config.tools = build.tools;
@@ -687,7 +868,13 @@ pub fn parse(args: &[String]) -> Config {
         set(&mut config.configure_args, build.configure_args);
         set(&mut config.local_rebuild, build.local_rebuild);
         set(&mut config.print_step_timings, build.print_step_timings);
+        set(&mut config.reserve_cpus, build.reserve_cpus);
         set(&mut config.print_step_rusage, build.print_step_rusage);
+        config.step_summary =
+            build.step_summary.map(|v| v.parse().expect("failed to parse build.step-summary"));
+        set(&mut config.print_cache_stats, build.print_cache_stats);
+        set(&mut config.verify_rlib_metadata, build.verify_rlib_metadata);
+        set(&mut config.hash_stamps, build.hash_stamps);
 
         // See https://github.com/rust-lang/compiler-team/issues/326
         config.stage = match config.cmd {
@@ -698,6 +885,9 @@ pub fn parse(args: &[String]) -> Config {
             Subcommand::Bench { .. } => flags.stage.or(build.bench_stage).unwrap_or(2),
             Subcommand::Dist { .. } => flags.stage.or(build.dist_stage).unwrap_or(2),
             Subcommand::Install { .. } => flags.stage.or(build.install_stage).unwrap_or(2),
+            Subcommand::AuditSysroot { .. } => flags.stage.unwrap_or(1),
+            Subcommand::ReproReport { .. } => flags.stage.or(build.dist_stage).unwrap_or(2),
+            Subcommand::VerifyReproducible { .. } => flags.stage.or(build.build_stage).unwrap_or(1),
             // These are all bootstrap tools, which don't depend on the compiler.
             // The stage we pass shouldn't matter, but use 0 just in case.
             Subcommand::Clean { .. }
@@ -705,7 +895,15 @@ pub fn parse(args: &[String]) -> Config {
             | Subcommand::Fix { .. }
             | Subcommand::Run { .. }
             | Subcommand::Setup { .. }
+            | Subcommand::Doctor
+            | Subcommand::Disasm { .. }
+            | Subcommand::SizeReport { .. }
+            | Subcommand::ShowConfig { .. }
+            | Subcommand::ListSteps
             | Subcommand::Format { .. } => flags.stage.unwrap_or(0),
+            // The profiles are gathered with a compiler linked to the
+            // instrumented LLVM, which only a stage 1 compiler can be.
+            Subcommand::ProfileLlvm => 1,
         };
 
         // CI should always run stage 2 builds, unless it specifically states otherwise
@@ -730,6 +928,15 @@ pub fn parse(args: &[String]) -> Config {
                 | Subcommand::Fix { .. }
                 | Subcommand::Run { .. }
                 | Subcommand::Setup { .. }
+                | Subcommand::AuditSysroot { .. }
+                | Subcommand::Doctor
+                | Subcommand::ReproReport { .. }
+                | Subcommand::VerifyReproducible { .. }
+                | Subcommand::Disasm { .. }
+                | Subcommand::SizeReport { .. }
+                | Subcommand::ShowConfig { .. }
+                | Subcommand::ListSteps
+                | Subcommand::ProfileLlvm
                 | Subcommand::Format { .. } => {}
             }
         }
@@ -744,6 +951,10 @@ pub fn parse(args: &[String]) -> Config {
             set(&mut config.bindir, install.bindir.map(PathBuf::from));
             config.libdir = install.libdir.map(PathBuf::from);
             config.mandir = install.mandir.map(PathBuf::from);
+            if let Some(on_conflict) = install.on_conflict {
+                config.install_on_conflict =
+                    on_conflict.parse().expect("failed to parse install.on-conflict");
+            }
         }
 
         // We want the llvm-skip-rebuild flag to take precedence over the
@@ -870,6 +1081,8 @@ pub fn parse(args: &[String]) -> Config {
                 .map(|v| v.parse().expect("failed to parse rust.llvm-libunwind"));
             set(&mut config.backtrace, rust.backtrace);
             set(&mut config.channel, rust.channel);
+            // Allow channel-suffix="" to not add a suffix at all.
+            config.channel_suffix = rust.channel_suffix.filter(|s| !s.is_empty());
             config.description = rust.description;
             set(&mut config.rust_dist_src, rust.dist_src);
             set(&mut config.verbose_tests, rust.verbose_tests);
@@ -889,21 +1102,36 @@ pub fn parse(args: &[String]) -> Config {
             set(&mut config.rust_verify_llvm_ir, rust.verify_llvm_ir);
             config.rust_thin_lto_import_instr_limit = rust.thin_lto_import_instr_limit;
             set(&mut config.rust_remap_debuginfo, rust.remap_debuginfo);
+            config.rust_remap_debuginfo_prefix = rust.remap_debuginfo_prefix;
+            config.rust_remap_debuginfo_llvm_prefix = rust.remap_debuginfo_llvm_prefix;
             set(&mut config.control_flow_guard, rust.control_flow_guard);
 
             if let Some(ref backends) = rust.codegen_backends {
                 config.rust_codegen_backends =
                     backends.iter().map(|s| INTERNER.intern_str(s)).collect();
             }
+            for (backend, path) in rust.codegen_backend_paths.unwrap_or_default() {
+                let backend = INTERNER.intern_string(backend);
+                config.rust_codegen_backend_paths.insert(backend, config.src.join(path));
+            }
 
             config.rust_codegen_units = rust.codegen_units.map(threads_from_config);
             config.rust_codegen_units_std = rust.codegen_units_std.map(threads_from_config);
             config.rust_profile_use = flags.rust_profile_use.or(rust.profile_use);
             config.rust_profile_generate = flags.rust_profile_generate.or(rust.profile_generate);
             config.download_rustc = rust.download_rustc.unwrap_or(false);
+            config.bpf_stack_size = flags.bpf_stack_size.or(rust.bpf_stack_size);
         } else {
             config.rust_profile_use = flags.rust_profile_use;
             config.rust_profile_generate = flags.rust_profile_generate;
+            config.bpf_stack_size = flags.bpf_stack_size;
+        }
+
+        if let Some(size) = config.bpf_stack_size {
+            if !size.is_power_of_two() {
+                eprintln!("error: the BPF stack size must be a power of two, got {}", size);
+                std::process::exit(1);
+            }
         }
 
         if let Some(t) = toml.target {
@@ -922,18 +1150,30 @@ pub fn parse(args: &[String]) -> Config {
                 if let Some(s) = cfg.no_std {
                     target.no_std = s;
                 }
+                target.llvm_filecheck_name = cfg.llvm_filecheck_name;
                 target.cc = cfg.cc.map(PathBuf::from);
                 target.cxx = cfg.cxx.map(PathBuf::from);
                 target.ar = cfg.ar.map(PathBuf::from);
                 target.ranlib = cfg.ranlib.map(PathBuf::from);
                 target.linker = cfg.linker.map(PathBuf::from);
+                set(&mut target.linker_args, cfg.linker_args);
                 target.crt_static = cfg.crt_static;
                 target.musl_root = cfg.musl_root.map(PathBuf::from);
                 target.musl_libdir = cfg.musl_libdir.map(PathBuf::from);
+                set(&mut target.probe_musl_libdir, cfg.probe_musl_libdir);
                 target.wasi_root = cfg.wasi_root.map(PathBuf::from);
                 target.qemu_rootfs = cfg.qemu_rootfs.map(PathBuf::from);
                 target.sanitizers = cfg.sanitizers;
                 target.profiler = cfg.profiler;
+                target.sbf_heap_size = cfg.sbf_heap_size;
+                set(&mut target.deterministic_build_id, cfg.deterministic_build_id);
+                set(&mut target.deny_warnings, cfg.deny_warnings);
+                set(&mut target.no_float, cfg.no_float);
+                set(&mut target.cflags, cfg.cflags);
+                target.min_cc_version = cfg.min_cc_version;
+                target.llvm_jobs = cfg.llvm_jobs;
+                set(&mut target.uplift_stage1, cfg.uplift_stage1);
+                set(&mut target.std_features, cfg.std_features);
 
                 config.target_config.insert(TargetSelection::from_user(&triple), target);
             }
@@ -958,8 +1198,22 @@ pub fn parse(args: &[String]) -> Config {
             config.dist_gpg_password_file = t.gpg_password_file.map(PathBuf::from);
             config.dist_upload_addr = t.upload_addr;
             config.dist_compression_formats = t.compression_formats;
+            config.dist_zstd_level = t.zstd_level;
             set(&mut config.rust_dist_src, t.src_tarball);
             set(&mut config.missing_tools, t.missing_tools);
+            set(&mut config.dist_strip_tools, t.strip_tools);
+            set(&mut config.dist_relocatable, t.relocatable);
+            set(&mut config.dist_combined, t.combined);
+            set(&mut config.dist_combined_components, t.combined_components);
+            for component in &config.dist_combined_components {
+                if !crate::dist::COMBINED_COMPONENTS.contains(&&component[..]) {
+                    panic!(
+                        "unknown component `{}` in `dist.combined-components`, expected one of {:?}",
+                        component,
+                        crate::dist::COMBINED_COMPONENTS
+                    );
+                }
+            }
         }
 
         config.initial_rustfmt = config.initial_rustfmt.or_else({
@@ -1071,6 +1325,125 @@ fn set<T>(field: &mut T, val: Option<T>) {
     }
 }
 
+/// Layers the build profile `name` from `[build-profiles]` over `toml`.
+///
+/// Values set by the profile take precedence over the rest of `config.toml`,
+/// but are still overridden by the corresponding command line flags.
+fn apply_build_profile(mut toml: TomlConfig, name: &str) -> TomlConfig {
+    let mut profiles = toml.build_profiles.take().unwrap_or_default();
+    let mut profile = match profiles.remove(name) {
+        Some(profile) => profile,
+        None => {
+            let mut names = profiles.keys().map(|s| &s[..]).collect::<Vec<_>>();
+            names.sort();
+            eprintln!("error: unknown build profile `{}`", name);
+            if names.is_empty() {
+                eprintln!("help: define one in a `[build-profiles.{}]` table in config.toml", name);
+            } else {
+                eprintln!("help: the build profiles in config.toml are: {}", names.join(", "));
+            }
+            std::process::exit(1);
+        }
+    };
+    if profile.changelog_seen.is_some()
+        || profile.profile.is_some()
+        || profile.target.is_some()
+        || profile.build_profiles.is_some()
+    {
+        eprintln!(
+            "error: build profile `{}` may only set values in the \
+             [build], [install], [llvm], [rust] and [dist] sections",
+            name
+        );
+        std::process::exit(1);
+    }
+
+    profile.changelog_seen = toml.changelog_seen.take();
+    profile.profile = toml.profile.take();
+    profile.target = toml.target.take();
+    profile.merge(toml);
+    profile
+}
+
+/// Prefix of the environment variables overriding single keys of `config.toml`.
+const OVERRIDE_PREFIX: &str = "BOOTSTRAP_OVERRIDE_";
+
+/// The sections of `config.toml` keys can be overridden in. Per-target keys
+/// can't be, target triples don't survive being part of a variable name.
+const OVERRIDE_SECTIONS: &[&str] = &["build", "install", "llvm", "rust", "dist"];
+
+/// Layers the `BOOTSTRAP_OVERRIDE_*` variables among `vars` over `toml`, see
+/// `env_overrides`. Like build profiles, they take precedence over
+/// `config.toml` but not over command line flags.
+fn apply_env_overrides(
+    mut toml: TomlConfig,
+    vars: impl Iterator<Item = (String, String)>,
+) -> TomlConfig {
+    let mut overrides = match env_overrides(vars) {
+        Ok(overrides) => overrides,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    overrides.changelog_seen = toml.changelog_seen.take();
+    overrides.profile = toml.profile.take();
+    overrides.target = toml.target.take();
+    overrides.build_profiles = toml.build_profiles.take();
+    overrides.merge(toml);
+    overrides
+}
+
+/// Collects the `config.toml` keys overridden by `BOOTSTRAP_OVERRIDE_*`
+/// variables among `vars`.
+///
+/// The rest of the name is the section and key in upper case, with `_` in
+/// place of both the `.` and the `-`s, so `BOOTSTRAP_OVERRIDE_RUST_DEBUG_ASSERTIONS`
+/// overrides `rust.debug-assertions`. No key has an `_` of its own, which
+/// makes that unambiguous. Values are read as TOML if that gives the key
+/// the type it expects, `true` or `4` say, and as strings otherwise, so
+/// paths don't need quotes.
+fn env_overrides(vars: impl Iterator<Item = (String, String)>) -> Result<TomlConfig, String> {
+    let mut vars = vars.filter(|(name, _)| name.starts_with(OVERRIDE_PREFIX)).collect::<Vec<_>>();
+    vars.sort();
+
+    let mut overrides = TomlConfig::default();
+    for (name, value) in vars {
+        let rest = name[OVERRIDE_PREFIX.len()..].to_lowercase();
+        let mut parts = rest.splitn(2, '_');
+        let (section, key) = match (parts.next(), parts.next()) {
+            (Some(section), Some(key)) if OVERRIDE_SECTIONS.contains(&section) => {
+                (section.to_string(), key.replace('_', "-"))
+            }
+            _ => {
+                return Err(format!(
+                    "`{}` doesn't name a key of the [{}] sections of config.toml",
+                    name,
+                    OVERRIDE_SECTIONS.join("], [")
+                ));
+            }
+        };
+
+        let with_value = |value: toml::Value| -> Result<TomlConfig, toml::de::Error> {
+            let mut keys = toml::value::Table::new();
+            keys.insert(key.clone(), value);
+            let mut sections = toml::value::Table::new();
+            sections.insert(section.clone(), toml::Value::Table(keys));
+            toml::Value::Table(sections).try_into()
+        };
+        let literal = toml::from_str::<toml::value::Table>(&format!("value = {}", value))
+            .ok()
+            .and_then(|mut table| table.remove("value"));
+        let parsed = match literal.map(&with_value) {
+            Some(Ok(parsed)) => parsed,
+            _ => with_value(toml::Value::String(value))
+                .map_err(|e| format!("`{}` can't override `{}.{}`: {}", name, section, key, e))?,
+        };
+        overrides.merge(parsed);
+    }
+    Ok(overrides)
+}
+
 fn threads_from_config(v: u32) -> u32 {
     match v {
         0 => num_cpus::get() as u32,
@@ -0,0 +1,112 @@
+use super::*;
+
+const CONFIG: &str = r#"
+[build]
+build-stage = 1
+
+[rust]
+debug-assertions = true
+deny-warnings = true
+
+[build-profiles.release.build]
+build-stage = 2
+
+[build-profiles.release.rust]
+deny-warnings = false
+"#;
+
+fn parse(args: &[&str]) -> Config {
+    parse_with_env(args, &[])
+}
+
+fn parse_with_env(args: &[&str], vars: &[(&str, &str)]) -> Config {
+    let mut flags = Flags::parse(&args.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+    flags.config = Some(PathBuf::from("config.toml"));
+    let vars = vars.iter().map(|(name, value)| (name.to_string(), value.to_string()));
+    Config::parse_inner(flags, |_| toml::from_str(CONFIG).unwrap(), vars)
+}
+
+#[test]
+fn without_build_profile() {
+    let config = parse(&["build"]);
+    assert_eq!(config.stage, 1);
+    assert!(config.deny_warnings);
+    assert!(config.rust_debug_assertions);
+}
+
+#[test]
+fn build_profile_overrides_config() {
+    let config = parse(&["build", "--profile", "release"]);
+    assert_eq!(config.stage, 2);
+    assert!(!config.deny_warnings);
+    // Values the profile doesn't mention come from the rest of the config.
+    assert!(config.rust_debug_assertions);
+}
+
+#[test]
+fn flags_override_build_profile() {
+    let config = parse(&["build", "--profile", "release", "--stage", "1", "--warnings", "deny"]);
+    assert_eq!(config.stage, 1);
+    assert!(config.deny_warnings);
+}
+
+#[test]
+fn rebuild_tools() {
+    assert!(parse(&["build"]).rebuild_tools.is_empty());
+    let config = parse(&["build", "--rebuild-tool", "tidy", "--rebuild-tool", "cargo"]);
+    assert_eq!(config.rebuild_tools, ["tidy", "cargo"]);
+}
+
+fn with_overrides(vars: &[(&str, &str)]) -> TomlConfig {
+    let vars = vars.iter().map(|(name, value)| (name.to_string(), value.to_string()));
+    apply_env_overrides(toml::from_str(CONFIG).unwrap(), vars)
+}
+
+#[test]
+fn env_override_bool() {
+    let toml = with_overrides(&[("BOOTSTRAP_OVERRIDE_RUST_DEBUG_ASSERTIONS", "false")]);
+    let rust = toml.rust.unwrap();
+    assert_eq!(rust.debug_assertions, Some(false));
+    // Keys that aren't overridden keep their value.
+    assert_eq!(rust.deny_warnings, Some(true));
+    assert!(toml.build_profiles.unwrap().contains_key("release"));
+}
+
+#[test]
+fn env_override_int() {
+    let toml = with_overrides(&[("BOOTSTRAP_OVERRIDE_BUILD_BUILD_STAGE", "2"), ("PATH", "/bin")]);
+    assert_eq!(toml.build.unwrap().build_stage, Some(2));
+}
+
+#[test]
+fn env_override_path() {
+    let toml = with_overrides(&[
+        ("BOOTSTRAP_OVERRIDE_BUILD_RUSTFMT", "/opt/rustfmt/bin/rustfmt"),
+        ("BOOTSTRAP_OVERRIDE_INSTALL_PREFIX", "\"/opt/solana\""),
+    ]);
+    assert_eq!(toml.build.unwrap().rustfmt, Some(PathBuf::from("/opt/rustfmt/bin/rustfmt")));
+    assert_eq!(toml.install.unwrap().prefix.as_deref(), Some("/opt/solana"));
+}
+
+#[test]
+fn env_override_beats_build_profile() {
+    let config = parse_with_env(
+        &["build", "--profile", "release"],
+        &[("BOOTSTRAP_OVERRIDE_RUST_DENY_WARNINGS", "true")],
+    );
+    assert!(config.deny_warnings);
+    // The rest of the profile still applies.
+    assert_eq!(config.stage, 2);
+}
+
+#[test]
+fn invalid_env_overrides() {
+    let overrides = |name: &str, value: &str| {
+        env_overrides(vec![(name.to_string(), value.to_string())].into_iter()).err().unwrap()
+    };
+    assert!(overrides("BOOTSTRAP_OVERRIDE_TARGET_CC", "clang").contains("doesn't name a key"));
+    assert!(overrides("BOOTSTRAP_OVERRIDE_RUST", "true").contains("doesn't name a key"));
+    assert!(overrides("BOOTSTRAP_OVERRIDE_RUST_NO_SUCH_KEY", "1").contains("unknown field"));
+    let e = overrides("BOOTSTRAP_OVERRIDE_BUILD_BUILD_STAGE", "two");
+    assert!(e.contains("can't override `build.build-stage`"), "{}", e);
+}
@@ -10,6 +10,7 @@
 
 use std::env;
 use std::fs;
+use std::iter;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
@@ -21,8 +22,8 @@
 use crate::config::TargetSelection;
 use crate::tarball::{GeneratedTarball, OverlayKind, Tarball};
 use crate::tool::{self, Tool};
-use crate::util::{exe, is_dylib, timeit};
-use crate::{Compiler, DependencyType, Mode, LLVM_TOOLS};
+use crate::util::{exe, is_dylib, sha256_file, timeit};
+use crate::{Build, Compiler, CopyStats, DependencyType, Mode, LLVM_TOOLS};
 use time::{self, Timespec};
 
 pub fn pkgname(builder: &Builder<'_>, component: &str) -> String {
@@ -33,6 +34,29 @@ pub(crate) fn distdir(builder: &Builder<'_>) -> PathBuf {
     builder.out.join("dist")
 }
 
+/// Writes `components.sha256` next to the tarballs generated by this build,
+/// so they can be verified without unpacking them.
+pub fn write_components_listing(build: &Build) {
+    let path = build.out.join("dist/components.sha256");
+    t!(fs::write(&path, components_listing(&build.dist_tarballs.borrow())));
+    build.info(&format!("Wrote the checksums of the dist tarballs to {}", path.display()));
+}
+
+/// Lists the sha256, size in bytes and file name of each tarball, one per line
+/// and sorted by name.
+fn components_listing(tarballs: &[PathBuf]) -> String {
+    let mut entries = tarballs
+        .iter()
+        .map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, t!(fs::metadata(path)).len(), sha256_file(path))
+        })
+        .collect::<Vec<_>>();
+    entries.sort();
+    entries.dedup();
+    entries.iter().map(|(name, size, sha256)| format!("{}  {}  {}\n", sha256, size, name)).collect()
+}
+
 pub fn tmpdir(builder: &Builder<'_>) -> PathBuf {
     builder.out.join("tmp/dist")
 }
@@ -376,11 +400,9 @@ fn prepare_image(builder: &Builder<'_>, compiler: Compiler, image: &Path) {
 
             // Copy over the codegen backends
             let backends_src = builder.sysroot_codegen_backends(compiler);
-            let backends_rel = backends_src
-                .strip_prefix(&src)
-                .unwrap()
-                .strip_prefix(builder.sysroot_libdir_relative(compiler))
-                .unwrap();
+            let backends_rel = Build::relativize_for_sysroot(&backends_src, &src).unwrap();
+            let backends_rel =
+                backends_rel.strip_prefix(builder.sysroot_libdir_relative(compiler)).unwrap();
             // Don't use custom libdir here because ^lib/ will be resolved again with installer
             let backends_dst = image.join("lib").join(&backends_rel);
 
@@ -472,10 +494,11 @@ fn should_run(run: ShouldRun<'_>) -> ShouldRun<'_> {
     }
 
     fn make_run(run: RunConfig<'_>) {
+        // The compiler is assembled by `Builder::compiler`, all that's needed
+        // here is where its sysroot ended up.
+        let compiler = run.builder.compiler(run.builder.top_stage, run.build_triple());
         run.builder.ensure(DebuggerScripts {
-            sysroot: run
-                .builder
-                .sysroot(run.builder.compiler(run.builder.top_stage, run.build_triple())),
+            sysroot: INTERNER.intern_path(run.builder.sysroot_for(compiler)),
             host: run.target,
         });
     }
@@ -539,13 +562,17 @@ fn copy_target_libs(builder: &Builder<'_>, target: TargetSelection, image: &Path
     let self_contained_dst = dst.join("self-contained");
     t!(fs::create_dir_all(&dst));
     t!(fs::create_dir_all(&self_contained_dst));
+    let mut stats = CopyStats::default();
     for (path, dependency_type) in builder.read_stamp_file(stamp) {
         if dependency_type == DependencyType::TargetSelfContained {
-            builder.copy(&path, &self_contained_dst.join(path.file_name().unwrap()));
+            stats.record(
+                builder.copy_link(&path, &self_contained_dst.join(path.file_name().unwrap())),
+            );
         } else if dependency_type == DependencyType::Target || builder.config.build == target {
-            builder.copy(&path, &dst.join(path.file_name().unwrap()));
+            stats.record(builder.copy_link(&path, &dst.join(path.file_name().unwrap())));
         }
     }
+    builder.verbose(&format!("target libraries for {}: {}", target, stats));
 }
 
 #[derive(Debug, PartialOrd, Ord, Copy, Clone, Hash, PartialEq, Eq)]
@@ -792,11 +819,21 @@ fn filter_fn(exclude_dirs: &[&str], dir: &str, path: &Path) -> bool {
     }
 
     // Copy the directories using our filter
+    let (mut copied, mut skipped) = (0, 0);
     for item in src_dirs {
         let dst = &dst_dir.join(item);
         t!(fs::create_dir_all(dst));
-        builder.cp_filtered(&base.join(item), dst, &|path| filter_fn(exclude_dirs, item, path));
-    }
+        let stats =
+            builder.cp_filtered(&base.join(item), dst, &|path| filter_fn(exclude_dirs, item, path));
+        copied += stats.copied;
+        skipped += stats.skipped;
+    }
+    builder.verbose(&format!(
+        "copied {} files to {}, skipped {} files",
+        copied,
+        dst_dir.display(),
+        skipped
+    ));
 }
 
 #[derive(Debug, PartialOrd, Ord, Copy, Clone, Hash, PartialEq, Eq)]
@@ -979,7 +1016,7 @@ fn run(self, builder: &Builder<'_>) -> GeneratedTarball {
         let mut tarball = Tarball::new(builder, "cargo", &target.triple);
         tarball.set_overlay(OverlayKind::Cargo);
 
-        tarball.add_file(&cargo, "bin", 0o755);
+        tarball.add_tool_binary(&cargo);
         tarball.add_file(etc.join("_cargo"), "share/zsh/site-functions", 0o644);
         tarball.add_renamed_file(etc.join("cargo.bashcomp.sh"), "etc/bash_completion.d", "cargo");
         tarball.add_dir(etc.join("man"), "share/man/man1");
@@ -1042,7 +1079,7 @@ fn run(self, builder: &Builder<'_>) -> Option<GeneratedTarball> {
         let mut tarball = Tarball::new(builder, "rls", &target.triple);
         tarball.set_overlay(OverlayKind::RLS);
         tarball.is_preview(true);
-        tarball.add_file(rls, "bin", 0o755);
+        tarball.add_tool_binary(rls);
         tarball.add_legal_and_readme_to("share/doc/rls");
         Some(tarball.generate())
     }
@@ -1091,7 +1128,7 @@ fn run(self, builder: &Builder<'_>) -> Option<GeneratedTarball> {
         let mut tarball = Tarball::new(builder, "rust-analyzer", &target.triple);
         tarball.set_overlay(OverlayKind::RustAnalyzer);
         tarball.is_preview(true);
-        tarball.add_file(rust_analyzer, "bin", 0o755);
+        tarball.add_tool_binary(rust_analyzer);
         tarball.add_legal_and_readme_to("share/doc/rust-analyzer");
         Some(tarball.generate())
     }
@@ -1140,8 +1177,8 @@ fn run(self, builder: &Builder<'_>) -> GeneratedTarball {
         let mut tarball = Tarball::new(builder, "clippy", &target.triple);
         tarball.set_overlay(OverlayKind::Clippy);
         tarball.is_preview(true);
-        tarball.add_file(clippy, "bin", 0o755);
-        tarball.add_file(cargoclippy, "bin", 0o755);
+        tarball.add_tool_binary(clippy);
+        tarball.add_tool_binary(cargoclippy);
         tarball.add_legal_and_readme_to("share/doc/clippy");
         tarball.generate()
     }
@@ -1193,8 +1230,8 @@ fn run(self, builder: &Builder<'_>) -> Option<GeneratedTarball> {
         let mut tarball = Tarball::new(builder, "miri", &target.triple);
         tarball.set_overlay(OverlayKind::Miri);
         tarball.is_preview(true);
-        tarball.add_file(miri, "bin", 0o755);
-        tarball.add_file(cargomiri, "bin", 0o755);
+        tarball.add_tool_binary(miri);
+        tarball.add_tool_binary(cargomiri);
         tarball.add_legal_and_readme_to("share/doc/miri");
         Some(tarball.generate())
     }
@@ -1245,8 +1282,8 @@ fn run(self, builder: &Builder<'_>) -> Option<GeneratedTarball> {
         let mut tarball = Tarball::new(builder, "rustfmt", &target.triple);
         tarball.set_overlay(OverlayKind::Rustfmt);
         tarball.is_preview(true);
-        tarball.add_file(rustfmt, "bin", 0o755);
-        tarball.add_file(cargofmt, "bin", 0o755);
+        tarball.add_tool_binary(rustfmt);
+        tarball.add_tool_binary(cargofmt);
         tarball.add_legal_and_readme_to("share/doc/rustfmt");
         Some(tarball.generate())
     }
@@ -1787,6 +1824,74 @@ fn filter(contents: &str, marker: &str) -> String {
     }
 }
 
+/// Components `dist.combined-components` can select, in the order they're
+/// combined in. Like for `Extended`, rustc comes before rust-std.
+pub const COMBINED_COMPONENTS: &[&str] =
+    &["rustc", "cargo", "rust-std", "clippy", "rustfmt", "llvm-tools"];
+
+/// A single installer with the toolchain of a host along with the standard
+/// library of every BPF target, to set up a toolchain without rustup.
+#[derive(Debug, PartialOrd, Ord, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct Combined {
+    stage: u32,
+    host: TargetSelection,
+}
+
+impl Step for Combined {
+    type Output = Vec<PathBuf>;
+    const DEFAULT: bool = true;
+    const ONLY_HOSTS: bool = true;
+
+    fn should_run(run: ShouldRun<'_>) -> ShouldRun<'_> {
+        let builder = run.builder;
+        run.path("combined").default_condition(builder.config.dist_combined)
+    }
+
+    fn make_run(run: RunConfig<'_>) {
+        run.builder.ensure(Combined { stage: run.builder.top_stage, host: run.target });
+    }
+
+    /// Returns the tarballs that go into `rust-combined`, in the order they're
+    /// combined in. A dry run only names them.
+    fn run(self, builder: &Builder<'_>) -> Vec<PathBuf> {
+        let host = self.host;
+        let compiler = builder.compiler_for(self.stage, builder.config.build, host);
+        let selected = |component: &str| {
+            builder.config.dist_combined_components.iter().any(|c| c == component)
+        };
+
+        let mut tarballs = Vec::new();
+        for &component in COMBINED_COMPONENTS.iter().filter(|c| selected(c)) {
+            match component {
+                "rustc" => tarballs
+                    .push(builder.ensure(Rustc { compiler: builder.compiler(self.stage, host) })),
+                "cargo" => tarballs.push(builder.ensure(Cargo { compiler, target: host })),
+                "rust-std" => {
+                    let bpf = builder.targets.iter().filter(|t| t.contains("bpf"));
+                    for &target in iter::once(&host).chain(bpf) {
+                        let compiler =
+                            builder.compiler_for(self.stage, builder.config.build, target);
+                        tarballs.extend(builder.ensure(Std { compiler, target }));
+                    }
+                }
+                "clippy" => tarballs.push(builder.ensure(Clippy { compiler, target: host })),
+                "rustfmt" => tarballs.extend(builder.ensure(Rustfmt { compiler, target: host })),
+                "llvm-tools" => tarballs.extend(builder.ensure(LlvmTools { target: host })),
+                _ => unreachable!("`{}` is checked when the config is parsed", component),
+            }
+        }
+
+        let inputs = tarballs.iter().map(|tarball| tarball.tarball().to_path_buf()).collect();
+        // Avoid producing tarballs during a dry run.
+        if builder.config.dry_run || tarballs.is_empty() {
+            return inputs;
+        }
+        builder.info(&format!("Dist combined stage{} ({})", compiler.stage, host));
+        Tarball::new(builder, "rust-combined", &host.triple).combine(&tarballs);
+        inputs
+    }
+}
+
 fn add_env(builder: &Builder<'_>, cmd: &mut Command, target: TargetSelection) {
     let mut parts = builder.version.split('.');
     cmd.env("CFG_RELEASE_INFO", builder.rust_version())
@@ -1853,9 +1958,11 @@ fn maybe_install_llvm(builder: &Builder<'_>, target: TargetSelection, dst_libdir
     } else if let Ok(llvm_config) = crate::native::prebuilt_llvm_config(builder, target) {
         let mut cmd = Command::new(llvm_config);
         cmd.arg("--libfiles");
-        builder.verbose(&format!("running {:?}", cmd));
-        let files = output(&mut cmd);
-        for file in files.trim_end().split(' ') {
+        let out = builder.run_with_output_capture(&mut cmd);
+        if !out.is_success() {
+            panic!("{:?} failed with {:?}:\n{}", cmd, out.status, out.stderr);
+        }
+        for file in out.stdout.split_whitespace() {
             builder.install(Path::new(file), dst_libdir, 0o644);
         }
         !builder.config.dry_run
@@ -1922,10 +2029,31 @@ fn run(self, builder: &Builder<'_>) -> Option<GeneratedTarball> {
 
         // Prepare the image directory
         let src_bindir = builder.llvm_out(target).join("bin");
+        // `opt` and `llc` may come from the cache instead of the LLVM build.
+        let pass_tools_dir = builder.ensure(crate::native::LlvmPassTools { target });
         let dst_bindir = format!("lib/rustlib/{}/bin", target.triple);
-        for tool in LLVM_TOOLS {
-            let exe = src_bindir.join(exe(tool, target));
-            tarball.add_file(&exe, &dst_bindir, 0o755);
+        let tools = LLVM_TOOLS
+            .iter()
+            .map(|tool| {
+                let src_bindir =
+                    if *tool == "opt" || *tool == "llc" { &pass_tools_dir } else { &src_bindir };
+                (*tool, src_bindir.join(exe(tool, target)))
+            })
+            .collect::<Vec<_>>();
+        if builder.config.dry_run {
+            for (_, exe) in &tools {
+                tarball.add_file(exe, &dst_bindir, 0o755);
+            }
+        } else {
+            let llvm_config = src_bindir.join(exe("llvm-config", target));
+            let (found, missing) = match compile::find_llvm_tools(tools, &llvm_config) {
+                Ok(tools) => tools,
+                Err(e) => panic!("{}", e),
+            };
+            for tool in missing {
+                eprintln!("warning: `{}` was not built by LLVM, leaving it out", tool);
+            }
+            builder.install_parallel(found, &tarball.image_dir().join(&dst_bindir), 0o755);
         }
 
         // Copy libLLVM.so to the target lib dir as well, so the RPATH like
@@ -2067,3 +2195,6 @@ fn run(self, builder: &Builder<'_>) -> Self::Output {
         Some(tarball.generate())
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,27 @@
+use super::*;
+use crate::util::test_dir;
+
+#[test]
+fn components_listing_fixture() {
+    let dir = test_dir();
+    let tarball = |name: &str, contents: &str| {
+        let path = dir.join(name);
+        t!(fs::write(&path, contents));
+        path
+    };
+    let std = tarball("rust-std-1.52.0-dev-bpfel-unknown-unknown.tar.xz", "abc");
+    let empty = tarball("rust-docs-1.52.0-dev-x86_64-unknown-linux-gnu.tar.gz", "");
+    let rustc = tarball("rustc-1.52.0-dev-x86_64-unknown-linux-gnu.tar.gz", "abc\n");
+
+    // Install steps generate the same tarballs again.
+    let listing = components_listing(&[std.clone(), rustc, empty, std]);
+    assert_eq!(
+        listing,
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  0  \
+         rust-docs-1.52.0-dev-x86_64-unknown-linux-gnu.tar.gz\n\
+         ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  3  \
+         rust-std-1.52.0-dev-bpfel-unknown-unknown.tar.xz\n\
+         edeaaff3f1774ad2888673770c6d64097e391bc362d7d6fb34982ddf0efd18cb  4  \
+         rustc-1.52.0-dev-x86_64-unknown-linux-gnu.tar.gz\n"
+    );
+}
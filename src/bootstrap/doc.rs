@@ -12,7 +12,7 @@
 use std::io;
 use std::path::{Path, PathBuf};
 
-use crate::Mode;
+use crate::{Build, Mode};
 use build_helper::{t, up_to_date};
 
 use crate::builder::{Builder, Compiler, RunConfig, ShouldRun, Step};
@@ -413,24 +413,48 @@ fn make_run(run: RunConfig<'_>) {
     ///
     /// This will generate all documentation for the standard library and its
     /// dependencies. This is largely just a wrapper around `cargo doc`.
+    ///
+    /// With `--output-format=json` rustdoc's JSON backend is used instead and
+    /// only the `<crate>.json` files are copied into the output directory.
     fn run(self, builder: &Builder<'_>) {
         let stage = self.stage;
         let target = self.target;
-        builder.info(&format!("Documenting stage{} std ({})", stage, target));
-        let out = builder.doc_out(target);
+        let json = builder.config.cmd.json();
+        builder.info(&format!(
+            "Documenting stage{} std ({}){}",
+            stage,
+            target,
+            if json { " as JSON" } else { "" }
+        ));
+        let out = if json { builder.json_doc_out(target) } else { builder.doc_out(target) };
         t!(fs::create_dir_all(&out));
         let compiler = builder.compiler(stage, builder.config.build);
 
         builder.ensure(compile::Std { compiler, target });
         let out_dir = builder.stage_out(compiler, Mode::Std).join(target.triple).join("doc");
 
-        t!(fs::copy(builder.src.join("src/doc/rust.css"), out.join("rust.css")));
+        if !json {
+            t!(fs::copy(builder.src.join("src/doc/rust.css"), out.join("rust.css")));
+        }
 
         let run_cargo_rustdoc_for = |package: &str| {
             let mut cargo =
                 builder.cargo(compiler, Mode::Std, SourceType::InTree, target, "rustdoc");
             compile::std_cargo(builder, target, compiler.stage, &mut cargo);
 
+            if json {
+                cargo
+                    .arg("-p")
+                    .arg(package)
+                    .arg("--")
+                    .arg("-Z")
+                    .arg("unstable-options")
+                    .arg("--output-format")
+                    .arg("json");
+                builder.run(&mut cargo.into());
+                return;
+            }
+
             cargo
                 .arg("-p")
                 .arg(package)
@@ -465,6 +489,14 @@ fn run(self, builder: &Builder<'_>) {
         for krate in &krates {
             run_cargo_rustdoc_for(krate);
         }
+
+        if json {
+            for krate in &krates {
+                let file = format!("{}.json", krate);
+                builder.copy(&out_dir.join(&file), &out.join(&file));
+            }
+            return;
+        }
         builder.cp_r(&out_dir, &out);
 
         // Look for library/std, library/core etc in the `x.py doc` arguments and
@@ -718,6 +750,27 @@ fn run(self, builder: &Builder<'_>) {
     }
 }
 
+/// Points the `latest` symlink next to each versioned documentation directory
+/// that was generated to the one of this release. The link is relative so
+/// that the tree can be uploaded as is, except on Windows where junctions
+/// need an absolute path.
+pub fn link_latest_docs(build: &Build) {
+    if build.config.dry_run {
+        return;
+    }
+    for &target in build.hosts.iter().chain(&build.targets) {
+        for out in [build.doc_out(target), build.compiler_doc_out(target)].iter() {
+            if !out.is_dir() {
+                continue;
+            }
+            let latest = out.with_file_name("latest");
+            let src =
+                if cfg!(windows) { out.clone() } else { PathBuf::from(out.file_name().unwrap()) };
+            t!(symlink_dir_force(&build.config, &src, &latest));
+        }
+    }
+}
+
 fn symlink_dir_force(config: &Config, src: &Path, dst: &Path) -> io::Result<()> {
     if config.dry_run {
         return Ok(());
@@ -0,0 +1,148 @@
+//! Implementation of `x.py doctor`.
+//!
+//! Runs the same compiler detection as a normal build (see `cc_detect`) and
+//! then, instead of panicking on the first problem like `sanity` does, probes
+//! every configured target and prints a table of what was found along with
+//! suggestions on how to fix whatever is missing. Nothing is built.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::config::TargetSelection;
+use crate::sanity::Finder;
+use crate::Build;
+
+/// The outcome of looking for a single tool.
+#[derive(Debug, PartialEq)]
+enum Status {
+    Found(PathBuf),
+    /// The tool doesn't exist yet but will be produced by the build itself.
+    Pending(&'static str),
+    /// Nothing is configured, the compiler's default will be used.
+    Default,
+    NotNeeded,
+    Missing {
+        fix: String,
+    },
+}
+
+struct TargetReport {
+    target: TargetSelection,
+    probes: Vec<(&'static str, Status)>,
+}
+
+impl TargetReport {
+    fn is_ready(&self) -> bool {
+        self.probes.iter().all(|(_, status)| !matches!(status, Status::Missing { .. }))
+    }
+}
+
+pub fn doctor(build: &Build) {
+    let mut finder = Finder::new();
+    let targets = build
+        .targets
+        .iter()
+        .chain(&build.hosts)
+        .chain(Some(&build.build))
+        .copied()
+        .collect::<BTreeSet<_>>();
+    let reports =
+        targets.into_iter().map(|t| target_report(build, &mut finder, t)).collect::<Vec<_>>();
+
+    let width = reports.iter().map(|r| r.target.triple.len()).max().unwrap_or(0);
+    for report in &reports {
+        let state = if report.is_ready() { "ready" } else { "NOT READY" };
+        println!("{:width$}  {}", report.target.triple, state, width = width);
+        for (name, status) in &report.probes {
+            let found = match status {
+                Status::Found(path) => path.display().to_string(),
+                Status::Pending(why) => format!("not built yet, {}", why),
+                Status::Default => "compiler default".to_string(),
+                Status::NotNeeded => "not needed".to_string(),
+                Status::Missing { .. } => "MISSING".to_string(),
+            };
+            println!("{:width$}    {:8} {}", "", name, found, width = width);
+            if let Status::Missing { fix } = status {
+                println!("{:width$}             help: {}", "", fix, width = width);
+            }
+        }
+    }
+
+    if reports.iter().any(|r| !r.is_ready()) {
+        process::exit(1);
+    }
+}
+
+fn target_report(build: &Build, finder: &mut Finder, target: TargetSelection) -> TargetReport {
+    let mut probes = Vec::new();
+    let key = |tool: &str| format!("target.{}.{}", target.triple, tool);
+
+    // Mirror what `sanity::check` skips: emscripten and wasm32 don't use a C
+    // compiler and BPF uses the clang from the in-tree LLVM.
+    if target.contains("emscripten") || target.contains("wasm32") {
+        probes.push(("cc", Status::NotNeeded));
+    } else if target.contains("bpf") {
+        probes.push(("cc", in_tree_llvm(build, build.cc(target))));
+        if let Some(ar) = build.ar(target) {
+            probes.push(("ar", in_tree_llvm(build, ar)));
+        }
+    } else {
+        let fix = format!("install a C compiler or set `{}` in config.toml", key("cc"));
+        probes.push(("cc", probe(finder, build.cc(target), fix)));
+        if let Some(ar) = build.ar(target) {
+            let fix = format!("install `{}` or set `{}` in config.toml", ar.display(), key("ar"));
+            probes.push(("ar", probe(finder, ar, fix)));
+        }
+    }
+
+    if build.hosts.contains(&target) {
+        let status = match build.cxx(target) {
+            Ok(cxx) => {
+                let fix = format!("install a C++ compiler or set `{}` in config.toml", key("cxx"));
+                probe(finder, cxx, fix)
+            }
+            Err(_) => Status::Missing { fix: format!("set `{}` in config.toml", key("cxx")) },
+        };
+        probes.push(("c++", status));
+    }
+
+    let status = match build.linker(target) {
+        Some(linker) => {
+            let fix =
+                format!("install `{}` or set `{}` in config.toml", linker.display(), key("linker"));
+            probe(finder, linker, fix)
+        }
+        None => Status::Default,
+    };
+    probes.push(("linker", status));
+
+    TargetReport { target, probes }
+}
+
+/// Looks for `cmd`, either as given if it's a path or otherwise in `PATH`.
+fn probe(finder: &mut Finder, cmd: &Path, fix: String) -> Status {
+    let found = if cmd.components().count() > 1 {
+        Some(cmd.to_path_buf()).filter(|path| path.exists())
+    } else {
+        finder.maybe_have(cmd)
+    };
+    match found {
+        Some(path) => Status::Found(path),
+        None => Status::Missing { fix },
+    }
+}
+
+/// Like `probe`, but for tools that are produced by building LLVM.
+fn in_tree_llvm(build: &Build, tool: &Path) -> Status {
+    if tool.exists() {
+        Status::Found(tool.to_path_buf())
+    } else if tool.starts_with(&build.out) {
+        Status::Pending("will be built along with LLVM")
+    } else {
+        Status::Missing { fix: format!("`{}` does not exist, check `llvm-config`", tool.display()) }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,26 @@
+use super::*;
+use std::env;
+
+fn report(linker: Status) -> TargetReport {
+    TargetReport {
+        target: TargetSelection::from_user("bpfel-unknown-unknown"),
+        probes: vec![("cc", Status::Pending("will be built along with LLVM")), ("linker", linker)],
+    }
+}
+
+#[test]
+fn missing_linker() {
+    let linker = env::temp_dir().join("rustbuild-doctor-test").join("no-such-ld");
+    let status = probe(&mut Finder::new(), &linker, "install it".to_string());
+    assert_eq!(status, Status::Missing { fix: "install it".to_string() });
+    assert!(!report(status).is_ready());
+}
+
+#[test]
+fn found_linker() {
+    let linker = env::current_exe().unwrap();
+    let status = probe(&mut Finder::new(), &linker, "install it".to_string());
+    assert_eq!(status, Status::Found(linker));
+    assert!(report(status).is_ready());
+    assert!(report(Status::Default).is_ready());
+}
@@ -13,7 +13,7 @@
 use crate::builder::Builder;
 use crate::config::{Config, TargetSelection};
 use crate::setup::Profile;
-use crate::{Build, DocTests};
+use crate::{Build, DocTests, TargetKind};
 
 pub enum Color {
     Always,
@@ -51,14 +51,24 @@ pub struct Flags {
     pub host: Option<Vec<TargetSelection>>,
     pub target: Option<Vec<TargetSelection>>,
     pub config: Option<PathBuf>,
+    pub build_profile: Option<String>,
     pub jobs: Option<u32>,
     pub cmd: Subcommand,
     pub incremental: bool,
     pub exclude: Vec<PathBuf>,
+    pub exclude_globs: Vec<String>,
     pub include_default_paths: bool,
     pub rustc_error_format: Option<String>,
     pub json_output: bool,
+    pub strict: bool,
     pub dry_run: bool,
+    pub build_manifest: bool,
+    pub dump_step_graph: bool,
+    pub explain: bool,
+    pub locked: bool,
+    pub offline: bool,
+    pub rebuild_tools: Vec<String>,
+    pub time_passes: bool,
     pub color: Color,
 
     // This overrides the deny-warnings configuration option,
@@ -71,6 +81,8 @@ pub struct Flags {
 
     pub rust_profile_use: Option<String>,
     pub rust_profile_generate: Option<String>,
+
+    pub bpf_stack_size: Option<u64>,
 }
 
 pub enum Subcommand {
@@ -96,6 +108,7 @@ pub enum Subcommand {
     Doc {
         paths: Vec<PathBuf>,
         open: bool,
+        json: bool,
     },
     Test {
         paths: Vec<PathBuf>,
@@ -108,26 +121,57 @@ pub enum Subcommand {
         fail_fast: bool,
         doc_tests: DocTests,
         rustfix_coverage: bool,
+        /// Only run the steps that run against this kind of target.
+        target_kind: Option<TargetKind>,
     },
     Bench {
         paths: Vec<PathBuf>,
         test_args: Vec<String>,
+        compute_units: bool,
     },
     Clean {
         all: bool,
+        /// Only clean these targets, if any.
+        targets: Vec<TargetSelection>,
     },
     Dist {
         paths: Vec<PathBuf>,
     },
     Install {
         paths: Vec<PathBuf>,
+        force: bool,
     },
     Run {
         paths: Vec<PathBuf>,
+        /// Arguments after `--`, passed on to the tool.
+        args: Vec<String>,
     },
     Setup {
         profile: Profile,
     },
+    AuditSysroot {
+        paths: Vec<PathBuf>,
+    },
+    Doctor,
+    ReproReport {
+        other_manifest: PathBuf,
+    },
+    VerifyReproducible {
+        paths: Vec<PathBuf>,
+    },
+    Disasm {
+        paths: Vec<PathBuf>,
+    },
+    SizeReport {
+        path: PathBuf,
+        /// Report written by an earlier run to show the deltas against.
+        compare: Option<PathBuf>,
+    },
+    ShowConfig {
+        json: bool,
+    },
+    ListSteps,
+    ProfileLlvm,
 }
 
 impl Default for Subcommand {
@@ -156,6 +200,15 @@ pub fn parse(args: &[String]) -> Flags {
     install     Install distribution artifacts
     run, r      Run tools contained in this repository
     setup       Create a config.toml (making it easier to use `x.py` itself)
+    audit-sysroot  Check BPF artifacts for unexpected dynamic dependencies
+    doctor      Check that every configured target has a usable toolchain
+    repro-report  Compare the artifacts of this build with another machine's
+    verify-reproducible  Build twice from a clean state and compare the artifacts
+    disasm      Disassemble a built BPF program
+    size-report Break the size of a built BPF program down by section
+    show-config Print the C toolchain detected for each target
+    list-steps  Print the steps each subcommand can run as JSON
+    profile-llvm  Build LLVM optimized with profiles of compiling a BPF program
 
 To learn more about a subcommand, run `./x.py <subcommand> -h`",
         );
@@ -165,10 +218,21 @@ pub fn parse(args: &[String]) -> Flags {
         opts.optflagmulti("v", "verbose", "use verbose output (-vv for very verbose)");
         opts.optflag("i", "incremental", "use incremental compilation");
         opts.optopt("", "config", "TOML configuration file for build", "FILE");
+        opts.optopt(
+            "",
+            "profile",
+            "build profile from the [build-profiles] table of config.toml to apply",
+            "PROFILE",
+        );
         opts.optopt("", "build", "build target of the stage0 compiler", "BUILD");
         opts.optmulti("", "host", "host targets to build", "HOST");
         opts.optmulti("", "target", "target targets to build", "TARGET");
-        opts.optmulti("", "exclude", "build paths to exclude", "PATH");
+        opts.optmulti(
+            "",
+            "exclude",
+            "build paths to exclude, or glob patterns like `src/doc/*` matching them",
+            "PATH",
+        );
         opts.optflag(
             "",
             "include-default-paths",
@@ -176,6 +240,35 @@ pub fn parse(args: &[String]) -> Flags {
         );
         opts.optopt("", "on-fail", "command to run on failure", "CMD");
         opts.optflag("", "dry-run", "dry run; don't build anything");
+        opts.optflag(
+            "",
+            "build-manifest",
+            "write the artifacts that were built and their hashes to build/manifest.json",
+        );
+        opts.optflag(
+            "",
+            "dump-step-graph",
+            "print the steps the given paths resolve to and their dependencies in DOT format \
+             instead of building",
+        );
+        opts.optflag(
+            "",
+            "explain",
+            "print why each step is scheduled (requested path, default rule or dependency) \
+             instead of building",
+        );
+        opts.optflag(
+            "",
+            "locked",
+            "pass --locked to every cargo command, failing instead of updating Cargo.lock",
+        );
+        opts.optflag("", "offline", "pass --offline to every cargo command");
+        opts.optflag(
+            "",
+            "time-passes",
+            "compile the standard library with -Ztime-passes, writing what each crate \
+             prints to build/<target>/time-passes (only on the nightly and dev channels)",
+        );
         opts.optopt(
             "",
             "stage",
@@ -197,6 +290,13 @@ pub fn parse(args: &[String]) -> Flags {
             (pass multiple times to keep e.g., both stages 0 and 1)",
             "N",
         );
+        opts.optmulti(
+            "",
+            "rebuild-tool",
+            "recompile this tool even if cargo considers it fresh \
+            (pass multiple times for several tools)",
+            "NAME",
+        );
         opts.optopt("", "src", "path to the root of the rust checkout", "DIR");
         let j_msg = format!(
             "number of jobs to run in parallel; \
@@ -205,6 +305,11 @@ pub fn parse(args: &[String]) -> Flags {
         );
         opts.optopt("j", "jobs", &j_msg, "JOBS");
         opts.optflag("h", "help", "print this help message");
+        opts.optflag(
+            "",
+            "strict",
+            "treat warnings from the sanity checks and the LLVM disk space check as errors",
+        );
         opts.optopt(
             "",
             "warnings",
@@ -214,6 +319,12 @@ pub fn parse(args: &[String]) -> Flags {
         opts.optopt("", "error-format", "rustc error format", "FORMAT");
         opts.optflag("", "json-output", "use message-format=json");
         opts.optopt("", "color", "whether to use color in cargo and rustc output", "STYLE");
+        opts.optopt(
+            "",
+            "bpf-stack-size",
+            "stack size in bytes the standard library assumes on BPF targets",
+            "BYTES",
+        );
         opts.optopt(
             "",
             "llvm-skip-rebuild",
@@ -248,6 +359,15 @@ pub fn parse(args: &[String]) -> Flags {
                 || (s == "run")
                 || (s == "r")
                 || (s == "setup")
+                || (s == "audit-sysroot")
+                || (s == "doctor")
+                || (s == "repro-report")
+                || (s == "verify-reproducible")
+                || (s == "disasm")
+                || (s == "size-report")
+                || (s == "show-config")
+                || (s == "list-steps")
+                || (s == "profile-llvm")
         });
         let subcommand = match subcommand {
             Some(s) => s,
@@ -299,25 +419,72 @@ pub fn parse(args: &[String]) -> Flags {
                     "enable this to generate a Rustfix coverage file, which is saved in \
                         `/<build_base>/rustfix_missing_coverage.txt`",
                 );
+                opts.optopt(
+                    "",
+                    "target-kind",
+                    "only run the tests that run against BPF targets or against hosts",
+                    "bpf | host",
+                );
             }
             "check" | "c" => {
                 opts.optflag("", "all-targets", "Check all targets");
             }
             "bench" => {
                 opts.optmulti("", "test-args", "extra arguments", "ARGS");
+                opts.optflag(
+                    "",
+                    "compute-units",
+                    "report the compute units the benchmarks of a BPF crate use",
+                );
             }
             "clippy" => {
                 opts.optflag("", "fix", "automatically apply lint suggestions");
             }
             "doc" => {
                 opts.optflag("", "open", "open the docs in a browser");
+                opts.optopt(
+                    "",
+                    "output-format",
+                    "format of the generated standard library documentation",
+                    "html | json",
+                );
             }
             "clean" => {
-                opts.optflag("", "all", "clean all build artifacts");
+                opts.optflag(
+                    "",
+                    "all",
+                    "clean all build artifacts, or with --target also those of host targets",
+                );
             }
             "fmt" => {
                 opts.optflag("", "check", "check formatting instead of applying.");
             }
+            "install" => {
+                opts.optflag(
+                    "",
+                    "force",
+                    "install even under sudo into a prefix that is owned by another user",
+                );
+            }
+            "repro-report" => {
+                opts.optopt(
+                    "",
+                    "other-manifest",
+                    "manifest written by `repro-report` on the other machine",
+                    "FILE",
+                );
+            }
+            "size-report" => {
+                opts.optopt(
+                    "",
+                    "compare",
+                    "report written by an earlier `size-report` to compare against",
+                    "FILE",
+                );
+            }
+            "show-config" => {
+                opts.optflag("", "json", "print the toolchains as JSON");
+            }
             _ => {}
         };
 
@@ -332,7 +499,17 @@ pub fn parse(args: &[String]) -> Flags {
 
                 let maybe_rules_help = Builder::get_help(&build, subcommand.as_str());
                 extra_help.push_str(maybe_rules_help.unwrap_or_default().as_str());
-            } else if !(subcommand.as_str() == "clean" || subcommand.as_str() == "fmt") {
+            } else if !(subcommand.as_str() == "clean"
+                || subcommand.as_str() == "fmt"
+                || subcommand.as_str() == "audit-sysroot"
+                || subcommand.as_str() == "doctor"
+                || subcommand.as_str() == "repro-report"
+                || subcommand.as_str() == "disasm"
+                || subcommand.as_str() == "size-report"
+                || subcommand.as_str() == "show-config"
+                || subcommand.as_str() == "list-steps"
+                || subcommand.as_str() == "profile-llvm")
+            {
                 extra_help.push_str(
                     format!("Run `./x.py {} -h -v` to see a list of available paths.", subcommand)
                         .as_str(),
@@ -346,6 +523,18 @@ pub fn parse(args: &[String]) -> Flags {
             process::exit(exit_code);
         };
 
+        if let Some(message) = retired_flag(args) {
+            println!("\n{}\n", message);
+            usage(1, &opts, false, &subcommand_help);
+        }
+
+        // Everything after `--` belongs to the tool `x.py run` runs, keep
+        // getopts from taking it for paths.
+        let (args, run_args) = match args.iter().position(|arg| arg == "--") {
+            Some(i) if matches!(subcommand.as_str(), "run" | "r") => (&args[..i], &args[i + 1..]),
+            _ => (args, &[][..]),
+        };
+
         // Done specifying what options are possible, so do the getopts parsing
         let matches = opts.parse(args).unwrap_or_else(|e| {
             // Invalid argument/option format
@@ -477,6 +666,10 @@ pub fn parse(args: &[String]) -> Flags {
 
         ./x.py test tidy
 
+    Check that a BPF program built with the new compiler can be loaded:
+
+        ./x.py test bpf-smoke --target bpfel-unknown-unknown
+
     If no arguments are passed then the complete artifacts for that stage are
     compiled and tested.
 
@@ -484,6 +677,22 @@ pub fn parse(args: &[String]) -> Flags {
         ./x.py test --stage 1",
                 );
             }
+            "bench" => {
+                subcommand_help.push_str(
+                    "\n
+Arguments:
+    This subcommand accepts a number of paths to crates to benchmark. For
+    example:
+
+        ./x.py bench library/core
+
+    With `--compute-units`, a single BPF crate's benchmarks are run in the VM
+    behind `remote-test-client`, which `TEST_DEVICE_ADDR` has to point to, and
+    the compute units each one used are reported instead of its run time:
+
+        ./x.py bench library/core --target bpfel-unknown-unknown --compute-units",
+                );
+            }
             "doc" => {
                 subcommand_help.push_str(
                     "\n
@@ -495,6 +704,7 @@ pub fn parse(args: &[String]) -> Flags {
         ./x.py doc src/doc/nomicon
         ./x.py doc src/doc/book library/std
         ./x.py doc library/std --open
+        ./x.py doc library/std --output-format=json
 
     If no arguments are passed then everything is documented:
 
@@ -511,7 +721,11 @@ pub fn parse(args: &[String]) -> Flags {
 
         ./x.py run src/tools/expand-yaml-anchors
 
-    At least a tool needs to be called.",
+    At least a tool needs to be called. Arguments after `--` are passed on to
+    the tool, which is built for the target and run through the emulator if
+    the target is tested remotely:
+
+        ./x.py run src/tools/rust-demangler -- --help",
                 );
             }
             "setup" => {
@@ -531,6 +745,118 @@ pub fn parse(args: &[String]) -> Flags {
                     Profile::all_for_help("        ").trim_end()
                 ));
             }
+            "audit-sysroot" => {
+                subcommand_help.push_str(
+                    "\n
+Arguments:
+    This subcommand accepts a number of shared objects to audit. If none are
+    given, the shared objects in the sysroot of each BPF target are audited
+    instead. For example:
+
+        ./x.py audit-sysroot --target bpfel-unknown-unknown
+        ./x.py audit-sysroot path/to/program.so",
+                );
+            }
+            "doctor" => {
+                subcommand_help.push_str(
+                    "\n
+Arguments:
+    This subcommand accepts no arguments. It looks for the C compiler, archiver
+    and linker of every configured host and target, as `build` would, and
+    prints which of them are missing without building anything. For example:
+
+        ./x.py doctor
+        ./x.py doctor --target bpfel-unknown-unknown",
+                );
+            }
+            "repro-report" => {
+                subcommand_help.push_str(
+                    "\n
+Arguments:
+    This subcommand hashes the artifacts in the sysroot of the given stage,
+    writes them to `build/repro-manifest.json` and compares them with the
+    manifest written on another machine, listing every artifact that differs
+    or only exists on one side. For example:
+
+        ./x.py repro-report --stage 2 --other-manifest other/repro-manifest.json",
+                );
+            }
+            "verify-reproducible" => {
+                subcommand_help.push_str(
+                    "\n
+Arguments:
+    This subcommand accepts the same paths as `build`. They are built twice,
+    removing the stage directories of every host before each pass, and the
+    libraries linked into a sysroot by each pass are hashed and compared. Any
+    library that differs, or was only built by one of the passes, is listed.
+    LLVM is only built once. For example:
+
+        ./x.py verify-reproducible library/std --target bpfel-unknown-unknown",
+                );
+            }
+            "disasm" => {
+                subcommand_help.push_str(
+                    "\n
+Arguments:
+    This subcommand accepts a number of BPF programs to disassemble with the
+    `llvm-objdump` of the configured LLVM. The triple is read from the ELF
+    header of each program. For example:
+
+        ./x.py disasm target/deploy/program.so
+
+    At least one program needs to be given.",
+                );
+            }
+            "size-report" => {
+                subcommand_help.push_str(
+                    "\n
+Arguments:
+    This subcommand accepts a BPF program and prints the sizes of its `.text`,
+    `.rodata`, `.data` and `.bss` sections and its total size, as counted by
+    the `llvm-size` of the configured LLVM. The report is written to
+    `build/size-report.json`, pass an earlier one to `--compare` to see how
+    much each section grew or shrank. For example:
+
+        ./x.py size-report target/deploy/program.so
+        ./x.py size-report target/deploy/program.so --compare before.json",
+                );
+            }
+            "show-config" => {
+                subcommand_help.push_str(
+                    "\n
+Arguments:
+    This subcommand accepts no arguments. It prints the C compiler, C++
+    compiler, archiver, ranlib and linker that were detected for every
+    configured host and target, as `build` would use them. For example:
+
+        ./x.py show-config --target bpfel-unknown-unknown
+        ./x.py show-config --json",
+                );
+            }
+            "list-steps" => {
+                subcommand_help.push_str(
+                    "\n
+Arguments:
+    This subcommand accepts no arguments. It prints the steps registered for
+    every subcommand that takes paths as JSON, with the paths and suites each
+    of them runs for and whether it runs when no path is given. For example:
+
+        ./x.py list-steps",
+                );
+            }
+            "profile-llvm" => {
+                subcommand_help.push_str(
+                    "\n
+Arguments:
+    This subcommand accepts no arguments. It builds LLVM instrumented for
+    profiling, compiles `src/test/bpf-smoke` for the first configured BPF
+    target with a stage 1 compiler linked to it, and then rebuilds LLVM
+    optimized with the profiles it left in `build/<host>/llvm/pgo`. LLVM
+    has to be built with clang for this. For example:
+
+        ./x.py profile-llvm --target bpfel-unknown-unknown",
+                );
+            }
             _ => {}
         };
         // Get any optional paths which occur after the subcommand
@@ -560,6 +886,14 @@ pub fn parse(args: &[String]) -> Flags {
                 rustc_args: matches.opt_strs("rustc-args"),
                 fail_fast: !matches.opt_present("no-fail-fast"),
                 rustfix_coverage: matches.opt_present("rustfix-coverage"),
+                target_kind: matches.opt_str("target-kind").map(|kind| match &kind[..] {
+                    "bpf" => TargetKind::Bpf,
+                    "host" => TargetKind::Host,
+                    _ => {
+                        println!("\n`--target-kind` should be either bpf or host\n");
+                        usage(1, &opts, verbose, &subcommand_help)
+                    }
+                }),
                 doc_tests: if matches.opt_present("doc") {
                     DocTests::Only
                 } else if matches.opt_present("no-doc") {
@@ -568,25 +902,48 @@ pub fn parse(args: &[String]) -> Flags {
                     DocTests::Yes
                 },
             },
-            "bench" => Subcommand::Bench { paths, test_args: matches.opt_strs("test-args") },
-            "doc" => Subcommand::Doc { paths, open: matches.opt_present("open") },
+            "bench" => {
+                let compute_units = matches.opt_present("compute-units");
+                // The report is written to a single file, keying it by crate
+                // as well would lift this.
+                if compute_units && paths.len() != 1 {
+                    println!("\n--compute-units benchmarks exactly one crate\n");
+                    usage(1, &opts, verbose, &subcommand_help);
+                }
+                Subcommand::Bench { paths, test_args: matches.opt_strs("test-args"), compute_units }
+            }
+            "doc" => {
+                let json = match matches.opt_str("output-format").as_deref() {
+                    None | Some("html") => false,
+                    Some("json") => true,
+                    Some(format) => {
+                        println!("\nunknown output format `{}`, expected html or json\n", format);
+                        usage(1, &opts, verbose, &subcommand_help);
+                    }
+                };
+                Subcommand::Doc { paths, open: matches.opt_present("open"), json }
+            }
             "clean" => {
                 if !paths.is_empty() {
                     println!("\nclean does not take a path argument\n");
                     usage(1, &opts, verbose, &subcommand_help);
                 }
 
-                Subcommand::Clean { all: matches.opt_present("all") }
+                let targets = split(&matches.opt_strs("target"))
+                    .iter()
+                    .map(|target| TargetSelection::from_user(target))
+                    .collect();
+                Subcommand::Clean { all: matches.opt_present("all"), targets }
             }
             "fmt" => Subcommand::Format { check: matches.opt_present("check") },
             "dist" => Subcommand::Dist { paths },
-            "install" => Subcommand::Install { paths },
+            "install" => Subcommand::Install { paths, force: matches.opt_present("force") },
             "run" | "r" => {
                 if paths.is_empty() {
                     println!("\nrun requires at least a path!\n");
                     usage(1, &opts, verbose, &subcommand_help);
                 }
-                Subcommand::Run { paths }
+                Subcommand::Run { paths, args: run_args.to_vec() }
             }
             "setup" => {
                 let profile = if paths.len() > 1 {
@@ -608,11 +965,51 @@ pub fn parse(args: &[String]) -> Flags {
                 };
                 Subcommand::Setup { profile }
             }
+            "audit-sysroot" => Subcommand::AuditSysroot { paths },
+            "doctor" => Subcommand::Doctor,
+            "repro-report" => {
+                let other_manifest = match matches.opt_str("other-manifest") {
+                    Some(path) => PathBuf::from(path),
+                    None => {
+                        println!("\nrepro-report requires --other-manifest\n");
+                        usage(1, &opts, verbose, &subcommand_help);
+                    }
+                };
+                Subcommand::ReproReport { other_manifest }
+            }
+            "verify-reproducible" => Subcommand::VerifyReproducible { paths },
+            "disasm" => {
+                if paths.is_empty() {
+                    println!("\ndisasm requires at least a path!\n");
+                    usage(1, &opts, verbose, &subcommand_help);
+                }
+                Subcommand::Disasm { paths }
+            }
+            "size-report" => {
+                if paths.len() != 1 {
+                    println!("\nsize-report requires exactly one path!\n");
+                    usage(1, &opts, verbose, &subcommand_help);
+                }
+                let compare = matches.opt_str("compare").map(PathBuf::from);
+                Subcommand::SizeReport { path: paths.pop().unwrap(), compare }
+            }
+            "show-config" => Subcommand::ShowConfig { json: matches.opt_present("json") },
+            "list-steps" => Subcommand::ListSteps,
+            "profile-llvm" => Subcommand::ProfileLlvm,
             _ => {
                 usage(1, &opts, verbose, &subcommand_help);
             }
         };
 
+        let (exclude_globs, exclude): (Vec<_>, Vec<_>) =
+            split(&matches.opt_strs("exclude")).into_iter().partition(|p| is_glob(p));
+        for pattern in &exclude_globs {
+            if let Err(e) = globset::Glob::new(pattern) {
+                println!("\ninvalid --exclude pattern: {}\n", e);
+                usage(1, &opts, verbose, &subcommand_help);
+            }
+        }
+
         if let Subcommand::Check { .. } = &cmd {
             if matches.opt_str("keep-stage").is_some()
                 || matches.opt_str("keep-stage-std").is_some()
@@ -626,9 +1023,17 @@ pub fn parse(args: &[String]) -> Flags {
             verbose: matches.opt_count("verbose"),
             stage: matches.opt_str("stage").map(|j| j.parse().expect("`stage` should be a number")),
             dry_run: matches.opt_present("dry-run"),
+            build_manifest: matches.opt_present("build-manifest"),
+            dump_step_graph: matches.opt_present("dump-step-graph"),
+            explain: matches.opt_present("explain"),
+            locked: matches.opt_present("locked"),
+            offline: matches.opt_present("offline"),
+            rebuild_tools: matches.opt_strs("rebuild-tool"),
+            time_passes: matches.opt_present("time-passes"),
             on_fail: matches.opt_str("on-fail"),
             rustc_error_format: matches.opt_str("error-format"),
             json_output: matches.opt_present("json-output"),
+            strict: matches.opt_present("strict"),
             keep_stage: matches
                 .opt_strs("keep-stage")
                 .into_iter()
@@ -660,13 +1065,12 @@ pub fn parse(args: &[String]) -> Flags {
                 None
             },
             config: cfg_file,
+            build_profile: matches.opt_str("profile"),
             jobs: matches.opt_str("jobs").map(|j| j.parse().expect("`jobs` should be a number")),
             cmd,
             incremental: matches.opt_present("incremental"),
-            exclude: split(&matches.opt_strs("exclude"))
-                .into_iter()
-                .map(|p| p.into())
-                .collect::<Vec<_>>(),
+            exclude: exclude.into_iter().map(|p| p.into()).collect::<Vec<_>>(),
+            exclude_globs,
             include_default_paths: matches.opt_present("include-default-paths"),
             deny_warnings: parse_deny_warnings(&matches),
             llvm_skip_rebuild: matches.opt_str("llvm-skip-rebuild").map(|s| s.to_lowercase()).map(
@@ -677,6 +1081,9 @@ pub fn parse(args: &[String]) -> Flags {
                 .expect("`color` should be `always`, `never`, or `auto`"),
             rust_profile_use: matches.opt_str("rust-profile-use"),
             rust_profile_generate: matches.opt_str("rust-profile-generate"),
+            bpf_stack_size: matches
+                .opt_str("bpf-stack-size")
+                .map(|s| s.parse().expect("`bpf-stack-size` should be a number")),
         }
     }
 }
@@ -691,6 +1098,13 @@ pub fn test_args(&self) -> Vec<&str> {
         }
     }
 
+    pub fn run_args(&self) -> &[String] {
+        match *self {
+            Subcommand::Run { ref args, .. } => args,
+            _ => &[],
+        }
+    }
+
     pub fn rustc_args(&self) -> Vec<&str> {
         match *self {
             Subcommand::Test { ref rustc_args, .. } => {
@@ -700,6 +1114,20 @@ pub fn rustc_args(&self) -> Vec<&str> {
         }
     }
 
+    pub fn force_install(&self) -> bool {
+        match *self {
+            Subcommand::Install { force, .. } => force,
+            _ => false,
+        }
+    }
+
+    pub fn compute_units(&self) -> bool {
+        match *self {
+            Subcommand::Bench { compute_units, .. } => compute_units,
+            _ => false,
+        }
+    }
+
     pub fn fail_fast(&self) -> bool {
         match *self {
             Subcommand::Test { fail_fast, .. } => fail_fast,
@@ -728,6 +1156,13 @@ pub fn rustfix_coverage(&self) -> bool {
         }
     }
 
+    pub fn target_kind(&self) -> Option<TargetKind> {
+        match *self {
+            Subcommand::Test { target_kind, .. } => target_kind,
+            _ => None,
+        }
+    }
+
     pub fn compare_mode(&self) -> Option<&str> {
         match *self {
             Subcommand::Test { ref compare_mode, .. } => compare_mode.as_ref().map(|s| &s[..]),
@@ -748,6 +1183,38 @@ pub fn open(&self) -> bool {
             _ => false,
         }
     }
+
+    pub fn json(&self) -> bool {
+        match *self {
+            Subcommand::Doc { json, .. } => json,
+            _ => false,
+        }
+    }
+}
+
+/// Flags `x.py` doesn't take (anymore) but that turn up in old instructions,
+/// along with what to do instead. getopts would only call them unknown.
+const RETIRED_FLAGS: &[(&str, &str)] = &[
+    ("--full-bootstrap", "set `build.full-bootstrap = true` in config.toml instead"),
+    ("--nocapture", "pass it to the test harness with `--test-args --nocapture` instead"),
+    ("--stage0", "use `--stage 0` instead"),
+];
+
+/// Returns the message for the first retired flag in `args`, if any. Anything
+/// after `--` is left alone, it's not for `x.py` to interpret.
+fn retired_flag(args: &[String]) -> Option<String> {
+    args.iter().take_while(|arg| *arg != "--").find_map(|arg| {
+        let name = arg.split('=').next().unwrap();
+        RETIRED_FLAGS
+            .iter()
+            .find(|(flag, _)| *flag == name)
+            .map(|(flag, help)| format!("`{}` is no longer supported, {}", flag, help))
+    })
+}
+
+/// Returns whether an `--exclude` value is a glob pattern rather than a path.
+fn is_glob(path: &str) -> bool {
+    path.contains(|c| matches!(c, '*' | '?' | '[' | '{'))
 }
 
 fn split(s: &[String]) -> Vec<String> {
@@ -765,3 +1232,6 @@ fn parse_deny_warnings(matches: &getopts::Matches) -> Option<bool> {
         None => None,
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,22 @@
+use super::*;
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn retired_flags() {
+    assert_eq!(retired_flag(&args(&["test", "library/core", "--stage", "1"])), None);
+    assert_eq!(
+        retired_flag(&args(&["test", "--nocapture", "library/core"])),
+        Some(
+            "`--nocapture` is no longer supported, pass it to the test harness with \
+             `--test-args --nocapture` instead"
+                .to_string()
+        )
+    );
+    let message = retired_flag(&args(&["build", "--full-bootstrap=true"])).unwrap();
+    assert!(message.starts_with("`--full-bootstrap` is no longer supported"), "{}", message);
+    // Arguments after `--` aren't for x.py.
+    assert_eq!(retired_flag(&args(&["test", "--", "--nocapture"])), None);
+}
@@ -5,8 +5,8 @@
 
 use std::env;
 use std::fs;
-use std::path::{Component, PathBuf};
-use std::process::Command;
+use std::path::{Component, Path, PathBuf};
+use std::process::{self, Command};
 
 use build_helper::t;
 
@@ -15,7 +15,10 @@
 use crate::Compiler;
 
 use crate::builder::{Builder, RunConfig, ShouldRun, Step};
-use crate::config::{Config, TargetSelection};
+use crate::config::{Config, OnConflict, TargetSelection};
+
+#[cfg(test)]
+mod tests;
 
 fn install_sh(
     builder: &Builder<'_>,
@@ -34,6 +37,31 @@ fn install_sh(
     let libdir = prefix.join(default_path(&builder.config.libdir, "lib"));
     let bindir = prefix.join(&builder.config.bindir); // Default in config.rs
 
+    let install_prefix = PathBuf::from(prepare_dir(prefix.clone()));
+    let force = builder.config.cmd.force_install();
+    let owner = prefix_owner(&install_prefix);
+    if let Err(e) = check_sudo_install(&install_prefix, owner, builder.is_sudo, force) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+
+    if builder.config.install_on_conflict != OnConflict::Overwrite && !builder.config.dry_run {
+        let dirs = InstallDirs {
+            prefix: install_dir(prefix.clone()),
+            sysconfdir: install_dir(sysconfdir.clone()),
+            datadir: install_dir(datadir.clone()),
+            docdir: install_dir(docdir.clone()),
+            mandir: install_dir(mandir.clone()),
+            libdir: install_dir(libdir.clone()),
+            bindir: install_dir(bindir.clone()),
+        };
+        let image = tarball.decompressed_output();
+        if let Err(e) = make_room_for_image(image, &dirs, builder.config.install_on_conflict) {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+
     let empty_dir = builder.out.join("tmp/empty_dir");
     t!(fs::create_dir_all(&empty_dir));
 
@@ -52,11 +80,172 @@ fn install_sh(
     t!(fs::remove_dir_all(&empty_dir));
 }
 
+/// Refuses to install as root into a prefix that belongs to somebody else,
+/// like `~/.local`. The installed files would be owned by root, so the user
+/// couldn't update or remove them anymore.
+fn check_sudo_install(
+    prefix: &Path,
+    owner: Option<u32>,
+    is_sudo: bool,
+    force: bool,
+) -> Result<(), String> {
+    match owner {
+        Some(uid) if is_sudo && uid != 0 && !force => Err(format!(
+            "refusing to install into `{}` under sudo: it is owned by user {}, but the \
+             installed files would be owned by root. Run `x.py install` without sudo, or \
+             pass `--force` to install anyway",
+            prefix.display(),
+            uid
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the owner of `path`, or of its closest ancestor that exists.
+#[cfg(unix)]
+fn prefix_owner(path: &Path) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    path.ancestors().find_map(|dir| fs::metadata(dir).ok()).map(|meta| meta.uid())
+}
+
+#[cfg(not(unix))]
+fn prefix_owner(_path: &Path) -> Option<u32> {
+    None
+}
+
+/// Clears the way for `src` to be installed as `dst`, following `on_conflict`
+/// if `dst` already exists with different contents.
+///
+/// Returns an error if `on_conflict` forbids replacing `dst`.
+pub(crate) fn make_room(src: &Path, dst: &Path, on_conflict: OnConflict) -> Result<(), String> {
+    if on_conflict == OnConflict::Overwrite {
+        drop(fs::remove_file(dst));
+        return Ok(());
+    }
+    let existing = match fs::read(dst) {
+        Ok(existing) => existing,
+        // Whatever is there can't be read as a file, it's not an installed
+        // file somebody could have edited.
+        Err(_) => {
+            drop(fs::remove_file(dst));
+            return Ok(());
+        }
+    };
+    if fs::read(src).map_or(false, |new| new == existing) {
+        drop(fs::remove_file(dst));
+        return Ok(());
+    }
+
+    if on_conflict == OnConflict::Error {
+        return Err(format!(
+            "`{}` already exists and differs from `{}`, refusing to overwrite it \
+             (see `install.on-conflict` in config.toml)",
+            dst.display(),
+            src.display()
+        ));
+    }
+    let name = dst.file_name().unwrap().to_str().unwrap();
+    let mut backup = dst.with_file_name(format!("{}.old", name));
+    let mut n = 0;
+    while backup.exists() {
+        n += 1;
+        backup = dst.with_file_name(format!("{}.old.{}", name, n));
+    }
+    fs::rename(dst, &backup).map_err(|e| format!("failed to back up `{}`: {}", dst.display(), e))
+}
+
+/// The directories `install.sh` puts the files of an image into, after
+/// `DESTDIR` has been applied.
+struct InstallDirs {
+    prefix: PathBuf,
+    sysconfdir: PathBuf,
+    datadir: PathBuf,
+    docdir: PathBuf,
+    mandir: PathBuf,
+    libdir: PathBuf,
+    bindir: PathBuf,
+}
+
+impl InstallDirs {
+    /// Returns where `install.sh` installs `file`, a path relative to the
+    /// component in the image. This mirrors the mapping in rust-installer's
+    /// `install-template.sh`.
+    fn install_path(&self, file: &Path) -> PathBuf {
+        if let Ok(rest) = file.strip_prefix("etc") {
+            self.sysconfdir.join(rest)
+        } else if let Ok(rest) = file.strip_prefix("bin") {
+            self.bindir.join(rest)
+        } else if let Ok(rest) = file.strip_prefix("lib") {
+            self.libdir.join(rest)
+        } else if let Ok(rest) = file.strip_prefix("share/man") {
+            self.mandir.join(rest)
+        } else if let Ok(rest) = file.strip_prefix("share/doc") {
+            // `share/doc/<product>/` is what `--docdir` replaces.
+            self.docdir.join(rest.components().skip(1).collect::<PathBuf>())
+        } else if let Ok(rest) = file.strip_prefix("share") {
+            self.datadir.join(rest)
+        } else {
+            self.prefix.join(file)
+        }
+    }
+}
+
+/// Applies `on_conflict` to every file of the rust-installer image in `image`
+/// that already exists where `install.sh` is about to put it.
+///
+/// The image lists its components in `components`, and each component lists
+/// its files and directories in `<component>/manifest.in`.
+fn make_room_for_image(
+    image: &Path,
+    dirs: &InstallDirs,
+    on_conflict: OnConflict,
+) -> Result<(), String> {
+    let read = |path: &Path| {
+        fs::read_to_string(path).map_err(|e| format!("failed to read `{}`: {}", path.display(), e))
+    };
+    for component in read(&image.join("components"))?.lines().filter(|c| !c.is_empty()) {
+        let component_dir = image.join(component);
+        for entry in read(&component_dir.join("manifest.in"))?.lines() {
+            let mut files = Vec::new();
+            if let Some(file) = entry.strip_prefix("file:") {
+                files.push(PathBuf::from(file));
+            } else if let Some(dir) = entry.strip_prefix("dir:") {
+                image_files(&component_dir, Path::new(dir), &mut files)?;
+            }
+            for file in files {
+                make_room(&component_dir.join(&file), &dirs.install_path(&file), on_conflict)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Collects the files below `dir`, relative to `component_dir`, into `files`.
+fn image_files(component_dir: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let path = component_dir.join(dir);
+    let entries =
+        fs::read_dir(&path).map_err(|e| format!("failed to read `{}`: {}", path.display(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let file = dir.join(entry.file_name());
+        if entry.file_type().map_err(|e| e.to_string())?.is_dir() {
+            image_files(component_dir, &file, files)?;
+        } else {
+            files.push(file);
+        }
+    }
+    Ok(())
+}
+
 fn default_path(config: &Option<PathBuf>, default: &str) -> PathBuf {
     config.as_ref().cloned().unwrap_or_else(|| PathBuf::from(default))
 }
 
-fn prepare_dir(mut path: PathBuf) -> String {
+fn prepare_dir(path: PathBuf) -> String {
+    sanitize_sh(&install_dir(path))
+}
+
+fn install_dir(mut path: PathBuf) -> PathBuf {
     // The DESTDIR environment variable is a standard way to install software in a subdirectory
     // while keeping the original directory structure, even if the prefix or other directories
     // contain absolute paths.
@@ -82,7 +271,7 @@ fn prepare_dir(mut path: PathBuf) -> String {
         assert!(path.is_absolute(), "could not make the path relative");
     }
 
-    sanitize_sh(&path)
+    path
 }
 
 macro_rules! install {
@@ -0,0 +1,148 @@
+use super::*;
+use crate::util::test_dir;
+
+/// Creates a fresh directory holding `src`, the file being installed, and an
+/// edited copy of it already at `dst`.
+fn conflict() -> (PathBuf, PathBuf, PathBuf) {
+    let dir = test_dir();
+    t!(fs::create_dir_all(dir.join("prefix")));
+    let src = dir.join("rust-gdb");
+    let dst = dir.join("prefix").join("rust-gdb");
+    t!(fs::write(&src, "new"));
+    t!(fs::write(&dst, "edited"));
+    (dir, src, dst)
+}
+
+#[test]
+fn overwrite() {
+    let (dir, src, dst) = conflict();
+    assert_eq!(make_room(&src, &dst, OnConflict::Overwrite), Ok(()));
+    assert!(!dst.exists());
+    assert_eq!(t!(fs::read_dir(dir.join("prefix"))).count(), 0);
+
+    // Neither file is read, they're never compared.
+    t!(fs::write(&dst, "edited"));
+    t!(fs::remove_file(&src));
+    assert_eq!(make_room(&src, &dst, OnConflict::Overwrite), Ok(()));
+    assert!(!dst.exists());
+}
+
+#[test]
+fn backup() {
+    let (dir, src, dst) = conflict();
+    assert_eq!(make_room(&src, &dst, OnConflict::Backup), Ok(()));
+    assert!(!dst.exists());
+    assert_eq!(t!(fs::read_to_string(dir.join("prefix/rust-gdb.old"))), "edited");
+
+    t!(fs::write(&dst, "edited again"));
+    assert_eq!(make_room(&src, &dst, OnConflict::Backup), Ok(()));
+    assert_eq!(t!(fs::read_to_string(dir.join("prefix/rust-gdb.old"))), "edited");
+    assert_eq!(t!(fs::read_to_string(dir.join("prefix/rust-gdb.old.1"))), "edited again");
+}
+
+#[test]
+fn error() {
+    let (_dir, src, dst) = conflict();
+    assert!(make_room(&src, &dst, OnConflict::Error).is_err());
+    assert_eq!(t!(fs::read_to_string(&dst)), "edited");
+
+    // Reinstalling an unchanged file isn't a conflict.
+    t!(fs::write(&dst, "new"));
+    assert_eq!(make_room(&src, &dst, OnConflict::Error), Ok(()));
+    assert!(!dst.exists());
+}
+
+/// Creates a rust-installer image of a `rustc` component with the given
+/// `manifest.in`, and the directories it's installed into.
+fn image(files: &[(&str, &str)], manifest: &str) -> (PathBuf, InstallDirs) {
+    let dir = test_dir();
+    let image = dir.join("image");
+    t!(fs::create_dir_all(image.join("rustc")));
+    t!(fs::write(image.join("components"), "rustc\n"));
+    t!(fs::write(image.join("rustc/manifest.in"), manifest));
+    for (file, contents) in files {
+        let path = image.join("rustc").join(file);
+        t!(fs::create_dir_all(path.parent().unwrap()));
+        t!(fs::write(path, contents));
+    }
+    let prefix = dir.join("prefix");
+    let dirs = InstallDirs {
+        sysconfdir: dir.join("etc"),
+        datadir: prefix.join("share"),
+        docdir: prefix.join("share/doc/rust"),
+        mandir: prefix.join("share/man"),
+        libdir: prefix.join("lib64"),
+        bindir: prefix.join("bin"),
+        prefix,
+    };
+    (image, dirs)
+}
+
+#[test]
+fn install_paths() {
+    let (_image, dirs) = image(&[], "");
+    let dir = dirs.prefix.parent().unwrap();
+    let installed = |file| dirs.install_path(Path::new(file)).strip_prefix(dir).unwrap().to_owned();
+    assert_eq!(installed("bin/rustc"), Path::new("prefix/bin/rustc"));
+    assert_eq!(installed("lib/libstd.so"), Path::new("prefix/lib64/libstd.so"));
+    assert_eq!(installed("etc/bash_completion.d/cargo"), Path::new("etc/bash_completion.d/cargo"));
+    assert_eq!(installed("share/man/man1/rustc.1"), Path::new("prefix/share/man/man1/rustc.1"));
+    assert_eq!(
+        installed("share/doc/rustc/README.md"),
+        Path::new("prefix/share/doc/rust/README.md")
+    );
+    assert_eq!(installed("share/zsh/_cargo"), Path::new("prefix/share/zsh/_cargo"));
+    assert_eq!(installed("README.md"), Path::new("prefix/README.md"));
+}
+
+#[test]
+fn image_backup() {
+    let (image, dirs) = image(
+        &[("bin/rustc", "new"), ("lib/rustlib/etc/gdb_load.py", "new")],
+        "file:bin/rustc\ndir:lib/rustlib\n",
+    );
+    t!(fs::create_dir_all(&dirs.bindir));
+    t!(fs::create_dir_all(dirs.libdir.join("rustlib/etc")));
+    t!(fs::write(dirs.bindir.join("rustc"), "edited"));
+    t!(fs::write(dirs.libdir.join("rustlib/etc/gdb_load.py"), "edited"));
+
+    assert_eq!(make_room_for_image(&image, &dirs, OnConflict::Backup), Ok(()));
+    assert!(!dirs.bindir.join("rustc").exists());
+    assert_eq!(t!(fs::read_to_string(dirs.bindir.join("rustc.old"))), "edited");
+    assert_eq!(t!(fs::read_to_string(dirs.libdir.join("rustlib/etc/gdb_load.py.old"))), "edited");
+}
+
+#[test]
+fn image_error() {
+    let (image, dirs) = image(&[("bin/rustc", "new"), ("bin/rustdoc", "new")], "dir:bin\n");
+    t!(fs::create_dir_all(&dirs.bindir));
+    t!(fs::write(dirs.bindir.join("rustdoc"), "new"));
+    assert_eq!(make_room_for_image(&image, &dirs, OnConflict::Error), Ok(()));
+    assert!(!dirs.bindir.join("rustdoc").exists());
+
+    t!(fs::write(dirs.bindir.join("rustc"), "edited"));
+    let err = make_room_for_image(&image, &dirs, OnConflict::Error).unwrap_err();
+    assert!(err.contains("rustc"), "{}", err);
+    assert_eq!(t!(fs::read_to_string(dirs.bindir.join("rustc"))), "edited");
+}
+
+#[test]
+fn sudo_user_prefix() {
+    let prefix = Path::new("/home/user/.local");
+    let err = check_sudo_install(prefix, Some(1000), true, false).unwrap_err();
+    assert!(err.contains("/home/user/.local") && err.contains("--force"), "{}", err);
+
+    assert_eq!(check_sudo_install(prefix, Some(1000), true, true), Ok(()));
+    assert_eq!(check_sudo_install(prefix, Some(1000), false, false), Ok(()));
+    assert_eq!(check_sudo_install(Path::new("/usr/local"), Some(0), true, false), Ok(()));
+}
+
+#[cfg(unix)]
+#[test]
+fn prefix_owner_of_missing_dir() {
+    use std::os::unix::fs::MetadataExt;
+
+    let (dir, _src, _dst) = conflict();
+    let uid = t!(fs::metadata(&dir)).uid();
+    assert_eq!(prefix_owner(&dir.join("prefix/not/there/yet")), Some(uid));
+}
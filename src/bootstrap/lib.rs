@@ -104,26 +104,35 @@
 //! also check out the `src/bootstrap/README.md` file for more information.
 
 use std::cell::{Cell, RefCell};
+use std::cmp;
 use std::collections::{HashMap, HashSet};
 use std::env;
-use std::fs::{self, File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use std::process::{self, Command};
+use std::process::{self, Command, ExitStatus, Stdio};
 use std::slice;
 use std::str;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 #[cfg(unix)]
 use std::os::unix::fs::symlink as symlink_file;
 #[cfg(windows)]
 use std::os::windows::fs::symlink_file;
 
-use build_helper::{mtime, output, run, run_suppressed, t, try_run, try_run_suppressed};
+use build_helper::{
+    output, run, run_suppressed, t, try_run, try_run_detailed, try_run_suppressed, RunOutcome,
+};
 use filetime::FileTime;
+use serde::{Deserialize, Serialize};
 
 use crate::config::{LlvmLibunwind, TargetSelection};
-use crate::util::{exe, libdir, CiEnv};
+use crate::util::{exe, libdir, relative_path, symlink_dir, CiEnv};
 
+mod audit;
 mod builder;
 mod cache;
 mod cc_detect;
@@ -134,20 +143,28 @@
 mod config;
 mod dist;
 mod doc;
+mod doctor;
 mod flags;
 mod format;
 mod install;
+mod manifest;
 mod metadata;
 mod native;
+mod relocate;
 mod run;
 mod sanity;
 mod setup;
+mod show_config;
+mod size_report;
 mod tarball;
 mod test;
 mod tool;
 mod toolstate;
 pub mod util;
 
+#[cfg(test)]
+mod tests;
+
 #[cfg(windows)]
 mod job;
 
@@ -181,12 +198,37 @@ pub unsafe fn setup(_build: &mut crate::Build) {}
     "llvm-ar",       // used for creating and modifying archive files
     "llvm-as",       // used to convert LLVM assembly to LLVM bitcode
     "llvm-dis",      // used to disassemble LLVM bitcode
+    "llvm-mc",       // used to assemble and disassemble machine code, e.g. SBF object files
     "llc",           // used to compile LLVM bytecode
     "opt",           // used to optimize LLVM bytecode
 ];
 
+/// Whether an optional in-tree crate is included, see `OPTIONAL_CRATES`.
+type CratePredicate = fn(&Build, Option<TargetSelection>) -> bool;
+
+/// In-tree crates that `in_tree_crates` only includes if their predicate holds,
+/// given the target being built for if there is one. Ideally this would be
+/// computed from `cargo metadata --features …`, but that is somewhat slow.
+const OPTIONAL_CRATES: &[(&str, CratePredicate)] = &[
+    // There aren't any operations we want to perform on it. In the future, we
+    // may want to consider just filtering all build and dev dependencies in
+    // metadata::build.
+    ("build_helper", |_, _| false),
+    ("profiler_builtins", |build, target| match target {
+        Some(target) => build.config.profiler_enabled(target),
+        None => build.config.any_profiler_enabled(),
+    }),
+    ("rustc_codegen_llvm", |build, _| build.config.llvm_enabled()),
+];
+
+/// Default cap on `RUST_TEST_THREADS` for remote-tested targets.
+const REMOTE_TEST_THREADS: u32 = 2;
+
 pub const VERSION: usize = 2;
 
+/// Beta number used when the merges since master can't be counted.
+const UNKNOWN_BETA_PRERELEASE_VERSION: u32 = 0;
+
 /// A structure representing a Rust compiler.
 ///
 /// Each compiler has a `stage` that it is associated with and a `host` that
@@ -208,6 +250,23 @@ pub enum DocTests {
     Only,
 }
 
+/// What a test step runs against, for `x.py test --target-kind`.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum TargetKind {
+    Bpf,
+    Host,
+}
+
+impl TargetKind {
+    pub fn of(target: TargetSelection) -> TargetKind {
+        if target.contains("bpf") {
+            TargetKind::Bpf
+        } else {
+            TargetKind::Host
+        }
+    }
+}
+
 pub enum GitRepo {
     Rustc,
     Llvm,
@@ -263,6 +322,7 @@ pub struct Build {
     cxx: HashMap<TargetSelection, cc::Tool>,
     ar: HashMap<TargetSelection, PathBuf>,
     ranlib: HashMap<TargetSelection, PathBuf>,
+    cc_version: HashMap<TargetSelection, cc_detect::CcVersion>,
     // Miscellaneous
     crates: HashMap<Interned<String>, Crate>,
     is_sudo: bool,
@@ -271,9 +331,20 @@ pub struct Build {
     prerelease_version: Cell<Option<u32>>,
     tool_artifacts:
         RefCell<HashMap<TargetSelection, HashMap<String, (&'static str, PathBuf, Vec<String>)>>>,
+    /// Stamp files of the libraries linked into a sysroot, for `--build-manifest`
+    /// and `x.py verify-reproducible`.
+    sysroot_stamps: RefCell<Vec<(TargetSelection, PathBuf)>>,
+    /// Paths returned by `llvm_filecheck`, which may have run `llvm-config`.
+    llvm_filecheck: RefCell<HashMap<TargetSelection, PathBuf>>,
+    /// Tarballs generated by dist steps, for `components.sha256`.
+    dist_tarballs: RefCell<Vec<PathBuf>>,
+    /// Set while `x.py profile-llvm` builds LLVM for one of its phases.
+    llvm_pgo: Option<native::PgoPhase>,
+    /// Results of `target_endianness`, which runs the initial rustc.
+    target_endianness: RefCell<HashMap<TargetSelection, Endian>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 struct Crate {
     name: Interned<String>,
     deps: HashSet<Interned<String>>,
@@ -288,7 +359,8 @@ fn local_path(&self, build: &Build) -> PathBuf {
 }
 
 /// When building Rust various objects are handled differently.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum DependencyType {
     /// Libraries originating from proc-macros.
     Host,
@@ -298,6 +370,83 @@ pub enum DependencyType {
     TargetSelfContained,
 }
 
+/// Byte order of a target, as returned by `Build::target_endianness`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+/// State of a submodule, from the first character `git submodule status`
+/// prints for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmoduleStatus {
+    Uninitialized,
+    /// Checked out at a commit other than the one recorded in the index.
+    OutOfDate,
+    Conflicted,
+    UpToDate,
+}
+
+fn parse_submodule_status(output: &str) -> Option<SubmoduleStatus> {
+    match output.chars().next()? {
+        '-' => Some(SubmoduleStatus::Uninitialized),
+        '+' => Some(SubmoduleStatus::OutOfDate),
+        'U' => Some(SubmoduleStatus::Conflicted),
+        ' ' => Some(SubmoduleStatus::UpToDate),
+        _ => None,
+    }
+}
+
+/// Counts the merge commits that happened since `src` branched off master.
+/// That's our beta number! Returns `None` for a shallow clone, where that
+/// history is cut off and the count would be meaningless.
+fn beta_merge_count(src: &Path) -> Option<u32> {
+    let shallow = output(
+        Command::new("git").arg("rev-parse").arg("--is-shallow-repository").current_dir(src),
+    );
+    if shallow.trim() == "true" {
+        return None;
+    }
+
+    // Note that we use a `..` range, not the `...` symmetric difference.
+    let count = output(
+        Command::new("git")
+            .arg("rev-list")
+            .arg("--count")
+            .arg("--merges")
+            .arg("refs/remotes/origin/master..HEAD")
+            .current_dir(src),
+    );
+    Some(count.trim().parse().unwrap())
+}
+
+/// Reads the version of the package whose manifest is `toml_file_name`. A
+/// version inherited with `version.workspace = true` is read from the
+/// `[workspace.package]` of the closest manifest above with a `[workspace]`.
+fn package_version(toml_file_name: &Path) -> Option<String> {
+    let parse = |toml: &str| toml.parse::<toml::Value>().ok();
+    let package = parse(&t!(fs::read_to_string(toml_file_name)))?;
+    let version = package.get("package")?.get("version")?;
+    if let Some(version) = version.as_str() {
+        return Some(version.to_owned());
+    }
+    if version.get("workspace")?.as_bool() != Some(true) {
+        return None;
+    }
+
+    for dir in toml_file_name.parent()?.ancestors() {
+        let root = match fs::read_to_string(dir.join("Cargo.toml")) {
+            Ok(root) => parse(&root)?,
+            Err(_) => continue,
+        };
+        if let Some(workspace) = root.get("workspace") {
+            return Some(workspace.get("package")?.get("version")?.as_str()?.to_owned());
+        }
+    }
+    None
+}
+
 /// The various "modes" of invoking Cargo.
 ///
 /// These entries currently correspond to the various output directories of the
@@ -329,15 +478,21 @@ pub enum Mode {
     /// anything that needs a fully functional rustc, such as rustdoc, clippy,
     /// cargo, rls, rustfmt, miri, etc.
     ToolRustc,
+
+    /// Build a tool for a BPF target with the locally built rustc and BPF std,
+    /// placing the output in the "stageN-tools-bpf" directory. This is used
+    /// for helper crates that run on-chain rather than on the host.
+    ToolBpf,
 }
 
 impl Mode {
     pub fn is_tool(&self) -> bool {
-        matches!(self, Mode::ToolBootstrap | Mode::ToolRustc | Mode::ToolStd)
+        matches!(self, Mode::ToolBootstrap | Mode::ToolRustc | Mode::ToolStd | Mode::ToolBpf)
     }
 
     pub fn must_support_dlopen(&self) -> bool {
-        matches!(self, Mode::Std | Mode::Codegen)
+        // BPF programs are shared objects loaded by the runtime.
+        matches!(self, Mode::Std | Mode::Codegen | Mode::ToolBpf)
     }
 }
 
@@ -390,14 +545,17 @@ pub fn new(config: Config) -> Build {
         } else {
             output(Command::new(&config.initial_rustc).arg("--print").arg("sysroot"))
         };
-        let initial_libdir = initial_target_dir
-            .parent()
-            .unwrap()
-            .parent()
-            .unwrap()
-            .strip_prefix(initial_sysroot.trim())
-            .unwrap()
-            .to_path_buf();
+        let initial_rustlib = initial_target_dir.parent().unwrap().parent().unwrap();
+        let initial_libdir =
+            Build::relativize_for_sysroot(initial_rustlib, Path::new(initial_sysroot.trim()))
+                .unwrap_or_else(|| {
+                    panic!(
+                        "`{}` is not inside the sysroot of `{}`, `{}`",
+                        initial_rustlib.display(),
+                        config.initial_rustc.display(),
+                        initial_sysroot.trim()
+                    )
+                });
 
         let version = std::fs::read_to_string(src.join("src").join("version"))
             .expect("failed to read src/version");
@@ -434,18 +592,32 @@ pub fn new(config: Config) -> Build {
             cxx: HashMap::new(),
             ar: HashMap::new(),
             ranlib: HashMap::new(),
+            cc_version: HashMap::new(),
             crates: HashMap::new(),
             is_sudo,
             ci_env: CiEnv::current(),
             delayed_failures: RefCell::new(Vec::new()),
             prerelease_version: Cell::new(None),
             tool_artifacts: Default::default(),
+            sysroot_stamps: Default::default(),
+            llvm_filecheck: Default::default(),
+            dist_tarballs: Default::default(),
+            llvm_pgo: None,
+            target_endianness: Default::default(),
         };
 
         build.verbose("finding compilers");
         cc_detect::find(&mut build);
-        build.verbose("running sanity check");
-        sanity::check(&mut build);
+        // `x.py doctor` reports missing tools itself rather than stopping at
+        // the first one, `x.py show-config` is how to find out which ones.
+        // `x.py list-steps` doesn't run anything.
+        if !matches!(
+            build.config.cmd,
+            Subcommand::Doctor | Subcommand::ShowConfig { .. } | Subcommand::ListSteps
+        ) {
+            build.verbose("running sanity check");
+            sanity::check(&mut build);
+        }
 
         // If local-rust is the same major.minor as the current version, then force a
         // local-rebuild
@@ -482,14 +654,59 @@ pub fn build(&mut self) {
             return format::format(self, check);
         }
 
-        if let Subcommand::Clean { all } = self.config.cmd {
-            return clean::clean(self, all);
+        if let Subcommand::Clean { all, ref targets } = self.config.cmd {
+            if targets.is_empty() {
+                return clean::clean(self, all);
+            }
+            for &target in targets {
+                if let Err(e) = self.clean_target(target, all) {
+                    eprintln!("error: {}", e);
+                    process::exit(1);
+                }
+            }
+            return;
         }
 
         if let Subcommand::Setup { profile } = &self.config.cmd {
             return setup::setup(&self.config.src, *profile);
         }
 
+        if let Subcommand::AuditSysroot { paths } = &self.config.cmd {
+            return audit::audit_sysroot(self, paths);
+        }
+
+        if let Subcommand::Doctor = self.config.cmd {
+            return doctor::doctor(self);
+        }
+
+        if let Subcommand::ReproReport { other_manifest } = &self.config.cmd {
+            return manifest::repro_report(self, other_manifest);
+        }
+
+        if let Subcommand::VerifyReproducible { .. } = self.config.cmd {
+            return manifest::verify_reproducible(self);
+        }
+
+        if let Subcommand::Disasm { paths } = &self.config.cmd {
+            return run::disasm(self, paths);
+        }
+
+        if let Subcommand::SizeReport { path, compare } = &self.config.cmd {
+            return size_report::size_report(self, path, compare.as_deref());
+        }
+
+        if let Subcommand::ShowConfig { json } = self.config.cmd {
+            return show_config::show_config(self, json);
+        }
+
+        if let Subcommand::ListSteps = self.config.cmd {
+            return self.print_step_tree();
+        }
+
+        if let Subcommand::ProfileLlvm = self.config.cmd {
+            return native::profile_llvm(self);
+        }
+
         {
             let builder = builder::Builder::new(&self);
             if let Some(path) = builder.paths.get(0) {
@@ -508,9 +725,13 @@ pub fn build(&mut self) {
             self.config.dry_run = false;
             let builder = builder::Builder::new(&self);
             builder.execute_cli();
+            builder.print_step_summary();
+            builder.print_cache_stats();
         } else {
             let builder = builder::Builder::new(&self);
             builder.execute_cli();
+            builder.print_step_graph();
+            builder.print_explanations();
         }
 
         // Check for postponed failures from `test --no-fail-fast`.
@@ -522,15 +743,85 @@ pub fn build(&mut self) {
             }
             process::exit(1);
         }
+
+        if self.config.build_manifest && !self.config.dry_run {
+            manifest::write_build_manifest(self);
+        }
+
+        if !self.dist_tarballs.borrow().is_empty() {
+            dist::write_components_listing(self);
+        }
+
+        if self.config.versioned_docs {
+            doc::link_latest_docs(self);
+        }
+    }
+
+    /// Implementation of `x.py list-steps`, for editors and other frontends
+    /// to find out what can be built without parsing the help of `x.py`.
+    fn print_step_tree(&self) {
+        println!("{}", t!(serde_json::to_string_pretty(&builder::Builder::step_tree(self))));
+    }
+
+    /// Remembers that the files listed in `stamp` were linked into the sysroot
+    /// for `target`, so they end up in the build manifest or are compared by
+    /// `x.py verify-reproducible`.
+    fn record_sysroot_stamp(&self, target: TargetSelection, stamp: &Path) {
+        let verifying = matches!(self.config.cmd, Subcommand::VerifyReproducible { .. });
+        if (self.config.build_manifest || verifying) && !self.config.dry_run {
+            self.sysroot_stamps.borrow_mut().push((target, stamp.to_path_buf()));
+        }
+    }
+
+    /// Checks every file of the stage0 toolchain that bootstrap.py extracted
+    /// against the sha256 it recorded in `stage0/.rustc-sha256`, so that a
+    /// truncated or damaged stage0 fails here instead of as some confusing
+    /// error halfway through the build.
+    ///
+    /// A toolchain from outside the build directory, i.e. a locally installed
+    /// one configured with `build.rustc`, isn't checked.
+    pub fn verify_artifact_hashes(&self) {
+        let stage0 = self.out.join(&*self.build.triple).join("stage0");
+        if !self.initial_rustc.starts_with(&stage0) {
+            return;
+        }
+        let sums = match fs::read_to_string(stage0.join(".rustc-sha256")) {
+            Ok(sums) => sums,
+            // Extracted before bootstrap.py recorded any hashes.
+            Err(_) => return,
+        };
+        self.verbose(&format!("verifying the sha256 of the files in {}", stage0.display()));
+        let corrupt = sanity::corrupt_stage0_files(&stage0, &sums);
+        if !corrupt.is_empty() {
+            eprintln!(
+                "error: the stage0 toolchain in `{}` is corrupt, these files are missing \
+                 or don't match the sha256 recorded when it was downloaded:",
+                stage0.display()
+            );
+            for file in corrupt {
+                eprintln!("    {}", file);
+            }
+            eprintln!(
+                "help: delete `{}` and `{}` to download it again",
+                stage0.display(),
+                self.out.join("cache").display()
+            );
+            process::exit(1);
+        }
     }
 
     /// Clear out `dir` if `input` is newer.
     ///
     /// After this executes, it will also ensure that `dir` exists.
+    ///
+    /// With `build.hash-stamps` the stamp records the hash of `input` and
+    /// `dir` is only cleared if that changed, see `util::stamp_is_dirty`. It
+    /// always is if the stamp was written by a build of another version.
     fn clear_if_dirty(&self, dir: &Path, input: &Path) -> bool {
         let stamp = dir.join(".stamp");
+        let hash = if self.config.hash_stamps { util::stamp_hash(input) } else { None };
         let mut cleared = false;
-        if mtime(&stamp) < mtime(input) {
+        if util::stamp_is_dirty(&stamp, input, &self.version, hash.as_deref()) {
             self.verbose(&format!("Dirty - {}", dir.display()));
             let _ = fs::remove_dir_all(dir);
             cleared = true;
@@ -538,13 +829,28 @@ fn clear_if_dirty(&self, dir: &Path, input: &Path) -> bool {
             return cleared;
         }
         t!(fs::create_dir_all(dir));
-        t!(File::create(stamp));
+        t!(fs::write(stamp, util::stamp_contents(&self.version, hash.as_deref())));
         cleared
     }
 
     /// Gets the space-separated set of activated features for the standard
-    /// library.
+    /// library. Only `alloc` is built for no-std targets, so none of std's
+    /// features like `panic-unwind` apply and `alloc`'s are returned instead.
+    ///
+    /// `target.<triple>.std-features` is applied last, see `merge_features`.
     fn std_features(&self, target: TargetSelection) -> String {
+        let features = self.default_std_features(target);
+        match self.config.target_config.get(&target) {
+            Some(t) => merge_features(&features, &t.std_features),
+            None => features,
+        }
+    }
+
+    fn default_std_features(&self, target: TargetSelection) -> String {
+        if self.no_std(target) == Some(true) {
+            return "compiler-builtins-mem".to_string();
+        }
+
         let mut features = "panic-unwind".to_string();
 
         match self.config.llvm_libunwind.unwrap_or_default() {
@@ -561,6 +867,18 @@ fn std_features(&self, target: TargetSelection) -> String {
         features
     }
 
+    /// Gets the environment variables the standard library's build picks
+    /// settings up from, in addition to its features.
+    fn std_env(&self, target: TargetSelection) -> Vec<(&'static str, String)> {
+        let mut env = Vec::new();
+        if target.contains("bpf") {
+            if let Some(size) = self.config.bpf_stack_size {
+                env.push(("RUST_BPF_STACK_SIZE", size.to_string()));
+            }
+        }
+        env
+    }
+
     /// Gets the space-separated set of activated features for the compiler.
     fn rustc_features(&self) -> String {
         let mut features = String::new();
@@ -598,6 +916,91 @@ fn tools_dir(&self, compiler: Compiler) -> PathBuf {
         out
     }
 
+    /// Returns `path` relative to `sysroot`, i.e. where it goes when the
+    /// sysroot is installed somewhere, or `None` if it isn't in the sysroot.
+    ///
+    /// Either of them may go through a symlink, e.g. when a toolchain is linked
+    /// into place, in which case they're compared with the links resolved.
+    /// `path` doesn't need to exist yet, as long as `sysroot` does.
+    pub fn relativize_for_sysroot(path: &Path, sysroot: &Path) -> Option<PathBuf> {
+        if let Ok(relative) = path.strip_prefix(sysroot) {
+            return Some(relative.to_path_buf());
+        }
+
+        let sysroot = fs::canonicalize(sysroot).ok()?;
+        let mut existing = path;
+        let mut missing = Vec::new();
+        let mut path = loop {
+            match fs::canonicalize(existing) {
+                Ok(resolved) => break resolved,
+                Err(_) => {
+                    missing.push(existing.file_name()?);
+                    existing = existing.parent()?;
+                }
+            }
+        };
+        path.extend(missing.iter().rev());
+        path.strip_prefix(&sysroot).ok().map(Path::to_path_buf)
+    }
+
+    /// Returns the sysroot that `compiler` is assembled into and run from.
+    ///
+    /// Unlike `Builder::sysroot` this doesn't create anything, it's only the
+    /// path. The stage0 compiler comes with its own sysroot, so the one here is
+    /// where this build puts the libraries it produces with it.
+    fn sysroot_for(&self, compiler: Compiler) -> PathBuf {
+        let dir = if compiler.stage == 0 {
+            "stage0-sysroot".to_string()
+        } else {
+            format!("stage{}", compiler.stage)
+        };
+        self.out.join(&*compiler.host.triple).join(dir)
+    }
+
+    /// Returns where `component` of the toolchain of `compiler` ends up once
+    /// it's been built, or `None` if this build doesn't produce it. Nothing is
+    /// built or created, and the path may not exist yet.
+    ///
+    /// The components are `rustc`, `rustdoc`, `cargo`, `rust-lld` and the
+    /// LLVM tools that are shipped next to it. The stage0 toolchain is the
+    /// downloaded snapshot, which doesn't come with the LLVM tools.
+    pub fn which_toolchain_component(
+        &self,
+        component: &str,
+        compiler: Compiler,
+    ) -> Option<PathBuf> {
+        let host = compiler.host;
+        if compiler.stage == 0 {
+            return match component {
+                "rustc" | "rustdoc" => {
+                    Some(self.initial_rustc.with_file_name(exe(component, host)))
+                }
+                "cargo" => Some(self.initial_cargo.clone()),
+                "rust-lld" => Some(self.initial_lld.clone()),
+                _ => None,
+            };
+        }
+
+        let sysroot = self.sysroot_for(compiler);
+        let libdir = self.config.libdir_relative().unwrap_or(Path::new("lib"));
+        let rustlib_bin = sysroot.join(libdir).join("rustlib").join(&*host.triple).join("bin");
+        match component {
+            "rustc" | "rustdoc" => Some(sysroot.join("bin").join(exe(component, host))),
+            // Like `tools_dir`, which creates the directory though.
+            "cargo" => Some(
+                self.out
+                    .join(&*host.triple)
+                    .join(format!("stage{}-tools-bin", compiler.stage))
+                    .join(exe("cargo", host)),
+            ),
+            "rust-lld" if self.config.lld_enabled => Some(rustlib_bin.join(exe(component, host))),
+            tool if LLVM_TOOLS.contains(&tool) && self.config.llvm_tools_enabled => {
+                Some(rustlib_bin.join(exe(tool, host)))
+            }
+            _ => None,
+        }
+    }
+
     /// Returns the root directory for all output generated in a particular
     /// stage when running with a particular host compiler.
     ///
@@ -609,6 +1012,7 @@ fn stage_out(&self, compiler: Compiler, mode: Mode) -> PathBuf {
             Mode::Codegen => "-codegen",
             Mode::ToolBootstrap => "-bootstrap-tools",
             Mode::ToolStd | Mode::ToolRustc => "-tools",
+            Mode::ToolBpf => "-tools-bpf",
         };
         self.out.join(&*compiler.host.triple).join(format!("stage{}{}", compiler.stage, suffix))
     }
@@ -632,9 +1036,43 @@ fn lld_out(&self, target: TargetSelection) -> PathBuf {
         self.out.join(&*target.triple).join("lld")
     }
 
+    /// Removes the outputs built for `target` alone: everything in
+    /// `build/$target`, like `llvm_out` or `doc_out`, along with its
+    /// `cargo_out` directories and sysroot libraries under every host.
+    ///
+    /// `build/$host` also holds the compilers built for `host`, so cleaning a
+    /// host is refused unless `all` is set.
+    pub fn clean_target(&self, target: TargetSelection, all: bool) -> Result<(), String> {
+        let is_host = target == self.build || self.hosts.contains(&target);
+        if is_host && !all {
+            return Err(format!(
+                "`{}` is a host, cleaning it would remove its compilers too; \
+                 pass `--all` to do so anyway",
+                target
+            ));
+        }
+        clean::rm_rf(&self.out.join(&*target.triple));
+
+        for host in self.hosts.iter().chain(Some(&self.build)).filter(|&&host| host != target) {
+            let entries = match self.out.join(&*host.triple).read_dir() {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries {
+                let entry = t!(entry);
+                if entry.file_name().to_string_lossy().starts_with("stage") {
+                    let stage = entry.path();
+                    clean::rm_rf(&stage.join(&*target.triple));
+                    clean::rm_rf(&stage.join("lib/rustlib").join(&*target.triple));
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Output directory for all documentation for a target
     fn doc_out(&self, target: TargetSelection) -> PathBuf {
-        self.out.join(&*target.triple).join("doc")
+        self.versioned_doc_dir(self.out.join(&*target.triple).join("doc"))
     }
 
     fn test_out(&self, target: TargetSelection) -> PathBuf {
@@ -643,7 +1081,22 @@ fn test_out(&self, target: TargetSelection) -> PathBuf {
 
     /// Output directory for all documentation for a target
     fn compiler_doc_out(&self, target: TargetSelection) -> PathBuf {
-        self.out.join(&*target.triple).join("compiler-doc")
+        self.versioned_doc_dir(self.out.join(&*target.triple).join("compiler-doc"))
+    }
+
+    /// With `build.versioned-docs`, documentation goes into a subdirectory of
+    /// `dir` named after the release, see `doc::link_latest_docs`.
+    fn versioned_doc_dir(&self, dir: PathBuf) -> PathBuf {
+        if self.config.versioned_docs {
+            dir.join(self.rust_release())
+        } else {
+            dir
+        }
+    }
+
+    /// Output directory for rustdoc JSON documentation for a target
+    fn json_doc_out(&self, target: TargetSelection) -> PathBuf {
+        self.out.join(&*target.triple).join("json-doc")
     }
 
     /// Output directory for some generated md crate documentation for a target (temporary)
@@ -676,22 +1129,133 @@ fn llvm_bin(&self, target: TargetSelection) -> PathBuf {
         }
     }
 
+    /// Fails with the offending symbols if the BPF shared object `artifact`
+    /// defines or references any of the host symbols listed in
+    /// `src/etc/bpf-host-symbols.txt`, which mean a host library or object
+    /// was linked into it. Nothing is checked in dry-run mode.
+    pub fn verify_no_host_symbols(&self, artifact: &Path) {
+        if self.config.dry_run {
+            return;
+        }
+        let denylist = t!(fs::read_to_string(self.src.join("src/etc/bpf-host-symbols.txt")));
+        let nm = self.llvm_bin(self.config.build).join(exe("llvm-nm", self.config.build));
+        let out = output(Command::new(nm).arg(artifact));
+        let found = audit::host_symbols(&out, &audit::parse_host_symbols(&denylist));
+        if !found.is_empty() {
+            eprintln!(
+                "error: `{}` contains symbols that only exist on the host:",
+                artifact.display()
+            );
+            for symbol in found {
+                eprintln!("    {}", symbol);
+            }
+            process::exit(1);
+        }
+    }
+
+    /// Fails with the offending calls if `target` is configured with
+    /// `no-float` and the BPF shared object `artifact` built for it calls any
+    /// soft float routine. Nothing is checked in dry-run mode.
+    pub fn verify_no_float(&self, target: TargetSelection, artifact: &Path) {
+        if self.config.dry_run || !self.no_float(target) {
+            return;
+        }
+        let objdump = self.llvm_bin(self.config.build).join(exe("llvm-objdump", self.config.build));
+        let out = output(Command::new(objdump).arg("-d").arg("-r").arg(artifact));
+        let found = audit::audit_float_ops(&out);
+        if !found.is_empty() {
+            eprintln!(
+                "error: `{}` uses floating point, which `target.{}.no-float` forbids:",
+                artifact.display(),
+                target
+            );
+            for problem in found {
+                eprintln!("    {}", problem);
+            }
+            process::exit(1);
+        }
+    }
+
+    /// Writes the loadable contents of the ELF file `elf`, built for `target`,
+    /// to `dst` as a raw binary with `llvm-objcopy -O binary`. That's the
+    /// format flashing and some deployment tools expect.
+    pub fn objcopy_to_binary(&self, target: TargetSelection, elf: &Path, dst: &Path) {
+        let objcopy = self.llvm_bin(target).join(exe("llvm-objcopy", self.config.build));
+        self.run(Command::new(objcopy).arg("-O").arg("binary").arg(elf).arg(dst));
+    }
+
+    /// Returns the byte order of `target`, as reported by the `target_endian`
+    /// cfg the initial rustc prints for it. Always little-endian in dry runs.
+    pub fn target_endianness(&self, target: TargetSelection) -> Endian {
+        if self.config.dry_run {
+            return Endian::Little;
+        }
+        if let Some(&endian) = self.target_endianness.borrow().get(&target) {
+            return endian;
+        }
+        let cfg = output(
+            Command::new(&self.initial_rustc)
+                .arg("--target")
+                .arg(target.rustc_target_arg())
+                .arg("--print")
+                .arg("cfg"),
+        );
+        let endian = match cfg.lines().find(|line| line.starts_with("target_endian=")) {
+            Some(r#"target_endian="little""#) => Endian::Little,
+            Some(r#"target_endian="big""#) => Endian::Big,
+            _ => panic!(
+                "`{} --print cfg` printed no endianness for {}",
+                self.initial_rustc.display(),
+                target
+            ),
+        };
+        self.target_endianness.borrow_mut().insert(target, endian);
+        endian
+    }
+
+    /// Returns the status of the submodule at `path`, relative to the source
+    /// directory, or `None` if the sources aren't a git checkout.
+    pub fn git_submodule_status(&self, path: &str) -> Option<SubmoduleStatus> {
+        if !self.rust_info.is_git() {
+            return None;
+        }
+        let output = Command::new("git")
+            .args(&["submodule", "status", "--", path])
+            .current_dir(&self.src)
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        parse_submodule_status(&String::from_utf8_lossy(&output.stdout))
+    }
+
     /// Returns the path to `FileCheck` binary for the specified target
     fn llvm_filecheck(&self, target: TargetSelection) -> PathBuf {
+        if let Some(path) = self.llvm_filecheck.borrow().get(&target) {
+            return path.clone();
+        }
+        let path = self.find_llvm_filecheck(target);
+        self.llvm_filecheck.borrow_mut().insert(target, path.clone());
+        path
+    }
+
+    fn find_llvm_filecheck(&self, target: TargetSelection) -> PathBuf {
         let target_config = self.config.target_config.get(&target);
         if let Some(s) = target_config.and_then(|c| c.llvm_filecheck.as_ref()) {
             s.to_path_buf()
         } else if let Some(s) = target_config.and_then(|c| c.llvm_config.as_ref()) {
+            let name = target_config.and_then(|c| c.llvm_filecheck_name.as_deref());
+            let name = exe(name.unwrap_or("FileCheck"), target);
             let llvm_bindir = output(Command::new(s).arg("--bindir"));
-            let filecheck = Path::new(llvm_bindir.trim()).join(exe("FileCheck", target));
+            let filecheck = Path::new(llvm_bindir.trim()).join(&name);
             if filecheck.exists() {
                 filecheck
             } else {
                 // On Fedora the system LLVM installs FileCheck in the
                 // llvm subdirectory of the libdir.
                 let llvm_libdir = output(Command::new(s).arg("--libdir"));
-                let lib_filecheck =
-                    Path::new(llvm_libdir.trim()).join("llvm").join(exe("FileCheck", target));
+                let lib_filecheck = Path::new(llvm_libdir.trim()).join("llvm").join(&name);
                 if lib_filecheck.exists() {
                     lib_filecheck
                 } else {
@@ -732,10 +1296,28 @@ fn test_helpers_out(&self, target: TargetSelection) -> PathBuf {
     }
 
     /// Adds the `RUST_TEST_THREADS` env var if necessary
-    fn add_rust_test_threads(&self, cmd: &mut Command) {
-        if env::var_os("RUST_TEST_THREADS").is_none() {
-            cmd.env("RUST_TEST_THREADS", self.jobs().to_string());
-        }
+    fn add_rust_test_threads(&self, cmd: &mut Command, target: TargetSelection) {
+        cmd.env(
+            "RUST_TEST_THREADS",
+            self.rust_test_threads(target, env::var_os("RUST_TEST_THREADS")),
+        );
+    }
+
+    /// Returns the number of threads to run the tests for `target` with,
+    /// `explicit` being what the user set `RUST_TEST_THREADS` to.
+    ///
+    /// Remote-tested targets run on emulators or small devices that can't keep
+    /// up with one test thread per job, so they get at most
+    /// `REMOTE_TEST_THREADS` unless the user asked for more.
+    fn rust_test_threads(&self, target: TargetSelection, explicit: Option<OsString>) -> OsString {
+        explicit.unwrap_or_else(|| {
+            let threads = if self.remote_tested(target) {
+                cmp::min(self.jobs(), REMOTE_TEST_THREADS)
+            } else {
+                self.jobs()
+            };
+            threads.to_string().into()
+        })
     }
 
     /// Returns the libdir of the snapshot compiler.
@@ -788,6 +1370,83 @@ fn try_run_quiet(&self, cmd: &mut Command) -> bool {
         try_run_suppressed(cmd)
     }
 
+    /// Runs a command, telling apart whether it failed to start or ran and
+    /// failed, for probes that want to explain which. Prints nothing on
+    /// failure. Commands count as successful in dry-run mode.
+    fn try_run_detailed(&self, cmd: &mut Command) -> RunOutcome {
+        if self.config.dry_run {
+            return RunOutcome::Succeeded;
+        }
+        self.verbose(&format!("running: {:?}", cmd));
+        try_run_detailed(cmd)
+    }
+
+    /// Runs the cargo command `cmd` with `--message-format=json-render-diagnostics`,
+    /// returning the artifacts it built and the diagnostics it printed. Both
+    /// outputs are still passed through as they come in, stderr without
+    /// colors since cargo sees it's captured. `cmd` must not pass arguments
+    /// on to rustc after a `--`. Exits if cargo couldn't be started, nothing
+    /// is run in dry-run mode.
+    pub fn run_captured_json(&self, cmd: &mut Command) -> compile::CargoJsonOutput {
+        let mut output = compile::CargoJsonOutput::default();
+        if self.config.dry_run {
+            return output;
+        }
+        cmd.arg("--message-format").arg("json-render-diagnostics");
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        self.verbose(&format!("running: {:?}", cmd));
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                println!("\n\nfailed to execute command: {:?}\nerror: {}\n\n", cmd, e);
+                process::exit(1);
+            }
+        };
+
+        let stderr = child.stderr.take().unwrap();
+        let stderr = thread::spawn(move || {
+            let mut captured = String::new();
+            for line in BufReader::new(stderr).lines() {
+                let line = t!(line);
+                eprintln!("{}", line);
+                captured.push_str(&line);
+                captured.push('\n');
+            }
+            captured
+        });
+        for line in BufReader::new(child.stdout.take().unwrap()).lines() {
+            let line = t!(line);
+            if !output.add_message(&line) {
+                println!("{}", line);
+            }
+        }
+
+        output.success = t!(child.wait()).success();
+        output.diagnostics = compile::rendered_diagnostics(&stderr.join().unwrap());
+        output
+    }
+
+    /// Runs a command and captures its stdout and stderr for the caller to
+    /// inspect. Exits if the command failed to execute at all, but a failing
+    /// status is left to the caller. Nothing is run in dry-run mode.
+    fn run_with_output_capture(&self, cmd: &mut Command) -> CommandOutput {
+        if self.config.dry_run {
+            return CommandOutput::default();
+        }
+        self.verbose(&format!("running: {:?}", cmd));
+        match cmd.output() {
+            Ok(output) => CommandOutput {
+                status: Some(output.status),
+                stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            },
+            Err(e) => {
+                println!("\n\nfailed to execute command: {:?}\nerror: {}\n\n", cmd, e);
+                process::exit(1);
+            }
+        }
+    }
+
     pub fn is_verbose(&self) -> bool {
         self.verbosity > 0
     }
@@ -818,30 +1477,76 @@ fn info(&self, msg: &str) {
     }
 
     /// Returns the number of parallel jobs that have been configured for this
-    /// build.
+    /// build, by default one per CPU that `build.reserve-cpus` leaves over.
     fn jobs(&self) -> u32 {
-        self.config.jobs.unwrap_or_else(|| num_cpus::get() as u32)
+        self.config
+            .jobs
+            .unwrap_or_else(|| unreserved_cpus(num_cpus::get() as u32, self.config.reserve_cpus))
     }
 
+    /// Returns the number of parallel jobs to build LLVM for `target` with.
+    fn llvm_jobs(&self, target: TargetSelection) -> u32 {
+        self.config
+            .target_config
+            .get(&target)
+            .and_then(|t| t.llvm_jobs)
+            .unwrap_or_else(|| self.jobs())
+    }
+
+    /// Returns the virtual directory the sources of `which` are remapped to in
+    /// debuginfo, `rust.remap-debuginfo-prefix` and
+    /// `rust.remap-debuginfo-llvm-prefix` default to `/rustc/$sha` and
+    /// `/rustc/llvm`.
     fn debuginfo_map_to(&self, which: GitRepo) -> Option<String> {
         if !self.config.rust_remap_debuginfo {
             return None;
         }
 
         match which {
-            GitRepo::Rustc => {
-                let sha = self.rust_sha().unwrap_or(&self.version);
-                Some(format!("/rustc/{}", sha))
-            }
-            GitRepo::Llvm => Some(String::from("/rustc/llvm")),
+            GitRepo::Rustc => match &self.config.rust_remap_debuginfo_prefix {
+                Some(prefix) => Some(prefix.clone()),
+                None => {
+                    let sha = self.rust_sha().unwrap_or(&self.version);
+                    Some(format!("/rustc/{}", sha))
+                }
+            },
+            GitRepo::Llvm => match &self.config.rust_remap_debuginfo_llvm_prefix {
+                Some(prefix) => Some(prefix.clone()),
+                None => Some(String::from("/rustc/llvm")),
+            },
         }
     }
 
+    /// Returns `cflags` for a command that runs the C compiler of `target`
+    /// directly. If they would make the command line too long they're moved
+    /// into the response file `<target>/cflags/<name>.rsp` instead.
+    fn cflags_for_command(
+        &self,
+        target: TargetSelection,
+        which: GitRepo,
+        name: &str,
+    ) -> Vec<String> {
+        let rsp = self.out.join(&*target.triple).join("cflags").join(format!("{}.rsp", name));
+        util::response_file_args(
+            self.cc(target),
+            self.cflags(target, which),
+            &rsp,
+            util::COMMAND_LINE_LIMIT,
+            target.contains("msvc"),
+        )
+    }
+
     /// Returns the path to the C compiler for the target specified.
     fn cc(&self, target: TargetSelection) -> &Path {
         self.cc[&target].path()
     }
 
+    /// Returns the version of the C compiler for `target`, if it could be
+    /// detected.
+    fn cc_version(&self, target: TargetSelection) -> Option<cc_detect::CcVersion> {
+        self.cc_version.get(&target).copied()
+    }
+
     /// Returns a list of flags to pass to the C compiler for the target
     /// specified.
     fn cflags(&self, target: TargetSelection, which: GitRepo) -> Vec<String> {
@@ -876,6 +1581,13 @@ fn cflags(&self, target: TargetSelection, which: GitRepo) -> Vec<String> {
             base.push("+solana".into());
         }
 
+        // Flags from `target.<triple>.cflags`, filtered like the ones above.
+        if let Some(config) = self.config.target_config.get(&target) {
+            let extra =
+                config.cflags.iter().filter(|s| !s.starts_with("-O") && !s.starts_with("/O"));
+            base.extend(extra.cloned());
+        }
+
         if let Some(map_to) = self.debuginfo_map_to(which) {
             let map = format!("{}={}", self.src.display(), map_to);
             let cc = self.cc(target);
@@ -918,18 +1630,34 @@ fn linker(&self, target: TargetSelection) -> Option<&Path> {
             // need to use CXX compiler as linker to resolve the exception functions
             // that are only existed in CXX libraries
             Some(self.cxx[&target].path())
-        } else if target != self.config.build
+        } else if self.is_cross_compiling(target)
             && util::use_host_linker(target)
             && !target.contains("msvc")
         {
             Some(self.cc(target))
-        } else if self.config.use_lld && !self.is_fuse_ld_lld(target) && self.build == target {
+        } else if self.config.use_lld
+            && !self.is_fuse_ld_lld(target)
+            && !self.is_cross_compiling(target)
+        {
             Some(&self.initial_lld)
         } else {
             None
         }
     }
 
+    /// Returns the `target.<triple>.linker-args` to link Rust code for `target`
+    /// with, whichever linker `linker` picks for it.
+    fn linker_flags(&self, target: TargetSelection) -> &[String] {
+        self.config.target_config.get(&target).map_or(&[], |t| &t.linker_args)
+    }
+
+    /// Returns whether `target` is anything but the build triple, in which case
+    /// its artifacts can't run on the machine doing the build. BPF targets are
+    /// never a build triple so they're always cross compiled.
+    fn is_cross_compiling(&self, target: TargetSelection) -> bool {
+        target != self.build
+    }
+
     // LLD is used through `-fuse-ld=lld` rather than directly.
     // Only MSVC targets use LLD directly at the moment.
     fn is_fuse_ld_lld(&self, target: TargetSelection) -> bool {
@@ -961,9 +1689,28 @@ fn musl_libdir(&self, target: TargetSelection) -> Option<PathBuf> {
         if let libdir @ Some(_) = &t.musl_libdir {
             return libdir.clone();
         }
+        if t.probe_musl_libdir {
+            if let Some(libdir) = self.probe_musl_libdir(target) {
+                return Some(libdir);
+            }
+        }
         self.musl_root(target).map(|root| root.join("lib"))
     }
 
+    /// Asks the C compiler of `target` for the directory its `libc.a` is in.
+    fn probe_musl_libdir(&self, target: TargetSelection) -> Option<PathBuf> {
+        let cc = self.cc.get(&target)?;
+        let out = Command::new(cc.path()).args(cc.args()).arg("-print-file-name=libc.a").output();
+        let out = out.ok().filter(|out| out.status.success())?;
+        // Compilers print the name back unchanged if they can't find the file.
+        let libc = PathBuf::from(String::from_utf8(out.stdout).ok()?.trim());
+        if libc.is_absolute() {
+            libc.parent().map(Path::to_path_buf)
+        } else {
+            None
+        }
+    }
+
     /// Returns the sysroot for the wasi target, if defined
     fn wasi_root(&self, target: TargetSelection) -> Option<&Path> {
         self.config.target_config.get(&target).and_then(|t| t.wasi_root.as_ref()).map(|p| &**p)
@@ -974,6 +1721,11 @@ fn no_std(&self, target: TargetSelection) -> Option<bool> {
         self.config.target_config.get(&target).map(|t| t.no_std)
     }
 
+    /// Returns whether programs for `target` must not use floating point.
+    fn no_float(&self, target: TargetSelection) -> bool {
+        self.config.target_config.get(&target).map_or(false, |t| t.no_float)
+    }
+
     /// Returns `true` if the target will be tested using the `remote-test-client`
     /// and `remote-test-server` binaries.
     fn remote_tested(&self, target: TargetSelection) -> bool {
@@ -1015,14 +1767,22 @@ fn extended_error_dir(&self) -> PathBuf {
     /// * The build isn't performing a full bootstrap
     /// * The `compiler` is in the final stage, 2
     /// * We're not cross-compiling, so the artifacts are already available in
-    ///   stage1
+    ///   stage1, or the target opted into `uplift-stage1`
     ///
     /// When all of these conditions are met the build will lift artifacts from
     /// the previous stage forward.
+    ///
+    /// Uplifted libraries were built by the stage1 compiler rather than the
+    /// stage2 one. Both come from the same source, so the output only differs
+    /// if stage0 miscompiled stage1, which is what a full bootstrap checks for.
+    /// Hosts need their stage1 libraries anyway, cross targets usually don't,
+    /// so for those it only pays off when asked for, e.g. for BPF.
     fn force_use_stage1(&self, compiler: Compiler, target: TargetSelection) -> bool {
         !self.config.full_bootstrap
             && compiler.stage >= 2
-            && (self.hosts.iter().any(|h| *h == target) || target == self.build)
+            && (self.hosts.contains(&target)
+                || !self.is_cross_compiling(target)
+                || self.config.target_config.get(&target).map_or(false, |t| t.uplift_stage1))
     }
 
     /// Given `num` in the form "a.b.c" return a "release string" which
@@ -1031,7 +1791,7 @@ fn force_use_stage1(&self, compiler: Compiler, target: TargetSelection) -> bool
     /// For example on nightly this returns "a.b.c-nightly", on beta it returns
     /// "a.b.c-beta.1" and on stable it just returns "a.b.c".
     fn release(&self, num: &str) -> String {
-        match &self.config.channel[..] {
+        let release = match &self.config.channel[..] {
             "stable" => num.to_string(),
             "beta" => {
                 if self.rust_info.is_git() {
@@ -1042,6 +1802,15 @@ fn release(&self, num: &str) -> String {
             }
             "nightly" => format!("{}-nightly", num),
             _ => format!("{}-dev", num),
+        };
+        self.with_channel_suffix(release)
+    }
+
+    /// Appends the vendor suffix configured with `rust.channel-suffix`.
+    fn with_channel_suffix(&self, version: String) -> String {
+        match self.config.channel_suffix {
+            Some(ref suffix) => format!("{}-{}", version, suffix),
+            None => version,
         }
     }
 
@@ -1050,22 +1819,65 @@ fn beta_prerelease_version(&self) -> u32 {
             return s;
         }
 
-        // Figure out how many merge commits happened since we branched off master.
-        // That's our beta number!
-        // (Note that we use a `..` range, not the `...` symmetric difference.)
-        let count = output(
-            Command::new("git")
-                .arg("rev-list")
-                .arg("--count")
-                .arg("--merges")
-                .arg("refs/remotes/origin/master..HEAD")
-                .current_dir(&self.src),
-        );
-        let n = count.trim().parse().unwrap();
+        let n = match beta_merge_count(&self.src) {
+            Some(n) => n,
+            None => {
+                println!(
+                    "warning: {} is a shallow clone, so the number of the beta can't be \
+                     counted; using {} instead",
+                    self.src.display(),
+                    UNKNOWN_BETA_PRERELEASE_VERSION
+                );
+                UNKNOWN_BETA_PRERELEASE_VERSION
+            }
+        };
         self.prerelease_version.set(Some(n));
         n
     }
 
+    /// Makes the next build of `tool` for `target` recompile it, for
+    /// `--rebuild-tool`: forgets the artifacts recorded for it and removes the
+    /// fingerprint of its binary from `cargo_out`, which cargo would otherwise
+    /// consider fresh.
+    pub fn ensure_tool_fresh(&self, target: TargetSelection, tool: &str, cargo_out: &Path) {
+        if let Some(artifacts) = self.tool_artifacts.borrow_mut().get_mut(&target) {
+            artifacts.retain(|_, artifact| artifact.0 != tool);
+        }
+        if self.config.dry_run {
+            return;
+        }
+
+        let fingerprints = match fs::read_dir(cargo_out.join(".fingerprint")) {
+            Ok(fingerprints) => fingerprints,
+            Err(_) => return,
+        };
+        let bin = format!("bin-{}", tool);
+        for dir in fingerprints {
+            let dir = t!(dir).path();
+            if dir.join(&bin).exists() {
+                self.verbose(&format!("removing {} to rebuild {}", dir.display(), tool));
+                t!(fs::remove_dir_all(&dir));
+            }
+        }
+    }
+
+    /// Flags that keep cargo from updating `Cargo.lock` or from accessing
+    /// the network when the configuration asks for it, passed to every cargo
+    /// command that resolves the dependencies of the workspace.
+    fn cargo_lock_flags(&self) -> Vec<&'static str> {
+        let mut flags = Vec::new();
+        if self.config.locked_deps {
+            flags.push("--locked");
+        }
+        if self.config.vendor || self.is_sudo {
+            flags.push("--frozen");
+        }
+        if self.config.offline {
+            flags.push("--offline");
+        }
+        flags
+    }
+
     /// Returns the value of `release` above for Rust itself.
     fn rust_release(&self) -> String {
         self.release(&self.version)
@@ -1078,12 +1890,13 @@ fn rust_release(&self) -> String {
     /// For channels like beta/nightly it's just the channel name, otherwise
     /// it's the `num` provided.
     fn package_vers(&self, num: &str) -> String {
-        match &self.config.channel[..] {
+        let vers = match &self.config.channel[..] {
             "stable" => num.to_string(),
             "beta" => "beta".to_string(),
             "nightly" => "nightly".to_string(),
             _ => format!("{}-dev", num),
-        }
+        };
+        self.with_channel_suffix(vers)
     }
 
     /// Returns the value of `package_vers` above for Rust itself.
@@ -1118,16 +1931,10 @@ fn rust_sha(&self) -> Option<&str> {
     /// Returns the `a.b.c` version that the given package is at.
     fn release_num(&self, package: &str) -> String {
         let toml_file_name = self.src.join(&format!("src/tools/{}/Cargo.toml", package));
-        let toml = t!(fs::read_to_string(&toml_file_name));
-        for line in toml.lines() {
-            if let Some(stripped) =
-                line.strip_prefix("version = \"").and_then(|s| s.strip_suffix("\""))
-            {
-                return stripped.to_owned();
-            }
+        match package_version(&toml_file_name) {
+            Some(version) => version,
+            None => panic!("failed to find version in {}'s Cargo.toml", package),
         }
-
-        panic!("failed to find version in {}'s Cargo.toml", package)
     }
 
     /// Returns `true` if unstable features should be enabled for the compiler
@@ -1155,20 +1962,8 @@ fn in_tree_crates(&self, root: &str, target: Option<TargetSelection>) -> Vec<&Cr
                     continue;
                 }
                 // Don't include optional deps if their features are not
-                // enabled. Ideally this would be computed from `cargo
-                // metadata --features …`, but that is somewhat slow. Just
-                // skip `build_helper` since there aren't any operations we
-                // want to perform on it. In the future, we may want to
-                // consider just filtering all build and dev dependencies in
-                // metadata::build.
-                if visited.insert(dep)
-                    && dep != "build_helper"
-                    && (dep != "profiler_builtins"
-                        || target
-                            .map(|t| self.config.profiler_enabled(t))
-                            .unwrap_or_else(|| self.config.any_profiler_enabled()))
-                    && (dep != "rustc_codegen_llvm" || self.config.llvm_enabled())
-                {
+                // enabled.
+                if visited.insert(dep) && self.crate_enabled(dep, target) {
                     list.push(*dep);
                 }
             }
@@ -1176,58 +1971,67 @@ fn in_tree_crates(&self, root: &str, target: Option<TargetSelection>) -> Vec<&Cr
         ret
     }
 
+    /// Returns whether `in_tree_crates` includes the crate `name`, see
+    /// `OPTIONAL_CRATES`.
+    fn crate_enabled(&self, name: &str, target: Option<TargetSelection>) -> bool {
+        match OPTIONAL_CRATES.iter().find(|(krate, _)| *krate == name) {
+            Some((_, enabled)) => enabled(self, target),
+            None => true,
+        }
+    }
+
     fn read_stamp_file(&self, stamp: &Path) -> Vec<(PathBuf, DependencyType)> {
         if self.config.dry_run {
             return Vec::new();
         }
 
-        let mut paths = Vec::new();
-        let contents = t!(fs::read(stamp), &stamp);
-        // This is the method we use for extracting paths from the stamp file passed to us. See
-        // run_cargo for more information (in compile.rs).
-        for part in contents.split(|b| *b == 0) {
-            if part.is_empty() {
-                continue;
-            }
-            let dependency_type = match part[0] as char {
-                'h' => DependencyType::Host,
-                's' => DependencyType::TargetSelfContained,
-                't' => DependencyType::Target,
-                _ => unreachable!(),
-            };
-            let path = PathBuf::from(t!(str::from_utf8(&part[1..])));
-            paths.push((path, dependency_type));
-        }
-        paths
+        parse_stamp(&t!(fs::read(stamp), &stamp))
     }
 
     /// Copies a file from `src` to `dst`
     pub fn copy(&self, src: &Path, dst: &Path) {
+        self.copy_link(src, dst);
+    }
+
+    /// Same as `copy`, but returns how the file was put in place, or `None` if
+    /// nothing had to be done.
+    pub fn copy_link(&self, src: &Path, dst: &Path) -> Option<CopyKind> {
         if self.config.dry_run {
-            return;
+            return None;
         }
         self.verbose_than(1, &format!("Copy {:?} to {:?}", src, dst));
-        if src == dst {
-            return;
+        match copy_file(src, dst, self.config.preserve_xattrs) {
+            Ok(kind) => kind,
+            Err(e) => panic!("failed to copy `{}` to `{}`: {}", src.display(), dst.display(), e),
         }
-        let _ = fs::remove_file(&dst);
-        let metadata = t!(src.symlink_metadata());
-        if metadata.file_type().is_symlink() {
-            let link = t!(fs::read_link(src));
-            t!(symlink_file(link, dst));
-        } else if let Ok(()) = fs::hard_link(src, dst) {
-            // Attempt to "easy copy" by creating a hard link
-            // (symlinks don't work on windows), but if that fails
-            // just fall back to a slow `copy` operation.
-        } else {
-            if let Err(e) = fs::copy(src, dst) {
-                panic!("failed to copy `{}` to `{}`: {}", src.display(), dst.display(), e)
-            }
-            t!(fs::set_permissions(dst, metadata.permissions()));
-            let atime = FileTime::from_last_access_time(&metadata);
-            let mtime = FileTime::from_last_modification_time(&metadata);
-            t!(filetime::set_file_times(dst, atime, mtime));
+    }
+
+    /// Like `copy_link`, but if the file is large, has to be copied rather than
+    /// hardlinked and stderr is a terminal, shows the progress of the copy
+    /// there so it doesn't look like the build is stuck.
+    pub fn copy_with_progress(&self, src: &Path, dst: &Path) -> Option<CopyKind> {
+        if self.config.dry_run {
+            return None;
+        }
+        self.verbose_than(1, &format!("Copy {:?} to {:?}", src, dst));
+        let link = |src: &Path, dst: &Path| fs::hard_link(src, dst);
+        let xattrs = self.config.preserve_xattrs;
+        let terminal = stderr_is_terminal();
+        match copy_file_with_progress(src, dst, terminal, xattrs, &link, &mut io::stderr()) {
+            Ok(kind) => kind,
+            Err(e) => panic!("failed to copy `{}` to `{}`: {}", src.display(), dst.display(), e),
+        }
+    }
+
+    /// Strips `artifact` in place with the `llvm-strip` of the LLVM in use, see
+    /// `util::strip_command` for what `keep_debug` keeps.
+    pub fn strip_artifact(&self, artifact: &Path, keep_debug: bool) {
+        if self.config.dry_run {
+            return;
         }
+        let strip = self.llvm_bin(self.config.build).join(exe("llvm-strip", self.config.build));
+        self.verbose(&format!("Strip {:?}", artifact));
+        self.run(&mut util::strip_command(&strip, artifact, keep_debug));
     }
 
     /// Search-and-replaces within a file. (Not maximally efficiently: allocates a
@@ -1267,16 +2071,64 @@ pub fn cp_r(&self, src: &Path, dst: &Path) {
         }
     }
 
+    /// Makes `dst` a link to the `src` directory, falling back to copying it
+    /// with `cp_r` where links can't be created, e.g. for users without the
+    /// privilege to create symlinks on Windows. `dst` must not exist or be an
+    /// empty directory.
+    ///
+    /// Only use this for directories nothing gets written into later, as
+    /// writes would go to `src` if the link works.
+    pub fn symlink_or_copy_dir(&self, src: &Path, dst: &Path) -> CopyKind {
+        self.link_or_copy_dir(src, dst, &|src, dst| symlink_dir(&self.config, src, dst))
+    }
+
+    fn link_or_copy_dir(
+        &self,
+        src: &Path,
+        dst: &Path,
+        link: &dyn Fn(&Path, &Path) -> io::Result<()>,
+    ) -> CopyKind {
+        match link(src, dst) {
+            Ok(()) => CopyKind::Symlink,
+            Err(e) => {
+                self.verbose(&format!(
+                    "failed to link {} to {} ({}), copying instead",
+                    dst.display(),
+                    src.display(),
+                    e
+                ));
+                t!(fs::create_dir_all(dst));
+                self.cp_r(src, dst);
+                CopyKind::Copy
+            }
+        }
+    }
+
     /// Copies the `src` directory recursively to `dst`. Both are assumed to exist
     /// when this function is called. Unwanted files or directories can be skipped
-    /// by returning `false` from the filter function.
-    pub fn cp_filtered(&self, src: &Path, dst: &Path, filter: &dyn Fn(&Path) -> bool) {
+    /// by returning `false` from the filter function, which are counted in the
+    /// returned stats.
+    pub fn cp_filtered(
+        &self,
+        src: &Path,
+        dst: &Path,
+        filter: &dyn Fn(&Path) -> bool,
+    ) -> FilterStats {
         // Immediately recurse with an empty relative path
-        self.recurse_(src, dst, Path::new(""), filter)
+        let mut stats = FilterStats::default();
+        self.recurse_(src, dst, Path::new(""), filter, &mut stats);
+        stats
     }
 
     // Inner function does the actual work
-    fn recurse_(&self, src: &Path, dst: &Path, relative: &Path, filter: &dyn Fn(&Path) -> bool) {
+    fn recurse_(
+        &self,
+        src: &Path,
+        dst: &Path,
+        relative: &Path,
+        filter: &dyn Fn(&Path) -> bool,
+        stats: &mut FilterStats,
+    ) {
         for f in self.read_dir(src) {
             let path = f.path();
             let name = path.file_name().unwrap();
@@ -1287,11 +2139,14 @@ fn recurse_(&self, src: &Path, dst: &Path, relative: &Path, filter: &dyn Fn(&Pat
                 if t!(f.file_type()).is_dir() {
                     let _ = fs::remove_dir_all(&dst);
                     self.create_dir(&dst);
-                    self.recurse_(&path, &dst, &relative, filter);
+                    self.recurse_(&path, &dst, &relative, filter, stats);
                 } else {
                     let _ = fs::remove_file(&dst);
                     self.copy(&path, &dst);
+                    stats.copied += 1;
                 }
+            } else {
+                stats.skipped += 1;
             }
         }
     }
@@ -1309,21 +2164,91 @@ fn install(&self, src: &Path, dstdir: &Path, perms: u32) {
         let dst = dstdir.join(src.file_name().unwrap());
         self.verbose_than(1, &format!("Install {:?} to {:?}", src, dst));
         t!(fs::create_dir_all(dstdir));
-        drop(fs::remove_file(&dst));
-        {
-            if !src.exists() {
-                panic!("Error: File \"{}\" not found!", src.display());
+        if let Err(e) = install_file(src, &dst, perms, self.config.preserve_xattrs) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Like `install`, but installs each of `srcs` on a pool of up to `jobs()`
+    /// threads.
+    pub fn install_parallel(&self, srcs: Vec<PathBuf>, dstdir: &Path, perms: u32) {
+        if self.config.dry_run {
+            return;
+        }
+        t!(fs::create_dir_all(dstdir));
+        let pairs = srcs
+            .into_iter()
+            .map(|src| {
+                let dst = dstdir.join(src.file_name().unwrap());
+                self.verbose_than(1, &format!("Install {:?} to {:?}", src, dst));
+                (src, dst)
+            })
+            .collect::<Vec<_>>();
+        let threads = cmp::min(self.jobs() as usize, pairs.len());
+        let xattrs = self.config.preserve_xattrs;
+        let queue = Arc::new(Mutex::new(pairs));
+        let workers = (0..threads)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                thread::spawn(move || -> Result<(), String> {
+                    loop {
+                        let next = queue.lock().unwrap().pop();
+                        let (src, dst) = match next {
+                            Some(pair) => pair,
+                            None => return Ok(()),
+                        };
+                        install_file(&src, &dst, perms, xattrs)?;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            if let Err(e) = worker.join().unwrap() {
+                panic!("{}", e);
             }
-            let metadata = t!(src.symlink_metadata());
-            if let Err(e) = fs::copy(&src, &dst) {
-                panic!("failed to copy `{}` to `{}`: {}", src.display(), dst.display(), e)
+        }
+    }
+
+    /// Installs `src` into `dstdir` as a relative symlink, which keeps shims
+    /// and other small wrappers from being copied around. Falls back to
+    /// `install` with `perms` where symlinks can't be created.
+    pub fn install_symlink(&self, src: &Path, dstdir: &Path, perms: u32) -> CopyKind {
+        self.link_or_install(src, dstdir, perms, &|src, dst| symlink_file(src, dst))
+    }
+
+    fn link_or_install(
+        &self,
+        src: &Path,
+        dstdir: &Path,
+        perms: u32,
+        link: &dyn Fn(&Path, &Path) -> io::Result<()>,
+    ) -> CopyKind {
+        if self.config.dry_run {
+            return CopyKind::Symlink;
+        }
+        let dst = dstdir.join(src.file_name().unwrap());
+        self.verbose_than(1, &format!("Install {:?} to {:?} as a symlink", src, dst));
+        t!(fs::create_dir_all(dstdir));
+        drop(fs::remove_file(&dst));
+        if !src.exists() {
+            panic!("Error: File \"{}\" not found!", src.display());
+        }
+        // Resolve both first so that the link survives `dstdir` being a
+        // symlink itself.
+        let target = relative_path(&t!(fs::canonicalize(src)), &t!(fs::canonicalize(dstdir)));
+        match link(&target, &dst) {
+            Ok(()) => CopyKind::Symlink,
+            Err(e) => {
+                self.verbose(&format!(
+                    "failed to link {} to {} ({}), copying instead",
+                    dst.display(),
+                    src.display(),
+                    e
+                ));
+                self.install(src, dstdir, perms);
+                CopyKind::Copy
             }
-            t!(fs::set_permissions(&dst, metadata.permissions()));
-            let atime = FileTime::from_last_access_time(&metadata);
-            let mtime = FileTime::from_last_modification_time(&metadata);
-            t!(filetime::set_file_times(&dst, atime, mtime));
         }
-        chmod(&dst, perms);
     }
 
     fn create(&self, path: &Path, s: &str) {
@@ -1408,6 +2333,267 @@ fn ninja(&self) -> bool {
     }
 }
 
+/// The result of `Build::run_with_output_capture`.
+#[derive(Debug, Default)]
+pub struct CommandOutput {
+    /// `None` in dry-run mode, where nothing is run.
+    pub status: Option<ExitStatus>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    /// Whether the command succeeded, commands skipped by dry-run mode count
+    /// as successful like they do for `Build::try_run`.
+    pub fn is_success(&self) -> bool {
+        self.status.map_or(true, |status| status.success())
+    }
+}
+
+/// How `Build::copy_link` put a file in place.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CopyKind {
+    /// `src` was a symlink and was recreated at `dst`.
+    Symlink,
+    Hardlink,
+    /// Hardlinking failed, so the contents were copied.
+    Copy,
+}
+
+/// Tally of `CopyKind`s, for reporting in verbose mode.
+#[derive(Debug, Default)]
+pub struct CopyStats {
+    pub symlinks: usize,
+    pub hardlinks: usize,
+    pub copies: usize,
+}
+
+impl CopyStats {
+    pub fn record(&mut self, kind: Option<CopyKind>) {
+        match kind {
+            Some(CopyKind::Symlink) => self.symlinks += 1,
+            Some(CopyKind::Hardlink) => self.hardlinks += 1,
+            Some(CopyKind::Copy) => self.copies += 1,
+            None => {}
+        }
+    }
+}
+
+impl fmt::Display for CopyStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} hardlinked, {} copied, {} symlinked",
+            self.hardlinks, self.copies, self.symlinks
+        )
+    }
+}
+
+/// What `Build::cp_filtered` did with the entries it came across. A skipped
+/// directory counts once, whatever is inside it isn't looked at.
+#[derive(Debug, Default, PartialEq)]
+pub struct FilterStats {
+    pub copied: usize,
+    pub skipped: usize,
+}
+
+/// Copies `src` to `dst` for `Build::copy`, preserving permissions and times,
+/// along with its extended attributes if `xattrs` is set. A hardlink shares
+/// them anyway.
+fn copy_file(src: &Path, dst: &Path, xattrs: bool) -> io::Result<Option<CopyKind>> {
+    if src == dst {
+        return Ok(None);
+    }
+    let _ = fs::remove_file(dst);
+    let metadata = src.symlink_metadata()?;
+    if metadata.file_type().is_symlink() {
+        let link = fs::read_link(src)?;
+        symlink_file(link, dst)?;
+        Ok(Some(CopyKind::Symlink))
+    } else if let Ok(()) = fs::hard_link(src, dst) {
+        // Attempt to "easy copy" by creating a hard link
+        // (symlinks don't work on windows), but if that fails
+        // just fall back to a slow `copy` operation.
+        Ok(Some(CopyKind::Hardlink))
+    } else {
+        fs::copy(src, dst)?;
+        fs::set_permissions(dst, metadata.permissions())?;
+        let atime = FileTime::from_last_access_time(&metadata);
+        let mtime = FileTime::from_last_modification_time(&metadata);
+        filetime::set_file_times(dst, atime, mtime)?;
+        if xattrs {
+            copy_xattrs(src, dst)?;
+        }
+        Ok(Some(CopyKind::Copy))
+    }
+}
+
+/// Files at least this large are copied with a progress indicator by
+/// `Build::copy_with_progress`.
+const PROGRESS_THRESHOLD: u64 = 64 << 20;
+
+/// Implementation of `Build::copy_with_progress`, hardlinking with `link` and
+/// writing the progress to `progress`.
+fn copy_file_with_progress(
+    src: &Path,
+    dst: &Path,
+    terminal: bool,
+    xattrs: bool,
+    link: &dyn Fn(&Path, &Path) -> io::Result<()>,
+    progress: &mut dyn Write,
+) -> io::Result<Option<CopyKind>> {
+    let metadata = src.symlink_metadata()?;
+    let len = metadata.len();
+    if !terminal || len < PROGRESS_THRESHOLD || metadata.file_type().is_symlink() || src == dst {
+        return copy_file(src, dst, xattrs);
+    }
+    let _ = fs::remove_file(dst);
+    if link(src, dst).is_ok() {
+        return Ok(Some(CopyKind::Hardlink));
+    }
+
+    let name = src.file_name().unwrap_or_default().to_string_lossy();
+    let mut reader = fs::File::open(src)?;
+    let mut writer = fs::File::create(dst)?;
+    let mut buf = vec![0; 1 << 20];
+    let mut copied = 0;
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buf[..n])?;
+        copied += n as u64;
+        write!(progress, "\rCopying {} ({} MiB, {}%)", name, len >> 20, copied * 100 / len)?;
+    }
+    writeln!(progress)?;
+    drop(writer);
+
+    fs::set_permissions(dst, metadata.permissions())?;
+    let atime = FileTime::from_last_access_time(&metadata);
+    let mtime = FileTime::from_last_modification_time(&metadata);
+    filetime::set_file_times(dst, atime, mtime)?;
+    if xattrs {
+        copy_xattrs(src, dst)?;
+    }
+    Ok(Some(CopyKind::Copy))
+}
+
+/// Copies the extended attributes of `src` to `dst`, which is where macOS
+/// keeps things like the flags of code signatures.
+#[cfg(target_os = "macos")]
+fn copy_xattrs(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::ptr;
+
+    let path = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    };
+    let (src, dst) = (path(src)?, path(dst)?);
+    let check =
+        |ret: isize| if ret < 0 { Err(io::Error::last_os_error()) } else { Ok(ret as usize) };
+
+    let len = check(unsafe { libc::listxattr(src.as_ptr(), ptr::null_mut(), 0, 0) })?;
+    let mut names = vec![0u8; len];
+    let len =
+        check(unsafe { libc::listxattr(src.as_ptr(), names.as_mut_ptr() as *mut _, len, 0) })?;
+    for name in names[..len].split(|&b| b == 0).filter(|name| !name.is_empty()) {
+        let name = CString::new(name).unwrap();
+        let len = check(unsafe {
+            libc::getxattr(src.as_ptr(), name.as_ptr(), ptr::null_mut(), 0, 0, 0)
+        })?;
+        let mut value = vec![0u8; len];
+        let len = check(unsafe {
+            libc::getxattr(src.as_ptr(), name.as_ptr(), value.as_mut_ptr() as *mut _, len, 0, 0)
+        })?;
+        let ret = unsafe {
+            libc::setxattr(dst.as_ptr(), name.as_ptr(), value.as_ptr() as *const _, len, 0, 0)
+        };
+        check(ret as isize)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn copy_xattrs(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn stderr_is_terminal() -> bool {
+    unsafe { libc::isatty(libc::STDERR_FILENO) == 1 }
+}
+
+#[cfg(not(unix))]
+fn stderr_is_terminal() -> bool {
+    false
+}
+
+/// Returns how many of `cpus` are left after setting `reserved` aside, always
+/// at least one.
+fn unreserved_cpus(cpus: u32, reserved: u32) -> u32 {
+    cpus.saturating_sub(reserved).max(1)
+}
+
+/// Applies the per-target overrides of `target.<triple>.std-features` to the
+/// space-separated `defaults`. `-name` removes a feature, anything else adds
+/// it, with later entries taking precedence over earlier ones.
+fn merge_features(defaults: &str, overrides: &[String]) -> String {
+    let mut features = defaults.split_whitespace().map(str::to_string).collect::<Vec<_>>();
+    for feature in overrides {
+        match feature.strip_prefix('-') {
+            Some(name) => features.retain(|f| f != name),
+            None if !features.contains(feature) => features.push(feature.clone()),
+            None => {}
+        }
+    }
+    features.join(" ")
+}
+
+/// Parses the contents of a stamp file, as written by `run_cargo` in
+/// compile.rs: each entry is a dependency type followed by a path and a NUL.
+fn parse_stamp(contents: &[u8]) -> Vec<(PathBuf, DependencyType)> {
+    let mut paths = Vec::new();
+    for part in contents.split(|b| *b == 0) {
+        if part.is_empty() {
+            continue;
+        }
+        let dependency_type = match part[0] as char {
+            'h' => DependencyType::Host,
+            's' => DependencyType::TargetSelfContained,
+            't' => DependencyType::Target,
+            _ => unreachable!(),
+        };
+        let path = PathBuf::from(t!(str::from_utf8(&part[1..])));
+        paths.push((path, dependency_type));
+    }
+    paths
+}
+
+/// Copies `src` to `dst` for `Build::install`, preserving times and setting
+/// `perms`.
+fn install_file(src: &Path, dst: &Path, perms: u32, xattrs: bool) -> Result<(), String> {
+    drop(fs::remove_file(dst));
+    if !src.exists() {
+        return Err(format!("Error: File \"{}\" not found!", src.display()));
+    }
+    let metadata = t!(src.symlink_metadata());
+    if let Err(e) = fs::copy(src, dst) {
+        return Err(format!("failed to copy `{}` to `{}`: {}", src.display(), dst.display(), e));
+    }
+    t!(fs::set_permissions(dst, metadata.permissions()));
+    let atime = FileTime::from_last_access_time(&metadata);
+    let mtime = FileTime::from_last_modification_time(&metadata);
+    t!(filetime::set_file_times(dst, atime, mtime));
+    if xattrs {
+        t!(copy_xattrs(src, dst));
+    }
+    chmod(dst, perms);
+    Ok(())
+}
+
 #[cfg(unix)]
 fn chmod(path: &Path, perms: u32) {
     use std::os::unix::fs::*;
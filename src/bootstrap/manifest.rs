@@ -0,0 +1,280 @@
+//! Artifact manifests, `x.py repro-report`, `x.py verify-reproducible` and
+//! `--build-manifest`.
+//!
+//! A manifest records the sha256 of every artifact in a sysroot. Building the
+//! same commit on two machines and comparing their manifests is how release
+//! builds are checked for reproducibility: each side runs `x.py repro-report`
+//! against the manifest the other one wrote. `x.py verify-reproducible` does
+//! the same on a single machine, comparing two builds from a clean state.
+//!
+//! The build manifest is the record of a single build instead, listing the
+//! libraries from the stamp files of everything that was linked into a
+//! sysroot so CI can tell what it is caching. It's a manifest like any other,
+//! only its paths are relative to the build directory and each artifact also
+//! records its `DependencyType`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use build_helper::t;
+use serde::{Deserialize, Serialize};
+
+use crate::builder::Builder;
+use crate::clean;
+use crate::util::sha256_file;
+use crate::{Build, Compiler, DependencyType};
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Manifest {
+    pub artifacts: Vec<Artifact>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Artifact {
+    /// Path of the artifact relative to the sysroot, or to the build directory
+    /// in the build manifest, always `/` separated.
+    pub path: String,
+    /// How the artifact was linked into the sysroot, only known from stamps.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<DependencyType>,
+    pub target: String,
+    pub sha256: String,
+}
+
+/// Result of comparing two manifests, each list holds `target: path` entries.
+#[derive(Debug, Default, PartialEq)]
+pub struct ReproReport {
+    matching: Vec<String>,
+    differing: Vec<String>,
+    only_local: Vec<String>,
+    only_other: Vec<String>,
+}
+
+impl ReproReport {
+    pub fn is_reproducible(&self) -> bool {
+        self.differing.is_empty() && self.only_local.is_empty() && self.only_other.is_empty()
+    }
+
+    /// Lists the artifacts that don't match, `only_local` and `only_other`
+    /// being the titles of the artifacts missing from one of the sides.
+    fn render(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        only_local: &str,
+        only_other: &str,
+    ) -> fmt::Result {
+        writeln!(f, "{} matching artifacts", self.matching.len())?;
+        let sections = [
+            ("differing", &self.differing),
+            (only_local, &self.only_local),
+            (only_other, &self.only_other),
+        ];
+        for (title, entries) in sections.iter() {
+            if entries.is_empty() {
+                continue;
+            }
+            writeln!(f, "{} {}:", entries.len(), title)?;
+            for entry in entries.iter() {
+                writeln!(f, "    {}", entry)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ReproReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.render(f, "missing on the other machine", "missing on this machine")
+    }
+}
+
+/// A report comparing the two builds of `x.py verify-reproducible`.
+struct PassReport<'a>(&'a ReproReport);
+
+impl fmt::Display for PassReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.render(f, "only built by the first pass", "only built by the second pass")
+    }
+}
+
+pub fn repro_report(build: &Build, other_manifest: &Path) {
+    if build.config.dry_run {
+        return;
+    }
+
+    let other: Manifest = match fs::read_to_string(other_manifest) {
+        Ok(contents) => t!(serde_json::from_str(&contents)),
+        Err(e) => {
+            eprintln!("error: failed to read `{}`: {}", other_manifest.display(), e);
+            process::exit(1);
+        }
+    };
+
+    let local = sysroot_manifest(build);
+    let path = build.out.join("repro-manifest.json");
+    t!(fs::write(&path, t!(serde_json::to_string_pretty(&local))));
+    build.info(&format!("Wrote the manifest for this machine to {}", path.display()));
+
+    let report = compare(&local, &other);
+    print!("{}", report);
+    if !report.is_reproducible() {
+        process::exit(1);
+    }
+}
+
+/// Hashes the libraries of every configured target in the sysroot of the
+/// configured stage, along with the binaries of the build compiler.
+fn sysroot_manifest(build: &Build) -> Manifest {
+    let sysroot = build.sysroot_for(Compiler { stage: build.config.stage, host: build.build });
+    let mut manifest = Manifest::default();
+    add_dir(&mut manifest, &sysroot, Path::new("bin"), &build.build.triple);
+    for target in &build.targets {
+        let libdir = Path::new("lib/rustlib").join(&*target.triple).join("lib");
+        add_dir(&mut manifest, &sysroot, &libdir, &target.triple);
+    }
+    manifest
+}
+
+/// Implementation of `x.py verify-reproducible`.
+pub fn verify_reproducible(build: &Build) {
+    if build.config.dry_run {
+        return;
+    }
+
+    let report = build_twice(build, || Builder::new(build).execute_cli());
+    print!("{}", PassReport(&report));
+    if !report.is_reproducible() {
+        process::exit(1);
+    }
+}
+
+/// Runs `run_build` twice and compares the libraries listed in the stamps
+/// each run linked into a sysroot. Both runs start from a build directory
+/// cleaned of every stage, so neither reuses what the other built.
+pub fn build_twice(build: &Build, run_build: impl Fn()) -> ReproReport {
+    let mut passes = Vec::new();
+    for pass in 1..=2 {
+        build.info(&format!("Building for pass {} of 2", pass));
+        clean_stages(build);
+        build.sysroot_stamps.borrow_mut().clear();
+        build.tool_artifacts.borrow_mut().clear();
+        run_build();
+        passes.push(stamps_manifest(build));
+    }
+    compare(&passes[0], &passes[1])
+}
+
+/// Removes the `stage*` directories of every host, keeping the downloaded
+/// `stage0` compiler. LLVM and the other native dependencies are left alone,
+/// rebuilding them would take far longer than the rest of the build.
+fn clean_stages(build: &Build) {
+    for host in build.hosts.iter().chain(Some(&build.build)) {
+        let entries = match build.out.join(&*host.triple).read_dir() {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries {
+            let entry = t!(entry);
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with("stage") && name != "stage0" {
+                clean::rm_rf(&entry.path());
+            }
+        }
+    }
+}
+
+/// Hashes the files listed in the stamps recorded by the last build, with
+/// their paths relative to the build directory.
+fn stamps_manifest(build: &Build) -> Manifest {
+    let stamps = build.sysroot_stamps.borrow();
+    build_manifest(
+        &build.out,
+        stamps.iter().map(|(target, stamp)| (&*target.triple, build.read_stamp_file(stamp))),
+    )
+}
+
+/// `path` with its components separated by `/` on every platform.
+fn slash_path(path: &Path) -> String {
+    let components = path.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>();
+    components.join("/")
+}
+
+/// Adds every file directly inside `root/dir`, which may not exist.
+fn add_dir(manifest: &mut Manifest, root: &Path, dir: &Path, target: &str) {
+    let entries = match fs::read_dir(root.join(dir)) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let mut files = entries.map(|e| t!(e).path()).filter(|p| p.is_file()).collect::<Vec<_>>();
+    files.sort();
+    for file in files {
+        manifest.artifacts.push(Artifact {
+            path: slash_path(t!(file.strip_prefix(root))),
+            target: target.to_string(),
+            kind: None,
+            sha256: sha256_file(&file),
+        });
+    }
+}
+
+pub fn write_build_manifest(build: &Build) {
+    let manifest = stamps_manifest(build);
+    let path = build.out.join("manifest.json");
+    t!(fs::write(&path, t!(serde_json::to_string_pretty(&manifest))));
+    build.info(&format!("Wrote the build manifest to {}", path.display()));
+}
+
+/// Hashes the files listed in each target's stamp, with their paths relative
+/// to `out`. The same stamp gets linked into more than one sysroot, so files
+/// already listed are skipped.
+fn build_manifest<'a>(
+    out: &Path,
+    stamps: impl Iterator<Item = (&'a str, Vec<(PathBuf, DependencyType)>)>,
+) -> Manifest {
+    let mut seen = BTreeSet::new();
+    let mut manifest = Manifest::default();
+    for (target, files) in stamps {
+        for (path, kind) in files {
+            if !seen.insert((target, path.clone())) {
+                continue;
+            }
+            manifest.artifacts.push(Artifact {
+                path: slash_path(path.strip_prefix(out).unwrap_or(&path)),
+                kind: Some(kind),
+                target: target.to_string(),
+                sha256: sha256_file(&path),
+            });
+        }
+    }
+    manifest
+}
+
+fn compare(local: &Manifest, other: &Manifest) -> ReproReport {
+    fn index(manifest: &Manifest) -> BTreeMap<String, &str> {
+        manifest
+            .artifacts
+            .iter()
+            .map(|a| (format!("{}: {}", a.target, a.path), a.sha256.as_str()))
+            .collect()
+    }
+    let local = index(local);
+    let mut other = index(other);
+
+    let mut report = ReproReport::default();
+    for (key, sha256) in local {
+        match other.remove(&key) {
+            Some(other_sha256) if other_sha256 == sha256 => report.matching.push(key),
+            Some(_) => report.differing.push(key),
+            None => report.only_local.push(key),
+        }
+    }
+    report.only_other = other.into_iter().map(|(key, _)| key).collect();
+    report
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,106 @@
+use super::*;
+use crate::util::test_dir;
+
+fn artifact(path: &str, sha256: &str) -> Artifact {
+    Artifact {
+        path: path.to_string(),
+        kind: None,
+        target: "bpfel-unknown-unknown".to_string(),
+        sha256: sha256.to_string(),
+    }
+}
+
+#[test]
+fn one_mismatch() {
+    let local = Manifest {
+        artifacts: vec![
+            artifact("lib/rustlib/bpfel-unknown-unknown/lib/libcore-1234.rlib", "aaaa"),
+            artifact("lib/rustlib/bpfel-unknown-unknown/lib/libstd-5678.rlib", "bbbb"),
+        ],
+    };
+    let other: Manifest = serde_json::from_str(
+        r#"{"artifacts": [
+            {"path": "lib/rustlib/bpfel-unknown-unknown/lib/libstd-5678.rlib",
+             "target": "bpfel-unknown-unknown", "sha256": "cccc"},
+            {"path": "lib/rustlib/bpfel-unknown-unknown/lib/libcore-1234.rlib",
+             "target": "bpfel-unknown-unknown", "sha256": "aaaa"}
+        ]}"#,
+    )
+    .unwrap();
+
+    let report = compare(&local, &other);
+    assert_eq!(
+        report,
+        ReproReport {
+            matching: vec![
+                "bpfel-unknown-unknown: lib/rustlib/bpfel-unknown-unknown/lib/libcore-1234.rlib"
+                    .to_string()
+            ],
+            differing: vec![
+                "bpfel-unknown-unknown: lib/rustlib/bpfel-unknown-unknown/lib/libstd-5678.rlib"
+                    .to_string()
+            ],
+            only_local: vec![],
+            only_other: vec![],
+        }
+    );
+    assert!(!report.is_reproducible());
+    assert_eq!(
+        report.to_string(),
+        "1 matching artifacts\n\
+         1 differing:\n    \
+         bpfel-unknown-unknown: lib/rustlib/bpfel-unknown-unknown/lib/libstd-5678.rlib\n"
+    );
+}
+
+#[test]
+fn missing_artifacts() {
+    let local = Manifest { artifacts: vec![artifact("bin/rustc", "aaaa")] };
+    let other = Manifest { artifacts: vec![artifact("bin/rustdoc", "bbbb")] };
+    let report = compare(&local, &other);
+    assert_eq!(report.only_local, ["bpfel-unknown-unknown: bin/rustc"]);
+    assert_eq!(report.only_other, ["bpfel-unknown-unknown: bin/rustdoc"]);
+    assert!(compare(&local, &local).is_reproducible());
+}
+
+#[test]
+fn build_manifest_schema() {
+    let dir = test_dir();
+    let libstd = dir.join("libstd-1234.rlib");
+    let crt = dir.join("crt1.o");
+    t!(fs::write(&libstd, "std"));
+    t!(fs::write(&crt, "crt"));
+
+    let files = vec![
+        (libstd.clone(), DependencyType::Target),
+        (crt.clone(), DependencyType::TargetSelfContained),
+    ];
+    // Linked into two sysroots, listed once.
+    let stamps = vec![("bpfel-unknown-unknown", files.clone()), ("bpfel-unknown-unknown", files)];
+    let json = serde_json::to_string(&build_manifest(&dir, stamps.into_iter())).unwrap();
+
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    let artifacts = value["artifacts"].as_array().unwrap();
+    assert_eq!(artifacts.len(), 2);
+    for artifact in artifacts {
+        let mut keys = artifact.as_object().unwrap().keys().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, ["kind", "path", "sha256", "target"]);
+        assert_eq!(artifact["target"], "bpfel-unknown-unknown");
+        let sha256 = artifact["sha256"].as_str().unwrap();
+        assert_eq!(sha256.len(), 64);
+        assert!(sha256.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+    assert_eq!(artifacts[0]["path"], "libstd-1234.rlib");
+    assert_eq!(artifacts[0]["kind"], "target");
+    assert_eq!(artifacts[1]["kind"], "target-self-contained");
+
+    // It's a manifest `x.py repro-report` can compare against.
+    let manifest: Manifest = serde_json::from_str(&json).unwrap();
+    assert_eq!(manifest.artifacts[1].path, "crt1.o");
+    assert_eq!(manifest.artifacts[1].kind, Some(DependencyType::TargetSelfContained));
+    assert_eq!(manifest.artifacts[1].sha256, sha256_file(&crt));
+    let sysroot =
+        Manifest { artifacts: vec![Artifact { kind: None, ..manifest.artifacts[1].clone() }] };
+    assert_eq!(compare(&sysroot, &manifest).matching, ["bpfel-unknown-unknown: crt1.o"]);
+}
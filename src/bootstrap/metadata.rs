@@ -1,10 +1,14 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::UNIX_EPOCH;
 
-use build_helper::output;
-use serde::Deserialize;
+use build_helper::{output, t};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-use crate::cache::INTERNER;
+use crate::cache::{Interned, INTERNER};
 use crate::{Build, Crate};
 
 #[derive(Deserialize)]
@@ -12,7 +16,7 @@ struct Output {
     packages: Vec<Package>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 struct Package {
     id: String,
     name: String,
@@ -21,13 +25,38 @@ struct Package {
     dependencies: Vec<Dependency>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 struct Dependency {
     name: String,
     source: Option<String>,
 }
 
+/// The in-tree packages found by the last `cargo metadata`, stored in
+/// `build/metadata.json`. They're reused for as long as none of the manifests
+/// they came from nor `Cargo.lock` changed, as recorded by `key`.
+#[derive(Serialize, Deserialize)]
+struct MetadataCache {
+    key: String,
+    packages: Vec<Package>,
+}
+
 pub fn build(build: &mut Build) {
+    let cache = build.out.join("metadata.json");
+    let packages = match load_cache(&cache, &build.src) {
+        Some(packages) => {
+            build.verbose("reusing the cached output of `cargo metadata`");
+            packages
+        }
+        None => {
+            let packages = cargo_metadata(build);
+            store_cache(&cache, &build.src, &packages);
+            packages
+        }
+    };
+    build.crates.extend(crates(packages));
+}
+
+fn cargo_metadata(build: &Build) -> Vec<Package> {
     // Run `cargo metadata` to figure out what crates we're testing.
     let mut cargo = Command::new(&build.initial_cargo);
     cargo
@@ -35,22 +64,64 @@ pub fn build(build: &mut Build) {
         .arg("--format-version")
         .arg("1")
         .arg("--no-deps")
+        .args(build.cargo_lock_flags())
         .arg("--manifest-path")
         .arg(build.src.join("Cargo.toml"));
     let output = output(&mut cargo);
     let output: Output = serde_json::from_str(&output).unwrap();
-    for package in output.packages {
-        if package.source.is_none() {
-            let name = INTERNER.intern_string(package.name);
-            let mut path = PathBuf::from(package.manifest_path);
-            path.pop();
-            let deps = package
-                .dependencies
-                .into_iter()
-                .filter(|dep| dep.source.is_none())
-                .map(|dep| INTERNER.intern_string(dep.name))
-                .collect();
-            build.crates.insert(name, Crate { name, id: package.id, deps, path });
-        }
+    output.packages.into_iter().filter(|package| package.source.is_none()).collect()
+}
+
+fn crates(packages: Vec<Package>) -> HashMap<Interned<String>, Crate> {
+    let mut crates = HashMap::new();
+    for package in packages {
+        let name = INTERNER.intern_string(package.name);
+        let mut path = PathBuf::from(package.manifest_path);
+        path.pop();
+        let deps = package
+            .dependencies
+            .into_iter()
+            .filter(|dep| dep.source.is_none())
+            .map(|dep| INTERNER.intern_string(dep.name))
+            .collect();
+        crates.insert(name, Crate { name, id: package.id, deps, path });
+    }
+    crates
+}
+
+/// Returns the packages in `cache` if they are still up to date.
+fn load_cache(cache: &Path, src: &Path) -> Option<Vec<Package>> {
+    let cache: MetadataCache = serde_json::from_slice(&fs::read(cache).ok()?).ok()?;
+    if cache_key(src, &cache.packages)? == cache.key {
+        Some(cache.packages)
+    } else {
+        None
     }
 }
+
+fn store_cache(cache: &Path, src: &Path, packages: &[Package]) {
+    if let Some(key) = cache_key(src, packages) {
+        t!(fs::create_dir_all(cache.parent().unwrap()));
+        let contents = MetadataCache { key, packages: packages.to_vec() };
+        t!(fs::write(cache, t!(serde_json::to_string(&contents))));
+    }
+}
+
+/// Hashes the modification times of the workspace's `Cargo.toml` and
+/// `Cargo.lock`, and of the manifests of `packages`. Adding or removing a
+/// crate always changes one of those. Returns `None` if one is missing.
+fn cache_key(src: &Path, packages: &[Package]) -> Option<String> {
+    let manifests = [src.join("Cargo.toml"), src.join("Cargo.lock")];
+    let manifests =
+        manifests.iter().cloned().chain(packages.iter().map(|p| PathBuf::from(&p.manifest_path)));
+    let mut hasher = Sha256::new();
+    for manifest in manifests {
+        let mtime = fs::metadata(&manifest).and_then(|m| m.modified()).ok()?;
+        let mtime = mtime.duration_since(UNIX_EPOCH).ok()?;
+        hasher.update(format!("{} {}\n", manifest.display(), mtime.as_nanos()));
+    }
+    Some(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,66 @@
+use super::*;
+use crate::util::test_dir;
+use std::time::{Duration, SystemTime};
+
+use filetime::FileTime;
+
+/// Creates a workspace with a single crate in a fresh directory, returning
+/// the directory and the package `cargo metadata` would report for it.
+fn workspace() -> (PathBuf, Vec<Package>) {
+    let dir = test_dir();
+    t!(fs::create_dir_all(dir.join("foo")));
+    t!(fs::write(dir.join("Cargo.toml"), "[workspace]\nmembers = [\"foo\"]\n"));
+    t!(fs::write(dir.join("Cargo.lock"), ""));
+    t!(fs::write(dir.join("foo/Cargo.toml"), "[package]\nname = \"foo\"\n"));
+
+    let dep = |name: &str, source: Option<&str>| Dependency {
+        name: name.to_string(),
+        source: source.map(str::to_string),
+    };
+    let foo = Package {
+        id: "foo 0.0.0 (path+file:///foo)".to_string(),
+        name: "foo".to_string(),
+        source: None,
+        manifest_path: dir.join("foo/Cargo.toml").display().to_string(),
+        dependencies: vec![
+            dep("core", None),
+            dep("libc", Some("registry+https://github.com/rust-lang/crates.io-index")),
+        ],
+    };
+    (dir, vec![foo])
+}
+
+#[test]
+fn cache_hit() {
+    let (dir, packages) = workspace();
+    let cache = dir.join("build/metadata.json");
+    assert_eq!(load_cache(&cache, &dir), None);
+
+    store_cache(&cache, &dir, &packages);
+    let cached = load_cache(&cache, &dir).expect("the cache is up to date");
+    assert_eq!(cached, packages);
+
+    // The crate graph doesn't depend on where the packages came from.
+    let crates_from_cache = crates(cached);
+    assert_eq!(crates_from_cache, crates(packages));
+    let foo = &crates_from_cache[&INTERNER.intern_str("foo")];
+    assert_eq!(foo.deps.iter().map(|dep| &dep[..]).collect::<Vec<_>>(), ["core"]);
+    assert_eq!(foo.path, dir.join("foo"));
+}
+
+#[test]
+fn cache_miss() {
+    let (dir, packages) = workspace();
+    let cache = dir.join("build/metadata.json");
+    store_cache(&cache, &dir, &packages);
+
+    let later = FileTime::from_system_time(SystemTime::now() + Duration::from_secs(60));
+    t!(filetime::set_file_mtime(dir.join("Cargo.lock"), later));
+    assert_eq!(load_cache(&cache, &dir), None);
+
+    // Same for the manifest of a crate, which is where its dependencies are.
+    store_cache(&cache, &dir, &packages);
+    assert!(load_cache(&cache, &dir).is_some());
+    t!(filetime::set_file_mtime(dir.join("foo/Cargo.toml"), later));
+    assert_eq!(load_cache(&cache, &dir), None);
+}
@@ -14,16 +14,19 @@
 use std::fs::{self, File};
 use std::io;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{self, Command};
 
 use build_helper::{output, t};
 
 use crate::builder::{Builder, RunConfig, ShouldRun, Step};
-use crate::config::TargetSelection;
+use crate::config::{Config, TargetSelection};
 use crate::util::{self, exe};
-use crate::GitRepo;
+use crate::{compile, test, Build, GitRepo};
 use build_helper::up_to_date;
 
+#[cfg(test)]
+mod tests;
+
 pub struct Meta {
     stamp: HashStamp,
     build_llvm_config: PathBuf,
@@ -134,7 +137,24 @@ fn run(self, builder: &Builder<'_>) -> PathBuf {
             panic!("shared linking to LLVM is not currently supported on {}", target.triple);
         }
 
+        let profile = match (builder.config.llvm_optimize, builder.config.llvm_release_debuginfo) {
+            (false, _) => "Debug",
+            (true, false) => "Release",
+            (true, true) => "RelWithDebInfo",
+        };
+
         builder.info(&format!("Building LLVM for {}", target));
+        if !builder.config.dry_run {
+            match util::free_space(&builder.out) {
+                Ok(available) => {
+                    let needed = llvm_disk_space_needed(profile);
+                    if let Err(e) = check_disk_space(available, needed, builder.config.strict) {
+                        panic!("{}", e);
+                    }
+                }
+                Err(e) => builder.verbose(&format!("failed to query free disk space: {}", e)),
+            }
+        }
         t!(stamp.remove());
         let _time = util::timeit(&builder);
         t!(fs::create_dir_all(&out_dir));
@@ -142,12 +162,6 @@ fn run(self, builder: &Builder<'_>) -> PathBuf {
         // http://llvm.org/docs/CMake.html
         let mut cfg = cmake::Config::new(builder.src.join(root));
 
-        let profile = match (builder.config.llvm_optimize, builder.config.llvm_release_debuginfo) {
-            (false, _) => "Debug",
-            (true, false) => "Release",
-            (true, true) => "RelWithDebInfo",
-        };
-
         // NOTE: remember to also update `config.toml.example` when changing the
         // defaults!
         let llvm_targets = match &builder.config.llvm_targets {
@@ -178,7 +192,7 @@ fn run(self, builder: &Builder<'_>) -> PathBuf {
             .define("LLVM_ENABLE_LIBEDIT", "OFF")
             .define("LLVM_ENABLE_BINDINGS", "OFF")
             .define("LLVM_ENABLE_Z3_SOLVER", "OFF")
-            .define("LLVM_PARALLEL_COMPILE_JOBS", builder.jobs().to_string())
+            .define("LLVM_PARALLEL_COMPILE_JOBS", builder.llvm_jobs(target).to_string())
             .define("LLVM_TARGET_ARCH", target_native.split('-').next().unwrap())
             .define("LLVM_DEFAULT_TARGET_TRIPLE", target_native);
 
@@ -206,6 +220,10 @@ fn run(self, builder: &Builder<'_>) -> PathBuf {
             }
         }
 
+        if let Some(phase) = &builder.llvm_pgo {
+            configure_pgo(&mut cfg, phase);
+        }
+
         // This setting makes the LLVM tools link to the dynamic LLVM library,
         // which saves both memory during parallel links and overall disk space
         // for the tools. We don't do this on every platform as it doesn't work
@@ -289,7 +307,7 @@ fn run(self, builder: &Builder<'_>) -> PathBuf {
         }
 
         // http://llvm.org/docs/HowToCrossCompileLLVM.html
-        if target != builder.config.build {
+        if builder.is_cross_compiling(target) {
             builder.ensure(Llvm { target: builder.config.build });
             // FIXME: if the llvm root for the build triple is overridden then we
             //        should use llvm-tblgen from there, also should verify that it
@@ -346,6 +364,240 @@ fn run(self, builder: &Builder<'_>) -> PathBuf {
     }
 }
 
+/// Which of the two builds of `x.py profile-llvm` LLVM is built for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PgoPhase {
+    /// Instrumented to write profiles of whatever it's running to the given
+    /// directory.
+    Instrument(PathBuf),
+    /// Optimized with the merged profiles at the given path.
+    Use(PathBuf),
+}
+
+/// Returns the CMake definitions building LLVM for `phase`. Those of the
+/// other phase are reset as CMake would otherwise keep them in its cache.
+fn pgo_defines(phase: &PgoPhase) -> Vec<(&'static str, String)> {
+    match phase {
+        PgoPhase::Instrument(profiles) => vec![
+            ("LLVM_BUILD_INSTRUMENTED", "IR".to_string()),
+            ("LLVM_PROFILE_DATA_DIR", profiles.display().to_string()),
+            ("LLVM_PROFDATA_FILE", String::new()),
+        ],
+        PgoPhase::Use(profdata) => vec![
+            ("LLVM_BUILD_INSTRUMENTED", "OFF".to_string()),
+            ("LLVM_PROFILE_DATA_DIR", String::new()),
+            ("LLVM_PROFDATA_FILE", profdata.display().to_string()),
+        ],
+    }
+}
+
+fn configure_pgo(cfg: &mut cmake::Config, phase: &PgoPhase) {
+    for (key, value) in pgo_defines(phase) {
+        cfg.define(key, value);
+    }
+}
+
+/// Returns the `llvm-profdata` of the C compiler LLVM is built with. The
+/// profiles are written by that compiler's runtime, and only a tool of the
+/// same version is sure to read them; the in-tree one may well be newer.
+fn host_profdata(build: &Build, host: TargetSelection) -> PathBuf {
+    let mut cmd = Command::new(build.cc(host));
+    PathBuf::from(output(cmd.arg("-print-prog-name=llvm-profdata")).trim())
+}
+
+/// Implementation of `x.py profile-llvm`.
+///
+/// Codegen is where most of the time compiling a BPF program goes, so LLVM
+/// is built instrumented first, then the standard library and the BPF smoke
+/// test are compiled for BPF by a stage 1 compiler linked to it, and finally
+/// LLVM is rebuilt optimized with the profiles it left in `llvm_out/pgo`. Later builds keep using the
+/// optimized LLVM until something makes it rebuild.
+pub fn profile_llvm(build: &mut Build) {
+    let host = build.config.build;
+    let bpf = match build.targets.iter().find(|target| target.contains("bpf")) {
+        Some(&target) => target,
+        None => {
+            eprintln!("error: profile-llvm needs a BPF target, pass one with `--target`");
+            process::exit(1);
+        }
+    };
+    let stamp = build.llvm_out(host).join("llvm-finished-building");
+    let profiles = build.llvm_out(host).join("pgo");
+    let profdata = profiles.join("merged.profdata");
+
+    build.info("Building LLVM instrumented for profiling");
+    build.llvm_pgo = Some(PgoPhase::Instrument(profiles.clone()));
+    if !build.config.dry_run {
+        let _ = fs::remove_file(&stamp);
+    }
+    {
+        let builder = Builder::new(build);
+        builder.ensure(Llvm { target: host });
+        let compiler = builder.compiler(1, host);
+        // Building LLVM and rustc ran instrumented tools, e.g. `llvm-tblgen`
+        // and `llvm-config`, whose profiles would only skew the ones of
+        // compiling for BPF.
+        if !builder.config.dry_run {
+            let _ = fs::remove_dir_all(&profiles);
+            t!(fs::create_dir_all(&profiles));
+        }
+
+        builder.info(&format!("Gathering LLVM profiles compiling for {}", bpf));
+        builder.ensure(compile::Std { compiler, target: bpf });
+        let program = profiles.join("entrypoint.so");
+        builder.run(&mut test::bpf_smoke_command(&builder, compiler, bpf, &program));
+
+        if !builder.config.dry_run {
+            let raw = t!(fs::read_dir(&profiles))
+                .map(|entry| t!(entry).path())
+                .filter(|path| path.extension().map_or(false, |ext| ext == "profraw"))
+                .collect::<Vec<_>>();
+            if raw.is_empty() {
+                panic!("no profiles were written to `{}`", profiles.display());
+            }
+            let mut merge = Command::new(host_profdata(&builder, host));
+            merge.arg("merge").arg("-o").arg(&profdata).args(&raw);
+            builder.run(&mut merge);
+        }
+    }
+
+    build.info("Rebuilding LLVM optimized with the profiles");
+    build.llvm_pgo = Some(PgoPhase::Use(profdata));
+    if !build.config.dry_run {
+        let _ = fs::remove_file(&stamp);
+    }
+    Builder::new(build).ensure(Llvm { target: host });
+    build.llvm_pgo = None;
+}
+
+/// The LLVM tools that make up the custom pass pipeline, see `LlvmPassTools`.
+const LLVM_PASS_TOOLS: &[&str] = &["opt", "llc"];
+
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct LlvmPassTools {
+    pub target: TargetSelection,
+}
+
+impl Step for LlvmPassTools {
+    type Output = PathBuf; // directory containing `opt` and `llc`
+
+    const ONLY_HOSTS: bool = true;
+
+    fn should_run(run: ShouldRun<'_>) -> ShouldRun<'_> {
+        run.never()
+    }
+
+    /// Keeps a copy of `opt` and `llc` in `build/cache`, keyed by the commit of
+    /// the LLVM submodule and the `[llvm]` options they're built with.
+    ///
+    /// The cache survives `x.py clean`, so as long as neither changes these
+    /// tools are reused. Callers must have built LLVM for `target` already,
+    /// its tools are copied into the cache when it's stale.
+    fn run(self, builder: &Builder<'_>) -> PathBuf {
+        let target = self.target;
+        let cache_dir = builder.out.join("cache").join("llvm-tools").join(&*target.triple);
+        let key = llvm_pass_tools_key(&builder.config, builder.in_tree_llvm_info.sha());
+        let stamp = HashStamp::new(cache_dir.join(".llvm-sha"), key.as_deref());
+        let tools = LLVM_PASS_TOOLS.iter().map(|tool| exe(tool, target)).collect::<Vec<_>>();
+
+        if tool_cache_is_fresh(&cache_dir, &stamp, &tools) {
+            builder.verbose(&format!("using cached LLVM pass tools from {}", cache_dir.display()));
+            return cache_dir;
+        }
+
+        if builder.config.dry_run {
+            return cache_dir;
+        }
+
+        builder.info(&format!("Caching LLVM pass tools ({})", target));
+        update_tool_cache(&cache_dir, &stamp, &builder.llvm_out(target).join("bin"), &tools);
+        cache_dir
+    }
+}
+
+/// Roughly how much disk space a build of LLVM with the given CMake profile
+/// takes up, most of which is debug info when there is any.
+fn llvm_disk_space_needed(profile: &str) -> u64 {
+    const GIB: u64 = 1 << 30;
+    match profile {
+        "Debug" => 50 * GIB,
+        "RelWithDebInfo" => 25 * GIB,
+        _ => 5 * GIB,
+    }
+}
+
+/// Running out of disk space halfway through leaves a broken LLVM build
+/// behind, which then has to be cleaned by hand. Warns if `available` bytes
+/// are less than `needed`, or fails if `strict`.
+fn check_disk_space(available: u64, needed: u64, strict: bool) -> Result<(), String> {
+    if available >= needed {
+        return Ok(());
+    }
+    const GIB: f64 = (1u64 << 30) as f64;
+    let problem = format!(
+        "only {:.1} GiB of disk space is available, but building LLVM takes about {:.0} GiB",
+        available as f64 / GIB,
+        needed as f64 / GIB
+    );
+    if strict {
+        Err(problem)
+    } else {
+        eprintln!("warning: {}", problem);
+        Ok(())
+    }
+}
+
+/// Returns what the LLVM pass tools cache is keyed by, the LLVM commit `sha`
+/// followed by every `[llvm]` option of `config` that changes how the tools
+/// are built. Without a known commit there's no key.
+fn llvm_pass_tools_key(config: &Config, sha: Option<&str>) -> Option<String> {
+    let sha = sha?;
+    Some(format!(
+        "{}\nassertions={} optimize={} thin-lto={} release-debuginfo={} static-libstdcpp={} \
+         link-shared={} use-libcxx={} targets={:?} experimental-targets={:?} \
+         version-suffix={:?} use-linker={:?} allow-old-toolchain={:?} polly={:?} \
+         clang-cl={:?} cflags={:?} cxxflags={:?} ldflags={:?}",
+        sha,
+        config.llvm_assertions,
+        config.llvm_optimize,
+        config.llvm_thin_lto,
+        config.llvm_release_debuginfo,
+        config.llvm_static_stdcpp,
+        config.llvm_link_shared,
+        config.llvm_use_libcxx,
+        config.llvm_targets,
+        config.llvm_experimental_targets,
+        config.llvm_version_suffix,
+        config.llvm_use_linker,
+        config.llvm_allow_old_toolchain,
+        config.llvm_polly,
+        config.llvm_clang_cl,
+        config.llvm_cflags,
+        config.llvm_cxxflags,
+        config.llvm_ldflags,
+    ))
+}
+
+/// Returns whether `cache_dir` holds all `tools` built for the key in `stamp`.
+/// Without a key the cache is never trusted.
+fn tool_cache_is_fresh(cache_dir: &Path, stamp: &HashStamp, tools: &[String]) -> bool {
+    stamp.hash.is_some()
+        && stamp.is_done()
+        && tools.iter().all(|tool| cache_dir.join(tool).exists())
+}
+
+/// Replaces the contents of `cache_dir` with `tools` from `src_bindir`.
+fn update_tool_cache(cache_dir: &Path, stamp: &HashStamp, src_bindir: &Path, tools: &[String]) {
+    if cache_dir.exists() {
+        t!(fs::remove_dir_all(cache_dir));
+    }
+    t!(fs::create_dir_all(cache_dir));
+    for tool in tools {
+        t!(fs::copy(src_bindir.join(tool), cache_dir.join(tool)));
+    }
+    t!(stamp.write());
+}
+
 fn check_llvm_version(builder: &Builder<'_>, llvm_config: &Path) {
     if !builder.config.llvm_version_check {
         return;
@@ -366,6 +618,12 @@ fn check_llvm_version(builder: &Builder<'_>, llvm_config: &Path) {
     panic!("\n\nbad LLVM version: {}, need >=9.0\n\n", version)
 }
 
+/// Arguments CMake passes on to the build tool, Ninja or Make, to cap the
+/// number of jobs it runs at once.
+fn build_jobs_args(build: &Build, target: TargetSelection) -> Vec<String> {
+    vec!["-j".to_string(), build.llvm_jobs(target).to_string()]
+}
+
 fn configure_cmake(
     builder: &Builder<'_>,
     target: TargetSelection,
@@ -386,7 +644,7 @@ fn configure_cmake(
     }
     cfg.target(&target.triple).host(&builder.config.build.triple);
 
-    if target != builder.config.build {
+    if builder.is_cross_compiling(target) {
         if target.contains("netbsd") {
             cfg.define("CMAKE_SYSTEM_NAME", "NetBSD");
         } else if target.contains("freebsd") {
@@ -478,7 +736,9 @@ fn configure_cmake(
             .define("CMAKE_ASM_COMPILER", sanitize_cc(cc));
     }
 
-    cfg.build_arg("-j").build_arg(builder.jobs().to_string());
+    for arg in build_jobs_args(builder, target) {
+        cfg.build_arg(arg);
+    }
     let mut cflags = builder.cflags(target, GitRepo::Llvm).join(" ");
     if let Some(ref s) = builder.config.llvm_cflags {
         cflags.push_str(&format!(" {}", s));
@@ -607,7 +867,7 @@ fn run(self, builder: &Builder<'_>) -> PathBuf {
         // we specifically tell it where to find those. This is likely super
         // brittle and will break over time. If anyone knows better how to
         // cross-compile LLD it would be much appreciated to fix this!
-        if target != builder.config.build {
+        if builder.is_cross_compiling(target) {
             cfg.env("LLVM_CONFIG_SHIM_REPLACE", &builder.config.build.triple)
                 .env("LLVM_CONFIG_SHIM_REPLACE_WITH", &target.triple)
                 .define(
@@ -0,0 +1,174 @@
+use super::*;
+use crate::builder::tests::configure;
+use crate::config::Config;
+use crate::util::test_dir;
+
+fn tool_cache_dirs() -> (PathBuf, PathBuf) {
+    let dir = test_dir();
+    let bindir = dir.join("llvm/bin");
+    t!(fs::create_dir_all(&bindir));
+    for tool in LLVM_PASS_TOOLS {
+        t!(fs::write(bindir.join(tool), "built from aaaa"));
+    }
+    (bindir, dir.join("cache"))
+}
+
+fn tools() -> Vec<String> {
+    LLVM_PASS_TOOLS.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn tool_cache_reused_for_same_sha() {
+    let (bindir, cache) = tool_cache_dirs();
+    let stamp = HashStamp::new(cache.join(".llvm-sha"), Some("aaaa"));
+    assert!(!tool_cache_is_fresh(&cache, &stamp, &tools()));
+
+    update_tool_cache(&cache, &stamp, &bindir, &tools());
+    let stamp = HashStamp::new(cache.join(".llvm-sha"), Some("aaaa"));
+    assert!(tool_cache_is_fresh(&cache, &stamp, &tools()));
+    assert_eq!(t!(fs::read_to_string(cache.join("opt"))), "built from aaaa");
+}
+
+#[test]
+fn tool_cache_rebuilt_for_new_sha() {
+    let (bindir, cache) = tool_cache_dirs();
+    let stamp = HashStamp::new(cache.join(".llvm-sha"), Some("aaaa"));
+    update_tool_cache(&cache, &stamp, &bindir, &tools());
+
+    let stamp = HashStamp::new(cache.join(".llvm-sha"), Some("bbbb"));
+    assert!(!tool_cache_is_fresh(&cache, &stamp, &tools()));
+
+    t!(fs::write(bindir.join("opt"), "built from bbbb"));
+    update_tool_cache(&cache, &stamp, &bindir, &tools());
+    assert!(tool_cache_is_fresh(&cache, &stamp, &tools()));
+    assert_eq!(t!(fs::read_to_string(cache.join("opt"))), "built from bbbb");
+}
+
+#[test]
+fn tool_cache_needs_known_sha() {
+    let (bindir, cache) = tool_cache_dirs();
+    let stamp = HashStamp::new(cache.join(".llvm-sha"), None);
+    update_tool_cache(&cache, &stamp, &bindir, &tools());
+    assert!(!tool_cache_is_fresh(&cache, &stamp, &tools()));
+}
+
+#[test]
+fn tool_cache_keyed_by_llvm_config() {
+    let mut config = Config::default();
+    let key = llvm_pass_tools_key(&config, Some("aaaa")).unwrap();
+    assert!(key.starts_with("aaaa\n"), "{}", key);
+    assert_eq!(llvm_pass_tools_key(&config, Some("aaaa")), Some(key.clone()));
+    assert_eq!(llvm_pass_tools_key(&config, None), None);
+
+    config.llvm_assertions = !config.llvm_assertions;
+    assert_ne!(llvm_pass_tools_key(&config, Some("aaaa")), Some(key.clone()));
+    config.llvm_assertions = !config.llvm_assertions;
+    config.llvm_targets = Some("BPF".to_string());
+    assert_ne!(llvm_pass_tools_key(&config, Some("aaaa")), Some(key));
+}
+
+#[test]
+fn ninja_jobs() {
+    let mut config = configure("build", &["A"], &["A", "bpfel-unknown-unknown"]);
+    config.jobs = Some(3);
+    let host = TargetSelection::from_user("A");
+    let bpf = TargetSelection::from_user("bpfel-unknown-unknown");
+    let mut target = crate::config::Target::from_triple(&bpf.triple);
+    target.llvm_jobs = Some(1);
+    config.target_config.insert(bpf, target);
+    let build = Build::new(config);
+
+    assert_eq!(build_jobs_args(&build, host), ["-j", "3"]);
+    assert_eq!(build_jobs_args(&build, bpf), ["-j", "1"]);
+}
+
+#[test]
+fn disk_space_threshold() {
+    const GIB: u64 = 1 << 30;
+    let release = llvm_disk_space_needed("Release");
+    assert!(release < llvm_disk_space_needed("RelWithDebInfo"));
+    assert!(llvm_disk_space_needed("RelWithDebInfo") < llvm_disk_space_needed("Debug"));
+
+    assert_eq!(check_disk_space(release, release, true), Ok(()));
+    assert_eq!(check_disk_space(100 * GIB, llvm_disk_space_needed("Debug"), true), Ok(()));
+    // Too little space only fails the build if asked to.
+    assert_eq!(check_disk_space(release - 1, release, false), Ok(()));
+    let err = check_disk_space(3 * GIB / 2, release, true).unwrap_err();
+    assert!(err.contains("only 1.5 GiB"), "{}", err);
+    assert!(err.contains("about 5 GiB"), "{}", err);
+}
+
+#[test]
+fn pgo_phases() {
+    let profiles = PathBuf::from("build/A/llvm/pgo");
+    let defines = pgo_defines(&PgoPhase::Instrument(profiles));
+    assert_eq!(
+        defines,
+        [
+            ("LLVM_BUILD_INSTRUMENTED", "IR".to_string()),
+            ("LLVM_PROFILE_DATA_DIR", "build/A/llvm/pgo".to_string()),
+            ("LLVM_PROFDATA_FILE", String::new())
+        ]
+    );
+
+    // The optimized build has to undo the instrumentation CMake cached.
+    let profdata = PathBuf::from("build/A/llvm/pgo/merged.profdata");
+    let defines = pgo_defines(&PgoPhase::Use(profdata));
+    assert_eq!(
+        defines,
+        [
+            ("LLVM_BUILD_INSTRUMENTED", "OFF".to_string()),
+            ("LLVM_PROFILE_DATA_DIR", String::new()),
+            ("LLVM_PROFDATA_FILE", "build/A/llvm/pgo/merged.profdata".to_string())
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn pgo_defines_reach_cmake() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = test_dir();
+    let log = dir.join("cmake.log");
+    let cmake = dir.join("cmake");
+    t!(fs::write(&cmake, format!("#!/bin/sh\necho \"$@\" >> {}\n", log.display())));
+    t!(fs::set_permissions(&cmake, fs::Permissions::from_mode(0o755)));
+    // No other test runs CMake, so pointing the `cmake` crate at the stub
+    // doesn't race with anything.
+    env::set_var("CMAKE", &cmake);
+
+    let out = dir.join("out");
+    t!(fs::create_dir_all(&out));
+    let profiles = dir.join("pgo");
+    let profdata = profiles.join("merged.profdata");
+    let phases = [PgoPhase::Instrument(profiles.clone()), PgoPhase::Use(profdata.clone())];
+    for phase in &phases {
+        let _ = fs::remove_file(&log);
+        let mut cfg = cmake::Config::new(&dir);
+        cfg.out_dir(&out)
+            .profile("Release")
+            .target(env!("BUILD_TRIPLE"))
+            .host(env!("BUILD_TRIPLE"));
+        configure_pgo(&mut cfg, phase);
+        cfg.build();
+
+        let configure = t!(fs::read_to_string(&log)).lines().next().unwrap().to_string();
+        let args = configure.split(' ').collect::<Vec<_>>();
+        let expected = match phase {
+            PgoPhase::Instrument(_) => [
+                "-DLLVM_BUILD_INSTRUMENTED=IR".to_string(),
+                format!("-DLLVM_PROFILE_DATA_DIR={}", profiles.display()),
+                "-DLLVM_PROFDATA_FILE=".to_string(),
+            ],
+            PgoPhase::Use(_) => [
+                "-DLLVM_BUILD_INSTRUMENTED=OFF".to_string(),
+                "-DLLVM_PROFILE_DATA_DIR=".to_string(),
+                format!("-DLLVM_PROFDATA_FILE={}", profdata.display()),
+            ],
+        };
+        for define in &expected {
+            assert!(args.contains(&define.as_str()), "`{}` missing from `{}`", define, configure);
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Rewriting the RPATHs of dist images for `dist.relocatable`.
+//!
+//! Binaries linked against the LLVM or the libraries of the build directory
+//! may carry an absolute `DT_RPATH` or `DT_RUNPATH`, which stops working as
+//! soon as the toolchain is unpacked anywhere else. Before an image is
+//! packaged every ELF file in it with such an entry has it replaced by one
+//! relative to `$ORIGIN`, pointing at the `lib` directory of the image.
+//!
+//! Like `patchelf --set-rpath` with the restriction that the string table of
+//! the file is rewritten in place: the new RPATH can't be any longer than the
+//! old one. Paths into a build directory are far longer than `$ORIGIN/../lib`
+//! so this doesn't come up in practice.
+
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use build_helper::t;
+
+use crate::util::relative_path;
+use crate::Build;
+
+const PT_LOAD: u64 = 1;
+const PT_DYNAMIC: u64 = 2;
+const DT_NULL: u64 = 0;
+const DT_STRTAB: u64 = 5;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+
+/// An ELF file, only as far as finding its dynamic section goes.
+struct Elf<'a> {
+    data: &'a [u8],
+    is_64: bool,
+    big_endian: bool,
+}
+
+impl<'a> Elf<'a> {
+    fn parse(data: &'a [u8]) -> Option<Elf<'a>> {
+        if data.get(..4)? != b"\x7fELF" {
+            return None;
+        }
+        Some(Elf { data, is_64: *data.get(4)? == 2, big_endian: *data.get(5)? == 2 })
+    }
+
+    fn uint(&self, offset: usize, size: usize) -> Option<u64> {
+        let bytes = self.data.get(offset..offset.checked_add(size)?)?;
+        let fold = |n: u64, &b: &u8| n << 8 | u64::from(b);
+        Some(if self.big_endian {
+            bytes.iter().fold(0, fold)
+        } else {
+            bytes.iter().rev().fold(0, fold)
+        })
+    }
+
+    /// Reads a field that is 4 bytes in ELF32 and 8 bytes in ELF64.
+    fn word(&self, offset32: usize, offset64: usize) -> Option<u64> {
+        if self.is_64 {
+            self.uint(offset64, 8)
+        } else {
+            self.uint(offset32, 4)
+        }
+    }
+
+    /// Returns `(type, offset, vaddr, filesz)` of each program header.
+    fn program_headers(&self) -> Option<Vec<(u64, u64, u64, u64)>> {
+        let phoff = self.word(0x1c, 0x20)? as usize;
+        let phentsize = self.uint(if self.is_64 { 0x36 } else { 0x2a }, 2)? as usize;
+        let phnum = self.uint(if self.is_64 { 0x38 } else { 0x2c }, 2)? as usize;
+        (0..phnum)
+            .map(|i| {
+                let ph = phoff.checked_add(i.checked_mul(phentsize)?)?;
+                Some((
+                    self.uint(ph, 4)?,
+                    self.word(ph + 4, ph + 8)?,
+                    self.word(ph + 8, ph + 16)?,
+                    self.word(ph + 16, ph + 32)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Returns the file offset and the value of each `DT_RPATH` and
+    /// `DT_RUNPATH` entry.
+    fn rpaths(&self) -> Option<Vec<(usize, String)>> {
+        let headers = self.program_headers()?;
+        let dynamic = match headers.iter().find(|h| h.0 == PT_DYNAMIC) {
+            Some(dynamic) => dynamic,
+            // Statically linked, there's nothing to rewrite.
+            None => return Some(Vec::new()),
+        };
+        let entry_size = if self.is_64 { 16 } else { 8 };
+        let mut strtab = None;
+        let mut offsets = Vec::new();
+        for i in 0..(dynamic.3 / entry_size) as usize {
+            let entry = dynamic.1 as usize + i * entry_size as usize;
+            let tag = self.word(entry, entry)?;
+            let value = self.word(entry + 4, entry + 8)?;
+            match tag {
+                DT_NULL => break,
+                DT_STRTAB => strtab = Some(value),
+                DT_RPATH | DT_RUNPATH => offsets.push(value),
+                _ => {}
+            }
+        }
+        if offsets.is_empty() {
+            return Some(Vec::new());
+        }
+
+        // `DT_STRTAB` is an address, find where the segment holding it is
+        // in the file.
+        let strtab = strtab?;
+        let load =
+            headers.iter().find(|h| h.0 == PT_LOAD && h.2 <= strtab && strtab < h.2 + h.3)?;
+        let strtab = (load.1 + strtab - load.2) as usize;
+        offsets
+            .into_iter()
+            .map(|offset| {
+                let start = strtab.checked_add(offset as usize)?;
+                let len = self.data.get(start..)?.iter().position(|&b| b == 0)?;
+                let value = String::from_utf8(self.data[start..start + len].to_vec()).ok()?;
+                Some((start, value))
+            })
+            .collect()
+    }
+}
+
+/// Replaces every RPATH of `data` that has an entry outside of `$ORIGIN` with
+/// `rpath`. Returns whether anything changed.
+fn set_rpath(data: &mut [u8], rpath: &str) -> Result<bool, String> {
+    let rpaths = match Elf::parse(data).and_then(|elf| elf.rpaths()) {
+        Some(rpaths) => rpaths,
+        None => return Ok(false),
+    };
+    let mut changed = false;
+    for (offset, old) in rpaths {
+        if old.split(':').all(|entry| entry.starts_with("$ORIGIN")) {
+            continue;
+        }
+        if rpath.len() > old.len() {
+            return Err(format!("`{}` doesn't fit in place of the RPATH `{}`", rpath, old));
+        }
+        let dst = &mut data[offset..offset + old.len()];
+        dst[..rpath.len()].copy_from_slice(rpath.as_bytes());
+        for b in &mut dst[rpath.len()..] {
+            *b = 0;
+        }
+        changed = true;
+    }
+    Ok(changed)
+}
+
+/// Makes the RPATH of every ELF file in `image` point at `image/lib`
+/// relative to `$ORIGIN`.
+pub fn relocate_image(build: &Build, image: &Path) {
+    if build.config.dry_run {
+        return;
+    }
+    relocate_dir(build, image, image);
+}
+
+fn relocate_dir(build: &Build, image: &Path, dir: &Path) {
+    for entry in t!(fs::read_dir(dir)) {
+        let entry = t!(entry);
+        let path = entry.path();
+        let file_type = t!(entry.file_type());
+        if file_type.is_dir() {
+            relocate_dir(build, image, &path);
+            continue;
+        } else if !file_type.is_file() {
+            continue;
+        }
+
+        let mut data = t!(fs::read(&path));
+        let libdir = relative_path(&image.join("lib"), dir);
+        let rpath = if libdir.as_os_str().is_empty() {
+            "$ORIGIN".to_string()
+        } else {
+            format!("$ORIGIN/{}", libdir.display())
+        };
+        match set_rpath(&mut data, &rpath) {
+            Ok(false) => {}
+            Ok(true) => {
+                build.verbose(&format!("Set the RPATH of {} to {}", path.display(), rpath));
+                t!(fs::write(&path, &data));
+            }
+            Err(e) => {
+                eprintln!("error: failed to relocate {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,94 @@
+use super::*;
+use crate::builder::tests::configure;
+use crate::util::test_dir;
+
+/// Builds a minimal little endian ELF64 file whose `DT_RUNPATH` is `rpath`.
+fn fake_elf(rpath: &str) -> Vec<u8> {
+    const BASE: u64 = 0x40_0000;
+    let (phoff, dynamic, strtab) = (64u64, 64 + 2 * 56, 64 + 2 * 56 + 3 * 16);
+    let len = strtab + 1 + rpath.len() as u64 + 1;
+
+    let mut elf = b"\x7fELF\x02\x01\x01".to_vec();
+    elf.resize(0x10, 0);
+    elf.extend_from_slice(&3u16.to_le_bytes()); // e_type: ET_DYN
+    elf.extend_from_slice(&0xf7u16.to_le_bytes()); // e_machine: EM_BPF
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    elf.extend_from_slice(&0u64.to_le_bytes());
+    elf.extend_from_slice(&phoff.to_le_bytes());
+    elf.resize(0x36, 0);
+    elf.extend_from_slice(&56u16.to_le_bytes());
+    elf.extend_from_slice(&2u16.to_le_bytes());
+    elf.resize(64, 0);
+
+    for &(kind, offset, size) in &[(PT_LOAD, 0, len), (PT_DYNAMIC, dynamic, 3 * 16)] {
+        elf.extend_from_slice(&(kind as u32).to_le_bytes());
+        elf.extend_from_slice(&0u32.to_le_bytes());
+        for field in &[offset, BASE + offset, BASE + offset, size, size, 0x1000] {
+            elf.extend_from_slice(&field.to_le_bytes());
+        }
+    }
+    for &(tag, value) in &[(DT_STRTAB, BASE + strtab), (DT_RUNPATH, 1), (DT_NULL, 0)] {
+        elf.extend_from_slice(&tag.to_le_bytes());
+        elf.extend_from_slice(&value.to_le_bytes());
+    }
+    elf.push(0);
+    elf.extend_from_slice(rpath.as_bytes());
+    elf.push(0);
+    assert_eq!(elf.len() as u64, len);
+    elf
+}
+
+fn read_rpaths(data: &[u8]) -> Vec<String> {
+    let rpaths = Elf::parse(data).and_then(|elf| elf.rpaths()).unwrap();
+    rpaths.into_iter().map(|(_, value)| value).collect()
+}
+
+#[test]
+fn read_fake_elf() {
+    assert_eq!(read_rpaths(&fake_elf("/build/lib:/opt/llvm/lib")), ["/build/lib:/opt/llvm/lib"]);
+    assert!(Elf::parse(b"!<arch>\n").is_none());
+}
+
+#[test]
+fn set_rpath_in_place() {
+    let mut elf = fake_elf("/home/ci/rust/build/x86_64-unknown-linux-gnu/llvm/lib");
+    let len = elf.len();
+    assert_eq!(set_rpath(&mut elf, "$ORIGIN/../lib"), Ok(true));
+    assert_eq!(elf.len(), len);
+    assert_eq!(read_rpaths(&elf), ["$ORIGIN/../lib"]);
+
+    // Already relative to `$ORIGIN`, nothing to do.
+    assert_eq!(set_rpath(&mut elf, "$ORIGIN"), Ok(false));
+    assert_eq!(read_rpaths(&elf), ["$ORIGIN/../lib"]);
+
+    let mut elf = fake_elf("/lib");
+    assert!(set_rpath(&mut elf, "$ORIGIN/../lib").is_err());
+    assert_eq!(set_rpath(&mut b"#!/bin/sh\n".to_vec(), "$ORIGIN"), Ok(false));
+}
+
+#[test]
+fn relocate_fake_image() {
+    let mut build = Build::new(configure("dist", &["A"], &["A"]));
+    build.config.dry_run = false;
+
+    let image = test_dir();
+    let rpath = "/home/ci/rust/build/x86_64-unknown-linux-gnu/stage2/lib";
+    let files = [
+        ("bin/rustc", fake_elf(rpath)),
+        ("lib/librustc_driver.so", fake_elf(rpath)),
+        ("lib/rustlib/x86_64-unknown-linux-gnu/bin/rust-lld", fake_elf(rpath)),
+        ("share/doc/rust/README.md", b"# Rust".to_vec()),
+    ];
+    for (path, contents) in &files {
+        let path = image.join(path);
+        t!(fs::create_dir_all(path.parent().unwrap()));
+        t!(fs::write(&path, contents));
+    }
+
+    relocate_image(&build, &image);
+    let rpath_of = |path: &str| read_rpaths(&t!(fs::read(image.join(path))));
+    assert_eq!(rpath_of("bin/rustc"), ["$ORIGIN/../lib"]);
+    assert_eq!(rpath_of("lib/librustc_driver.so"), ["$ORIGIN"]);
+    assert_eq!(rpath_of("lib/rustlib/x86_64-unknown-linux-gnu/bin/rust-lld"), ["$ORIGIN/../../.."]);
+    assert_eq!(t!(fs::read(image.join("share/doc/rust/README.md"))), b"# Rust");
+}
@@ -1,8 +1,15 @@
-use crate::builder::{Builder, RunConfig, ShouldRun, Step};
+use crate::builder::{Builder, Compiler, RunConfig, ShouldRun, Step};
+use crate::config::TargetSelection;
 use crate::dist::distdir;
-use crate::tool::Tool;
+use crate::test::RemoteCopyLibs;
+use crate::tool::{TargetTool, Tool};
+use crate::util::exe;
+use crate::Build;
 use build_helper::output;
-use std::process::Command;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, ExitStatus};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct ExpandYamlAnchors;
@@ -82,3 +89,156 @@ fn run(self, builder: &Builder<'_>) {
         builder.run(&mut cmd);
     }
 }
+
+/// Tools `x.py run` builds for the target and runs with the arguments after
+/// `--`, as `(path, name)`.
+const TARGET_TOOLS: &[(&str, &str)] = &[("src/tools/rust-demangler", "rust-demangler")];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct RunTool {
+    pub compiler: Compiler,
+    pub target: TargetSelection,
+    pub tool: &'static str,
+    pub path: &'static str,
+}
+
+impl Step for RunTool {
+    type Output = ();
+
+    fn should_run(run: ShouldRun<'_>) -> ShouldRun<'_> {
+        TARGET_TOOLS.iter().fold(run, |run, &(path, _)| run.path(path))
+    }
+
+    fn make_run(run: RunConfig<'_>) {
+        let builder = run.builder;
+        let &(path, tool) =
+            TARGET_TOOLS.iter().find(|(path, _)| run.path == Path::new(path)).unwrap();
+        // The stage0 compiler doesn't know about BPF targets, host tools are
+        // built with it like the other bootstrap tools.
+        let stage = if run.target.contains("bpf") { builder.top_stage } else { 0 };
+        let compiler = builder.compiler(stage, builder.config.build);
+        builder.ensure(RunTool { compiler, target: run.target, tool, path });
+    }
+
+    /// Builds the tool for the target and runs it with the arguments after
+    /// `--`, on the emulator if the target is tested remotely. Exits with the
+    /// exit code of the tool if it fails.
+    fn run(self, builder: &Builder<'_>) {
+        let compiler = self.compiler;
+        let target = self.target;
+        let exe = builder.ensure(TargetTool { compiler, target, tool: self.tool, path: self.path });
+        if builder.remote_tested(target) {
+            builder.ensure(RemoteCopyLibs { compiler, target });
+        }
+        let code = run_tool(builder, target, &exe, builder.config.cmd.run_args());
+        if code != 0 {
+            process::exit(code);
+        }
+    }
+}
+
+/// Runs `tool` with `args` and returns its exit code. Tools for a target that
+/// is tested remotely go through `remote-test-client`, like test binaries.
+pub fn run_tool(
+    builder: &Builder<'_>,
+    target: TargetSelection,
+    tool: &Path,
+    args: &[String],
+) -> i32 {
+    let mut cmd = if builder.remote_tested(target) {
+        let mut cmd = builder.tool_cmd(Tool::RemoteTestClient);
+        cmd.arg("run").arg("0").arg(tool);
+        cmd
+    } else {
+        Command::new(tool)
+    };
+    cmd.args(args);
+
+    builder.verbose(&format!("running: {:?}", cmd));
+    if builder.config.dry_run {
+        return 0;
+    }
+    match cmd.status() {
+        Ok(status) => exit_code(status),
+        Err(e) => {
+            eprintln!("error: failed to run {}: {}", tool.display(), e);
+            1
+        }
+    }
+}
+
+/// The exit code of a process, with a killing signal reported the way shells
+/// do, as 128 plus its number.
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// `e_machine` of eBPF and, by extension, SBF programs.
+const EM_BPF: u16 = 247;
+
+/// Implementation of `x.py disasm`.
+///
+/// Disassembles each of `paths` with the `llvm-objdump` of the configured
+/// LLVM, annotated with relocations and immediates in hex so calls to
+/// syscalls and other programs can be told apart.
+pub fn disasm(build: &Build, paths: &[PathBuf]) {
+    if build.config.dry_run {
+        return;
+    }
+
+    let objdump = build.llvm_bin(build.config.build).join(exe("llvm-objdump", build.config.build));
+    for path in paths {
+        let mut header = Vec::new();
+        let read = File::open(path).and_then(|f| f.take(20).read_to_end(&mut header));
+        let triple = match read.map_err(|e| e.to_string()).and_then(|_| sbf_triple(&header)) {
+            Ok(triple) => triple,
+            Err(e) => {
+                eprintln!("error: cannot disassemble `{}`: {}", path.display(), e);
+                process::exit(1);
+            }
+        };
+        if paths.len() > 1 {
+            println!("{}:", path.display());
+        }
+        build.run(
+            Command::new(&objdump)
+                .arg("--disassemble")
+                .arg("--reloc")
+                .arg("--print-imm-hex")
+                .arg(format!("--triple={}", triple))
+                .arg("--mattr=+solana")
+                .arg(path),
+        );
+    }
+}
+
+/// Returns the LLVM triple to disassemble an ELF file with, given its first
+/// 20 bytes, or why it isn't a BPF program.
+fn sbf_triple(header: &[u8]) -> Result<&'static str, String> {
+    if header.len() < 20 || &header[..4] != b"\x7fELF" {
+        return Err("not an ELF file".to_string());
+    }
+    // BPF only comes in 64-bit, ELFCLASS64 is 2.
+    if header[4] != 2 {
+        return Err("not a 64-bit ELF file, so not a BPF program".to_string());
+    }
+    let (triple, machine) = match header[5] {
+        1 => ("bpfel", u16::from_le_bytes([header[18], header[19]])),
+        2 => ("bpfeb", u16::from_be_bytes([header[18], header[19]])),
+        data => return Err(format!("unknown ELF data encoding {}", data)),
+    };
+    if machine != EM_BPF {
+        return Err(format!("not a BPF program, ELF machine is {}", machine));
+    }
+    Ok(triple)
+}
+
+#[cfg(test)]
+mod tests;
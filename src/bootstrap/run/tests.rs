@@ -0,0 +1,56 @@
+use super::{run_tool, sbf_triple, EM_BPF};
+use crate::builder::tests::configure;
+use crate::builder::Builder;
+use crate::config::{Config, TargetSelection};
+use crate::Build;
+use build_helper::t;
+use std::fs;
+use std::path::Path;
+
+fn header(class: u8, data: u8, machine: u16) -> Vec<u8> {
+    let mut header = b"\x7fELF".to_vec();
+    header.extend_from_slice(&[class, data, 1, 0]);
+    header.extend_from_slice(&[0; 10]);
+    let machine = if data == 2 { machine.to_be_bytes() } else { machine.to_le_bytes() };
+    header.extend_from_slice(&machine);
+    header
+}
+
+#[test]
+fn sbf_triple_from_header() {
+    assert_eq!(sbf_triple(&header(2, 1, EM_BPF)), Ok("bpfel"));
+    assert_eq!(sbf_triple(&header(2, 2, EM_BPF)), Ok("bpfeb"));
+
+    // x86_64
+    assert!(sbf_triple(&header(2, 1, 62)).unwrap_err().contains("not a BPF program"));
+    assert!(sbf_triple(&header(1, 1, EM_BPF)).is_err());
+    assert!(sbf_triple(b"#!/bin/sh\nexit 0\n\n\n\n").unwrap_err().contains("not an ELF"));
+    assert!(sbf_triple(&header(2, 1, EM_BPF)[..16]).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn run_host_tool() {
+    let config = Config::parse(&[
+        "run".to_owned(),
+        "src/tools/rust-demangler".to_owned(),
+        "--".to_owned(),
+        "--verbose".to_owned(),
+        "two words".to_owned(),
+    ]);
+    assert_eq!(config.cmd.run_args(), ["--verbose", "two words"]);
+
+    let mut build = Build::new(configure("build", &["A"], &["A"]));
+    build.config.dry_run = false;
+    let builder = Builder::new(&build);
+    let a = TargetSelection::from_user("A");
+    let out = build.out.join("tool-args");
+    let script = format!("printf '%s\\n' \"$@\" > {}; exit 3", out.display());
+    let args = ["-c".to_owned(), script, "tool".to_owned(), "two words".to_owned()];
+    assert_eq!(run_tool(&builder, a, Path::new("sh"), &args), 3);
+    assert_eq!(t!(fs::read_to_string(&out)), "two words\n");
+
+    let args = ["-c".to_owned(), "kill -9 $$".to_owned()];
+    assert_eq!(run_tool(&builder, a, Path::new("sh"), &args), 128 + 9);
+    assert_eq!(run_tool(&builder, a, Path::new("true"), &[]), 0);
+}
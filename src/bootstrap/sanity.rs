@@ -12,14 +12,19 @@
 use std::env;
 use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 
-use build_helper::{output, t};
+use build_helper::{t, RunOutcome};
 
 use crate::cache::INTERNER;
-use crate::config::Target;
-use crate::Build;
+use crate::cc_detect::CcVersion;
+use crate::config::{Target, TargetSelection};
+use crate::util::sha256_file;
+use crate::{Build, Endian, SubmoduleStatus};
+
+#[cfg(test)]
+mod tests;
 
 pub struct Finder {
     cache: HashMap<OsString, Option<PathBuf>>,
@@ -62,6 +67,40 @@ pub fn must_have<S: AsRef<OsStr>>(&mut self, cmd: S) -> PathBuf {
     }
 }
 
+/// Returns the files listed in `sums` that are missing from `dir` or whose
+/// sha256 no longer matches. `sums` is the `.rustc-sha256` that bootstrap.py
+/// writes after extracting the stage0 toolchain into `dir`, one hash and
+/// relative path per line.
+pub fn corrupt_stage0_files(dir: &Path, sums: &str) -> Vec<String> {
+    sums.lines()
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, "  ");
+            match (parts.next(), parts.next()) {
+                (Some(expected), Some(file)) => {
+                    let path = dir.join(file);
+                    if path.is_file() && sha256_file(&path) == expected {
+                        None
+                    } else {
+                        Some(file.to_string())
+                    }
+                }
+                // A line that can't be read doesn't verify anything either.
+                _ => Some(line.to_string()),
+            }
+        })
+        .collect()
+}
+
+/// Returns GNU tar, which is `tar` on Linux but usually `gtar` elsewhere.
+pub(crate) fn find_gnu_tar(cmd_finder: &mut Finder) -> Option<PathBuf> {
+    let is_gnu_tar = |tar: &Path| {
+        let version = Command::new(tar).arg("--version").output();
+        version.map_or(false, |out| String::from_utf8_lossy(&out.stdout).contains("GNU tar"))
+    };
+    ["tar", "gtar"].iter().filter_map(|tar| cmd_finder.maybe_have(*tar)).find(|tar| is_gnu_tar(tar))
+}
+
 pub fn check(build: &mut Build) {
     let path = env::var_os("PATH").unwrap_or_default();
     // On Windows, quotes are invalid characters for filename paths, and if
@@ -72,6 +111,8 @@ pub fn check(build: &mut Build) {
         panic!("PATH contains invalid character '\"'");
     }
 
+    build.verify_artifact_hashes();
+
     let mut cmd_finder = Finder::new();
     // If we've got a git directory we're gonna need git to update
     // submodules and learn about various other aspects.
@@ -127,6 +168,19 @@ pub fn check(build: &mut Build) {
         .map(|p| cmd_finder.must_have(p))
         .or_else(|| cmd_finder.maybe_have("gdb"));
 
+    // `.tar.zst` dist tarballs are archived by GNU tar, to sort the entries
+    // and reset their owners and times, and compressed by `zstd`.
+    let formats = build.config.dist_compression_formats.as_ref();
+    if formats.map_or(false, |formats| formats.iter().any(|f| f == "zst")) {
+        build.config.zstd = Some(cmd_finder.must_have("zstd"));
+        build.config.gnu_tar = Some(find_gnu_tar(&mut cmd_finder).unwrap_or_else(|| {
+            panic!(
+                "\n\ncouldn't find GNU tar as `tar` or `gtar`, which `.tar.zst` \
+                 dist tarballs need\n\n"
+            )
+        }));
+    }
+
     // We're gonna build some custom C code here and there, host triples
     // also build some C++ shims for LLVM so we need a C++ compiler.
     for target in &build.targets {
@@ -161,8 +215,22 @@ pub fn check(build: &mut Build) {
     if build.config.rust_codegen_backends.contains(&INTERNER.intern_str("llvm")) {
         // Externally configured LLVM requires FileCheck to exist
         let filecheck = build.llvm_filecheck(build.build);
-        if !filecheck.starts_with(&build.out) && !filecheck.exists() && build.config.codegen_tests {
-            panic!("FileCheck executable {:?} does not exist", filecheck);
+        if !filecheck.starts_with(&build.out) && build.config.codegen_tests {
+            let mut version = Command::new(&filecheck);
+            version.arg("--version").stdout(Stdio::null()).stderr(Stdio::null());
+            match build.try_run_detailed(&mut version) {
+                RunOutcome::Succeeded => {}
+                RunOutcome::SpawnFailed(e) if !filecheck.exists() => {
+                    panic!("FileCheck executable {:?} does not exist: {}", filecheck, e)
+                }
+                RunOutcome::SpawnFailed(e) => {
+                    panic!("FileCheck executable {:?} could not be run: {}", filecheck, e)
+                }
+                RunOutcome::Failed(status) => panic!(
+                    "FileCheck executable {:?} is broken, `--version` exited with {}",
+                    filecheck, status
+                ),
+            }
         }
     }
 
@@ -210,8 +278,13 @@ pub fn check(build: &mut Build) {
             // There are three builds of cmake on windows: MSVC, MinGW, and
             // Cygwin. The Cygwin build does not have generators for Visual
             // Studio, so detect that here and error.
-            let out = output(Command::new("cmake").arg("--help"));
-            if !out.contains("Visual Studio") {
+            let mut help = Command::new("cmake");
+            help.arg("--help");
+            let out = build.run_with_output_capture(&mut help);
+            if !out.is_success() {
+                panic!("{:?} failed with {:?}:\n{}", help, out.status, out.stderr);
+            }
+            if !build.config.dry_run && !out.stdout.contains("Visual Studio") {
                 panic!(
                     "
 cmake does not support Visual Studio generators.
@@ -234,6 +307,39 @@ pub fn check(build: &mut Build) {
         cmd_finder.must_have(s);
     }
 
+    // A dry run doesn't get as far as running cmake.
+    if building_llvm && !build.config.dry_run {
+        let status = build.git_submodule_status("src/llvm-project");
+        if let Err(e) = check_llvm_submodule(&build.src, status) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let endianness = |target| build.target_endianness(target);
+    if let Err(e) = check_bpf_endian(&build.targets, build.config.strict, endianness) {
+        panic!("{}", e);
+    }
+    if let Err(e) = check_bpf_linker_scripts(&build.targets) {
+        panic!("{}", e);
+    }
+    for target in &build.targets {
+        let config = build.config.target_config.get(target);
+        let min = match config.and_then(|c| c.min_cc_version.as_ref()) {
+            Some(min) => min,
+            None => continue,
+        };
+        // The in-tree clang of BPF targets doesn't exist yet, it's as recent
+        // as the LLVM it's built with anyway.
+        if build.config.dry_run || build.cc(*target).starts_with(&build.out) {
+            continue;
+        }
+        if let Err(e) = check_cc_version(*target, build.cc(*target), build.cc_version(*target), min)
+        {
+            panic!("{}", e);
+        }
+    }
+
     if build.config.channel == "stable" {
         let stage0 = t!(fs::read_to_string(build.src.join("src/stage0.txt")));
         if stage0.contains("\ndev:") {
@@ -244,3 +350,132 @@ pub fn check(build: &mut Build) {
         }
     }
 }
+
+/// Checks that `detected`, the version of the C compiler `cc` of `target`, is
+/// at least `target.<triple>.min-cc-version`.
+fn check_cc_version(
+    target: TargetSelection,
+    cc: &Path,
+    detected: Option<CcVersion>,
+    min: &str,
+) -> Result<(), String> {
+    let required = CcVersion::parse(min).ok_or_else(|| {
+        format!("`target.{}.min-cc-version` is `{}`, which isn't a version", target, min)
+    })?;
+    match detected {
+        Some(detected) if detected >= required => Ok(()),
+        Some(detected) => Err(format!(
+            "the C compiler `{}` of target `{}` is version {}, but at least {} is required \
+             by `target.{}.min-cc-version`",
+            cc.display(),
+            target,
+            detected,
+            required,
+            target
+        )),
+        None => Err(format!(
+            "couldn't find out the version of the C compiler `{}` of target `{}` from its \
+             `--version`, but `target.{}.min-cc-version` requires at least {}",
+            cc.display(),
+            target,
+            target,
+            required
+        )),
+    }
+}
+
+/// The BPF loader only accepts little-endian objects, but nothing stops a BPF
+/// target from declaring itself big-endian, which the backend then happily
+/// produces code for. Warns about every such target, or fails if `strict`.
+/// The byte order of a target is looked up with `endianness`.
+fn check_bpf_endian(
+    targets: &[TargetSelection],
+    strict: bool,
+    endianness: impl Fn(TargetSelection) -> Endian,
+) -> Result<(), String> {
+    let mut problems = Vec::new();
+    for &target in targets.iter().filter(|t| t.contains("bpf")) {
+        if endianness(target) == Endian::Big {
+            problems.push(format!(
+                "target `{}` is big-endian, but BPF programs have to be little-endian \
+                 to be loaded",
+                target
+            ));
+        }
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else if strict {
+        Err(problems.join("\n"))
+    } else {
+        for problem in problems {
+            eprintln!("warning: {}", problem);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the linker scripts the specification of `target` passes to the
+/// linker with `--script` or `-T`, relative paths being relative to the
+/// specification. Built-in targets embed their script instead, so for them
+/// there's nothing to return.
+fn linker_scripts(target: TargetSelection) -> Vec<PathBuf> {
+    let file = match target.spec_file() {
+        Some(file) => file,
+        None => return Vec::new(),
+    };
+    let spec: serde_json::Value = t!(serde_json::from_str(&t!(fs::read_to_string(&file))));
+    let dir = file.parent().unwrap_or_else(|| Path::new(""));
+    let mut scripts = Vec::new();
+    for key in &["pre-link-args", "late-link-args", "post-link-args"] {
+        let flavors = match spec[key].as_object() {
+            Some(flavors) => flavors,
+            None => continue,
+        };
+        for args in flavors.values().filter_map(|args| args.as_array()) {
+            let mut args = args.iter().filter_map(|arg| arg.as_str());
+            while let Some(arg) = args.next() {
+                let script = match arg.strip_prefix("--script=") {
+                    Some(script) => Some(script),
+                    None if arg == "--script" || arg == "-T" => args.next(),
+                    None => None,
+                };
+                scripts.extend(script.map(|script| dir.join(script)));
+            }
+        }
+    }
+    scripts
+}
+
+/// Building LLVM from an uninitialized `src/llvm-project` only fails once
+/// cmake runs, with an error about a missing `CMakeLists.txt`. A source
+/// tarball has no git status, so the sources are looked for as well.
+fn check_llvm_submodule(src: &Path, status: Option<SubmoduleStatus>) -> Result<(), String> {
+    if status != Some(SubmoduleStatus::Uninitialized)
+        && src.join("src/llvm-project/llvm/CMakeLists.txt").exists()
+    {
+        return Ok(());
+    }
+    Err("\
+error: `src/llvm-project` is empty, but LLVM is configured to be built from source
+help: run `git submodule update --init src/llvm-project`, or set `llvm-config` for the host
+      in the `[target]` section of `config.toml` to use an existing LLVM"
+        .to_string())
+}
+
+/// A missing linker script only shows up once lld is run on the first BPF
+/// program, with an error that doesn't say which script it was looking for.
+fn check_bpf_linker_scripts(targets: &[TargetSelection]) -> Result<(), String> {
+    for &target in targets.iter().filter(|t| t.contains("bpf")) {
+        for script in linker_scripts(target) {
+            if !script.exists() {
+                return Err(format!(
+                    "the linker script `{}` of target `{}` does not exist",
+                    script.display(),
+                    target
+                ));
+            }
+        }
+    }
+    Ok(())
+}
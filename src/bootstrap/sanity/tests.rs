@@ -0,0 +1,144 @@
+use super::*;
+use crate::util::test_dir;
+
+#[test]
+fn stage0_hashes() {
+    let dir = test_dir();
+    t!(fs::create_dir_all(dir.join("bin")));
+    t!(fs::create_dir_all(dir.join("lib")));
+    let files = ["bin/rustc", "bin/cargo", "lib/librustc_driver-1234.so", "lib/libstd-1234.so"];
+    for file in &files {
+        t!(fs::write(dir.join(file), file));
+    }
+    let sums = files
+        .iter()
+        .map(|file| format!("{}  {}\n", sha256_file(&dir.join(file)), file))
+        .collect::<String>();
+    assert!(corrupt_stage0_files(&dir, &sums).is_empty());
+
+    // A truncated library is caught, not just the binaries.
+    t!(fs::write(dir.join("lib/librustc_driver-1234.so"), "lib/librustc"));
+    t!(fs::remove_file(dir.join("bin/cargo")));
+    assert_eq!(corrupt_stage0_files(&dir, &sums), ["bin/cargo", "lib/librustc_driver-1234.so"]);
+    assert_eq!(corrupt_stage0_files(&dir, "bin/rustc\n"), ["bin/rustc"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn gnu_tar() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = test_dir();
+    let tool = |name: &str, version: &str| {
+        let path = dir.join(name);
+        t!(fs::write(&path, format!("#!/bin/sh\necho '{}'\n", version)));
+        t!(fs::set_permissions(&path, fs::Permissions::from_mode(0o755)));
+    };
+    let finder = || Finder { cache: HashMap::new(), path: dir.clone().into_os_string() };
+
+    // Like on macOS, where `tar` is bsdtar.
+    tool("tar", "bsdtar 3.5.1 - libarchive 3.5.1");
+    assert_eq!(find_gnu_tar(&mut finder()), None);
+    tool("gtar", "tar (GNU tar) 1.34");
+    assert_eq!(find_gnu_tar(&mut finder()), Some(dir.join("gtar")));
+    tool("tar", "tar (GNU tar) 1.30");
+    assert_eq!(find_gnu_tar(&mut finder()), Some(dir.join("tar")));
+}
+
+#[test]
+fn bpf_endian() {
+    let little = TargetSelection::from_user("bpfel-unknown-unknown");
+    let big = TargetSelection::from_user("bpfeb-unknown-unknown");
+    // Not BPF, so its endianness doesn't matter.
+    let ppc = TargetSelection::from_user("powerpc-unknown-linux-gnu");
+    let endianness = |t: TargetSelection| if t == little { Endian::Little } else { Endian::Big };
+
+    assert_eq!(check_bpf_endian(&[little, ppc], true, endianness), Ok(()));
+    // Only a warning unless strict.
+    assert_eq!(check_bpf_endian(&[little, big], false, endianness), Ok(()));
+    let err = check_bpf_endian(&[little, big], true, endianness).unwrap_err();
+    assert!(err.contains("`bpfeb-unknown-unknown`"), "{}", err);
+    assert!(err.contains("big-endian"), "{}", err);
+}
+
+#[test]
+fn bpf_linker_script() {
+    let dir = test_dir();
+    let spec = |name: &str| {
+        let path = dir.join(format!("{}.json", name));
+        t!(fs::write(
+            &path,
+            r#"{"pre-link-args": {"ld.lld": ["--entry=entrypoint", "-T", "sbf.ld"]}}"#
+        ));
+        TargetSelection::from_user(path.to_str().unwrap())
+    };
+    let bpf = spec("bpfel-custom");
+    // Not BPF, so its script isn't checked.
+    let other = spec("riscv64-custom");
+    assert_eq!(linker_scripts(bpf), [dir.join("sbf.ld")]);
+    assert!(linker_scripts(TargetSelection::from_user("bpfel-unknown-unknown")).is_empty());
+
+    let err = check_bpf_linker_scripts(&[bpf, other]).unwrap_err();
+    assert!(err.contains(&dir.join("sbf.ld").display().to_string()), "{}", err);
+    assert_eq!(check_bpf_linker_scripts(&[other]), Ok(()));
+    t!(fs::write(dir.join("sbf.ld"), "SECTIONS {}"));
+    assert_eq!(check_bpf_linker_scripts(&[bpf, other]), Ok(()));
+}
+
+#[test]
+fn submodule_status() {
+    let status = |output: String| crate::parse_submodule_status(&output);
+    let sha = "6c1e41f5a8e6ad0e95d89d9bd64fe1b3e8d05b3e";
+    assert_eq!(
+        status(format!("-{} src/llvm-project\n", sha)),
+        Some(SubmoduleStatus::Uninitialized)
+    );
+    assert_eq!(
+        status(format!("+{} src/llvm-project (llvmorg-12.0.0-1-g6c1e41f5a8e6)\n", sha)),
+        Some(SubmoduleStatus::OutOfDate)
+    );
+    assert_eq!(status(format!("U{} src/llvm-project\n", sha)), Some(SubmoduleStatus::Conflicted));
+    assert_eq!(
+        status(format!(" {} src/llvm-project (heads/solana-rustc/12.0)\n", sha)),
+        Some(SubmoduleStatus::UpToDate)
+    );
+    assert_eq!(status(String::new()), None);
+}
+
+#[test]
+fn llvm_submodule() {
+    let src = test_dir();
+    t!(fs::create_dir_all(src.join("src/llvm-project")));
+
+    // An empty directory, as left behind by a clone without `--recursive`.
+    let err = check_llvm_submodule(&src, Some(SubmoduleStatus::Uninitialized)).unwrap_err();
+    assert!(err.contains("git submodule update --init src/llvm-project"), "{}", err);
+    assert!(check_llvm_submodule(&src, None).is_err());
+
+    t!(fs::create_dir_all(src.join("src/llvm-project/llvm")));
+    t!(fs::write(src.join("src/llvm-project/llvm/CMakeLists.txt"), ""));
+    assert_eq!(check_llvm_submodule(&src, Some(SubmoduleStatus::UpToDate)), Ok(()));
+    assert_eq!(check_llvm_submodule(&src, Some(SubmoduleStatus::OutOfDate)), Ok(()));
+    // Not a git checkout, e.g. a source tarball.
+    assert_eq!(check_llvm_submodule(&src, None), Ok(()));
+}
+
+#[test]
+fn min_cc_version() {
+    let target = TargetSelection::from_user("x86_64-unknown-linux-gnu");
+    let cc = Path::new("/usr/bin/cc");
+    let version = |s| CcVersion::parse(s);
+    assert_eq!(check_cc_version(target, cc, version("14.0.6"), "14.0"), Ok(()));
+    assert_eq!(check_cc_version(target, cc, version("14.0.0"), "14.0"), Ok(()));
+    assert_eq!(check_cc_version(target, cc, version("15.0.0"), "14.0.6"), Ok(()));
+
+    let err = check_cc_version(target, cc, version("9.4.0"), "14.0").unwrap_err();
+    assert!(err.contains("`/usr/bin/cc`"), "{}", err);
+    assert!(err.contains("version 9.4.0, but at least 14.0.0"), "{}", err);
+    let err = check_cc_version(target, cc, version("13.1.0"), "13.2").unwrap_err();
+    assert!(err.contains("version 13.1.0, but at least 13.2.0"), "{}", err);
+    let err = check_cc_version(target, cc, None, "14.0").unwrap_err();
+    assert!(err.contains("couldn't find out the version"), "{}", err);
+    let err = check_cc_version(target, cc, version("14.0.0"), "fourteen").unwrap_err();
+    assert!(err.contains("isn't a version"), "{}", err);
+}
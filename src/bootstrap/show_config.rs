@@ -0,0 +1,80 @@
+//! Implementation of `x.py show-config`.
+//!
+//! Prints the C toolchain that `cc_detect` settled on for every configured
+//! host and target, i.e. the paths the build hands to build scripts and to
+//! rustc. Unlike `x.py doctor` nothing is probed, the paths are printed as
+//! they were resolved even if they don't exist.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use build_helper::t;
+use serde::Serialize;
+
+use crate::Build;
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Toolchain {
+    target: String,
+    cc: Option<PathBuf>,
+    cxx: Option<PathBuf>,
+    ar: Option<PathBuf>,
+    ranlib: Option<PathBuf>,
+    linker: Option<PathBuf>,
+}
+
+pub fn show_config(build: &Build, json: bool) {
+    let toolchains = toolchains(build);
+    if json {
+        println!("{}", t!(serde_json::to_string_pretty(&toolchains)));
+    } else {
+        print!("{}", table(&toolchains));
+    }
+}
+
+fn toolchains(build: &Build) -> Vec<Toolchain> {
+    let targets = build
+        .targets
+        .iter()
+        .chain(&build.hosts)
+        .chain(Some(&build.build))
+        .copied()
+        .collect::<BTreeSet<_>>();
+    targets
+        .into_iter()
+        .map(|target| Toolchain {
+            target: target.triple.to_string(),
+            cc: build.cc.get(&target).map(|cc| cc.path().to_path_buf()),
+            cxx: build.cxx(target).ok().map(PathBuf::from),
+            ar: build.ar(target).map(PathBuf::from),
+            ranlib: build.ranlib(target).map(PathBuf::from),
+            linker: build.linker(target).map(PathBuf::from),
+        })
+        .collect()
+}
+
+fn table(toolchains: &[Toolchain]) -> String {
+    let mut out = String::new();
+    for toolchain in toolchains {
+        let rows = [
+            ("cc", &toolchain.cc),
+            ("c++", &toolchain.cxx),
+            ("ar", &toolchain.ar),
+            ("ranlib", &toolchain.ranlib),
+            ("linker", &toolchain.linker),
+        ];
+        writeln!(out, "{}", toolchain.target).unwrap();
+        for (name, path) in rows.iter() {
+            let path = match path {
+                Some(path) => path.display().to_string(),
+                None => "-".to_string(),
+            };
+            writeln!(out, "    {:8} {}", name, path).unwrap();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests;
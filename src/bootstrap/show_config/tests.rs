@@ -0,0 +1,17 @@
+use super::*;
+use crate::builder::tests::configure;
+
+#[test]
+fn every_target_listed() {
+    let build = Build::new(configure("show-config", &["A", "B"], &["A", "bpfel-unknown-unknown"]));
+
+    let toolchains = toolchains(&build);
+    let targets = toolchains.iter().map(|t| &*t.target).collect::<Vec<_>>();
+    assert_eq!(targets, ["A", "B", "bpfel-unknown-unknown"]);
+    assert!(toolchains.iter().all(|t| t.cc.is_some()));
+
+    let table = table(&toolchains);
+    for target in targets {
+        assert!(table.lines().any(|line| line == target), "{} missing from\n{}", target, table);
+    }
+}
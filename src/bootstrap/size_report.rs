@@ -0,0 +1,134 @@
+//! Implementation of `x.py size-report`.
+//!
+//! Breaks the size of a BPF program down into its code, read-only data,
+//! writable data and zero-initialized data, as `llvm-size` counts them, to
+//! see what keeps a program from fitting under the limits of the chain. The
+//! report is written to `build/size-report.json` so a later run can be
+//! compared against it.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::{self, Command};
+
+use build_helper::{output, t};
+use serde::{Deserialize, Serialize};
+
+use crate::util::exe;
+use crate::Build;
+
+/// Sizes in bytes, each section counted along with its `.<name>.*`
+/// subsections.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct SizeReport {
+    pub text: u64,
+    pub rodata: u64,
+    pub data: u64,
+    pub bss: u64,
+    /// All sections, as totalled by `llvm-size`.
+    pub total: u64,
+}
+
+impl SizeReport {
+    /// Parses the output of `llvm-size -A`, a line per section after a
+    /// header and a `Total` line at the end.
+    fn parse(output: &str) -> Result<SizeReport, String> {
+        let mut report = SizeReport::default();
+        let mut total = None;
+        for line in output.lines() {
+            let mut columns = line.split_whitespace();
+            let (name, size) = match (columns.next(), columns.next()) {
+                (Some(name), Some(size)) => (name, size),
+                _ => continue,
+            };
+            let size = match size.parse::<u64>() {
+                Ok(size) => size,
+                // The header, or the name of the file.
+                Err(_) => continue,
+            };
+            let in_section = |section: &str| {
+                name == section
+                    || (name.starts_with(section) && name[section.len()..].starts_with('.'))
+            };
+            if name == "Total" {
+                total = Some(size);
+            } else if in_section(".text") {
+                report.text += size;
+            } else if in_section(".rodata") {
+                report.rodata += size;
+            } else if in_section(".data") {
+                report.data += size;
+            } else if in_section(".bss") {
+                report.bss += size;
+            }
+        }
+        report.total = total.ok_or("no `Total` in the output of llvm-size")?;
+        Ok(report)
+    }
+
+    fn rows(&self) -> [(&'static str, u64); 5] {
+        [
+            (".text", self.text),
+            (".rodata", self.rodata),
+            (".data", self.data),
+            (".bss", self.bss),
+            ("total", self.total),
+        ]
+    }
+}
+
+/// A report, along with the one it's compared against.
+struct Comparison<'a> {
+    report: &'a SizeReport,
+    previous: Option<&'a SizeReport>,
+}
+
+impl fmt::Display for Comparison<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<10}{:>12}", "section", "bytes")?;
+        if self.previous.is_some() {
+            write!(f, "{:>12}", "delta")?;
+        }
+        writeln!(f)?;
+        for (i, &(name, size)) in self.report.rows().iter().enumerate() {
+            write!(f, "{:<10}{:>12}", name, size)?;
+            if let Some(previous) = self.previous {
+                let delta = size as i64 - previous.rows()[i].1 as i64;
+                write!(f, "{:>12}", format!("{:+}", delta))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn size_report(build: &Build, path: &Path, compare: Option<&Path>) {
+    if build.config.dry_run {
+        return;
+    }
+
+    let previous: Option<SizeReport> = compare.map(|compare| match fs::read_to_string(compare) {
+        Ok(contents) => t!(serde_json::from_str(&contents)),
+        Err(e) => {
+            eprintln!("error: failed to read `{}`: {}", compare.display(), e);
+            process::exit(1);
+        }
+    });
+
+    let llvm_size = build.llvm_bin(build.config.build).join(exe("llvm-size", build.config.build));
+    let report = match SizeReport::parse(&output(Command::new(llvm_size).arg("-A").arg(path))) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("error: cannot report the size of `{}`: {}", path.display(), e);
+            process::exit(1);
+        }
+    };
+    print!("{}", Comparison { report: &report, previous: previous.as_ref() });
+
+    let out = build.out.join("size-report.json");
+    t!(fs::write(&out, t!(serde_json::to_string_pretty(&report))));
+    build.info(&format!("Wrote the report to {}", out.display()));
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,54 @@
+use super::*;
+
+const LLVM_SIZE: &str = "\
+program.so  :
+section             size   addr
+.text              12416    288
+.rodata             1203  12704
+.data.rel.ro         160  13912
+.dynamic             176  14072
+.bss.stack            24  14248
+.dynsym              504      0
+.dynstr              317      0
+.rel.dyn             960      0
+.comment              19      0
+Total              15779
+";
+
+#[test]
+fn parse_llvm_size() {
+    assert_eq!(
+        SizeReport::parse(LLVM_SIZE),
+        Ok(SizeReport { text: 12416, rodata: 1203, data: 160, bss: 24, total: 15779 })
+    );
+    assert!(SizeReport::parse("program.so  :\nsection size addr\n").is_err());
+}
+
+#[test]
+fn compare_reports() {
+    let report = SizeReport::parse(LLVM_SIZE).unwrap();
+    assert_eq!(
+        Comparison { report: &report, previous: None }.to_string(),
+        "\
+section          bytes
+.text            12416
+.rodata           1203
+.data              160
+.bss                24
+total            15779
+"
+    );
+
+    let previous = SizeReport { text: 12000, rodata: 1203, data: 200, bss: 24, total: 15403 };
+    assert_eq!(
+        Comparison { report: &report, previous: Some(&previous) }.to_string(),
+        "\
+section          bytes       delta
+.text            12416        +416
+.rodata           1203          +0
+.data              160         -40
+.bss                24          +0
+total            15779        +376
+"
+    );
+}
@@ -1,6 +1,7 @@
 use std::{
+    env,
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Stdio},
 };
 
 use build_helper::t;
@@ -172,6 +173,17 @@ pub(crate) fn add_file(&self, src: impl AsRef<Path>, destdir: impl AsRef<Path>,
         self.builder.install(src.as_ref(), &destdir, perms);
     }
 
+    /// Adds a tool executable to `bin`, stripping it if `dist.strip-tools` is set.
+    pub(crate) fn add_tool_binary(&self, src: impl AsRef<Path>) {
+        let src = src.as_ref();
+        self.add_file(src, "bin", 0o755);
+        if self.builder.config.dist_strip_tools {
+            let keep_debug = self.builder.config.rust_debuginfo_level_tools > 0;
+            let dst = self.image_dir.join("bin").join(src.file_name().unwrap());
+            self.builder.strip_artifact(&dst, keep_debug);
+        }
+    }
+
     pub(crate) fn add_renamed_file(
         &self,
         src: impl AsRef<Path>,
@@ -183,6 +195,13 @@ pub(crate) fn add_renamed_file(
         self.builder.copy(src.as_ref(), &destdir.join(new_name));
     }
 
+    /// Rewrites the RPATHs in the image if `dist.relocatable` is set.
+    fn relocate(&self) {
+        if self.builder.config.dist_relocatable {
+            crate::relocate::relocate_image(self.builder, &self.image_dir);
+        }
+    }
+
     pub(crate) fn add_legal_and_readme_to(&self, destdir: impl AsRef<Path>) {
         for file in self.overlay.legal_and_readme() {
             self.add_file(self.builder.src.join(file), destdir.as_ref(), 0o644);
@@ -197,6 +216,7 @@ pub(crate) fn add_dir(&self, src: impl AsRef<Path>, dest: impl AsRef<Path>) {
     }
 
     pub(crate) fn generate(self) -> GeneratedTarball {
+        self.relocate();
         let mut component_name = self.component.clone();
         if self.is_preview {
             component_name.push_str("-preview");
@@ -237,6 +257,7 @@ pub(crate) fn bare(self) -> GeneratedTarball {
         // Bare tarballs should have the top level directory match the package
         // name, not "image". We rename the image directory just before passing
         // into rust-installer.
+        self.relocate();
         let dest = self.temp_dir.join(self.package_name());
         t!(std::fs::rename(&self.image_dir, &dest));
 
@@ -245,7 +266,7 @@ pub(crate) fn bare(self) -> GeneratedTarball {
                 .arg("--input")
                 .arg(&dest)
                 .arg("--output")
-                .arg(crate::dist::distdir(this.builder).join(this.package_name()));
+                .arg(this.installer_output_dir().join(this.package_name()));
         })
     }
 
@@ -266,7 +287,19 @@ fn non_bare_args(&self, cmd: &mut Command) {
             .arg("--non-installed-overlay")
             .arg(&self.overlay_dir)
             .arg("--output-dir")
-            .arg(crate::dist::distdir(self.builder));
+            .arg(self.installer_output_dir());
+    }
+
+    /// Returns where rust-installer writes its tarballs. They go in the dist
+    /// directory unless only `.tar.zst` ones were asked for, then they only
+    /// stay around in the work directory for `combine` to read.
+    fn installer_output_dir(&self) -> PathBuf {
+        let formats = self.builder.config.dist_compression_formats.as_ref();
+        if formats.map_or(false, |formats| formats.iter().all(|f| f == "zst")) {
+            self.temp_dir.clone()
+        } else {
+            crate::dist::distdir(self.builder)
+        }
     }
 
     fn run(self, build_cli: impl FnOnce(&Tarball<'a>, &mut Command)) -> GeneratedTarball {
@@ -287,28 +320,111 @@ fn run(self, build_cli: impl FnOnce(&Tarball<'a>, &mut Command)) -> GeneratedTar
 
         build_cli(&self, &mut cmd);
         cmd.arg("--work-dir").arg(&self.temp_dir);
-        if let Some(formats) = &self.builder.config.dist_compression_formats {
+        let formats = self.builder.config.dist_compression_formats.as_ref();
+        let zstd = formats.map_or(false, |formats| formats.iter().any(|f| f == "zst"));
+        if let Some(formats) = formats {
             assert!(!formats.is_empty(), "dist.compression-formats can't be empty");
-            cmd.arg("--compression-formats").arg(formats.join(","));
+            // rust-installer can't write zstd, those tarballs are created below.
+            // It always writes at least one tarball though, so if only zstd was
+            // asked for it writes a gzip one that stays out of the dist
+            // directory.
+            let installer_formats =
+                formats.iter().filter(|f| *f != "zst").cloned().collect::<Vec<_>>();
+            let installer_formats = if installer_formats.is_empty() {
+                "gz".to_string()
+            } else {
+                installer_formats.join(",")
+            };
+            cmd.arg("--compression-formats").arg(installer_formats);
         }
         self.builder.run(&mut cmd);
+        if zstd && !self.builder.config.dry_run {
+            let config = &self.builder.config;
+            let tools = config.gnu_tar.as_ref().zip(config.zstd.as_ref());
+            let (tar, zstd) = tools.expect("the sanity checks find GNU tar and zstd");
+            let dst = crate::dist::distdir(self.builder).join(format!("{}.tar.zst", package_name));
+            let level = config.dist_zstd_level.unwrap_or(19);
+            tar_zstd(tar, zstd, &self.temp_dir, &package_name, &dst, level, archive_mtime());
+        }
+        self.record_tarballs(&package_name);
 
-        // Use either the first compression format defined, or "gz" as the default.
-        let ext = self
-            .builder
-            .config
-            .dist_compression_formats
-            .as_ref()
-            .and_then(|formats| formats.get(0))
-            .map(|s| s.as_str())
-            .unwrap_or("gz");
-
+        // `combine` passes the tarball back to rust-installer, so it's one
+        // that rust-installer wrote.
+        let ext = installer_format(formats);
         GeneratedTarball {
-            path: crate::dist::distdir(self.builder).join(format!("{}.tar.{}", package_name, ext)),
+            path: self.installer_output_dir().join(format!("{}.tar.{}", package_name, ext)),
             decompressed_output: self.temp_dir.join(package_name),
             work: self.temp_dir,
         }
     }
+
+    /// Remembers the tarballs of every compression format that were just
+    /// generated, for `components.sha256`.
+    fn record_tarballs(&self, package_name: &str) {
+        if self.builder.config.dry_run {
+            return;
+        }
+        let prefix = format!("{}.tar.", package_name);
+        for entry in t!(std::fs::read_dir(crate::dist::distdir(self.builder))) {
+            let path = t!(entry).path();
+            if path.file_name().map_or(false, |name| name.to_string_lossy().starts_with(&prefix)) {
+                self.builder.dist_tarballs.borrow_mut().push(path);
+            }
+        }
+    }
+}
+
+/// Returns the format of the first tarball rust-installer writes for
+/// `formats`: the first format asked for other than `zst`, which
+/// rust-installer can neither read nor write, or gzip if there's none.
+fn installer_format(formats: Option<&Vec<String>>) -> &str {
+    formats.and_then(|formats| formats.iter().find(|f| *f != "zst")).map_or("gz", |f| f.as_str())
+}
+
+/// The modification time of every file in a `.tar.zst`, `SOURCE_DATE_EPOCH`
+/// if it's set and the epoch otherwise.
+fn archive_mtime() -> u64 {
+    match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => match epoch.parse() {
+            Ok(epoch) => epoch,
+            Err(e) => panic!("could not parse SOURCE_DATE_EPOCH: {}", e),
+        },
+        Err(_) => 0,
+    }
+}
+
+/// Archives `dir/name` into the `.tar.zst` at `dst`, compressed at `level`.
+/// The image rust-installer leaves in its work directory is what it puts in
+/// its own tarballs, so this produces the same contents.
+///
+/// The archive only depends on those contents: entries are sorted by name,
+/// owned by root and all modified at `mtime`. That takes GNU tar, `tar` has
+/// to be one, which the sanity checks make sure of.
+fn tar_zstd(tar: &Path, zstd: &Path, dir: &Path, name: &str, dst: &Path, level: u32, mtime: u64) {
+    let mut tar = t!(Command::new(tar)
+        .arg("--sort=name")
+        .arg(format!("--mtime=@{}", mtime))
+        .arg("--owner=0")
+        .arg("--group=0")
+        .arg("--numeric-owner")
+        .arg("-cf")
+        .arg("-")
+        .arg("-C")
+        .arg(dir)
+        .arg(name)
+        .stdout(Stdio::piped())
+        .spawn());
+    let mut zstd = Command::new(zstd);
+    zstd.arg("-q").arg("-f").arg("-T0").arg(format!("-{}", level));
+    // Levels above 19 use a lot more memory and have to be opted into.
+    if level > 19 {
+        zstd.arg("--ultra");
+    }
+    let status = t!(zstd.arg("-o").arg(dst).stdin(tar.stdout.take().unwrap()).status());
+    let tar_status = t!(tar.wait());
+    if !tar_status.success() || !status.success() {
+        panic!("failed to create `{}`", dst.display());
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -331,3 +447,6 @@ pub(crate) fn work_dir(&self) -> &Path {
         &self.work
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,87 @@
+use super::*;
+use crate::sanity::{find_gnu_tar, Finder};
+use crate::util::{skip_test, test_dir};
+use filetime::FileTime;
+use std::fs;
+
+/// Returns GNU tar and `zstd` for `tar_zstd`, or why it can't run here.
+fn tools() -> Result<(PathBuf, PathBuf), &'static str> {
+    let mut finder = Finder::new();
+    let tar = find_gnu_tar(&mut finder).ok_or("GNU `tar` isn't installed")?;
+    let zstd = finder.maybe_have("zstd").ok_or("`zstd` isn't installed")?;
+    Ok((tar, zstd))
+}
+
+#[test]
+fn installer_formats() {
+    let formats = |formats: &[&str]| formats.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+    assert_eq!(installer_format(None), "gz");
+    assert_eq!(installer_format(Some(&formats(&["xz", "gz"]))), "xz");
+    // rust-installer can't read `.tar.zst`, so `combine` is given the next one.
+    assert_eq!(installer_format(Some(&formats(&["zst", "xz"]))), "xz");
+    assert_eq!(installer_format(Some(&formats(&["zst"]))), "gz");
+}
+
+#[test]
+fn tar_zstd_round_trip() {
+    let (tar, zstd) = match tools() {
+        Ok(tools) => tools,
+        Err(reason) => return skip_test(reason),
+    };
+
+    let dir = test_dir();
+    let image = dir.join("image/pkg-1.0");
+    t!(fs::create_dir_all(image.join("lib")));
+    let lib = (0..64 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    t!(fs::write(image.join("lib/libfoo.rlib"), &lib));
+    t!(fs::write(image.join("version"), "1.0\n"));
+
+    let tarball = dir.join("pkg-1.0.tar.zst");
+    tar_zstd(&tar, &zstd, &dir.join("image"), "pkg-1.0", &tarball, 22, 0);
+
+    let unpacked = dir.join("unpacked");
+    t!(fs::create_dir_all(&unpacked));
+    let mut zstd =
+        t!(Command::new(&zstd).arg("-d").arg("-c").arg(&tarball).stdout(Stdio::piped()).spawn());
+    let status = t!(Command::new(&tar)
+        .arg("-xf")
+        .arg("-")
+        .arg("-C")
+        .arg(&unpacked)
+        .stdin(zstd.stdout.take().unwrap())
+        .status());
+    assert!(status.success() && t!(zstd.wait()).success());
+
+    assert_eq!(t!(fs::read(unpacked.join("pkg-1.0/lib/libfoo.rlib"))), lib);
+    assert_eq!(t!(fs::read_to_string(unpacked.join("pkg-1.0/version"))), "1.0\n");
+}
+
+#[test]
+fn tar_zstd_reproducible() {
+    let (tar, zstd) = match tools() {
+        Ok(tools) => tools,
+        Err(reason) => return skip_test(reason),
+    };
+
+    let dir = test_dir();
+    let image = |name: &str, files: &[&str], mtime: i64| {
+        let image = dir.join(name).join("pkg-1.0");
+        for file in files {
+            t!(fs::create_dir_all(image.join(file).parent().unwrap()));
+            t!(fs::write(image.join(file), file));
+            let mtime = FileTime::from_unix_time(mtime, 0);
+            t!(filetime::set_file_mtime(image.join(file), mtime));
+        }
+        dir.join(name)
+    };
+    // The same files, created in another order at another time.
+    let first = image("first", &["lib/b.rlib", "lib/a.rlib", "version"], 1_000_000);
+    let second = image("second", &["version", "lib/a.rlib", "lib/b.rlib"], 2_000_000);
+
+    let tarball = |image: &Path, name: &str| {
+        let dst = dir.join(name);
+        tar_zstd(&tar, &zstd, image, "pkg-1.0", &dst, 3, 1_500_000);
+        t!(fs::read(&dst))
+    };
+    assert_eq!(tarball(&first, "first.tar.zst"), tarball(&second, "second.tar.zst"));
+}
@@ -3,13 +3,14 @@
 //! This file implements the various regression test suites that we execute on
 //! our CI.
 
+use std::collections::BTreeMap;
 use std::env;
 use std::ffi::OsString;
 use std::fmt;
 use std::fs;
 use std::iter;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{self, Command};
 
 use build_helper::{self, output, t};
 
@@ -22,9 +23,9 @@
 use crate::native;
 use crate::tool::{self, SourceType, Tool};
 use crate::toolstate::ToolState;
-use crate::util::{self, add_link_lib_path, dylib_path, dylib_path_var};
+use crate::util::{self, add_link_lib_path, dylib_path, dylib_path_var, exe};
 use crate::Crate as CargoCrate;
-use crate::{envify, DocTests, GitRepo, Mode};
+use crate::{envify, DocTests, GitRepo, Mode, TargetKind};
 
 const ADB_TEST_DIR: &str = "/data/tmp/work";
 
@@ -1355,7 +1356,7 @@ fn run(self, builder: &Builder<'_>) {
             }
         }
         cmd.env("RUSTC_BOOTSTRAP", "1");
-        builder.add_rust_test_threads(&mut cmd);
+        builder.add_rust_test_threads(&mut cmd, target);
 
         if builder.config.sanitizers_enabled(target) {
             cmd.env("RUSTC_SANITIZER_SUPPORT", "1");
@@ -1462,7 +1463,7 @@ fn run_ext_doc(self, builder: &Builder<'_>) {
         let mut rustbook_cmd = builder.tool_cmd(Tool::Rustbook);
         let path = builder.src.join(&self.path);
         rustbook_cmd.env("PATH", new_path).arg("test").arg(path);
-        builder.add_rust_test_threads(&mut rustbook_cmd);
+        builder.add_rust_test_threads(&mut rustbook_cmd, compiler.host);
         builder.info(&format!("Testing rustbook {}", self.path.display()));
         let _time = util::timeit(&builder);
         let toolstate = if try_run(builder, &mut rustbook_cmd) {
@@ -1610,7 +1611,7 @@ fn markdown_test(builder: &Builder<'_>, compiler: Compiler, markdown: &Path) ->
 
     builder.info(&format!("doc tests for: {}", markdown.display()));
     let mut cmd = builder.rustdoc_cmd(compiler);
-    builder.add_rust_test_threads(&mut cmd);
+    builder.add_rust_test_threads(&mut cmd, compiler.host);
     cmd.arg("--test");
     cmd.arg(markdown);
     cmd.env("RUSTC_BOOTSTRAP", "1");
@@ -1808,7 +1809,12 @@ fn run(self, builder: &Builder<'_>) {
         cargo.arg("--");
         cargo.args(&builder.config.cmd.test_args());
 
-        if !builder.config.verbose_tests {
+        let compute_units = builder.config.cmd.compute_units();
+        if compute_units {
+            // The counts the VM logs are attributed to the benchmark libtest
+            // last announced, so benchmarks have to run one after the other.
+            cargo.arg("--test-threads=1");
+        } else if !builder.config.verbose_tests {
             cargo.arg("--quiet");
         }
 
@@ -1834,8 +1840,80 @@ fn run(self, builder: &Builder<'_>) {
             test_kind, krate, compiler.stage, &compiler.host, target
         ));
         let _time = util::timeit(&builder);
-        try_run(builder, &mut cargo.into());
+        if compute_units {
+            bench_compute_units(builder, target, &mut cargo.into());
+        } else {
+            try_run(builder, &mut cargo.into());
+        }
+    }
+}
+
+/// Runs the benchmarks `cmd` builds for a BPF target in the VM behind
+/// `remote-test-client` and writes the compute units each of them used to
+/// `build/compute-units.json`.
+fn bench_compute_units(builder: &Builder<'_>, target: TargetSelection, cmd: &mut Command) {
+    if !target.contains("bpf") || !builder.remote_tested(target) {
+        eprintln!(
+            "error: --compute-units needs a BPF target with `TEST_DEVICE_ADDR` pointing to \
+             the `remote-test-server` running the VM, not {}",
+            target
+        );
+        process::exit(1);
+    }
+
+    if builder.config.dry_run {
+        try_run(builder, cmd);
+        return;
+    }
+
+    // Libtest and the VM both write to the log, which is read back for the
+    // report once the benchmarks are done.
+    let log = builder.out.join("tmp").join(format!("compute-units-{}.log", target.triple));
+    t!(fs::create_dir_all(log.parent().unwrap()));
+    let file = t!(fs::File::create(&log));
+    cmd.stdout(t!(file.try_clone())).stderr(file);
+    builder.info(&format!("Logging the benchmark output to {}", log.display()));
+    let success = try_run(builder, cmd);
+    let output = t!(fs::read_to_string(&log));
+    print!("{}", output);
+    if !success {
+        return;
+    }
+
+    let report = compute_units_report(&output);
+    let width = report.keys().map(|name| name.len()).max().unwrap_or(0);
+    for (name, units) in &report {
+        println!("{:width$}  {:>10} CU", name, units, width = width);
+    }
+    let path = builder.out.join("compute-units.json");
+    t!(fs::write(&path, t!(serde_json::to_string_pretty(&report))));
+    builder.info(&format!("Wrote the compute unit report to {}", path.display()));
+}
+
+/// Adds up the compute units each benchmark used from the output of a run.
+/// Libtest prints `test <name> ... ` as it starts a benchmark, and the VM logs
+/// `consumed <n> of <limit> compute units` for every program it runs.
+fn compute_units_report(output: &str) -> BTreeMap<String, u64> {
+    let mut report = BTreeMap::new();
+    let mut current = None;
+    for line in output.lines() {
+        if let Some(test) = line.strip_prefix("test ") {
+            if let Some(end) = test.find(" ...") {
+                let name = test[..end].to_string();
+                report.entry(name.clone()).or_insert(0);
+                current = Some(name);
+            }
+        }
+        let consumed = match line.find("consumed ") {
+            Some(pos) if line.trim_end().ends_with("compute units") => &line[pos + 9..],
+            _ => continue,
+        };
+        let units = consumed.split_whitespace().next().and_then(|n| n.parse::<u64>().ok());
+        if let (Some(name), Some(units)) = (&current, units) {
+            *report.get_mut(name).unwrap() += units;
+        }
     }
+    report
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -2017,8 +2095,8 @@ fn run(self, builder: &Builder<'_>) {
 /// the build target (us) and the server is built for the target.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct RemoteCopyLibs {
-    compiler: Compiler,
-    target: TargetSelection,
+    pub compiler: Compiler,
+    pub target: TargetSelection,
 }
 
 impl Step for RemoteCopyLibs {
@@ -2249,3 +2327,213 @@ fn run(self, builder: &Builder<'_>) {
         });
     }
 }
+
+/// Compiles a minimal program for a BPF target with the compiler that was just
+/// built and checks that the result is a shared object the loader can run.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BpfSmoke {
+    pub compiler: Compiler,
+    pub target: TargetSelection,
+}
+
+impl Step for BpfSmoke {
+    type Output = ();
+    const TARGET_KIND: Option<TargetKind> = Some(TargetKind::Bpf);
+
+    fn should_run(run: ShouldRun<'_>) -> ShouldRun<'_> {
+        run.path("src/test/bpf-smoke")
+    }
+
+    fn make_run(run: RunConfig<'_>) {
+        if !run.target.contains("bpf") {
+            return;
+        }
+        run.builder.ensure(BpfSmoke {
+            compiler: run.builder.compiler(run.builder.top_stage, run.builder.config.build),
+            target: run.target,
+        });
+    }
+
+    fn run(self, builder: &Builder<'_>) {
+        let (compiler, target) = (self.compiler, self.target);
+        builder.ensure(compile::Std { compiler, target });
+
+        let out_dir = builder.test_out(target).join("bpf-smoke");
+        t!(fs::create_dir_all(&out_dir));
+        let program = out_dir.join("entrypoint.so");
+        let mut cmd = bpf_smoke_command(builder, compiler, target, &program);
+
+        builder.info(&format!("BPF smoke test stage{} ({})", compiler.stage, target));
+        builder.run(&mut cmd);
+        if builder.config.dry_run {
+            return;
+        }
+
+        let readobj =
+            builder.llvm_bin(builder.config.build).join(exe("llvm-readobj", builder.config.build));
+        let out =
+            output(Command::new(&readobj).arg("--file-headers").arg("--symbols").arg(&program));
+        if let Err(e) = check_bpf_program(&out) {
+            eprintln!("error: `{}` is not a loadable BPF program: {}", program.display(), e);
+            process::exit(1);
+        }
+        builder.verify_no_host_symbols(&program);
+        builder.verify_no_float(target, &program);
+
+        // Deployment tools that want the raw payload extract it like this.
+        let payload = out_dir.join("entrypoint.bin");
+        builder.objcopy_to_binary(target, &program, &payload);
+        if t!(fs::read(&payload)).is_empty() {
+            eprintln!("error: `{}` has nothing to load", program.display());
+            process::exit(1);
+        }
+
+        // The heap size std was built with has to end up in the program.
+        let dump = output(Command::new(&readobj).arg("--hex-dump=.sbf.heap_size").arg(&program));
+        let expected = compile::sbf_heap_size(builder, target);
+        let heap_size = sbf_heap_size(&dump);
+        if heap_size != Some(expected) {
+            eprintln!(
+                "error: expected `{}` to request a heap of {} bytes, found {:?}",
+                program.display(),
+                expected,
+                heap_size
+            );
+            process::exit(1);
+        }
+
+        // A second build has to come out with the same build id.
+        let source = builder.src.join("src/test/bpf-smoke/entrypoint.rs");
+        if let Some(expected) = compile::sbf_build_id(builder, compiler, target, &[&source]) {
+            let rebuilt = out_dir.join("entrypoint-rebuilt.so");
+            builder.run(&mut bpf_smoke_command(builder, compiler, target, &rebuilt));
+            for program in &[&program, &rebuilt] {
+                let notes = output(Command::new(&readobj).arg("--notes").arg(program));
+                let id = build_id(&notes);
+                if id != Some(&expected[..]) {
+                    eprintln!(
+                        "error: expected `{}` to have the build id {}, found {:?}",
+                        program.display(),
+                        expected,
+                        id
+                    );
+                    process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+/// Returns the command compiling the BPF smoke test program to `program`,
+/// with `compiler`'s std for `target` having been built already.
+pub fn bpf_smoke_command(
+    builder: &Builder<'_>,
+    compiler: Compiler,
+    target: TargetSelection,
+    program: &Path,
+) -> Command {
+    let source = builder.src.join("src/test/bpf-smoke/entrypoint.rs");
+    let mut cmd = Command::new(builder.rustc(compiler));
+    cmd.arg("--target")
+        .arg(target.rustc_target_arg())
+        .arg("--crate-type=cdylib")
+        .arg("-Copt-level=2")
+        .arg("-o")
+        .arg(program)
+        .arg(&source);
+    if let Some(linker) = builder.linker(target) {
+        cmd.arg(format!("-Clinker={}", linker.display()));
+    }
+    cmd.args(compile::link_args(builder, target));
+    if let Some(id) = compile::sbf_build_id(builder, compiler, target, &[&source]) {
+        cmd.arg(format!("-Clink-arg=--build-id=0x{}", id));
+    }
+    builder.add_rustc_lib_path(compiler, &mut cmd);
+    cmd
+}
+
+/// Returns the build id in the output of `llvm-readobj --notes`.
+fn build_id(notes: &str) -> Option<&str> {
+    notes.lines().find_map(|line| line.trim().strip_prefix("Build ID:")).map(str::trim)
+}
+
+/// Returns the heap size in the output of
+/// `llvm-readobj --hex-dump=.sbf.heap_size`, the section holds a little
+/// endian `u64`.
+fn sbf_heap_size(dump: &str) -> Option<u64> {
+    let mut bytes = Vec::new();
+    for line in dump.lines().map(|line| line.trim()).filter(|line| line.starts_with("0x")) {
+        // The offset, then groups of up to four bytes, then the same bytes
+        // as ASCII.
+        for group in line.split_whitespace().skip(1) {
+            if bytes.len() == 8 || !group.bytes().all(|b| b.is_ascii_hexdigit()) {
+                break;
+            }
+            for i in (0..group.len()).step_by(2) {
+                bytes.push(u8::from_str_radix(group.get(i..i + 2)?, 16).ok()?);
+            }
+        }
+    }
+    if bytes.len() != 8 {
+        return None;
+    }
+    let mut le = [0; 8];
+    le.copy_from_slice(&bytes);
+    Some(u64::from_le_bytes(le))
+}
+
+/// Checks the output of `llvm-readobj --file-headers --symbols` for a BPF
+/// shared object that exports a defined `entrypoint` function.
+fn check_bpf_program(output: &str) -> Result<(), String> {
+    let header = |name: &str| {
+        output
+            .lines()
+            .map(|line| line.trim())
+            .find_map(|line| line.strip_prefix(name))
+            .map(str::trim)
+    };
+    match header("Format:") {
+        Some("elf64-bpf") => {}
+        format => return Err(format!("expected a 64-bit BPF ELF, found {:?}", format)),
+    }
+    match header("Type:") {
+        Some(ty) if ty.starts_with("SharedObject") => {}
+        ty => return Err(format!("expected a shared object, found {:?}", ty)),
+    }
+    match header("Machine:") {
+        Some(machine) if machine.starts_with("EM_BPF") => {}
+        machine => return Err(format!("expected machine EM_BPF, found {:?}", machine)),
+    }
+
+    // Symbols are printed as blocks of `Field: value` lines between braces.
+    let mut symbol = BTreeMap::new();
+    for line in output.lines().map(|line| line.trim()) {
+        if line == "Symbol {" {
+            symbol.clear();
+        } else if line == "}" {
+            let is_entrypoint = symbol
+                .get("Name")
+                .map_or(false, |name: &&str| name.split_whitespace().next() == Some("entrypoint"));
+            if !is_entrypoint {
+                continue;
+            }
+            let field = |name: &str| symbol.get(name).copied().unwrap_or("");
+            if !field("Binding").starts_with("Global") {
+                return Err(format!(
+                    "`entrypoint` isn't global, its binding is `{}`",
+                    field("Binding")
+                ));
+            }
+            if !field("Type").starts_with("Function") || field("Section").starts_with("Undefined") {
+                return Err("`entrypoint` isn't a function defined by the program".to_string());
+            }
+            return Ok(());
+        } else if let Some(pos) = line.find(": ") {
+            symbol.insert(&line[..pos], &line[pos + 2..]);
+        }
+    }
+    Err("no `entrypoint` symbol".to_string())
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,103 @@
+use super::*;
+
+#[test]
+fn compute_units_report_by_benchmark() {
+    let output = "\
+running 3 tests
+test hash::bench_sha256 ... Program consumed 1200 of 200000 compute units
+Program consumed 800 of 200000 compute units
+bench:           0 ns/iter (+/- 0)
+test iter::bench_sum ... bench:           0 ns/iter (+/- 0)
+test num::bench_parse ... bench:           0 ns/iter (+/- 0)
+Program consumed 5123 of 200000 compute units
+
+test result: ok. 0 passed; 0 failed; 0 ignored; 3 measured; 0 filtered out
+";
+    let report = compute_units_report(output);
+    let report = report.iter().map(|(name, units)| (name.as_str(), *units)).collect::<Vec<_>>();
+    assert_eq!(
+        report,
+        [("hash::bench_sha256", 2000), ("iter::bench_sum", 0), ("num::bench_parse", 5123)]
+    );
+}
+
+/// Trimmed `llvm-readobj --file-headers --symbols` output for a BPF program.
+fn bpf_readobj(binding: &str, section: &str) -> String {
+    format!(
+        "\
+File: entrypoint.so
+Format: elf64-bpf
+Arch: bpfel
+AddressSize: 64bit
+ElfHeader {{
+  Type: SharedObject (0x3)
+  Machine: EM_BPF (0xF7)
+}}
+Symbols [
+  Symbol {{
+    Name:  (0)
+    Value: 0x0
+    Section: Undefined (0x0)
+  }}
+  Symbol {{
+    Name: entrypoint (12)
+    Value: 0x120
+    Size: 48
+    Binding: {}
+    Type: Function (0x2)
+    Other: 0
+    Section: {}
+  }}
+]
+",
+        binding, section
+    )
+}
+
+#[test]
+fn bpf_program_checks() {
+    assert_eq!(check_bpf_program(&bpf_readobj("Global (0x1)", ".text (0x5)")), Ok(()));
+
+    let local = check_bpf_program(&bpf_readobj("Local (0x0)", ".text (0x5)"));
+    assert!(local.unwrap_err().contains("isn't global"));
+    let undefined = check_bpf_program(&bpf_readobj("Global (0x1)", "Undefined (0x0)"));
+    assert!(undefined.unwrap_err().contains("isn't a function defined"));
+
+    let x86 = bpf_readobj("Global (0x1)", ".text (0x5)").replace("elf64-bpf", "elf64-x86-64");
+    assert!(check_bpf_program(&x86).unwrap_err().contains("64-bit BPF ELF"));
+    let renamed =
+        bpf_readobj("Global (0x1)", ".text (0x5)").replace("entrypoint (12)", "main (12)");
+    assert_eq!(check_bpf_program(&renamed), Err("no `entrypoint` symbol".to_string()));
+}
+
+#[test]
+fn heap_size_from_hex_dump() {
+    let dump = "
+Hex dump of section '.sbf.heap_size':
+0x00000000 00000100 00000000                   ........
+
+";
+    assert_eq!(sbf_heap_size(dump), Some(64 * 1024));
+    let short = "Hex dump of section '.sbf.heap_size':\n0x00000000 00800000    ....\n";
+    assert_eq!(sbf_heap_size(short), None);
+    assert_eq!(sbf_heap_size("Hex dump of section '.text':\n"), None);
+}
+
+#[test]
+fn build_id_from_notes() {
+    let notes = "\
+Notes [
+  NoteSection {
+    Name: .note.gnu.build-id
+    Note {
+      Owner: GNU
+      Data size: 0x14
+      Type: NT_GNU_BUILD_ID (unique build ID bitstring)
+      Build ID: c89156ebdabf859f4ee70cb0c303004dccf1ae51
+    }
+  }
+]
+";
+    assert_eq!(build_id(notes), Some("c89156ebdabf859f4ee70cb0c303004dccf1ae51"));
+    assert_eq!(build_id("Notes [\n]\n"), None);
+}
@@ -0,0 +1,940 @@
+use super::*;
+use crate::builder::tests::configure;
+
+#[test]
+fn target_cflags() {
+    let mut config = configure("build", &["A"], &["A", "bpfel-unknown-unknown"]);
+    let bpf = TargetSelection::from_user("bpfel-unknown-unknown");
+    let mut target = crate::config::Target::from_triple(&bpf.triple);
+    target.cflags = vec!["-DSOL_SHIM=1".to_string(), "-O3".to_string()];
+    config.target_config.insert(bpf, target);
+    let build = Build::new(config);
+
+    let cflags = build.cflags(bpf, crate::GitRepo::Rustc);
+    assert!(cflags.ends_with(&["+solana".to_string(), "-DSOL_SHIM=1".to_string()]));
+    let a = TargetSelection::from_user("A");
+    assert!(!build.cflags(a, crate::GitRepo::Rustc).contains(&"-DSOL_SHIM=1".to_string()));
+}
+
+#[test]
+fn debuginfo_map_to() {
+    let gcc = |config: &mut Config| {
+        let mut target = crate::config::Target::from_triple("A");
+        target.cc = Some(PathBuf::from("/usr/bin/gcc"));
+        config.target_config.insert(TargetSelection::from_user("A"), target);
+    };
+    let a = TargetSelection::from_user("A");
+
+    let mut config = configure("build", &["A"], &["A"]);
+    config.rust_remap_debuginfo = true;
+    gcc(&mut config);
+    let build = Build::new(config);
+    let rustc = build.debuginfo_map_to(GitRepo::Rustc).unwrap();
+    assert!(rustc.starts_with("/rustc/"));
+    assert_eq!(build.debuginfo_map_to(GitRepo::Llvm).as_deref(), Some("/rustc/llvm"));
+    let map = format!("-fdebug-prefix-map={}=/rustc/llvm", build.src.display());
+    assert!(build.cflags(a, GitRepo::Llvm).contains(&map));
+
+    let mut config = configure("build", &["A"], &["A"]);
+    config.rust_remap_debuginfo = true;
+    config.rust_remap_debuginfo_prefix = Some("/solana/rust".into());
+    config.rust_remap_debuginfo_llvm_prefix = Some("/solana/llvm".into());
+    gcc(&mut config);
+    let build = Build::new(config);
+    assert_eq!(build.debuginfo_map_to(GitRepo::Rustc).as_deref(), Some("/solana/rust"));
+    assert_eq!(build.debuginfo_map_to(GitRepo::Llvm).as_deref(), Some("/solana/llvm"));
+    let map = format!("-fdebug-prefix-map={}=/solana/rust", build.src.display());
+    assert!(build.cflags(a, GitRepo::Rustc).contains(&map));
+}
+
+#[test]
+fn is_cross_compiling() {
+    let build = Build::new(configure("build", &["A"], &["A", "B", "bpfel-unknown-unknown"]));
+    assert!(!build.is_cross_compiling(TargetSelection::from_user("A")));
+    assert!(build.is_cross_compiling(TargetSelection::from_user("B")));
+    assert!(build.is_cross_compiling(TargetSelection::from_user("bpfel-unknown-unknown")));
+}
+
+#[cfg(unix)]
+#[test]
+fn target_endianness() {
+    use crate::Endian;
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut build = Build::new(configure("build", &["A"], &["A", "B"]));
+    build.config.dry_run = false;
+    // Prints the cfg of a big-endian target for `B`, and logs each call.
+    let rustc = build.out.join("stub-rustc");
+    let log = build.out.join("stub-rustc.log");
+    let _ = fs::remove_file(&log);
+    t!(fs::write(
+        &rustc,
+        format!(
+            "#!/bin/sh\n\
+             echo \"$@\" >> {}\n\
+             echo 'target_arch=\"bpf\"'\n\
+             if [ \"$2\" = B ]; then echo 'target_endian=\"big\"'; \
+             else echo 'target_endian=\"little\"'; fi\n",
+            log.display()
+        )
+    ));
+    t!(fs::set_permissions(&rustc, fs::Permissions::from_mode(0o755)));
+    build.initial_rustc = rustc;
+
+    let a = TargetSelection::from_user("A");
+    let b = TargetSelection::from_user("B");
+    assert_eq!(build.target_endianness(a), Endian::Little);
+    assert_eq!(build.target_endianness(b), Endian::Big);
+    assert_eq!(build.target_endianness(b), Endian::Big);
+    assert_eq!(t!(fs::read_to_string(&log)), "--target A --print cfg\n--target B --print cfg\n");
+}
+
+/// Finds FileCheck next to a stub `llvm-config` whose bindir holds `in_bindir`
+/// and whose `llvm` libdir subdirectory holds `in_libdir`.
+#[cfg(unix)]
+fn external_filecheck(name: Option<&str>, in_bindir: &str, in_libdir: &str) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut config = configure("test", &["A"], &["A"]);
+    let a = TargetSelection::from_user("A");
+    let root = config.out.join(format!("external-llvm-{}-{}", in_bindir, in_libdir));
+    let _ = fs::remove_dir_all(&root);
+    let (bindir, libdir) = (root.join("bin"), root.join("lib"));
+    t!(fs::create_dir_all(&bindir));
+    t!(fs::create_dir_all(libdir.join("llvm")));
+    t!(fs::write(bindir.join(in_bindir), ""));
+    t!(fs::write(libdir.join("llvm").join(in_libdir), ""));
+    let llvm_config = root.join("llvm-config");
+    t!(fs::write(
+        &llvm_config,
+        format!(
+            "#!/bin/sh\n\
+             if [ \"$1\" = --bindir ]; then echo {}; else echo {}; fi\n",
+            bindir.display(),
+            libdir.display()
+        )
+    ));
+    t!(fs::set_permissions(&llvm_config, fs::Permissions::from_mode(0o755)));
+
+    let mut target = crate::config::Target::from_triple("A");
+    target.llvm_config = Some(llvm_config);
+    target.llvm_filecheck_name = name.map(str::to_string);
+    config.target_config.insert(a, target);
+    let mut build = Build::new(config);
+    build.config.dry_run = false;
+    build.llvm_filecheck(a)
+}
+
+#[cfg(unix)]
+#[test]
+fn filecheck_default_name() {
+    let filecheck = external_filecheck(None, "FileCheck", "FileCheck-12");
+    assert!(filecheck.ends_with("bin/FileCheck"), "{}", filecheck.display());
+}
+
+#[cfg(unix)]
+#[test]
+fn filecheck_overridden_name() {
+    let filecheck = external_filecheck(Some("FileCheck-12"), "FileCheck-12", "FileCheck");
+    assert!(filecheck.ends_with("bin/FileCheck-12"), "{}", filecheck.display());
+}
+
+#[cfg(unix)]
+#[test]
+fn filecheck_libdir_fallback() {
+    let filecheck = external_filecheck(Some("FileCheck-12"), "FileCheck", "FileCheck-12");
+    assert!(filecheck.ends_with("lib/llvm/FileCheck-12"), "{}", filecheck.display());
+    let filecheck = external_filecheck(None, "FileCheck-12", "FileCheck");
+    assert!(filecheck.ends_with("lib/llvm/FileCheck"), "{}", filecheck.display());
+}
+
+#[cfg(unix)]
+#[test]
+fn try_run_detailed() {
+    use crate::RunOutcome;
+
+    let mut build = Build::new(configure("build", &["A"], &["A"]));
+    build.config.dry_run = false;
+    let missing = build.out.join("no-such-binary");
+    match build.try_run_detailed(&mut Command::new(&missing)) {
+        RunOutcome::SpawnFailed(e) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+        outcome => panic!("unexpected outcome {:?}", outcome),
+    }
+    match build.try_run_detailed(Command::new("sh").arg("-c").arg("exit 3")) {
+        RunOutcome::Failed(status) => assert_eq!(status.code(), Some(3)),
+        outcome => panic!("unexpected outcome {:?}", outcome),
+    }
+    assert!(matches!(build.try_run_detailed(&mut Command::new("true")), RunOutcome::Succeeded));
+
+    // Nothing runs in dry-run mode.
+    build.config.dry_run = true;
+    assert!(matches!(build.try_run_detailed(&mut Command::new(&missing)), RunOutcome::Succeeded));
+}
+
+#[cfg(unix)]
+#[test]
+fn run_captured_json() {
+    let mut build = Build::new(configure("build", &["A"], &["A"]));
+    assert_eq!(build.run_captured_json(&mut Command::new("cargo")), Default::default());
+
+    build.config.dry_run = false;
+    let artifact = r#"{"reason":"compiler-artifact","package_id":"hello 0.1.0","features":[],"filenames":["libhello.rlib"],"target":{"crate_types":["lib"]}}"#;
+    let script = format!(
+        "echo '{}'; echo 'warning: unused variable' >&2; echo '  = note: on by default' >&2; \
+         test \"$*\" = \"build --message-format json-render-diagnostics\"",
+        artifact
+    );
+    let output = build
+        .run_captured_json(Command::new("sh").arg("-c").arg(&script).arg("cargo").arg("build"));
+    assert!(output.success);
+    assert_eq!(output.artifacts.len(), 1);
+    assert_eq!(output.artifacts[0].filenames, [PathBuf::from("libhello.rlib")]);
+    assert_eq!(output.diagnostics, ["warning: unused variable\n  = note: on by default"]);
+}
+
+#[test]
+fn ensure_tool_fresh() {
+    let mut build = Build::new(configure("build", &["A"], &["A"]));
+    build.config.dry_run = false;
+    let a = TargetSelection::from_user("A");
+    let cargo_out = build.out.join("tool-fresh");
+    let _ = fs::remove_dir_all(&cargo_out);
+    let fingerprint = |dir: &str, bin: &str| {
+        let dir = cargo_out.join(".fingerprint").join(dir);
+        t!(fs::create_dir_all(&dir));
+        t!(fs::write(dir.join(bin), ""));
+        dir
+    };
+    let tidy = fingerprint("tidy-0123", "bin-tidy");
+    let linkchecker = fingerprint("linkchecker-4567", "bin-linkchecker");
+
+    let artifact = |tool: &'static str, name: &str, features: &str| {
+        let rlib = PathBuf::from(format!("A/release/deps/lib{}-{}.rlib", name, features));
+        (tool, rlib, vec![features.to_string()])
+    };
+    let record = |id: &str, val| crate::tool::record_tool_artifact(&build, a, id, &val);
+    assert_eq!(record("serde 1.0.0", artifact("tidy", "serde", "std")), None);
+    assert_eq!(record("regex 1.4.0", artifact("linkchecker", "regex", "std")), None);
+
+    // The next build of tidy finds nothing recorded for it, so it starts
+    // over, and cargo finds no fingerprint to consider its binary fresh.
+    build.ensure_tool_fresh(a, "tidy", &cargo_out);
+    {
+        let tool_artifacts = build.tool_artifacts.borrow();
+        assert!(!tool_artifacts[&a].contains_key("serde 1.0.0"));
+        assert!(tool_artifacts[&a].contains_key("regex 1.4.0"));
+    }
+    assert!(!tidy.exists());
+    assert!(linkchecker.exists());
+
+    // Rebuilt with other features, tidy's artifacts are recorded anew rather
+    // than reported as duplicates, unlike those it shares with linkchecker.
+    assert_eq!(record("serde 1.0.0", artifact("tidy", "serde", "derive")), None);
+    assert_eq!(
+        build.tool_artifacts.borrow()[&a]["serde 1.0.0"],
+        artifact("tidy", "serde", "derive")
+    );
+    assert_eq!(
+        record("regex 1.4.0", artifact("tidy", "regex", "unicode")),
+        Some(artifact("linkchecker", "regex", "std"))
+    );
+}
+
+#[test]
+fn which_toolchain_component() {
+    let mut config = configure("build", &["A"], &["A"]);
+    config.lld_enabled = false;
+    config.llvm_tools_enabled = true;
+    let build = Build::new(config);
+    let a = TargetSelection::from_user("A");
+    let which =
+        |component, stage| build.which_toolchain_component(component, Compiler { stage, host: a });
+
+    assert_eq!(which("rustc", 0), Some(build.initial_rustc.clone()));
+    assert_eq!(which("cargo", 0), Some(build.initial_cargo.clone()));
+    assert_eq!(which("llvm-objdump", 0), None);
+
+    let stage1 = build.out.join("A/stage1");
+    assert_eq!(which("rustc", 1), Some(stage1.join("bin").join(exe("rustc", a))));
+    assert_eq!(which("rustdoc", 1), Some(stage1.join("bin").join(exe("rustdoc", a))));
+    assert_eq!(
+        which("llvm-objdump", 1),
+        Some(stage1.join("lib/rustlib/A/bin").join(exe("llvm-objdump", a)))
+    );
+    assert_eq!(which("rust-lld", 1), None);
+
+    let stage2 = build.out.join("A/stage2");
+    assert_eq!(which("rustc", 2), Some(stage2.join("bin").join(exe("rustc", a))));
+    assert_eq!(which("cargo", 2), Some(build.out.join("A/stage2-tools-bin").join(exe("cargo", a))));
+    assert_eq!(which("miri", 2), None);
+}
+
+#[test]
+fn relativize_for_sysroot() {
+    let sysroot = Path::new("/build/A/stage1");
+    let relative = |path: &str| Build::relativize_for_sysroot(Path::new(path), sysroot);
+    assert_eq!(relative("/build/A/stage1/bin/rustc"), Some(PathBuf::from("bin/rustc")));
+    assert_eq!(
+        relative("/build/A/stage1/lib64/rustlib/A/lib"),
+        Some(PathBuf::from("lib64/rustlib/A/lib"))
+    );
+    assert_eq!(relative("/build/A/stage2/bin/rustc"), None);
+}
+
+#[cfg(unix)]
+#[test]
+fn relativize_for_symlinked_sysroot() {
+    let build = Build::new(configure("build", &["A"], &["A"]));
+    let real = build.out.join("real-sysroot");
+    let link = build.out.join("linked-sysroot");
+    let _ = fs::remove_dir_all(&real);
+    let _ = fs::remove_file(&link);
+    t!(fs::create_dir_all(real.join("lib/rustlib/A/lib")));
+    t!(std::os::unix::fs::symlink(&real, &link));
+
+    // The path may not exist yet, only the sysroot has to.
+    let libdir = PathBuf::from("lib/rustlib/A/lib");
+    let rlib = libdir.join("libstd.rlib");
+    assert_eq!(Build::relativize_for_sysroot(&real.join(&libdir), &link), Some(libdir.clone()));
+    assert_eq!(Build::relativize_for_sysroot(&link.join(&rlib), &real), Some(rlib.clone()));
+    assert_eq!(Build::relativize_for_sysroot(&real.join(&rlib), &link), Some(rlib));
+    assert_eq!(Build::relativize_for_sysroot(&build.out, &link), None);
+}
+
+#[test]
+fn reserve_cpus() {
+    assert_eq!(crate::unreserved_cpus(8, 0), 8);
+    assert_eq!(crate::unreserved_cpus(8, 2), 6);
+    assert_eq!(crate::unreserved_cpus(2, 2), 1);
+    assert_eq!(crate::unreserved_cpus(1, 4), 1);
+
+    let mut config = configure("build", &["A"], &["A"]);
+    config.reserve_cpus = 1;
+    let build = Build::new(config);
+    assert_eq!(build.jobs(), crate::unreserved_cpus(num_cpus::get() as u32, 1));
+
+    // An explicit `-j` is used as is.
+    let mut config = configure("build", &["A"], &["A"]);
+    config.jobs = Some(3);
+    config.reserve_cpus = 2;
+    assert_eq!(Build::new(config).jobs(), 3);
+}
+
+#[test]
+fn copy_with_progress() {
+    let mut build = Build::new(configure("build", &["A"], &["A"]));
+    build.config.dry_run = false;
+    let dir = build.out.join("copy-with-progress");
+    let _ = fs::remove_dir_all(&dir);
+    t!(fs::create_dir_all(&dir));
+    let small = dir.join("small");
+    t!(fs::write(&small, "small"));
+    let large = dir.join("large");
+    t!(t!(fs::File::create(&large)).set_len(crate::PROGRESS_THRESHOLD));
+
+    let denied =
+        |_: &Path, _: &Path| Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+    let linked = |src: &Path, dst: &Path| fs::hard_link(src, dst);
+    let copy = |src: &Path, terminal, link: &dyn Fn(&Path, &Path) -> std::io::Result<()>| {
+        let mut progress = Vec::new();
+        let dst = dir.join("dst");
+        let kind =
+            t!(crate::copy_file_with_progress(src, &dst, terminal, false, link, &mut progress));
+        assert_eq!(t!(fs::metadata(&dst)).len(), t!(fs::metadata(src)).len());
+        (kind, String::from_utf8(progress).unwrap())
+    };
+
+    // Only a large file that can't be hardlinked is streamed with progress.
+    let (kind, progress) = copy(&large, true, &denied);
+    assert_eq!(kind, Some(crate::CopyKind::Copy));
+    assert!(progress.ends_with("Copying large (64 MiB, 100%)\n"), "{:?}", progress);
+    assert_eq!(copy(&large, true, &linked), (Some(crate::CopyKind::Hardlink), String::new()));
+    assert_eq!(copy(&large, false, &denied).1, "");
+    assert_eq!(copy(&small, true, &denied).1, "");
+}
+
+#[cfg(unix)]
+#[test]
+fn install_parallel() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut build = Build::new(configure("build", &["A"], &["A"]));
+    build.config.dry_run = false;
+    build.config.jobs = Some(2);
+    let dir = build.out.join("install-parallel");
+    let _ = fs::remove_dir_all(&dir);
+    t!(fs::create_dir_all(dir.join("src")));
+    let tools = ["llvm-nm", "llvm-objdump", "llvm-size", "opt", "llc"]
+        .iter()
+        .map(|tool| {
+            let src = dir.join("src").join(tool);
+            t!(fs::write(&src, tool));
+            t!(filetime::set_file_mtime(&src, FileTime::from_unix_time(1_000_000, 0)));
+            src
+        })
+        .collect::<Vec<_>>();
+
+    let bin = dir.join("bin");
+    build.install_parallel(tools.clone(), &bin, 0o755);
+    for src in &tools {
+        let dst = bin.join(src.file_name().unwrap());
+        assert_eq!(t!(fs::read(&dst)), t!(fs::read(src)));
+        let metadata = t!(fs::metadata(&dst));
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o755);
+        assert_eq!(FileTime::from_last_modification_time(&metadata).unix_seconds(), 1_000_000);
+    }
+
+    // A failed install on any thread panics with the path.
+    let missing = dir.join("src/llvm-ar");
+    let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        build.install_parallel(vec![tools[0].clone(), missing.clone()], &bin, 0o755)
+    }))
+    .unwrap_err();
+    let err = err.downcast_ref::<String>().unwrap();
+    assert!(err.contains(&missing.display().to_string()), "{}", err);
+
+    // Nothing is installed in dry-run mode.
+    build.config.dry_run = true;
+    build.install_parallel(tools, &dir.join("dry-run"), 0o755);
+    assert!(!dir.join("dry-run").exists());
+}
+
+#[cfg(target_os = "macos")]
+#[test]
+fn preserve_xattrs() {
+    let mut config = configure("build", &["A"], &["A"]);
+    config.dry_run = false;
+    config.preserve_xattrs = true;
+    let build = Build::new(config);
+    let dir = build.out.join("xattrs");
+    let _ = fs::remove_dir_all(&dir);
+    t!(fs::create_dir_all(&dir));
+    let src = dir.join("signed");
+    t!(fs::write(&src, "binary"));
+    let mut cmd = Command::new("xattr");
+    cmd.args(&["-w", "com.example.signed", "yes"]).arg(&src);
+    assert!(t!(cmd.status()).success());
+    let xattr = |path: &Path| {
+        let out = t!(Command::new("xattr").args(&["-p", "com.example.signed"]).arg(path).output());
+        String::from_utf8(out.stdout).unwrap().trim().to_string()
+    };
+
+    // `install` always copies, it never hardlinks.
+    build.install(&src, &dir.join("installed"), 0o755);
+    assert_eq!(xattr(&dir.join("installed/signed")), "yes");
+
+    let copy = dir.join("copy");
+    t!(fs::write(&copy, "binary"));
+    t!(crate::copy_xattrs(&src, &copy));
+    assert_eq!(xattr(&copy), "yes");
+}
+
+#[test]
+fn symlink_or_copy_dir() {
+    let mut build = Build::new(configure("build", &["A"], &["A"]));
+    build.config.dry_run = false;
+    let src = build.out.join("linked-src");
+    let _ = fs::remove_dir_all(&src);
+    t!(fs::create_dir_all(src.join("lib")));
+    t!(fs::write(src.join("lib/libfoo.rlib"), "foo"));
+
+    // Creating the link is denied, so `src` gets copied instead.
+    let copied = build.out.join("copied");
+    let _ = fs::remove_dir_all(&copied);
+    let denied =
+        |_: &Path, _: &Path| Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+    assert_eq!(build.link_or_copy_dir(&src, &copied, &denied), crate::CopyKind::Copy);
+    assert!(!t!(copied.symlink_metadata()).file_type().is_symlink());
+    assert_eq!(t!(fs::read_to_string(copied.join("lib/libfoo.rlib"))), "foo");
+
+    let linked = build.out.join("linked");
+    let _ = fs::remove_dir_all(&linked);
+    let _ = fs::remove_file(&linked);
+    assert_eq!(build.symlink_or_copy_dir(&src, &linked), crate::CopyKind::Symlink);
+    assert_eq!(t!(fs::read_to_string(linked.join("lib/libfoo.rlib"))), "foo");
+}
+
+#[cfg(unix)]
+#[test]
+fn install_symlink() {
+    let rel = |path: &str, base: &str| crate::util::relative_path(Path::new(path), Path::new(base));
+    assert_eq!(rel("/a/b/bin/shim", "/a/b/bin"), PathBuf::from("shim"));
+    assert_eq!(rel("/a/b/bin/shim", "/a/c/bin"), PathBuf::from("../../b/bin/shim"));
+
+    let mut build = Build::new(configure("build", &["A"], &["A"]));
+    build.config.dry_run = false;
+    let dir = build.out.join("install-symlink");
+    let _ = fs::remove_dir_all(&dir);
+    t!(fs::create_dir_all(dir.join("src")));
+    let shim = dir.join("src/shim");
+    t!(fs::write(&shim, "#!/bin/sh\n"));
+
+    let bin = dir.join("prefix/bin");
+    assert_eq!(build.install_symlink(&shim, &bin, 0o755), crate::CopyKind::Symlink);
+    assert_eq!(t!(fs::read_link(bin.join("shim"))), Path::new("../../src/shim"));
+    assert_eq!(t!(fs::read_to_string(bin.join("shim"))), "#!/bin/sh\n");
+
+    // Creating the link is denied, so the shim gets copied instead.
+    let denied =
+        |_: &Path, _: &Path| Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+    assert_eq!(build.link_or_install(&shim, &bin, 0o755, &denied), crate::CopyKind::Copy);
+    assert!(!t!(bin.join("shim").symlink_metadata()).file_type().is_symlink());
+    assert_eq!(t!(fs::read_to_string(bin.join("shim"))), "#!/bin/sh\n");
+
+    // Nothing is installed in dry-run mode.
+    build.config.dry_run = true;
+    build.install_symlink(&shim, &dir.join("dry-run"), 0o755);
+    assert!(!dir.join("dry-run").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn versioned_docs() {
+    let a = TargetSelection::from_user("A");
+    let build = Build::new(configure("doc", &["A"], &["A"]));
+    assert_eq!(build.doc_out(a), build.out.join("A/doc"));
+    assert_eq!(build.compiler_doc_out(a), build.out.join("A/compiler-doc"));
+
+    let mut config = configure("doc", &["A"], &["A"]);
+    config.versioned_docs = true;
+    let mut build = Build::new(config);
+    let release = build.rust_release();
+    assert_eq!(build.doc_out(a), build.out.join("A/doc").join(&release));
+    assert_eq!(build.compiler_doc_out(a), build.out.join("A/compiler-doc").join(&release));
+
+    // Only the documentation that was generated gets a `latest` link.
+    build.config.dry_run = false;
+    let _ = fs::remove_dir_all(build.out.join("A"));
+    t!(fs::create_dir_all(build.doc_out(a)));
+    t!(fs::write(build.doc_out(a).join("index.html"), "std"));
+    crate::doc::link_latest_docs(&build);
+    let latest = build.out.join("A/doc/latest");
+    assert_eq!(t!(fs::read_link(&latest)), PathBuf::from(&release));
+    assert_eq!(t!(fs::read_to_string(latest.join("index.html"))), "std");
+    assert!(!build.out.join("A/compiler-doc/latest").exists());
+
+    // Linking again replaces the old link.
+    crate::doc::link_latest_docs(&build);
+    assert_eq!(t!(fs::read_link(&latest)), PathBuf::from(&release));
+}
+
+#[cfg(unix)]
+#[test]
+fn probe_musl_libdir() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let musl = TargetSelection::from_user("x86_64-unknown-linux-musl");
+    let musl_libdir = |probe: bool, libdir: Option<&str>| {
+        let mut config = configure("build", &["A"], &["A", &musl.triple]);
+        let root = config.out.join("musl");
+        t!(fs::create_dir_all(root.join("lib/x86_64")));
+        t!(fs::write(root.join("lib/libc.a"), ""));
+        t!(fs::write(root.join("lib/x86_64/libc.a"), ""));
+        let cc = config.out.join("musl-gcc");
+        let libc = root.join("lib/x86_64/libc.a");
+        t!(fs::write(&cc, format!("#!/bin/sh\necho {}\n", libc.display())));
+        t!(fs::set_permissions(&cc, fs::Permissions::from_mode(0o755)));
+
+        let mut target = crate::config::Target::from_triple(&musl.triple);
+        target.cc = Some(cc);
+        target.musl_root = Some(root.clone());
+        target.musl_libdir = libdir.map(|dir| root.join(dir));
+        target.probe_musl_libdir = probe;
+        config.target_config.insert(musl, target);
+        let libdir = Build::new(config).musl_libdir(musl).unwrap();
+        t!(libdir.strip_prefix(&root)).to_path_buf()
+    };
+
+    assert_eq!(musl_libdir(true, None), Path::new("lib/x86_64"));
+    // An explicit libdir still wins, and without probing the root is used.
+    assert_eq!(musl_libdir(true, Some("lib")), Path::new("lib"));
+    assert_eq!(musl_libdir(false, None), Path::new("lib"));
+}
+
+#[cfg(unix)]
+#[test]
+fn run_with_output_capture() {
+    let mut build = Build::new(configure("build", &["A"], &["A"]));
+    build.config.dry_run = false;
+    let sh = |script: &str| {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(script);
+        cmd
+    };
+
+    let out = build.run_with_output_capture(&mut sh("echo out; echo err >&2"));
+    assert!(out.is_success());
+    assert_eq!(out.stdout, "out\n");
+    assert_eq!(out.stderr, "err\n");
+
+    let out = build.run_with_output_capture(&mut sh("echo partial; echo broken >&2; exit 3"));
+    assert!(!out.is_success());
+    assert_eq!(out.status.and_then(|status| status.code()), Some(3));
+    assert_eq!(out.stdout, "partial\n");
+    assert_eq!(out.stderr, "broken\n");
+
+    // Nothing runs in dry-run mode, so the file is never created.
+    let marker = build.out.join("captured");
+    build.config.dry_run = true;
+    let out = build.run_with_output_capture(&mut sh(&format!("touch {}", marker.display())));
+    assert!(out.is_success());
+    assert!(out.status.is_none());
+    assert_eq!((out.stdout.as_str(), out.stderr.as_str()), ("", ""));
+    assert!(!marker.exists());
+}
+
+#[test]
+fn clean_target() {
+    let build = Build::new(configure("clean", &["A"], &["A", "B"]));
+    let host = |path: &str| build.out.join("A").join(path);
+    let removed = [
+        build.out.join("B/llvm/bin/clang"),
+        build.out.join("B/native/libfoo.a"),
+        build.out.join("B/doc/index.html"),
+        host("stage1-std/B/release/libstd.rlib"),
+        host("stage1/lib/rustlib/B/lib/libstd.rlib"),
+        build.out.join(&*build.build.triple).join("stage0-std/B/release/libcore.rlib"),
+    ];
+    let kept = [
+        host("llvm/bin/clang"),
+        host("stage1-std/A/release/libstd.rlib"),
+        host("stage1/lib/rustlib/A/lib/libstd.rlib"),
+        host("stage1/bin/rustc"),
+    ];
+    for file in removed.iter().chain(&kept) {
+        t!(fs::create_dir_all(file.parent().unwrap()));
+        t!(fs::write(file, ""));
+    }
+
+    build.clean_target(TargetSelection::from_user("B"), false).unwrap();
+    assert!(!build.out.join("B").exists());
+    for file in &removed {
+        assert!(!file.exists(), "{} still exists", file.display());
+    }
+    for file in &kept {
+        assert!(file.exists(), "{} was removed", file.display());
+    }
+
+    // Hosts are only cleaned with `--all`.
+    let a = TargetSelection::from_user("A");
+    assert!(build.clean_target(a, false).is_err());
+    assert!(host("stage1/bin/rustc").exists());
+    build.clean_target(a, true).unwrap();
+    assert!(!build.out.join("A").exists());
+}
+
+#[test]
+fn uplift_stage1() {
+    let bpf = TargetSelection::from_user("bpfel-unknown-unknown");
+    let force_use_stage1 = |uplift: bool, full_bootstrap: bool, stage: u32| {
+        let mut config = configure("build", &["A"], &["A", &bpf.triple]);
+        config.full_bootstrap = full_bootstrap;
+        let mut target = crate::config::Target::from_triple(&bpf.triple);
+        target.uplift_stage1 = uplift;
+        config.target_config.insert(bpf, target);
+        let build = Build::new(config);
+        let host = TargetSelection::from_user("A");
+        build.force_use_stage1(Compiler { stage, host }, bpf)
+    };
+
+    assert!(!force_use_stage1(false, false, 2));
+    assert!(force_use_stage1(true, false, 2));
+    assert!(!force_use_stage1(true, false, 1));
+    assert!(!force_use_stage1(true, true, 2));
+}
+
+#[test]
+fn no_std_features() {
+    let none = TargetSelection::from_user("thumbv7em-none-eabi");
+    let mut config = configure("build", &["A"], &["A", &none.triple]);
+    config.backtrace = true;
+    config.profiler = true;
+    config.target_config.insert(none, crate::config::Target::from_triple(&none.triple));
+    let build = Build::new(config);
+
+    assert_eq!(build.std_features(none), "compiler-builtins-mem");
+    let std = build.std_features(TargetSelection::from_user("A"));
+    assert!(std.starts_with("panic-unwind"), "{}", std);
+    assert!(std.contains("backtrace") && std.contains("profiler"), "{}", std);
+}
+
+#[test]
+fn cp_filtered_stats() {
+    let build = Build::new(configure("dist", &["A"], &["A"]));
+    let (src, dst) = (build.out.join("cp-src"), build.out.join("cp-dst"));
+    let _ = fs::remove_dir_all(&src);
+    let _ = fs::remove_dir_all(&dst);
+    for file in &["a.rs", "b.orig", "sub/c.rs", "sub/d.orig", ".git/HEAD", ".git/config"] {
+        let file = src.join(file);
+        t!(fs::create_dir_all(file.parent().unwrap()));
+        t!(fs::write(file, ""));
+    }
+    t!(fs::create_dir_all(&dst));
+
+    let stats = build.cp_filtered(&src, &dst, &|path| {
+        path.extension().map_or(true, |ext| ext != "orig") && !path.ends_with(".git")
+    });
+    // `.git` is skipped as a whole, without looking at what's inside.
+    assert_eq!(stats, crate::FilterStats { copied: 2, skipped: 3 });
+}
+
+#[test]
+fn std_features_overrides() {
+    let strings = |s: &[&str]| s.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    assert_eq!(crate::merge_features("panic-unwind backtrace", &[]), "panic-unwind backtrace");
+    assert_eq!(
+        crate::merge_features("panic-unwind backtrace", &strings(&["-backtrace", "profiler"])),
+        "panic-unwind profiler"
+    );
+    // Later entries win, and features aren't added twice.
+    assert_eq!(
+        crate::merge_features("panic-unwind", &strings(&["profiler", "-profiler"])),
+        "panic-unwind"
+    );
+    assert_eq!(
+        crate::merge_features("panic-unwind", &strings(&["-profiler", "profiler", "profiler"])),
+        "panic-unwind profiler"
+    );
+
+    let mut config = configure("build", &["A"], &["A", "B"]);
+    config.backtrace = true;
+    config.profiler = false;
+    let b = TargetSelection::from_user("B");
+    let mut target = crate::config::Target::from_triple(&b.triple);
+    target.std_features = strings(&["-backtrace", "profiler"]);
+    config.target_config.insert(b, target);
+    let build = Build::new(config);
+    assert_eq!(build.std_features(TargetSelection::from_user("A")), "panic-unwind backtrace");
+    assert_eq!(build.std_features(b), "panic-unwind profiler");
+}
+
+#[test]
+fn optional_crates() {
+    let mut config = configure("build", &["A"], &["A", "B"]);
+    config.profiler = false;
+    config.rust_codegen_backends = vec![INTERNER.intern_str("cranelift")];
+    let b = TargetSelection::from_user("B");
+    let mut target = crate::config::Target::from_triple(&b.triple);
+    target.profiler = Some(true);
+    config.target_config.insert(b, target);
+    let build = Build::new(config);
+    let a = TargetSelection::from_user("A");
+
+    assert!(build.crate_enabled("std", Some(a)));
+    assert!(!build.crate_enabled("build_helper", None));
+    assert!(!build.crate_enabled("profiler_builtins", Some(a)));
+    assert!(build.crate_enabled("profiler_builtins", Some(b)));
+    assert!(build.crate_enabled("profiler_builtins", None));
+    assert!(!build.crate_enabled("rustc_codegen_llvm", Some(a)));
+}
+
+#[test]
+fn in_tree_crates_cycle() {
+    let mut build = Build::new(configure("build", &["A"], &["A"]));
+    let mut add = |name: &str, deps: &[&str]| {
+        let name = INTERNER.intern_str(name);
+        let deps = deps.iter().map(|dep| INTERNER.intern_str(dep)).collect();
+        let krate = crate::Crate { name, deps, id: name.to_string(), path: PathBuf::from(&*name) };
+        build.crates.insert(name, krate);
+    };
+    // `core` and `alloc` depend on each other.
+    add("std", &["core", "alloc", "profiler_builtins", "build_helper"]);
+    add("core", &["alloc"]);
+    add("alloc", &["core"]);
+    add("profiler_builtins", &["core"]);
+    add("build_helper", &[]);
+    build.config.profiler = false;
+
+    let names = |build: &Build| {
+        let mut names =
+            build.in_tree_crates("std", None).iter().map(|c| c.name).collect::<Vec<_>>();
+        names.sort();
+        names
+    };
+    assert_eq!(names(&build), ["alloc", "core", "std"]);
+    build.config.profiler = true;
+    assert_eq!(names(&build), ["alloc", "core", "profiler_builtins", "std"]);
+}
+
+#[test]
+fn channel_suffix() {
+    let versions = |channel: &str, suffix: Option<&str>| {
+        let mut config = configure("dist", &["A"], &["A"]);
+        config.ignore_git = true;
+        config.channel = channel.to_string();
+        config.channel_suffix = suffix.map(|s| s.to_string());
+        let build = Build::new(config);
+        (build.release("1.2.3"), build.package_vers("1.2.3"))
+    };
+
+    let release = |r: &str, p: &str| (r.to_string(), p.to_string());
+    assert_eq!(versions("stable", None), release("1.2.3", "1.2.3"));
+    assert_eq!(versions("beta", None), release("1.2.3-beta", "beta"));
+    assert_eq!(versions("nightly", None), release("1.2.3-nightly", "nightly"));
+    assert_eq!(versions("dev", None), release("1.2.3-dev", "1.2.3-dev"));
+
+    let suffix = Some("solana.3");
+    assert_eq!(versions("stable", suffix), release("1.2.3-solana.3", "1.2.3-solana.3"));
+    assert_eq!(versions("beta", suffix), release("1.2.3-beta-solana.3", "beta-solana.3"));
+    assert_eq!(versions("nightly", suffix), release("1.2.3-nightly-solana.3", "nightly-solana.3"));
+    assert_eq!(versions("dev", suffix), release("1.2.3-dev-solana.3", "1.2.3-dev-solana.3"));
+
+    let mut config = configure("build", &["A"], &["A"]);
+    config.ignore_git = true;
+    config.channel = "stable".to_string();
+    config.channel_suffix = Some("solana.3".to_string());
+    let build = Build::new(config);
+    assert_eq!(build.rust_version(), format!("{}-solana.3", build.version));
+}
+
+#[test]
+fn beta_merges_of_shallow_clone() {
+    let dir = configure("build", &["A"], &["A"]).out.join("beta-merges");
+    let _ = fs::remove_dir_all(&dir);
+    t!(fs::create_dir_all(&dir));
+    let git = |cwd: &Path, args: &[&str]| {
+        let status = t!(Command::new("git")
+            .args(&["-c", "user.name=A", "-c", "user.email=a@example.com"])
+            .args(args)
+            .current_dir(cwd)
+            .status());
+        assert!(status.success(), "git {:?} failed", args);
+    };
+    let upstream = dir.join("upstream");
+    t!(fs::create_dir_all(&upstream));
+    git(&upstream, &["init", "-q"]);
+    git(&upstream, &["commit", "-q", "--allow-empty", "-m", "first"]);
+    git(&upstream, &["commit", "-q", "--allow-empty", "-m", "second"]);
+    git(&upstream, &["branch", "-M", "master"]);
+    let url = format!("file://{}", upstream.display());
+
+    git(&dir, &["clone", "-q", &url, "full"]);
+    assert_eq!(crate::beta_merge_count(&dir.join("full")), Some(0));
+    git(&dir, &["clone", "-q", "--depth", "1", &url, "shallow"]);
+    assert_eq!(crate::beta_merge_count(&dir.join("shallow")), None);
+}
+
+#[test]
+fn package_version() {
+    let dir = configure("build", &["A"], &["A"]).out.join("package-version");
+    let _ = fs::remove_dir_all(&dir);
+    t!(fs::create_dir_all(dir.join("src/tools/direct")));
+    t!(fs::create_dir_all(dir.join("src/tools/inherited")));
+    t!(fs::write(
+        dir.join("Cargo.toml"),
+        "[workspace]\nmembers = [\"src/tools/*\"]\n\n[workspace.package]\nversion = \"1.2.3\"\n",
+    ));
+    t!(fs::write(
+        dir.join("src/tools/direct/Cargo.toml"),
+        "[package]\nname = \"direct\"\nversion = \"0.4.0\"\n\n\
+         [dependencies]\nserde = { version = \"1.0\" }\n",
+    ));
+    t!(fs::write(
+        dir.join("src/tools/inherited/Cargo.toml"),
+        "[package]\nname = \"inherited\"\nversion.workspace = true\n",
+    ));
+
+    let version =
+        |tool: &str| crate::package_version(&dir.join("src/tools").join(tool).join("Cargo.toml"));
+    assert_eq!(version("direct").as_deref(), Some("0.4.0"));
+    assert_eq!(version("inherited").as_deref(), Some("1.2.3"));
+}
+
+#[test]
+fn rust_test_threads() {
+    let mut config = configure("test", &["A"], &["A", "bpfel-unknown-unknown"]);
+    config.jobs = Some(16);
+    let bpf = TargetSelection::from_user("bpfel-unknown-unknown");
+    let mut target = crate::config::Target::from_triple(&bpf.triple);
+    target.qemu_rootfs = Some(PathBuf::from("/nonexistent/rootfs"));
+    config.target_config.insert(bpf, target);
+    let build = Build::new(config);
+    let a = TargetSelection::from_user("A");
+
+    assert_eq!(build.rust_test_threads(a, None), "16");
+    assert_eq!(build.rust_test_threads(bpf, None), "2");
+    // An explicit value is passed through, even for remote-tested targets.
+    assert_eq!(build.rust_test_threads(bpf, Some("8".into())), "8");
+    assert_eq!(build.rust_test_threads(a, Some("1".into())), "1");
+}
+
+#[test]
+fn tool_bpf_stage_out() {
+    let build = Build::new(configure("build", &["A"], &["A", "bpfel-unknown-unknown"]));
+    let compiler = Compiler { host: TargetSelection::from_user("A"), stage: 1 };
+    let bpf = TargetSelection::from_user("bpfel-unknown-unknown");
+
+    let stage_out = build.stage_out(compiler, Mode::ToolBpf);
+    assert!(stage_out.ends_with("A/stage1-tools-bpf"));
+    assert_ne!(stage_out, build.stage_out(compiler, Mode::ToolRustc));
+    let cargo_out = build.cargo_out(compiler, Mode::ToolBpf, bpf);
+    assert!(cargo_out.starts_with(stage_out.join("bpfel-unknown-unknown")));
+
+    assert!(Mode::ToolBpf.is_tool());
+    assert!(Mode::ToolBpf.must_support_dlopen());
+    assert!(!Mode::ToolRustc.must_support_dlopen());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn objcopy_to_binary() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = env::var_os("PATH").unwrap_or_default();
+    let bindir = match env::split_paths(&path).find(|dir| dir.join("llvm-objcopy").exists()) {
+        Some(bindir) => bindir,
+        None => return crate::util::skip_test("`llvm-objcopy` isn't installed"),
+    };
+    let mut config = configure("build", &["A"], &["A"]);
+    // Don't have the sanity check fail on the missing FileCheck.
+    config.codegen_tests = false;
+    let a = TargetSelection::from_user("A");
+    let llvm_config = config.out.join("llvm-config");
+    t!(fs::write(&llvm_config, format!("#!/bin/sh\necho {}\n", bindir.display())));
+    t!(fs::set_permissions(&llvm_config, fs::Permissions::from_mode(0o755)));
+    let mut target = crate::config::Target::from_triple(&a.triple);
+    target.llvm_config = Some(llvm_config);
+    config.target_config.insert(a, target);
+    let mut build = Build::new(config);
+    build.config.dry_run = false;
+
+    // The test binary itself serves as the ELF file.
+    let elf = t!(env::current_exe());
+    let bin = build.out.join("payload.bin");
+    let _ = fs::remove_file(&bin);
+    build.objcopy_to_binary(a, &elf, &bin);
+    let contents = t!(fs::read(&bin));
+    assert!(!contents.is_empty());
+    assert!(!contents.starts_with(b"\x7fELF"));
+}
+
+#[cfg(unix)]
+#[test]
+fn llvm_filecheck_cached() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut config = configure("test", &["A"], &["A"]);
+    // Don't have the sanity check fail on the missing FileCheck.
+    config.codegen_tests = false;
+    let a = TargetSelection::from_user("A");
+    let calls = config.out.join("llvm-config-calls");
+    let llvm_config = config.out.join("llvm-config");
+    let _ = fs::remove_file(&calls);
+    t!(fs::write(
+        &llvm_config,
+        format!("#!/bin/sh\necho \"$1\" >> {}\necho /nonexistent/llvm/bin\n", calls.display()),
+    ));
+    t!(fs::set_permissions(&llvm_config, fs::Permissions::from_mode(0o755)));
+    let mut target = crate::config::Target::from_triple(&a.triple);
+    target.llvm_config = Some(llvm_config);
+    config.target_config.insert(a, target);
+    let build = Build::new(config);
+
+    // FileCheck is in neither the bindir nor the libdir, so the path in
+    // the bindir is returned for errors to refer to.
+    let filecheck = PathBuf::from("/nonexistent/llvm/bin").join(exe("FileCheck", a));
+    for _ in 0..3 {
+        assert_eq!(build.llvm_filecheck(a), filecheck);
+    }
+    assert_eq!(t!(fs::read_to_string(&calls)), "--bindir\n--libdir\n");
+}
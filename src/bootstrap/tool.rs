@@ -12,6 +12,7 @@
 use crate::config::TargetSelection;
 use crate::toolstate::ToolState;
 use crate::util::{add_dylib_path, exe};
+use crate::Build;
 use crate::Compiler;
 use crate::Mode;
 
@@ -54,10 +55,16 @@ fn run(self, builder: &Builder<'_>) -> Option<PathBuf> {
         match self.mode {
             Mode::ToolRustc => builder.ensure(compile::Rustc { compiler, target }),
             Mode::ToolStd => builder.ensure(compile::Std { compiler, target }),
+            Mode::ToolBpf => compile::bpf_tool_std(builder, compiler, target),
             Mode::ToolBootstrap => {} // uses downloaded stage0 compiler libs
             _ => panic!("unexpected Mode for tool build"),
         }
 
+        if builder.config.rebuild_tools.iter().any(|name| name == tool) {
+            let cargo_out = builder.cargo_out(compiler, self.mode, target);
+            builder.ensure_tool_fresh(target, tool, &cargo_out);
+        }
+
         let cargo = prepare_tool_cargo(
             builder,
             compiler,
@@ -116,37 +123,11 @@ fn run(self, builder: &Builder<'_>) -> Option<PathBuf> {
                     }
                 }
 
-                // Record that we've built an artifact for `id`, and if one was
-                // already listed then we need to see if we reused the same
-                // artifact or produced a duplicate.
-                let mut artifacts = builder.tool_artifacts.borrow_mut();
-                let prev_artifacts = artifacts.entry(target).or_default();
-                let prev = match prev_artifacts.get(&*id) {
-                    Some(prev) => prev,
-                    None => {
-                        prev_artifacts.insert(id.to_string(), val);
-                        continue;
-                    }
-                };
-                if prev.1 == val.1 {
-                    return; // same path, same artifact
-                }
-
-                // If the paths are different and one of them *isn't* inside of
-                // `release/deps`, then it means it's probably in
-                // `$target/release`, or it's some final artifact like
-                // `libcargo.rlib`. In these situations Cargo probably just
-                // copied it up from `$target/release/deps/libcargo-xxxx.rlib`,
-                // so if the features are equal we can just skip it.
-                let prev_no_hash = prev.1.parent().unwrap().ends_with("release/deps");
-                let val_no_hash = val.1.parent().unwrap().ends_with("release/deps");
-                if prev.2 == val.2 || !prev_no_hash || !val_no_hash {
-                    return;
+                // ... and if this looks like we duplicated some sort of
+                // compilation, record it to generate an error later.
+                if let Some(prev) = record_tool_artifact(builder, target, &id, &val) {
+                    duplicates.push((id.to_string(), val, prev));
                 }
-
-                // ... and otherwise this looks like we duplicated some sort of
-                // compilation, so record it to generate an error later.
-                duplicates.push((id.to_string(), val, prev.clone()));
             }
         });
 
@@ -223,6 +204,41 @@ fn run(self, builder: &Builder<'_>) -> Option<PathBuf> {
     }
 }
 
+/// Records that we've built the artifact `val` for `id`, and if one was
+/// already listed then we need to see if we reused the same artifact or
+/// produced a duplicate. Returns the one listed before in the latter case.
+pub fn record_tool_artifact(
+    build: &Build,
+    target: TargetSelection,
+    id: &str,
+    val: &(&'static str, PathBuf, Vec<String>),
+) -> Option<(&'static str, PathBuf, Vec<String>)> {
+    let mut artifacts = build.tool_artifacts.borrow_mut();
+    let prev_artifacts = artifacts.entry(target).or_default();
+    let prev = match prev_artifacts.get(id) {
+        Some(prev) => prev,
+        None => {
+            prev_artifacts.insert(id.to_string(), val.clone());
+            return None;
+        }
+    };
+    if prev.1 == val.1 {
+        return None; // same path, same artifact
+    }
+
+    // If the paths are different and one of them *isn't* inside of
+    // `release/deps`, then it means it's probably in `$target/release`, or
+    // it's some final artifact like `libcargo.rlib`. In these situations Cargo
+    // probably just copied it up from `$target/release/deps/libcargo-xxxx.rlib`,
+    // so if the features are equal we can just skip it.
+    let prev_no_hash = prev.1.parent().unwrap().ends_with("release/deps");
+    let val_no_hash = val.1.parent().unwrap().ends_with("release/deps");
+    if prev.2 == val.2 || !prev_no_hash || !val_no_hash {
+        return None;
+    }
+    Some(prev.clone())
+}
+
 pub fn prepare_tool_cargo(
     builder: &Builder<'_>,
     compiler: Compiler,
@@ -275,6 +291,14 @@ pub fn prepare_tool_cargo(
     if !features.is_empty() {
         cargo.arg("--features").arg(&features.join(", "));
     }
+    // BPF programs get the same build id as the smoke test, keyed on the
+    // tool's manifest so that two tools don't share one.
+    if mode == Mode::ToolBpf {
+        let manifest = dir.join("Cargo.toml");
+        if let Some(id) = compile::sbf_build_id(builder, compiler, target, &[&manifest]) {
+            cargo.rustflag(&format!("-Clink-arg=--build-id=0x{}", id));
+        }
+    }
     cargo
 }
 
@@ -376,6 +400,39 @@ fn run(self, builder: &Builder<'_>) -> PathBuf {
     JsonDocCk, "src/tools/jsondocck", "jsondocck";
 );
 
+/// A tool in `src/tools` built for `target`, which for a BPF target means
+/// with `Mode::ToolBpf` against the BPF standard library.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct TargetTool {
+    pub compiler: Compiler,
+    pub target: TargetSelection,
+    pub tool: &'static str,
+    pub path: &'static str,
+}
+
+impl Step for TargetTool {
+    type Output = PathBuf;
+
+    fn should_run(run: ShouldRun<'_>) -> ShouldRun<'_> {
+        run.never()
+    }
+
+    fn run(self, builder: &Builder<'_>) -> PathBuf {
+        builder
+            .ensure(ToolBuild {
+                compiler: self.compiler,
+                target: self.target,
+                tool: self.tool,
+                mode: if self.target.contains("bpf") { Mode::ToolBpf } else { Mode::ToolBootstrap },
+                path: self.path,
+                is_optional_tool: false,
+                source_type: SourceType::InTree,
+                extra_features: Vec::new(),
+            })
+            .expect("expected to build -- essential tool")
+    }
+}
+
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, Ord, PartialOrd)]
 pub struct ErrorIndex {
     pub compiler: Compiler,
@@ -536,14 +593,25 @@ fn run(self, builder: &Builder<'_>) -> PathBuf {
             "Building rustdoc for stage{} ({})",
             target_compiler.stage, target_compiler.host
         ));
-        builder.run(&mut cargo.into());
+        let output = builder.run_captured_json(&mut cargo.into());
+        if !builder.config.dry_run && !output.success {
+            eprintln!("failed to build rustdoc");
+            exit(1);
+        }
 
         // Cargo adds a number of paths to the dylib search path on windows, which results in
         // the wrong rustdoc being executed. To avoid the conflicting rustdocs, we name the "tool"
         // rustdoc a different name.
-        let tool_rustdoc = builder
-            .cargo_out(build_compiler, Mode::ToolRustc, target)
-            .join(exe("rustdoc_tool_binary", target_compiler.host));
+        let name = exe("rustdoc_tool_binary", target_compiler.host);
+        let tool_rustdoc = output
+            .artifacts
+            .iter()
+            .flat_map(|artifact| &artifact.filenames)
+            .find(|path| path.file_name().map_or(false, |file| file == &*name))
+            .cloned()
+            .unwrap_or_else(|| {
+                builder.cargo_out(build_compiler, Mode::ToolRustc, target).join(&name)
+            });
 
         // don't create a stage0-sysroot/bin directory.
         if target_compiler.stage > 0 {
@@ -4,18 +4,22 @@
 //! not a lot of interesting happenings here unfortunately.
 
 use std::env;
-use std::fs;
-use std::io;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str;
 use std::time::Instant;
 
-use build_helper::t;
+use build_helper::{mtime, t};
+use sha2::{Digest, Sha256};
 
 use crate::builder::Builder;
 use crate::config::{Config, TargetSelection};
 
+#[cfg(test)]
+mod tests;
+
 /// Returns the `name` as the filename of a static library for `target`.
 pub fn staticlib(name: &str, target: TargetSelection) -> String {
     if target.contains("windows") { format!("{}.lib", name) } else { format!("lib{}.a", name) }
@@ -117,6 +121,94 @@ pub fn push_exe_path(mut buf: PathBuf, components: &[&str]) -> PathBuf {
     buf
 }
 
+/// Returns the hex encoded sha256 of the contents of `path`.
+///
+/// The file is hashed in chunks as it's read, so this is fine to use on
+/// large artifacts.
+pub fn sha256_file(path: &Path) -> String {
+    let mut file = t!(File::open(path));
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = t!(file.read(&mut buf));
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Copies the lines rustc prints for `-Ztime-passes` from its `stderr` to
+/// `passes`, and everything else, like diagnostics, to `rest`.
+pub fn split_time_passes(
+    mut stderr: impl BufRead,
+    passes: &mut impl Write,
+    rest: &mut impl Write,
+) -> io::Result<()> {
+    let mut line = Vec::new();
+    while stderr.read_until(b'\n', &mut line)? != 0 {
+        if line.starts_with(b"time:") {
+            passes.write_all(&line)?;
+        } else {
+            rest.write_all(&line)?;
+        }
+        line.clear();
+    }
+    Ok(())
+}
+
+/// Inputs of `Build::clear_if_dirty` larger than this are compared by mtime
+/// even with `build.hash-stamps`, hashing them could take longer than the
+/// rebuild it might save.
+const STAMP_HASH_LIMIT: u64 = 256 * 1024 * 1024;
+
+/// Returns the hash to record in the stamp of `input`, if it's a file small
+/// enough to hash.
+pub fn stamp_hash(input: &Path) -> Option<String> {
+    let metadata = fs::metadata(input).ok()?;
+    if !metadata.is_file() || metadata.len() > STAMP_HASH_LIMIT {
+        return None;
+    }
+    Some(sha256_file(input))
+}
+
+/// Returns what `Build::clear_if_dirty` records in a stamp, the `version` of
+/// the toolchain being built followed by the `hash` of the input, if any.
+pub fn stamp_contents(version: &str, hash: Option<&str>) -> String {
+    format!("version {}\n{}", version, hash.unwrap_or_default())
+}
+
+/// Returns whether whatever was built from `input` is out of date. That's
+/// always the case if `stamp` wasn't written for `version`, including stamps
+/// from before versions were recorded, as the artifacts of another version
+/// must not end up in this one's sysroot. Given the `hash` of `input` it's
+/// then the case if `stamp` records a different one, otherwise, or if `stamp`
+/// was written without a hash, if `input` is newer.
+pub fn stamp_is_dirty(stamp: &Path, input: &Path, version: &str, hash: Option<&str>) -> bool {
+    let recorded = fs::read_to_string(stamp).unwrap_or_default();
+    let mut lines = recorded.splitn(2, '\n');
+    if lines.next().and_then(|line| line.strip_prefix("version ")) != Some(version) {
+        return true;
+    }
+    if let Some(hash) = hash {
+        match lines.next() {
+            Some(recorded) if !recorded.is_empty() => return recorded != hash,
+            _ => {}
+        }
+    }
+    mtime(stamp) < mtime(input)
+}
+
+/// Returns the command stripping `artifact` in place with the `llvm-strip` at
+/// `strip`. All symbols and debug info are removed, unless `keep_debug` is set
+/// in which case only local symbols are, and the debug sections stay.
+pub fn strip_command(strip: &Path, artifact: &Path, keep_debug: bool) -> Command {
+    let mut cmd = Command::new(strip);
+    cmd.arg(if keep_debug { "--discard-all" } else { "--strip-all" }).arg(artifact);
+    cmd
+}
+
 pub struct TimeIt(bool, Instant);
 
 /// Returns an RAII structure that prints out how long it took to drop.
@@ -243,6 +335,136 @@ fn to_u16s<S: AsRef<OsStr>>(s: S) -> io::Result<Vec<u16>> {
     }
 }
 
+/// Returns the path leading from the directory `base` to `path`, both of
+/// which have to be absolute and free of `..` components.
+pub fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let common = path.components().zip(base.components()).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for _ in base.components().skip(common) {
+        relative.push("..");
+    }
+    relative.extend(path.components().skip(common));
+    relative
+}
+
+/// The longest command line that is safe to spawn. Windows limits the whole
+/// command line to 32767 UTF-16 units. Unix systems limit the combined size
+/// of the arguments and the environment instead, to 256KiB on macOS and
+/// usually 2MiB on Linux, so leave room for a large environment there.
+pub const COMMAND_LINE_LIMIT: usize = if cfg!(windows) {
+    32_000
+} else if cfg!(target_os = "macos") {
+    128 * 1024
+} else {
+    1024 * 1024
+};
+
+/// Returns `args` unchanged if a command line made of `program` and them stays
+/// below `limit` bytes, or writes them to the response file `rsp` and returns
+/// the single argument `@rsp` otherwise. gcc, clang and MSVC all accept those,
+/// with MSVC quoting rules if `msvc`.
+pub fn response_file_args(
+    program: &Path,
+    args: Vec<String>,
+    rsp: &Path,
+    limit: usize,
+    msvc: bool,
+) -> Vec<String> {
+    let len = program.as_os_str().len() + args.iter().map(|arg| arg.len() + 3).sum::<usize>();
+    if len < limit {
+        return args;
+    }
+    let contents = args
+        .iter()
+        .map(|arg| if msvc { quote_msvc_arg(arg) } else { quote_gnu_arg(arg) })
+        .collect::<Vec<_>>()
+        .join("\n");
+    t!(fs::create_dir_all(rsp.parent().unwrap()));
+    t!(fs::write(rsp, contents));
+    vec![format!("@{}", rsp.display())]
+}
+
+/// Quotes `arg` for a gcc or clang response file, where a backslash escapes
+/// any character.
+fn quote_gnu_arg(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        if c == '\\' || c == '"' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Quotes `arg` like the Windows command line parser expects, where
+/// backslashes are only special in front of a quote.
+fn quote_msvc_arg(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        if c == '\\' {
+            backslashes += 1;
+        } else {
+            if c == '"' {
+                quoted.extend(std::iter::repeat('\\').take(backslashes + 1));
+            }
+            backslashes = 0;
+        }
+        quoted.push(c);
+    }
+    // Keep the closing quote from being escaped.
+    quoted.extend(std::iter::repeat('\\').take(backslashes));
+    quoted.push('"');
+    quoted
+}
+
+/// Returns the number of bytes available to the current user on the
+/// filesystem holding `path`.
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    return free_space_inner(path);
+
+    #[cfg(not(windows))]
+    fn free_space_inner(path: &Path) -> io::Result<u64> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        unsafe {
+            let mut stat: libc::statvfs = std::mem::zeroed();
+            if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+        }
+    }
+
+    #[cfg(windows)]
+    fn free_space_inner(path: &Path) -> io::Result<u64> {
+        use std::os::windows::ffi::OsStrExt;
+        use std::ptr;
+
+        use winapi::um::fileapi::GetDiskFreeSpaceExW;
+        use winapi::um::winnt::ULARGE_INTEGER;
+
+        let path = path.as_os_str().encode_wide().chain(Some(0)).collect::<Vec<_>>();
+        unsafe {
+            let mut available: ULARGE_INTEGER = std::mem::zeroed();
+            let res = GetDiskFreeSpaceExW(
+                path.as_ptr(),
+                &mut available,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+            if res == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(*available.QuadPart())
+        }
+    }
+}
+
 /// The CI environment rustbuild is running in. This mainly affects how the logs
 /// are printed.
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -308,3 +530,24 @@ pub fn use_host_linker(target: TargetSelection) -> bool {
         || target.contains("fortanix")
         || target.contains("fuchsia"))
 }
+
+/// Returns an empty directory for the test running on the current thread,
+/// removing whatever an earlier run of it left behind.
+#[cfg(test)]
+pub fn test_dir() -> PathBuf {
+    let dir = PathBuf::from(env::var_os("BOOTSTRAP_OUTPUT_DIRECTORY").unwrap())
+        .join("tmp-rustbuild-tests")
+        .join(std::thread::current().name().unwrap_or("unknown").replace(":", "-"));
+    let _ = fs::remove_dir_all(&dir);
+    t!(fs::create_dir_all(&dir));
+    dir
+}
+
+/// Reports that the test running on the current thread is skipped because of
+/// `reason`. It's written to stderr directly, which unlike `eprintln!` isn't
+/// captured by the test harness, so skips show up even when tests pass.
+#[cfg(test)]
+pub fn skip_test(reason: &str) {
+    let test = std::thread::current().name().unwrap_or("unknown").to_string();
+    let _ = writeln!(io::stderr(), "skipping {}: {}", test, reason);
+}
@@ -0,0 +1,162 @@
+use super::*;
+use filetime::FileTime;
+
+const FIXTURE: &str = "\
+static int helper(int x) { return x * 2; }
+int entry(int x) { return helper(x); }
+";
+
+/// Returns the number of sections of the ELF object at `path`, or `None` if it
+/// isn't a 64-bit little endian ELF file.
+fn elf_section_count(path: &Path) -> Option<u16> {
+    let bytes = t!(fs::read(path));
+    if bytes.len() < 64 || &bytes[..6] != b"\x7fELF\x02\x01" {
+        return None;
+    }
+    Some(u16::from_le_bytes([bytes[60], bytes[61]]))
+}
+
+#[test]
+fn strip_drops_sections() {
+    let dir = test_dir();
+    let src = dir.join("fixture.c");
+    t!(fs::write(&src, FIXTURE));
+
+    // Needs a C compiler and `llvm-strip` on the host, which not every
+    // machine running these tests has.
+    let object = dir.join("fixture.o");
+    let compiled = Command::new("cc").arg("-g").arg("-c").arg(&src).arg("-o").arg(&object).status();
+    if !compiled.map_or(false, |s| s.success()) {
+        return skip_test("`cc` can't compile the fixture");
+    }
+    let before = match elf_section_count(&object) {
+        Some(count) => count,
+        None => return skip_test("`cc` doesn't produce 64-bit little endian ELF objects"),
+    };
+    let stripped = dir.join("stripped.o");
+    t!(fs::copy(&object, &stripped));
+    match strip_command(Path::new("llvm-strip"), &stripped, false).status() {
+        Ok(status) => assert!(status.success()),
+        Err(_) => return skip_test("`llvm-strip` isn't installed"),
+    }
+    let after = elf_section_count(&stripped).unwrap();
+    assert!(after < before, "{} sections before stripping, {} after", before, after);
+
+    let kept = dir.join("kept.o");
+    t!(fs::copy(&object, &kept));
+    assert!(strip_command(Path::new("llvm-strip"), &kept, true).status().unwrap().success());
+    assert!(elf_section_count(&kept).unwrap() > after);
+}
+
+const VERSION: &str = "1.52.0";
+
+/// Writes `input` and a stamp recording its hash, with `input` touched
+/// afterwards as a checkout would.
+fn touched_input(dir: &Path, contents: &str) -> (PathBuf, PathBuf) {
+    let input = dir.join("rustc");
+    let stamp = dir.join(".stamp");
+    t!(fs::write(&input, "rustc"));
+    t!(fs::write(&stamp, stamp_contents(VERSION, stamp_hash(&input).as_deref())));
+    t!(fs::write(&input, contents));
+    let written = FileTime::from_last_modification_time(&t!(fs::metadata(&stamp)));
+    let later = FileTime::from_unix_time(written.unix_seconds() + 10, 0);
+    t!(filetime::set_file_mtime(&input, later));
+    (input, stamp)
+}
+
+#[test]
+fn stamp_touched_but_unchanged() {
+    let dir = test_dir();
+    let (input, stamp) = touched_input(&dir, "rustc");
+    assert!(!stamp_is_dirty(&stamp, &input, VERSION, stamp_hash(&input).as_deref()));
+    // Without hashing, touching is enough.
+    assert!(stamp_is_dirty(&stamp, &input, VERSION, None));
+}
+
+#[test]
+fn stamp_changed() {
+    let dir = test_dir();
+    let (input, stamp) = touched_input(&dir, "rustc, but different");
+    assert!(stamp_is_dirty(&stamp, &input, VERSION, stamp_hash(&input).as_deref()));
+
+    // A stamp written without a hash falls back to comparing mtimes.
+    let (input, stamp) = touched_input(&dir, "rustc");
+    t!(fs::write(&stamp, stamp_contents(VERSION, None)));
+    assert!(stamp_is_dirty(&stamp, &input, VERSION, stamp_hash(&input).as_deref()));
+}
+
+#[test]
+fn stamp_of_other_version() {
+    let dir = test_dir();
+    let (input, stamp) = touched_input(&dir, "rustc");
+    let hash = stamp_hash(&input);
+    assert!(!stamp_is_dirty(&stamp, &input, VERSION, hash.as_deref()));
+    assert!(stamp_is_dirty(&stamp, &input, "1.53.0", hash.as_deref()));
+    assert!(stamp_is_dirty(&stamp, &input, "1.53.0", None));
+
+    // Stamps from before versions were recorded only hold the hash.
+    t!(fs::write(&stamp, hash.as_deref().unwrap()));
+    assert!(stamp_is_dirty(&stamp, &input, VERSION, hash.as_deref()));
+    t!(fs::write(&stamp, ""));
+    t!(filetime::set_file_mtime(&input, FileTime::zero()));
+    assert!(stamp_is_dirty(&stamp, &input, VERSION, None));
+}
+
+#[test]
+fn cflags_response_file() {
+    let dir = test_dir();
+    let rsp = dir.join("cflags/print-file-name.rsp");
+    let cc = Path::new("cc");
+
+    let short = vec!["-ffunction-sections".to_string(), "-fPIC".to_string()];
+    assert_eq!(response_file_args(cc, short.clone(), &rsp, COMMAND_LINE_LIMIT, false), short);
+    assert!(!rsp.exists());
+
+    let mut long = (0..2000)
+        .map(|i| format!("-fdebug-prefix-map=/checkout/build/src/crate-{}=/rustc/{:040}", i, i))
+        .collect::<Vec<_>>();
+    long.push(r#"-DGREETING="hello world""#.to_string());
+    long.push(r"-IC:\Program Files\include\".to_string());
+    assert_eq!(
+        response_file_args(cc, long.clone(), &rsp, 32_000, false),
+        [format!("@{}", rsp.display())]
+    );
+    let contents = t!(fs::read_to_string(&rsp));
+    assert_eq!(contents.lines().count(), long.len());
+    assert!(contents.contains(r#""-DGREETING=\"hello world\"""#));
+    assert!(contents.ends_with(r#""-IC:\\Program Files\\include\\""#));
+
+    response_file_args(cc, long, &rsp, 32_000, true);
+    let contents = t!(fs::read_to_string(&rsp));
+    assert!(contents.contains(r#""-DGREETING=\"hello world\"""#));
+    assert!(contents.ends_with(r#""-IC:\Program Files\include\\""#));
+
+    // The C compiler of the host has to read the flags back, if there is one.
+    let flags = vec![r#"-DGREETING="hello world""#.to_string(); 2000];
+    let args = response_file_args(cc, flags, &rsp, 32_000, false);
+    assert_eq!(args.len(), 1);
+    let src = dir.join("greeting.c");
+    t!(fs::write(&src, "const char *greeting = GREETING;\n"));
+    let out = match Command::new(cc).args(&args).arg("-E").arg(&src).output() {
+        Ok(out) => out,
+        Err(_) => return skip_test("`cc` isn't installed"),
+    };
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let out = String::from_utf8_lossy(&out.stdout);
+    assert!(out.contains(r#"greeting = "hello world";"#), "{}", out);
+}
+
+#[test]
+fn time_passes_output() {
+    let stderr = "\
+time:   0.002; rss:   41MB ->   43MB (   +2MB)\tparse_crate
+{\"message\":\"unused variable: `x`\",\"level\":\"warning\"}
+time:   0.010; rss:   43MB ->   52MB (   +9MB)\t  expand_crate
+time:   0.120; rss:   39MB ->   60MB (  +21MB)\ttotal";
+    let (mut passes, mut rest) = (Vec::new(), Vec::new());
+    split_time_passes(stderr.as_bytes(), &mut passes, &mut rest).unwrap();
+    let passes = String::from_utf8(passes).unwrap();
+    assert_eq!(passes.lines().count(), 3);
+    assert!(passes.ends_with("\ttotal"), "{}", passes);
+    assert_eq!(rest, b"{\"message\":\"unused variable: `x`\",\"level\":\"warning\"}\n");
+}
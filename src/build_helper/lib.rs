@@ -1,7 +1,8 @@
 use std::ffi::{OsStr, OsString};
 use std::fmt::Display;
+use std::io;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::process::{Command, ExitStatus, Stdio};
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::{env, fs};
 
@@ -82,6 +83,26 @@ pub fn try_run(cmd: &mut Command) -> bool {
     status.success()
 }
 
+/// How a command run by `try_run_detailed` ended.
+#[derive(Debug)]
+pub enum RunOutcome {
+    /// The command couldn't be started at all, usually because it doesn't exist.
+    SpawnFailed(io::Error),
+    /// The command ran but exited unsuccessfully.
+    Failed(ExitStatus),
+    Succeeded,
+}
+
+/// Like `try_run`, but tells a command that couldn't be started apart from
+/// one that failed, and leaves reporting either to the caller.
+pub fn try_run_detailed(cmd: &mut Command) -> RunOutcome {
+    match cmd.status() {
+        Ok(status) if status.success() => RunOutcome::Succeeded,
+        Ok(status) => RunOutcome::Failed(status),
+        Err(e) => RunOutcome::SpawnFailed(e),
+    }
+}
+
 pub fn run_suppressed(cmd: &mut Command) {
     if !try_run_suppressed(cmd) {
         std::process::exit(1);
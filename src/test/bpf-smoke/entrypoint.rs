@@ -0,0 +1,16 @@
+// The program `x.py test bpf-smoke` builds with the freshly built compiler
+// to check that it produces something the loader accepts.
+
+#![no_std]
+
+#[no_mangle]
+pub unsafe extern "C" fn entrypoint(input: *mut u8) -> u64 {
+    // Touch the input so the function isn't folded into a constant.
+    let len = *(input as *const u64);
+    (len > 0) as u64
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo<'_>) -> ! {
+    loop {}
+}
@@ -0,0 +1,74 @@
+// Checks that aggregates passed indirectly on BPF carry their natural
+// alignment, including over-aligned ones, and that callers allocate their
+// copies that aligned.
+
+// compile-flags: --target bpfel-unknown-unknown -C no-prepopulate-passes
+// needs-llvm-components: bpf
+
+#![crate_type = "lib"]
+#![feature(no_core, lang_items)]
+#![no_core]
+
+#[lang="sized"]
+trait Sized { }
+#[lang="freeze"]
+trait Freeze { }
+#[lang="copy"]
+trait Copy { }
+
+#[repr(C)]
+pub struct Small {
+    a: u16,
+    b: u16,
+    c: u16,
+    d: u16,
+    e: u16,
+}
+
+#[repr(C)]
+pub struct Big {
+    a: u64,
+    b: u64,
+    c: u64,
+}
+
+#[repr(C, align(32))]
+pub struct OverAligned {
+    a: u64,
+    b: u64,
+}
+
+// CHECK: @pass_small({{.*}} align 2 dereferenceable(10) %
+#[no_mangle]
+pub extern "C" fn pass_small(_x: Small) {}
+
+// CHECK: @pass_big({{.*}} align 8 dereferenceable(24) %
+#[no_mangle]
+pub extern "C" fn pass_big(_x: Big) {}
+
+// CHECK: @pass_over_aligned({{.*}} align 32 dereferenceable(32) %
+#[no_mangle]
+pub extern "C" fn pass_over_aligned(_x: OverAligned) {}
+
+// CHECK-LABEL: @call_over_aligned(
+// CHECK: alloca {{.*}}, align 32
+// CHECK: call void @pass_over_aligned(
+#[no_mangle]
+pub extern "C" fn call_over_aligned() {
+    pass_over_aligned(OverAligned { a: 1, b: 2 });
+}
+
+// The callee reads the argument in place, it's aligned like its type.
+// CHECK-LABEL: @read_over_aligned(
+// CHECK-NOT: @llvm.memcpy
+// CHECK: load i64, i64* %{{.*}}, align 32
+#[no_mangle]
+pub extern "C" fn read_over_aligned(x: OverAligned) -> u64 {
+    x.a
+}
+
+// CHECK: @return_over_aligned({{.*}} sret{{.*}} align 32 dereferenceable(32) %
+#[no_mangle]
+pub extern "C" fn return_over_aligned(x: OverAligned) -> OverAligned {
+    x
+}
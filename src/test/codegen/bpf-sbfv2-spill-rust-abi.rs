@@ -0,0 +1,68 @@
+// Checks that under the SBFv2 calling convention closure calls, whose
+// arguments are untupled, and `#[track_caller]` functions, which take the
+// caller's location as an extra argument, build with more arguments than
+// fit in registers.
+
+// compile-flags: --target bpfel-unknown-unknown -C target-feature=+solana-v2
+// compile-flags: -C no-prepopulate-passes
+// needs-llvm-components: bpf
+
+#![crate_type = "lib"]
+#![feature(no_core, lang_items, unboxed_closures)]
+#![no_core]
+
+#[lang="sized"]
+trait Sized { }
+#[lang="freeze"]
+trait Freeze { }
+#[lang="copy"]
+trait Copy { }
+
+impl Copy for u64 { }
+
+#[lang = "fn_once"]
+trait FnOnce<Args> {
+    #[lang = "fn_once_output"]
+    type Output;
+
+    extern "rust-call" fn call_once(self, args: Args) -> Self::Output;
+}
+
+#[lang = "fn_mut"]
+trait FnMut<Args>: FnOnce<Args> {
+    extern "rust-call" fn call_mut(&mut self, args: Args) -> Self::Output;
+}
+
+#[lang = "fn"]
+trait Fn<Args>: FnMut<Args> {
+    extern "rust-call" fn call(&self, args: Args) -> Self::Output;
+}
+
+#[lang = "panic_location"]
+struct Location<'a> {
+    file: &'a str,
+    line: u32,
+    col: u32,
+}
+
+// CHECK-LABEL: @call_closure
+// CHECK: call i64 @{{.*}}call_closure{{.*}}closure{{.*}}({{.*}}, i64 %{{.*}}, i64 %{{.*}}
+#[no_mangle]
+pub fn call_closure(x: u64) -> u64 {
+    let f = |_a: u64, _b: u64, _c: u64, _d: u64, _e: u64, f: u64| f;
+    f(1, 2, 3, 4, 5, x)
+}
+
+// CHECK-LABEL: define i64 @tracked(i64 %a, i64 %b, i64 %c, i64 %d
+#[track_caller]
+#[no_mangle]
+pub fn tracked(a: u64, b: u64, c: u64, d: u64, e: u64, f: u64) -> u64 {
+    f
+}
+
+// CHECK-LABEL: @call_tracked
+// CHECK: call i64 @tracked(i64 1, i64 2, i64 3, i64 4
+#[no_mangle]
+pub fn call_tracked() -> u64 {
+    tracked(1, 2, 3, 4, 5, 6)
+}
@@ -0,0 +1,33 @@
+// Checks that under the SBFv2 calling convention the arguments which don't
+// fit in registers are stored in one block, whose address is passed last.
+
+// compile-flags: --target bpfel-unknown-unknown -C target-feature=+solana-v2
+// compile-flags: -C no-prepopulate-passes
+// needs-llvm-components: bpf
+
+#![crate_type = "lib"]
+#![feature(no_core, lang_items)]
+#![no_core]
+
+#[lang="sized"]
+trait Sized { }
+#[lang="freeze"]
+trait Freeze { }
+#[lang="copy"]
+trait Copy { }
+
+impl Copy for u64 { }
+
+// CHECK: define i64 @six(i64 %a, i64 %b, i64 %c, i64 %d, i8* %0)
+// CHECK: getelementptr inbounds i8, i8* %0, i64 8
+#[no_mangle]
+pub extern "C" fn six(a: u64, b: u64, c: u64, d: u64, e: u64, f: u64) -> u64 {
+    f
+}
+
+// CHECK-LABEL: @call_six
+// CHECK: call i64 @six(i64 1, i64 2, i64 3, i64 4, i8* %{{.*}})
+#[no_mangle]
+pub extern "C" fn call_six() -> u64 {
+    six(1, 2, 3, 4, 5, 6)
+}
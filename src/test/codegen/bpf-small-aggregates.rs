@@ -0,0 +1,68 @@
+// Checks that structs of scalars which fit in a register are passed in one on
+// BPF, the same way clang passes C structs, while larger ones and unions stay
+// indirect.
+
+// compile-flags: --target bpfel-unknown-unknown -C no-prepopulate-passes
+// needs-llvm-components: bpf
+
+#![crate_type = "lib"]
+#![feature(no_core, lang_items)]
+#![no_core]
+
+#[lang="sized"]
+trait Sized { }
+#[lang="freeze"]
+trait Freeze { }
+#[lang="copy"]
+trait Copy { }
+
+impl Copy for u32 { }
+impl Copy for f32 { }
+
+#[repr(C)]
+pub struct Pair {
+    a: u32,
+    b: u32,
+}
+
+#[repr(C)]
+pub struct Wrapper(u64);
+
+#[repr(C)]
+pub struct Triple {
+    a: u16,
+    b: u8,
+}
+
+#[repr(C)]
+pub union Bits {
+    a: u32,
+    b: f32,
+}
+
+#[repr(C)]
+pub struct Big {
+    a: u64,
+    b: u64,
+    c: u64,
+}
+
+// CHECK: define void @pass_pair(i64 %{{.*}})
+#[no_mangle]
+pub extern "C" fn pass_pair(_x: Pair) {}
+
+// CHECK: define void @pass_wrapper(i64 %{{.*}})
+#[no_mangle]
+pub extern "C" fn pass_wrapper(_x: Wrapper) {}
+
+// CHECK: define void @pass_triple(i32 %{{.*}})
+#[no_mangle]
+pub extern "C" fn pass_triple(_x: Triple) {}
+
+// CHECK: define void @pass_bits(%Bits* {{.*}} %_x)
+#[no_mangle]
+pub extern "C" fn pass_bits(_x: Bits) {}
+
+// CHECK: define void @pass_big(%Big* {{.*}} %_x)
+#[no_mangle]
+pub extern "C" fn pass_big(_x: Big) {}
@@ -0,0 +1,24 @@
+// Checks that zero-sized arguments take up no parameter on BPF.
+
+// compile-flags: --target bpfel-unknown-unknown -C no-prepopulate-passes
+// needs-llvm-components: bpf
+
+#![crate_type = "lib"]
+#![feature(no_core, lang_items)]
+#![no_core]
+
+#[lang="sized"]
+trait Sized { }
+#[lang="freeze"]
+trait Freeze { }
+#[lang="copy"]
+trait Copy { }
+
+#[repr(C)]
+pub struct Marker;
+
+// CHECK: define i64 @take_marker(i64 %x)
+#[no_mangle]
+pub extern "C" fn take_marker(_m: Marker, x: u64) -> u64 {
+    x
+}
@@ -0,0 +1,18 @@
+// compile-flags: --target bpfel-unknown-unknown
+// needs-llvm-components: bpf
+
+#![feature(no_core, lang_items)]
+#![no_core]
+
+#[lang = "sized"]
+trait Sized {}
+
+// @has bpf_target.json "$.index[*][?(@.name=='Account')].visibility" \"public\"
+pub struct Account;
+
+// @has - "$.index[*][?(@.name=='entrypoint')].visibility" \"public\"
+// @has - "$.index[*][?(@.name=='entrypoint')].inner.abi" '"\"C\""'
+pub extern "C" fn entrypoint() {}
+
+// @!has - "$.index[*][?(@.name=='helper')]"
+fn helper() {}